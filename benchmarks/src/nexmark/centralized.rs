@@ -15,9 +15,9 @@
 mod rainbow;
 
 use super::add_extra_metadata;
-use super::create_nexmark_functions;
 use super::create_nexmark_source;
 use super::create_physical_plans;
+use super::deploy_nexmark_query;
 use crate::NexmarkBenchmarkOpt;
 
 use datafusion::arrow::util::pretty::pretty_format_batches;
@@ -48,12 +48,8 @@ pub async fn nexmark_benchmark(opt: &mut NexmarkBenchmarkOpt) -> Result<()> {
 
     let mut ctx = register_nexmark_tables().await?;
     let plans = create_physical_plans(&mut ctx, query_number).await?;
-    let worker = create_nexmark_functions(
-        opt,
-        nexmark_conf.window.clone(),
-        plans.last().unwrap().clone(),
-    )
-    .await?;
+    let launcher = deploy_nexmark_query(opt, plans.last().unwrap().clone()).await?;
+    let worker = &launcher.data_source_ctx.as_ref().unwrap().next;
 
     // The source generator function needs the metadata to determine the type of the
     // workers such as single function or a group. We don't want to keep this info
@@ -11,8 +11,6 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-extern crate daggy;
-
 #[path = "../rainbow.rs"]
 mod rainbow;
 
@@ -20,13 +18,12 @@ use super::add_extra_metadata;
 use super::create_nexmark_source;
 use super::create_physical_plans;
 use super::nexmark_query;
+use super::nexmark_side_inputs;
 use crate::NexmarkBenchmarkOpt;
-use daggy::NodeIndex;
 use datafusion::execution::context::ExecutionConfig;
 use flock::aws::lambda;
-use flock::distributed_plan::QueryDag;
+use flock::launcher::Launcher;
 use flock::prelude::*;
-use humantime::parse_duration;
 use lazy_static::lazy_static;
 use log::info;
 use nexmark::register_nexmark_tables_with_config;
@@ -65,9 +62,12 @@ pub async fn nexmark_benchmark(opt: &mut NexmarkBenchmarkOpt) -> Result<()> {
         _ => unreachable!(),
     };
 
+    let side_inputs = nexmark_side_inputs(query_number);
     let mut launcher =
-        AwsLambdaLauncher::try_new(query_code, plan, sink_type, state_backend).await?;
-    launcher.create_cloud_contexts(*FLOCK_FUNCTION_CONCURRENCY)?;
+        AwsLambdaLauncher::try_new(query_code, plan, sink_type, state_backend, side_inputs).await?;
+    launcher.architecture = opt.architecture.clone();
+    launcher.memory_size = Some(opt.memory_size);
+    launcher.deploy().await?;
 
     info!(
         "Streaming: {}",
@@ -92,18 +92,16 @@ pub async fn nexmark_benchmark(opt: &mut NexmarkBenchmarkOpt) -> Result<()> {
         info!("Physical Plan:\n{}", stage.get_plan_str());
     }
 
-    let dag = &mut launcher.dag;
-    create_nexmark_functions(dag, opt, *FLOCK_FUNCTION_CONCURRENCY).await?;
-
     let mut metadata = HashMap::new();
     add_extra_metadata(opt, &mut metadata).await?;
 
+    let source_function = FunctionId::try_new(&format!("q{}", opt.query_number), 0)?;
     let tasks = (0..opt.generators)
         .into_iter()
         .map(|i| {
             let s = nexmark_conf.clone();
             let m = metadata.clone();
-            let f = format!("q{}-{:02}", opt.query_number, 0);
+            let f = source_function.to_cloud_name();
             tokio::spawn(async move {
                 info!(
                     "[OK] Invoking NEXMark source function: {} by generator {}\n",
@@ -127,58 +125,3 @@ pub async fn nexmark_benchmark(opt: &mut NexmarkBenchmarkOpt) -> Result<()> {
 
     Ok(())
 }
-
-/// Create lambda functions for a given NexMark query.
-async fn create_nexmark_functions(
-    dag: &mut QueryDag,
-    opt: &NexmarkBenchmarkOpt,
-    group_size: usize,
-) -> Result<()> {
-    let count = dag.node_count();
-    assert!(count < 100);
-
-    let func_types = (0..count)
-        .map(|i| dag.get_node(NodeIndex::new(i)).unwrap().get_function_type())
-        .collect::<Vec<CloudFunctionType>>();
-
-    for i in (0..count).rev() {
-        let node = dag.get_node_mut(NodeIndex::new(i)).unwrap();
-        if func_types[i] == CloudFunctionType::Group {
-            let group_name = format!("q{}-{:02}", opt.query_number, count - 1 - i);
-            info!(
-                "Creating lambda function group: {}",
-                rainbow_string(format!("({}, {})", group_name, group_size))
-            );
-            let tasks = (0..group_size)
-                .into_iter()
-                .map(|j| {
-                    let mut ctx = node.context.clone().unwrap();
-                    let name = group_name.clone();
-                    let memory_size = opt.memory_size;
-                    let architecture = opt.architecture.clone();
-                    tokio::spawn(async move {
-                        ctx.name = format!("{}-{:02}", name, j);
-                        lambda::create_function(&ctx, memory_size, &architecture).await?;
-                        info!("Created function member: {}", rainbow_string(&ctx.name));
-                        lambda::set_concurrency(&ctx.name, 1).await
-                    })
-                })
-                .collect::<Vec<JoinHandle<Result<()>>>>();
-            futures::future::join_all(tasks).await;
-            tokio::time::sleep(parse_duration("2s").unwrap()).await;
-        } else {
-            lambda::create_function(
-                node.context.as_ref().unwrap(),
-                opt.memory_size,
-                &opt.architecture,
-            )
-            .await?;
-            info!(
-                "Created lambda function: {}",
-                rainbow_string(format!("q{}-{:02}", opt.query_number, count - 1 - i))
-            );
-        }
-    }
-
-    Ok(())
-}
@@ -25,8 +25,10 @@ use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::datasource::MemTable;
 use datafusion::execution::context::ExecutionContext as DataFusionExecutionContext;
 use datafusion::physical_plan::ExecutionPlan;
-use flock::aws::{efs, lambda, s3};
+use flock::aws::efs;
 use flock::prelude::*;
+use flock::stream::tvf::rewrite_window_functions;
+use flock::stream::WindowFunction;
 use lazy_static::lazy_static;
 use log::info;
 use nexmark::event::{side_input_schema, Auction, Bid, Person};
@@ -35,7 +37,6 @@ use rainbow::rainbow_string;
 use std::collections::HashMap;
 use std::sync::Arc;
 use structopt::StructOpt;
-use tokio::task::JoinHandle;
 
 static SIDE_INPUT_DOWNLOAD_URL: &str = concat!(
     "https://gist.githubusercontent.com/gangliao/",
@@ -50,9 +51,6 @@ lazy_static! {
     pub static ref NEXMARK_PERSON: SchemaRef = Arc::new(Person::schema());
     pub static ref NEXMARK_AUCTION: SchemaRef = Arc::new(Auction::schema());
     pub static ref NEXMARK_SOURCE_LOG_GROUP: String = "/aws/lambda/flock_datasource".to_string();
-    pub static ref NEXMARK_Q4_S3_KEY: String = FLOCK_CONF["nexmark"]["q4_s3_key"].to_string();
-    pub static ref NEXMARK_Q6_S3_KEY: String = FLOCK_CONF["nexmark"]["q6_s3_key"].to_string();
-    pub static ref NEXMARK_Q9_S3_KEY: String = FLOCK_CONF["nexmark"]["q9_s3_key"].to_string();
     pub static ref NEXMARK_Q13_S3_SIDE_INPUT_KEY: String = FLOCK_CONF["nexmark"]["q13_s3_side_input_key"].to_string();
 }
 
@@ -113,13 +111,17 @@ async fn main() -> Result<()> {
 }
 
 pub async fn create_nexmark_source(opt: &mut NexmarkBenchmarkOpt) -> Result<NEXMarkSource> {
-    let window = match opt.query_number {
-        0..=4 | 6 | 9 | 10 | 13 => Window::ElementWise,
-        5 => Window::Hopping((10, 5)),
-        7..=8 => Window::Tumbling(Schedule::Seconds(10)),
-        11 => Window::Session(Schedule::Seconds(10)),
-        12 => Window::Global(Schedule::Seconds(10)),
-        _ => unreachable!(),
+    // The window function in the SQL takes precedence over the default window.
+    let window = match nexmark_window_function(opt.query_number)? {
+        Some(function) => function.window,
+        None => match opt.query_number {
+            0..=4 | 6 | 9 | 10 | 13 => Window::ElementWise,
+            5 => Window::Hopping((10, 5)),
+            7..=8 => Window::Tumbling(Schedule::Seconds(10)),
+            11 => Window::Session(Schedule::Seconds(10)),
+            12 => Window::Global(Schedule::Seconds(10)),
+            _ => unreachable!(),
+        },
     };
 
     if opt.query_number == 10 {
@@ -133,12 +135,13 @@ pub async fn create_nexmark_source(opt: &mut NexmarkBenchmarkOpt) -> Result<NEXM
             .text_with_charset("utf-8")
             .await
             .map_err(|_| "Failed to read side input data")?;
-        s3::put_object_if_missing(
-            &FLOCK_S3_BUCKET,
-            &NEXMARK_Q13_S3_SIDE_INPUT_KEY,
-            data.as_bytes().to_vec(),
-        )
-        .await?;
+        object_store()
+            .put_if_missing(
+                &FLOCK_S3_BUCKET,
+                &NEXMARK_Q13_S3_SIDE_INPUT_KEY,
+                data.as_bytes().to_vec(),
+            )
+            .await?;
     }
 
     Ok(NEXMarkSource::new(
@@ -149,36 +152,12 @@ pub async fn create_nexmark_source(opt: &mut NexmarkBenchmarkOpt) -> Result<NEXM
     ))
 }
 
-pub async fn plan_placement(
-    query_number: usize,
-    physcial_plan: Arc<dyn ExecutionPlan>,
-) -> Result<(Arc<dyn ExecutionPlan>, Option<(String, String)>)> {
-    match query_number {
-        4 | 6 | 9 => {
-            let (s3_bucket, s3_key) = match query_number {
-                4 => (FLOCK_S3_BUCKET.clone(), NEXMARK_Q4_S3_KEY.clone()),
-                6 => (FLOCK_S3_BUCKET.clone(), NEXMARK_Q6_S3_KEY.clone()),
-                9 => (FLOCK_S3_BUCKET.clone(), NEXMARK_Q9_S3_KEY.clone()),
-                _ => unreachable!(),
-            };
-            s3::put_object_if_missing(&s3_bucket, &s3_key, serde_json::to_vec(&physcial_plan)?)
-                .await?;
-            Ok((FLOCK_EMPTY_PLAN.clone(), Some((s3_bucket, s3_key))))
-        }
-        _ => Ok((physcial_plan, None)),
-    }
-}
-
-/// Create lambda functions for a given NexMark query.
-/// The returned function is the worker group as a whole which will be executed
-/// by the NexmarkBenchmark data generator function.
-pub async fn create_nexmark_functions(
+/// Deploys the functions of a given NexMark query for both the centralized and
+/// the distributed execution modes.
+pub async fn deploy_nexmark_query(
     opt: &NexmarkBenchmarkOpt,
-    window: Window,
-    physcial_plan: Arc<dyn ExecutionPlan>,
-) -> Result<CloudFunction> {
-    let worker_func_name = format!("q{}-00", opt.query_number);
-
+    plan: Arc<dyn ExecutionPlan>,
+) -> Result<AwsLambdaLauncher> {
     let state_backend: Arc<dyn StateBackend> = match opt.state_backend.as_str() {
         "hashmap" => Arc::new(HashMapStateBackend::new()),
         "s3" => Arc::new(S3StateBackend::new()),
@@ -186,77 +165,24 @@ pub async fn create_nexmark_functions(
         _ => unreachable!(),
     };
 
-    let granule_size = if opt.async_type {
-        *FLOCK_ASYNC_GRANULE_SIZE * 2
-    } else {
-        *FLOCK_SYNC_GRANULE_SIZE * 2
-    };
-
-    let next_func_name = if window != Window::ElementWise || opt.events_per_second > granule_size {
-        CloudFunction::Group((worker_func_name.clone(), *FLOCK_FUNCTION_CONCURRENCY))
-    } else {
-        CloudFunction::Lambda(worker_func_name.clone())
-    };
+    let mut launcher = AwsLambdaLauncher::try_new(
+        format!("q{}", opt.query_number),
+        plan,
+        DataSinkType::new(&opt.data_sink_type)?,
+        state_backend,
+        nexmark_side_inputs(opt.query_number),
+    )
+    .await?;
+    launcher.architecture = opt.architecture.clone();
+    launcher.memory_size = Some(opt.memory_size);
 
-    let (plan, s3) = plan_placement(opt.query_number, physcial_plan).await?;
-    let nexmark_source_ctx = ExecutionContext {
-        plan:          CloudExecutionPlan::new(vec![FLOCK_EMPTY_PLAN.clone()], s3.clone()),
-        name:          FLOCK_DATA_SOURCE_FUNC_NAME.clone(),
-        next:          next_func_name.clone(),
-        state_backend: state_backend.clone(),
-    };
-
-    let nexmark_worker_ctx = ExecutionContext {
-        plan:          CloudExecutionPlan::new(vec![plan.clone()], s3.clone()),
-        name:          worker_func_name.clone(),
-        next:          CloudFunction::Sink(DataSinkType::new(&opt.data_sink_type)?),
-        state_backend: state_backend.clone(),
-    };
-
-    // Create the function for the nexmark source generator.
     info!(
-        "Creating lambda function: {}",
-        rainbow_string(FLOCK_DATA_SOURCE_FUNC_NAME.clone())
+        "Deploying NEXMark query: {}",
+        rainbow_string(format!("q{}", opt.query_number))
     );
-    lambda::create_function(&nexmark_source_ctx, 4096 /* MB */, &opt.architecture).await?;
-
-    // Create the function for the nexmark worker.
-    match next_func_name.clone() {
-        CloudFunction::Lambda(name) => {
-            info!("Creating lambda function: {}", rainbow_string(name));
-            lambda::create_function(&nexmark_worker_ctx, opt.memory_size, &opt.architecture)
-                .await?;
-        }
-        CloudFunction::Group((name, concurrency)) => {
-            info!(
-                "Creating lambda function group: {}",
-                rainbow_string(format!("{:?}", nexmark_source_ctx.next))
-            );
-
-            let tasks = (0..concurrency)
-                .into_iter()
-                .map(|i| {
-                    let mut worker_ctx = nexmark_worker_ctx.clone();
-                    let group_name = name.clone();
-                    let memory_size = opt.memory_size;
-                    let architecture = opt.architecture.clone();
-                    tokio::spawn(async move {
-                        worker_ctx.name = format!("{}-{:02}", group_name, i);
-                        info!(
-                            "Creating function member: {}",
-                            rainbow_string(&worker_ctx.name)
-                        );
-                        lambda::create_function(&worker_ctx, memory_size, &architecture).await?;
-                        lambda::set_concurrency(&worker_ctx.name, 1).await
-                    })
-                })
-                .collect::<Vec<JoinHandle<Result<()>>>>();
-            futures::future::join_all(tasks).await;
-        }
-        CloudFunction::Sink(_) => unreachable!(),
-    }
+    launcher.deploy().await?;
 
-    Ok(next_func_name)
+    Ok(launcher)
 }
 
 /// Create an Elastic file system access point for Flock.
@@ -293,7 +219,21 @@ pub async fn create_physical_plans(
     query_number: usize,
 ) -> Result<Vec<Arc<dyn ExecutionPlan>>> {
     let mut plans = vec![];
-    plans.push(physical_plan(ctx, &nexmark_query(query_number)[0]).await?);
+    let (sql, functions) = rewrite_window_functions(&nexmark_query(query_number)[0])?;
+    for function in functions {
+        // The table of the window function has the window columns.
+        let schema = match function.table.as_str() {
+            "bid" => NEXMARK_BID.clone(),
+            "person" => NEXMARK_PERSON.clone(),
+            "auction" => NEXMARK_AUCTION.clone(),
+            _ => unreachable!(),
+        };
+        let schema = Arc::new(function.extend_schema(&schema));
+        let table = MemTable::try_new(schema.clone(), vec![vec![RecordBatch::new_empty(schema)]])?;
+        ctx.deregister_table(function.table.as_str())?;
+        ctx.register_table(function.table.as_str(), Arc::new(table))?;
+    }
+    plans.push(physical_plan(ctx, &sql).await?);
 
     if query_number == 12 {
         ctx.deregister_table("bid")?;
@@ -346,25 +286,30 @@ pub async fn add_extra_metadata(
     if opt.query_number == 11 || opt.query_number == 12 {
         metadata.insert("session_key".to_string(), "bidder".to_string());
         metadata.insert("session_name".to_string(), "bid".to_string());
+        metadata.insert("session_time".to_string(), "b_date_time".to_string());
     }
 
-    if opt.query_number == 13 {
-        metadata.insert(
-            "side_input_s3_key".to_string(),
-            NEXMARK_Q13_S3_SIDE_INPUT_KEY.clone(),
-        );
-        metadata.insert("side_input_format".to_string(), "csv".to_string());
-
-        let side_input_schema = Arc::new(side_input_schema());
-        metadata.insert(
-            "side_input_schema".to_string(),
-            base64::encode(schema_to_bytes(side_input_schema)),
-        );
+    if let Some(function) = nexmark_window_function(opt.query_number)? {
+        function.to_metadata(metadata);
     }
 
     Ok(())
 }
 
+/// Returns the side inputs of the query. Q13 joins the bids with a lookup
+/// table in the object store, which is downloaded by `create_nexmark_source`.
+pub fn nexmark_side_inputs(query_number: usize) -> Vec<SideInput> {
+    match query_number {
+        13 => vec![SideInput::new(
+            "side_input",
+            NEXMARK_Q13_S3_SIDE_INPUT_KEY.clone(),
+            SideInputFormat::Csv,
+            side_input_schema(),
+        )],
+        _ => vec![],
+    }
+}
+
 pub async fn nexmark_benchmark(opt: &mut NexmarkBenchmarkOpt) -> Result<()> {
     if opt.distributed {
         distributed::nexmark_benchmark(opt).await
@@ -373,6 +318,15 @@ pub async fn nexmark_benchmark(opt: &mut NexmarkBenchmarkOpt) -> Result<()> {
     }
 }
 
+/// Returns the windowing table-valued function in the SQL of the query, if any.
+pub fn nexmark_window_function(query_number: usize) -> Result<Option<WindowFunction>> {
+    let mut functions = vec![];
+    for sql in nexmark_query(query_number) {
+        functions.append(&mut rewrite_window_functions(&sql)?.1);
+    }
+    Ok(functions.pop())
+}
+
 /// Returns Nextmark query strings based on the query number.
 pub fn nexmark_query(query_number: usize) -> Vec<String> {
     match query_number {
@@ -55,12 +55,8 @@ async fn benchmark(opt: &mut NexmarkBenchmarkOpt) -> Result<()> {
 
     let mut ctx = register_nexmark_tables().await?;
     let plans = create_physical_plans(&mut ctx, query_number).await?;
-    let worker = create_nexmark_functions(
-        opt,
-        nexmark_conf.window.clone(),
-        plans.last().unwrap().clone(),
-    )
-    .await?;
+    let launcher = deploy_nexmark_query(opt, plans.last().unwrap().clone()).await?;
+    let worker = &launcher.data_source_ctx.as_ref().unwrap().next;
 
     // The source generator function needs the metadata to determine the type of the
     // workers such as single function or a group. We don't want to keep this info
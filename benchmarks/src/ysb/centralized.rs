@@ -120,7 +120,8 @@ async fn create_ysb_functions(
     opt: &YSBBenchmarkOpt,
     physcial_plan: Arc<dyn ExecutionPlan>,
 ) -> Result<CloudFunction> {
-    let worker_func_name = "ysb-00".to_string();
+    let worker_id = FunctionId::try_new("ysb", 0)?;
+    let worker_func_name = worker_id.to_cloud_name();
     let next_func_name =
         CloudFunction::Group((worker_func_name.clone(), *FLOCK_FUNCTION_CONCURRENCY));
 
@@ -131,12 +132,14 @@ async fn create_ysb_functions(
         ..Default::default()
     };
 
-    let ysb_worker_ctx = ExecutionContext {
+    let mut ysb_worker_ctx = ExecutionContext {
         plan: CloudExecutionPlan::new(vec![physcial_plan], None),
         name: worker_func_name.clone(),
+        id: Some(worker_id),
         next: CloudFunction::Sink(DataSinkType::new(&opt.data_sink_type)?),
         ..Default::default()
     };
+    ysb_worker_ctx.place_plan(*FLOCK_CONTEXT_SIZE_LIMIT).await?;
 
     // Create the function for the ysb source generator.
     info!(
@@ -147,7 +150,7 @@ async fn create_ysb_functions(
 
     // Create the function for the ysb worker.
     match next_func_name.clone() {
-        CloudFunction::Group((name, concurrency)) => {
+        CloudFunction::Group((_, concurrency)) => {
             info!(
                 "Creating lambda function group: {}",
                 rainbow_string(format!("{:?}", ysb_source_ctx.next))
@@ -156,12 +159,11 @@ async fn create_ysb_functions(
             let tasks = (0..concurrency)
                 .into_iter()
                 .map(|i| {
-                    let mut worker_ctx = ysb_worker_ctx.clone();
-                    let group_name = name.clone();
+                    let worker_ctx = ysb_worker_ctx.group_member(i);
                     let memory_size = opt.memory_size;
                     let architecture = opt.architecture.clone();
                     tokio::spawn(async move {
-                        worker_ctx.name = format!("{}-{:02}", group_name, i);
+                        let worker_ctx = worker_ctx?;
                         info!(
                             "Creating function member: {}",
                             rainbow_string(&worker_ctx.name)
@@ -11,8 +11,6 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-extern crate daggy;
-
 #[path = "../rainbow.rs"]
 mod rainbow;
 
@@ -20,12 +18,10 @@ use super::create_ysb_source;
 use super::ysb_query;
 use crate::YSBBenchmarkOpt;
 
-use daggy::NodeIndex;
 use datafusion::execution::context::ExecutionConfig;
 use flock::aws::lambda;
-use flock::distributed_plan::QueryDag;
+use flock::launcher::Launcher;
 use flock::prelude::*;
-use humantime::parse_duration;
 use lazy_static::lazy_static;
 use log::info;
 use rainbow::{rainbow_println, rainbow_string};
@@ -63,8 +59,10 @@ pub async fn ysb_benchmark(opt: &mut YSBBenchmarkOpt) -> Result<()> {
     };
 
     let mut launcher =
-        AwsLambdaLauncher::try_new(query_code, plan, sink_type, state_backend).await?;
-    launcher.create_cloud_contexts(*FLOCK_FUNCTION_CONCURRENCY)?;
+        AwsLambdaLauncher::try_new(query_code, plan, sink_type, state_backend, vec![]).await?;
+    launcher.architecture = opt.architecture.clone();
+    launcher.memory_size = Some(opt.memory_size);
+    launcher.deploy().await?;
 
     info!(
         "Streaming: {}",
@@ -86,9 +84,6 @@ pub async fn ysb_benchmark(opt: &mut YSBBenchmarkOpt) -> Result<()> {
         info!("Physical Plan:\n{}", stage.get_plan_str());
     }
 
-    let dag = &mut launcher.dag;
-    create_ysb_functions(dag, opt, *FLOCK_FUNCTION_CONCURRENCY).await?;
-
     let mut metadata = HashMap::new();
     metadata.insert(
         "invocation_type".to_string(),
@@ -99,12 +94,13 @@ pub async fn ysb_benchmark(opt: &mut YSBBenchmarkOpt) -> Result<()> {
         },
     );
 
+    let source_function = FunctionId::try_new("ysb", 0)?;
     let tasks = (0..opt.generators)
         .into_iter()
         .map(|i| {
             let s = ysb_conf.clone();
             let m = metadata.clone();
-            let f = format!("ysb-{:02}", 0);
+            let f = source_function.to_cloud_name();
             tokio::spawn(async move {
                 info!(
                     "[OK] Invoking YSB source function: {} by generator {}\n",
@@ -127,58 +123,3 @@ pub async fn ysb_benchmark(opt: &mut YSBBenchmarkOpt) -> Result<()> {
 
     Ok(())
 }
-
-/// Create lambda functions for a given YSB query.
-async fn create_ysb_functions(
-    dag: &mut QueryDag,
-    opt: &YSBBenchmarkOpt,
-    group_size: usize,
-) -> Result<()> {
-    let count = dag.node_count();
-    assert!(count < 100);
-
-    let func_types = (0..count)
-        .map(|i| dag.get_node(NodeIndex::new(i)).unwrap().get_function_type())
-        .collect::<Vec<CloudFunctionType>>();
-
-    for i in (0..count).rev() {
-        let node = dag.get_node_mut(NodeIndex::new(i)).unwrap();
-        if func_types[i] == CloudFunctionType::Group {
-            let group_name = format!("ysb-{:02}", count - 1 - i);
-            info!(
-                "Creating lambda function group: {}",
-                rainbow_string(format!("({}, {})", group_name, group_size))
-            );
-            let tasks = (0..group_size)
-                .into_iter()
-                .map(|j| {
-                    let mut ctx = node.context.clone().unwrap();
-                    let name = group_name.clone();
-                    let memory_size = opt.memory_size;
-                    let architecture = opt.architecture.clone();
-                    tokio::spawn(async move {
-                        ctx.name = format!("{}-{:02}", name, j);
-                        lambda::create_function(&ctx, memory_size, &architecture).await?;
-                        info!("Created function member: {}", rainbow_string(&ctx.name));
-                        lambda::set_concurrency(&ctx.name, 1).await
-                    })
-                })
-                .collect::<Vec<JoinHandle<Result<()>>>>();
-            futures::future::join_all(tasks).await;
-            tokio::time::sleep(parse_duration("2s").unwrap()).await;
-        } else {
-            lambda::create_function(
-                node.context.as_ref().unwrap(),
-                opt.memory_size,
-                &opt.architecture,
-            )
-            .await?;
-            info!(
-                "Created lambda function: {}",
-                rainbow_string(format!("ysb-{:02}", count - 1 - i))
-            );
-        }
-    }
-
-    Ok(())
-}
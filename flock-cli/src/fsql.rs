@@ -12,14 +12,24 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 //! fsql is a terminal-based front-end to Flock.
+//!
+//! The statements are planned against the tables of the NEXMark and the Yahoo!
+//! streaming benchmarks. The supported statement is:
+//!
+//! - `EXPLAIN DISTRIBUTED [DOT] <sql>;` shows how the query is split into query
+//!   stages and cloud functions, as text or as a Graphviz DOT graph.
 
 use anyhow::{anyhow, Result};
 use benchmarks::rainbow_println;
 use clap::{App, ArgMatches};
+use flock::prelude::*;
 use rustyline::Editor;
+use std::sync::Arc;
 
 pub fn command(_: &ArgMatches) -> Result<()> {
-    futures::executor::block_on(fsql())
+    // The query planner blocks on its own futures, so the statements are
+    // executed on the tokio runtime instead of `futures::executor`.
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fsql()))
 }
 
 pub fn command_args() -> App<'static> {
@@ -65,7 +75,57 @@ fn is_exit_command(line: &str) -> bool {
     line == "quit" || line == "exit"
 }
 
-async fn exec_and_print(_: String) -> Result<()> {
-    rainbow_println("CLI is under construction. Please try Flock API directly.");
+async fn exec_and_print(statement: String) -> Result<()> {
+    let statement = statement.trim().trim_end_matches(';');
+    match parse_explain_distributed(statement) {
+        Some((format, sql)) => {
+            let query = Query {
+                sql: sql.trim().to_owned(),
+                tables: benchmark_tables(),
+                ..Default::default()
+            };
+            println!("{}", query.explain_distributed(format).await?);
+        }
+        None => rainbow_println(
+            "CLI is under construction. Only `EXPLAIN DISTRIBUTED [DOT] <sql>;` is supported. \
+             Please try Flock API directly.",
+        ),
+    }
     Ok(())
 }
+
+/// Parses `EXPLAIN DISTRIBUTED [DOT] <sql>`.
+///
+/// # Returns
+/// The output format and the SQL to explain, or `None` if the statement is not
+/// `EXPLAIN DISTRIBUTED`.
+fn parse_explain_distributed(statement: &str) -> Option<(ExplainFormat, &str)> {
+    let rest = strip_keyword(statement, "EXPLAIN")?;
+    let rest = strip_keyword(rest, "DISTRIBUTED")?;
+    match strip_keyword(rest, "DOT") {
+        Some(sql) => Some((ExplainFormat::Dot, sql)),
+        None => Some((ExplainFormat::Text, rest)),
+    }
+}
+
+/// Strips the case-insensitive keyword at the beginning of the statement.
+fn strip_keyword<'a>(statement: &'a str, keyword: &str) -> Option<&'a str> {
+    let statement = statement.trim_start();
+    let (word, rest) = statement.split_at(statement.find(char::is_whitespace)?);
+    if word.eq_ignore_ascii_case(keyword) {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+/// Returns the tables of the NEXMark and the Yahoo! streaming benchmarks.
+fn benchmark_tables() -> Vec<Table> {
+    vec![
+        Table::new("bid", Arc::new(nexmark::event::Bid::schema())),
+        Table::new("person", Arc::new(nexmark::event::Person::schema())),
+        Table::new("auction", Arc::new(nexmark::event::Auction::schema())),
+        Table::new("ad_event", Arc::new(ysb::event::AdEvent::schema())),
+        Table::new("campaign", Arc::new(ysb::event::Campaign::schema())),
+    ]
+}
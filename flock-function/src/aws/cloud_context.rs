@@ -11,6 +11,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use crate::actor;
 use flock::prelude::*;
 use hashring::HashRing;
 use lazy_static::lazy_static;
@@ -53,45 +54,46 @@ pub static mut CONSISTENT_HASH_CONTEXT: ConsistentHashContext =
 macro_rules! init_exec_context {
     () => {{
         unsafe {
+            // Whether the execution context is initialized by this invocation.
+            let mut fresh = false;
             // Init query executor from the cloud evironment.
-            let init_context = || match std::env::var(&**CONTEXT_NAME) {
-                Ok(s) => {
-                    let ctx = context::unmarshal(&s).unwrap();
-                    let next_function = match &ctx.next {
-                        CloudFunction::Lambda(name) => (name.clone(), 1),
-                        CloudFunction::Group((name, group_size)) => {
-                            (name.clone(), group_size.clone())
-                        }
-                        CloudFunction::Sink(..) => (String::new(), 0),
-                    };
-
-                    // The *consistent hash* technique distributes the data packets in a time window
-                    // to the same function name in the function group. Because each function in the
-                    // function group has a concurrency of *1*, all data packets from the same query
-                    // can be routed to the same function execution environment.
-                    let mut ring: HashRing<String> = HashRing::new();
-                    if next_function.1 == 1 {
-                        ring.add(next_function.0.clone());
-                    } else if next_function.1 > 1 {
-                        (0..next_function.1).for_each(|i| {
-                            ring.add(format!("{}-{:02}", next_function.0, i));
-                        });
+            let mut init_context = || -> Result<()> {
+                match std::env::var(&**CONTEXT_NAME) {
+                    Ok(s) => {
+                        let ctx = context::unmarshal(&s)?;
+                        let group_name = match &ctx.next {
+                            CloudFunction::Lambda(name) => name.clone(),
+                            CloudFunction::Group((name, _)) => name.clone(),
+                            CloudFunction::Sink(..) => String::new(),
+                        };
+                        let ring = actor::hash_ring(&ctx.next)?;
+                        CONSISTENT_HASH_CONTEXT = ConsistentHashContext::Lambda((ring, group_name));
+                        EXECUTION_CONTEXT =
+                            CloudFunctionContext::Lambda((Box::new(ctx), Arena::new()));
+                        fresh = true;
+                        Ok(())
+                    }
+                    Err(_) => {
+                        panic!("No execution context in the cloud environment.");
                     }
-                    CONSISTENT_HASH_CONTEXT =
-                        ConsistentHashContext::Lambda((ring, next_function.0));
-                    EXECUTION_CONTEXT = CloudFunctionContext::Lambda((Box::new(ctx), Arena::new()));
-                }
-                Err(_) => {
-                    panic!("No execution context in the cloud environment.");
                 }
             };
             if IS_TESTING.with(|t| t.get()) {
-                init_context();
+                init_context()?;
             } else {
-                INIT.call_once(init_context);
+                let mut result = Ok(());
+                INIT.call_once(|| result = init_context());
+                result?;
             }
             match &mut EXECUTION_CONTEXT {
-                CloudFunctionContext::Lambda((ctx, arena)) => (ctx, arena),
+                CloudFunctionContext::Lambda((ctx, arena)) => {
+                    // The function may be cold started in the middle of a window. The in-flight
+                    // windows and the processed windows are rehydrated from the state backend.
+                    if fresh {
+                        actor::init_arena(ctx, arena).await?;
+                    }
+                    (ctx, arena)
+                }
                 CloudFunctionContext::Uninitialized => panic!("Uninitialized execution context!"),
             }
         }
@@ -125,34 +127,12 @@ pub fn update_consistent_hash_context(metadata: &Option<HashMap<String, String>>
         if let Some(workers) = metadata.get("workers") {
             let next_function = serde_json::from_str(workers)?;
 
-            let (group_name, group_size) = match &next_function {
-                CloudFunction::Lambda(name) => (name.clone(), 1),
-                CloudFunction::Group((name, size)) => (name.clone(), *size),
-                CloudFunction::Sink(..) => (String::new(), 0),
+            let group_name = match &next_function {
+                CloudFunction::Lambda(name) => name.clone(),
+                CloudFunction::Group((name, _)) => name.clone(),
+                CloudFunction::Sink(..) => String::new(),
             };
-
-            // The *consistent hash* technique distributes the data packets in a time window
-            // to the same function name in the function group. Because each function in the
-            // function group has a concurrency of *1*, all data packets from the same query
-            // can be routed to the same function execution environment.
-            let mut ring: HashRing<String> = HashRing::new();
-            match group_size {
-                0 => {
-                    unreachable!("group_size should not be 0.");
-                }
-                1 => {
-                    // only one function in the function group, the data packets are routed to the
-                    // next function.
-                    ring.add(group_name.clone());
-                }
-                _ => {
-                    // multiple functions in the function group, the data packets are routed to the
-                    // function with the same hash value.
-                    (0..group_size).for_each(|i| {
-                        ring.add(format!("{}-{:02}", group_name, i));
-                    });
-                }
-            }
+            let ring = actor::hash_ring(&next_function)?;
 
             unsafe {
                 // `ring`: the consistent hashing ring to forward the windowed events to the
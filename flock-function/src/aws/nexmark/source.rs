@@ -59,18 +59,57 @@ pub async fn handler(ctx: &mut ExecutionContext, payload: Payload) -> Result<Val
             tumbling::launch_tasks(ctx, payload, events, sec, window_size).await?;
         }
         Window::Hopping((window_size, hop_size)) => {
-            hopping::launch_tasks(payload, events, sec, window_size, hop_size).await?;
+            hopping::launch_tasks(ctx, payload, events, sec, window_size, hop_size).await?;
         }
         Window::ElementWise => {
             elementwise::launch_tasks(ctx, payload, events, sec).await?;
         }
         Window::Session(Schedule::Seconds(timeout)) => {
-            session::launch_tasks(payload, events, sec, timeout).await?;
+            session::launch_tasks(ctx, payload, events, sec, timeout, None).await?;
         }
         Window::Global(Schedule::Seconds(window_size)) => {
-            global::launch_tasks(payload, events, sec, window_size).await?;
+            global::launch_tasks(ctx, payload, events, sec, window_size, None).await?;
+        }
+        Window::Sliding(..) | Window::Stagger(..) => {
+            launch_event_time_tasks(
+                ctx,
+                payload,
+                events,
+                sec,
+                source.window,
+                EventTime::default(),
+                None,
+            )
+            .await?;
+        }
+        Window::EventTime(..) | Window::Firing(..) => {
+            let (window, event_time, firing) = source.window.into_parts();
+            match (window, event_time) {
+                // The session and the global windows close by their own timeouts.
+                (Window::Session(Schedule::Seconds(timeout)), None) => {
+                    session::launch_tasks(ctx, payload, events, sec, timeout, firing).await?;
+                }
+                (Window::Global(Schedule::Seconds(window_size)), None) => {
+                    global::launch_tasks(ctx, payload, events, sec, window_size, firing).await?;
+                }
+                (window, event_time) => {
+                    let event_time = event_time.unwrap_or_default();
+                    launch_event_time_tasks(ctx, payload, events, sec, window, event_time, firing)
+                        .await?;
+                }
+            }
+        }
+        Window::Tumbling(ref schedule) | Window::Global(ref schedule) => {
+            triggered::launch_tasks(ctx, payload, events, sec, schedule).await?;
+        }
+        // A session window closes by the timeout, and has no trigger.
+        Window::Session(ref schedule) => {
+            return Err(FlockError::NotImplemented(format!(
+                "The session window with the schedule {:?} is not supported in the NEXMark \
+                 benchmark.",
+                schedule
+            )));
         }
-        _ => unimplemented!(),
     };
 
     Ok(Value::Null)
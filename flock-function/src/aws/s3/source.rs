@@ -18,8 +18,6 @@ use chrono::Utc;
 use datafusion::physical_plan::Partitioning;
 use flock::prelude::*;
 use log::info;
-use rusoto_core::ByteStream;
-use rusoto_s3::{PutObjectRequest, S3};
 use serde_json::json;
 use serde_json::Value;
 use std::sync::Arc;
@@ -125,15 +123,9 @@ pub async fn handler(_ctx: &ExecutionContext, payload: Payload) -> Result<Value>
     );
     info!("Writing {} function payload to S3...", function_name);
     let s3_key = format!("{}_payload", function_name);
-    FLOCK_S3_CLIENT
-        .put_object(PutObjectRequest {
-            bucket: FLOCK_S3_BUCKET.clone(),
-            key: s3_key.clone(),
-            body: Some(ByteStream::from(bytes)),
-            ..Default::default()
-        })
-        .await
-        .map_err(|e| FlockError::AWS(e.to_string()))?;
+    object_store()
+        .put(&FLOCK_S3_BUCKET, &s3_key, bytes)
+        .await?;
 
     info!("[OK] {} function payload written to S3.", function_name);
 
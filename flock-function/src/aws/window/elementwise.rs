@@ -15,7 +15,6 @@ use crate::actor::*;
 use crate::{consistent_hash_context, ConsistentHashContext, CONSISTENT_HASH_CONTEXT};
 use chrono::Utc;
 use datafusion::physical_plan::empty::EmptyExec;
-use flock::aws::{lambda, s3};
 use flock::prelude::*;
 use log::info;
 use std::sync::Arc;
@@ -64,7 +63,8 @@ pub async fn launch_tasks(
                     function_name,
                     bytes.len()
                 );
-                lambda::invoke_function(&function_name, &invocation_type, Some(bytes.into()))
+                ctx.invoker
+                    .invoke(&function_name, &invocation_type, bytes.into())
                     .await?;
             } else {
                 // distributed mode
@@ -81,21 +81,18 @@ pub async fn launch_tasks(
                     }
                 }
 
+                // The range boundaries of `ORDER BY` are sampled from the whole epoch.
+                let mut metadata = metadata.clone();
+                ctx.sample_range_boundaries(&input, &mut metadata)?;
+                ctx.feed_range_boundaries(&metadata).await?;
                 ctx.feed_data_sources(input).await?;
                 let output = Arc::new(ctx.execute_partitioned().await?);
                 let size = output[0].len();
                 let mut uuid_builder =
-                    UuidBuilder::new_with_ts(group_name, Utc::now().timestamp(), size);
+                    UuidBuilder::new_with_window(group_name, &metadata, epoch, size)?;
 
-                // Creates the S3 bucket for the current query if state backend is S3.
-                if ctx
-                    .state_backend
-                    .as_any()
-                    .downcast_ref::<S3StateBackend>()
-                    .is_some()
-                {
-                    s3::create_bucket(&uuid_builder.qid).await?;
-                }
+                // Prepares the namespace of the current query in the state backend.
+                ctx.state_backend.prepare_query(&uuid_builder.qid).await?;
 
                 let tasks = (0..size)
                     .map(|i| {
@@ -103,6 +100,7 @@ pub async fn launch_tasks(
                         let function_name = group_name.clone();
                         let meta = metadata.clone();
                         let invoke_type = invocation_type.clone();
+                        let invoker = ctx.invoker.clone();
                         let uuid = uuid_builder.next_uuid();
                         tokio::spawn(async move {
                             let mut payload = to_payload(
@@ -120,13 +118,10 @@ pub async fn launch_tasks(
                                 function_name,
                                 bytes.len()
                             );
-                            lambda::invoke_function(
-                                &function_name,
-                                &invoke_type,
-                                Some(bytes.into()),
-                            )
-                            .await
-                            .map(|_| ())
+                            invoker
+                                .invoke(&function_name, &invoke_type, bytes.into())
+                                .await
+                                .map(|_| ())
                         })
                     })
                     .collect::<Vec<tokio::task::JoinHandle<Result<()>>>>();
@@ -143,6 +138,12 @@ pub async fn launch_tasks(
             )?;
             let size = if a.len() > b.len() { a.len() } else { b.len() };
 
+            // The range boundaries of `ORDER BY` are sampled from the whole epoch.
+            let relations = [a, b];
+            let mut metadata = metadata.clone();
+            ctx.sample_range_boundaries(&relations, &mut metadata)?;
+            let [a, b] = relations;
+
             let mut uuid_builder =
                 UuidBuilder::new_with_ts(group_name, Utc::now().timestamp(), size);
 
@@ -176,7 +177,8 @@ pub async fn launch_tasks(
                     function_name,
                     bytes.len()
                 );
-                lambda::invoke_function(&function_name, &invocation_type, Some(bytes.into()))
+                ctx.invoker
+                    .invoke(&function_name, &invocation_type, bytes.into())
                     .await?;
             }
         }
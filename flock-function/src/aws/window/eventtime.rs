@@ -0,0 +1,87 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use super::{send_window, write_late_records};
+use crate::actor::*;
+use flock::prelude::*;
+use flock::stream::{EventTimeWindows, Firing};
+use log::warn;
+use std::sync::Arc;
+
+/// Generate event-time tumbling or hopping windows workloads for the benchmark
+/// on cloud function services. The records are assigned to the windows by the
+/// timestamps in the event-time column, and each window is sent once the
+/// watermark passes its end plus the allowed lateness. With a firing policy,
+/// each window also sends its early and late panes.
+///
+/// # Arguments
+/// * `ctx` - The execution context of the function.
+/// * `payload` - The payload of the function.
+/// * `stream` - the source stream of events.
+/// * `seconds` - the total number of seconds to generate workloads.
+/// * `(window_size, hop_size)` - the size of the window and the hop in seconds.
+/// * `event_time` - the event-time settings of the window.
+/// * `firing` - the early and late firings of the window.
+pub async fn launch_tasks(
+    ctx: &ExecutionContext,
+    payload: Payload,
+    stream: Arc<dyn DataStream>,
+    seconds: usize,
+    (window_size, hop_size): (usize, usize),
+    event_time: EventTime,
+    firing: Option<Firing>,
+) -> Result<()> {
+    if seconds < window_size {
+        warn!(
+            "seconds: {} is less than window_size: {}",
+            seconds, window_size
+        );
+    }
+    let sync = infer_invocation_type(&payload.metadata)?;
+    let columns = infer_window_columns(&payload.metadata);
+    let invocation_type = if sync {
+        FLOCK_LAMBDA_SYNC_CALL.to_string()
+    } else {
+        FLOCK_LAMBDA_ASYNC_CALL.to_string()
+    };
+
+    let late_sink = event_time.late_sink.clone();
+    let mut windows = EventTimeWindows::new(event_time, window_size, hop_size, 2)?;
+    if let Some(firing) = firing {
+        windows = windows.with_firing(firing);
+    }
+
+    for time in 0..seconds {
+        let (r1, r2) = stream.select_event_to_batches(
+            time,
+            0, // generator id
+            payload.query_number,
+            sync,
+        )?;
+        for (relation, partitions) in [r1, r2].iter().enumerate() {
+            let late = windows.insert(relation, partitions)?;
+            write_late_records(ctx, relation, late, &late_sink).await?;
+        }
+
+        for window in windows.advance() {
+            send_window(ctx, window, &invocation_type, sync, columns).await?;
+        }
+    }
+
+    // The end of the stream closes all the open windows.
+    for window in windows.flush() {
+        send_window(ctx, window, &invocation_type, sync, columns).await?;
+    }
+
+    Ok(())
+}
@@ -11,7 +11,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use super::coalesce_windows;
+use super::coalesce_panes;
 use crate::actor::*;
 use crate::{consistent_hash_context, ConsistentHashContext, CONSISTENT_HASH_CONTEXT};
 use chrono::{DateTime, NaiveDateTime, Utc};
@@ -23,91 +23,79 @@ use datafusion::logical_plan::{col, count_distinct};
 use datafusion::physical_plan::collect_partitioned;
 use datafusion::physical_plan::expressions::col as expr_col;
 use datafusion::physical_plan::Partitioning::{HashDiff, RoundRobinBatch};
-use flock::aws::lambda;
 use flock::prelude::*;
+use flock::stream::{Firing, FiringStates, Pane};
 use log::{info, warn};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Instant;
 
+/// The open tumbling windows indexed by the bidder. Each window keeps the
+/// processing time of its first bid.
+type TumblingWindows = HashMap<usize, (DateTime<Utc>, Vec<Vec<RecordBatch>>)>;
+
+/// Returns the bidder and the processing time of the partition.
+fn bidder_and_process_time(partition: &[RecordBatch]) -> (usize, DateTime<Utc>) {
+    let bidder = partition[0]
+        .column(1 /* bidder field */)
+        .as_any()
+        .downcast_ref::<Int32Array>()
+        .unwrap()
+        .value(0);
+    let timestamp = partition[0]
+        .column(4 /* p_time field */)
+        .as_any()
+        .downcast_ref::<TimestampNanosecondArray>()
+        .unwrap()
+        .value(0);
+    let process_time = DateTime::<Utc>::from_utc(
+        NaiveDateTime::from_timestamp(timestamp / 1000 / 1000 / 1000, 0),
+        Utc,
+    );
+    (bidder as usize, process_time)
+}
+
 /// Add each unique partition to a distinct tumbling window.
 ///
 /// # Arguments
 /// * `partitions` - the partitions to be added.
 /// * `windows` - the current tumbling windows.
+/// * `panes` - the firing states of the tumbling windows.
 /// * `window_size` - the size of the window.
 ///
 /// # Return
-/// The updated session windows.
+/// The closed tumbling windows with their on-time panes.
 fn add_partitions_to_tumbling_windows(
     partitions: Vec<Vec<RecordBatch>>,
-    windows: &mut HashMap<usize, Vec<Vec<RecordBatch>>>,
+    windows: &mut TumblingWindows,
+    panes: &mut FiringStates<usize>,
     window_size: usize,
-) -> Result<Vec<Vec<Vec<RecordBatch>>>> {
-    Ok(partitions
-        .into_iter()
-        .filter(|p| !p.is_empty())
-        .map(|p| {
-            let mut window = vec![];
-            let bidder = p[0]
-                .column(1 /* bidder field */)
-                .as_any()
-                .downcast_ref::<Int32Array>()
-                .unwrap()
-                .value(0);
-            let current_timestamp = p[0]
-                .column(4 /* p_time field */)
-                .as_any()
-                .downcast_ref::<TimestampNanosecondArray>()
-                .unwrap()
-                .value(0);
-            let current_process_time = DateTime::<Utc>::from_utc(
-                NaiveDateTime::from_timestamp(current_timestamp / 1000 / 1000 / 1000, 0),
-                Utc,
-            );
-
-            if !windows.contains_key(&(bidder as usize)) {
-                windows
-                    .entry(bidder as usize)
-                    .or_insert_with(Vec::new)
-                    .push(p);
-            } else {
-                // get the first batch in the window.
-                let batch = windows
-                    .get(&(bidder as usize))
-                    .unwrap()
-                    .first()
-                    .unwrap()
-                    .first()
-                    .unwrap();
-                // get the first bid's process time.
-                let first_timestamp = batch
-                    .column(4 /* p_time field */)
-                    .as_any()
-                    .downcast_ref::<TimestampNanosecondArray>()
-                    .unwrap()
-                    .value(0);
-                let first_process_time = DateTime::<Utc>::from_utc(
-                    NaiveDateTime::from_timestamp(first_timestamp / 1000 / 1000 / 1000, 0),
-                    Utc,
-                );
-
-                // If the current process time isn't 10 seconds later than the beginning of
-                // the entry, then we can add the current batch to the map. Otherwise, we
-                // have a new tumbling window.
-                if current_process_time.signed_duration_since(first_process_time)
-                    > chrono::Duration::seconds(window_size as i64)
-                {
-                    window = windows.remove(&(bidder as usize)).unwrap();
+) -> Result<Vec<(Vec<Vec<RecordBatch>>, Pane)>> {
+    let mut closed = vec![];
+    for p in partitions.into_iter().filter(|p| !p.is_empty()) {
+        let (bidder, current_process_time) = bidder_and_process_time(&p);
+
+        // If the current process time isn't 10 seconds later than the beginning of
+        // the entry, then we can add the current batch to the map. Otherwise, we
+        // have a new tumbling window.
+        if let Some((first_process_time, _)) = windows.get(&bidder) {
+            if current_process_time.signed_duration_since(*first_process_time)
+                > chrono::Duration::seconds(window_size as i64)
+            {
+                let (_, window) = windows.remove(&bidder).unwrap();
+                if let Some(pane) = panes.close(&bidder) {
+                    closed.push((window, pane));
                 }
-                windows
-                    .entry(bidder as usize)
-                    .or_insert_with(Vec::new)
-                    .push(p);
             }
-            window
-        })
-        .collect::<Vec<Vec<Vec<RecordBatch>>>>())
+        }
+        panes.observe(&bidder, p.iter().map(|b| b.num_rows()).sum());
+        windows
+            .entry(bidder)
+            .or_insert_with(|| (current_process_time, vec![]))
+            .1
+            .push(p);
+    }
+    Ok(closed)
 }
 
 /// Find new tumbling windows after timeout.
@@ -118,51 +106,63 @@ fn add_partitions_to_tumbling_windows(
 ///
 /// # Return
 /// The keys of the new tumbling windows.
-fn find_timeout_tumbling_windows(
-    windows: &HashMap<usize, Vec<Vec<RecordBatch>>>,
-    timeout: usize,
-) -> Result<Vec<usize>> {
-    let mut to_remove = vec![];
-
-    windows.iter().for_each(|(bidder, batches)| {
-        // get the first bid's prcessing time
-        let first_timestamp = batches[0][0]
-            .column(4 /* p_time field */)
-            .as_any()
-            .downcast_ref::<TimestampNanosecondArray>()
-            .unwrap()
-            .value(0);
-
-        let first_process_time = DateTime::<Utc>::from_utc(
-            NaiveDateTime::from_timestamp(first_timestamp / 1000 / 1000 / 1000, 0),
-            Utc,
-        );
-        if Utc::now().signed_duration_since(first_process_time)
-            > chrono::Duration::seconds(timeout as i64)
-        {
-            to_remove.push(bidder.to_owned());
-        }
-    });
+fn find_timeout_tumbling_windows(windows: &TumblingWindows, timeout: usize) -> Result<Vec<usize>> {
+    Ok(windows
+        .iter()
+        .filter(|(_, (first_process_time, _))| {
+            Utc::now().signed_duration_since(*first_process_time)
+                > chrono::Duration::seconds(timeout as i64)
+        })
+        .map(|(bidder, _)| bidder.to_owned())
+        .collect())
+}
 
-    Ok(to_remove)
+/// Fires the open tumbling windows early by the firing policy.
+///
+/// # Arguments
+/// * `windows` - the tumbling windows.
+/// * `panes` - the firing states of the tumbling windows.
+/// * `now` - the processing time in milliseconds.
+///
+/// # Return
+/// The early panes of the tumbling windows.
+fn fire_early_tumbling_windows(
+    windows: &mut TumblingWindows,
+    panes: &mut FiringStates<usize>,
+    now: i64,
+) -> Vec<(Vec<Vec<RecordBatch>>, Pane)> {
+    let mode = panes.mode();
+    panes
+        .fire_early(now)
+        .into_iter()
+        .filter_map(|(bidder, pane)| {
+            let (_, window) = windows.get_mut(&bidder)?;
+            Some((mode.pane_records(window), pane))
+        })
+        .collect()
 }
 
 /// A global windows assigner assigns all elements with the same key to the same
 /// single global window. This windowing scheme is only useful if you also
 /// specify a custom trigger. Otherwise, no computation will be performed, as
 /// the global window does not have a natural end at which we could process the
-/// aggregated elements.
+/// aggregated elements. With a firing policy, the open windows also send their
+/// early panes.
 ///
 /// # Arguments
+/// * `ctx` - The execution context of the function.
 /// * `payload` - The payload of the function invocation.
 /// * `stream` - The data stream.
 /// * `seconds` - The number of seconds to group events into.
 /// * `window_size` - The size of the window.
+/// * `firing` - The early firings of the windows.
 pub async fn launch_tasks(
+    ctx: &ExecutionContext,
     payload: Payload,
     stream: Arc<dyn DataStream>,
     seconds: usize,
     window_size: usize,
+    firing: Option<Firing>,
 ) -> Result<()> {
     if seconds < window_size {
         warn!(
@@ -184,8 +184,11 @@ pub async fn launch_tasks(
         )
     };
 
+    let function_ctx = ctx;
+    let invoker = ctx.invoker.clone();
     let mut ctx = DataFusionExecutionContext::new();
-    let mut windows: HashMap<usize, Vec<Vec<RecordBatch>>> = HashMap::new();
+    let mut windows: TumblingWindows = HashMap::new();
+    let mut panes = FiringStates::new(firing);
 
     let events = (0..seconds)
         .map(|t| {
@@ -240,20 +243,37 @@ pub async fn launch_tasks(
 
         // Update the window.
         let mut tumblings =
-            add_partitions_to_tumbling_windows(partitions, &mut windows, window_size)?;
+            add_partitions_to_tumbling_windows(partitions, &mut windows, &mut panes, window_size)?;
         let to_remove = find_timeout_tumbling_windows(&windows, window_size)?;
         to_remove.iter().for_each(|bidder| {
-            tumblings.push(windows.remove(bidder).unwrap());
+            let (_, window) = windows.remove(bidder).unwrap();
+            if let Some(pane) = panes.close(bidder) {
+                tumblings.push((window, pane));
+            }
         });
-
-        let tasks = coalesce_windows(tumblings, granule_size)?
+        tumblings.append(&mut fire_early_tumbling_windows(
+            &mut windows,
+            &mut panes,
+            Utc::now().timestamp_millis(),
+        ));
+        let tasks = coalesce_panes(tumblings, granule_size)?
             .into_iter()
-            .filter(|window| !window.is_empty())
-            .map(|window| {
+            .filter(|(window, _)| !window.is_empty())
+            .map(|(window, pane)| -> Result<tokio::task::JoinHandle<Result<()>>> {
+                // The firing sequence number lets the sinks upsert the results.
+                let mut metadata = HashMap::new();
+                pane.to_metadata(&mut metadata);
+
+                // The range boundaries of `ORDER BY` are sampled from the whole window.
+                let mut metadata = Some(metadata);
+                function_ctx
+                    .sample_range_boundaries(std::slice::from_ref(&window), &mut metadata)?;
+
                 let function_group = group_name.clone();
                 let invoke_type = invocation_type.clone();
+                let invoker = invoker.clone();
 
-                let query_code = group_name.split('-').next().unwrap();
+                let query_code = FunctionId::query_code_of(group_name);
                 let timestamp = Utc::now().timestamp();
                 let rand_id = uuid::Uuid::new_v4().as_u128();
                 let qid = format!("{}-{}-{}", query_code, timestamp, rand_id);
@@ -262,7 +282,7 @@ pub async fn launch_tasks(
                 let function_name = ring.get(&qid).expect("hash ring failure.").to_string();
                 info!("Tumbling window -> function name: {}", function_name);
 
-                tokio::spawn(async move {
+                Ok(tokio::spawn(async move {
                     let window = repartition(window, RoundRobinBatch(1)).await?;
                     let window = coalesce_batches(window, granule_size * 2).await?;
                     let size = window[0].len();
@@ -271,30 +291,29 @@ pub async fn launch_tasks(
 
                     // Call the next stage of the dataflow graph.
                     info!(
-                        "[OK] Send {} events from a tumbling window to function: {}.",
-                        size, function_name
+                        "[OK] Send {} events from a tumbling window (firing: {} #{}) to function: {}.",
+                        size, pane.timing, pane.seq, function_name
                     );
 
                     for (eid, partition) in window.iter().enumerate() {
-                        let payload = serde_json::to_vec(&to_payload(
-                            partition,
-                            &[],
-                            uuid_builder.next_uuid(),
-                            sync,
-                        ))?;
+                        let mut payload =
+                            to_payload(partition, &[], uuid_builder.next_uuid(), sync);
+                        payload.metadata = metadata.clone();
+                        let payload = serde_json::to_vec(&payload)?;
                         info!(
                             "[OK] Event {} - {} function's payload bytes: {}",
                             eid,
                             function_name,
                             payload.len()
                         );
-                        lambda::invoke_function(&function_name, &invoke_type, Some(payload.into()))
+                        invoker
+                            .invoke(&function_name, &invoke_type, payload.into())
                             .await?;
                     }
                     Ok(())
-                })
+                }))
             })
-            .collect::<Vec<tokio::task::JoinHandle<Result<()>>>>();
+            .collect::<Result<Vec<tokio::task::JoinHandle<Result<()>>>>>()?;
         futures::future::join_all(tasks).await;
 
         let elapsed = now.elapsed().as_millis() as u64;
@@ -305,3 +324,99 @@ pub async fn launch_tasks(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use flock::stream::{AccumulationMode, EarlyFiring, FiringTiming};
+
+    fn bids(bidder: i32, process_time: DateTime<Utc>) -> Result<Vec<RecordBatch>> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("auction", DataType::Int32, false),
+            Field::new("bidder", DataType::Int32, false),
+            Field::new("price", DataType::Int32, false),
+            Field::new("b_date_time", DataType::Int32, false),
+            Field::new(
+                "p_time",
+                DataType::Timestamp(TimeUnit::Nanosecond, None),
+                false,
+            ),
+        ]));
+        Ok(vec![RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int32Array::from(vec![1])),
+                Arc::new(Int32Array::from(vec![bidder])),
+                Arc::new(Int32Array::from(vec![100])),
+                Arc::new(Int32Array::from(vec![0])),
+                Arc::new(TimestampNanosecondArray::from(vec![
+                    process_time.timestamp_nanos()
+                ])),
+            ],
+        )?])
+    }
+
+    #[tokio::test]
+    async fn global_window_firings() -> Result<()> {
+        let mut windows: TumblingWindows = HashMap::new();
+        let mut panes = FiringStates::new(Some(Firing {
+            early: vec![EarlyFiring::Elements(1)],
+            late:  false,
+            mode:  AccumulationMode::Accumulating,
+        }));
+        let window_size = 10;
+        let start = Utc::now() - chrono::Duration::seconds(60);
+
+        // Each new bid fires the window early.
+        let closed = add_partitions_to_tumbling_windows(
+            vec![bids(7, start)?],
+            &mut windows,
+            &mut panes,
+            window_size,
+        )?;
+        assert!(closed.is_empty());
+        let fired = fire_early_tumbling_windows(&mut windows, &mut panes, 0);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(
+            fired[0].1,
+            Pane {
+                timing: FiringTiming::Early,
+                seq:    0,
+            }
+        );
+        assert!(fire_early_tumbling_windows(&mut windows, &mut panes, 0).is_empty());
+
+        // A bid after the window size closes the window, and the on-time pane
+        // accumulates all the bids of the window.
+        add_partitions_to_tumbling_windows(
+            vec![bids(7, start + chrono::Duration::seconds(5))?],
+            &mut windows,
+            &mut panes,
+            window_size,
+        )?;
+        let closed = add_partitions_to_tumbling_windows(
+            vec![bids(7, start + chrono::Duration::seconds(11))?],
+            &mut windows,
+            &mut panes,
+            window_size,
+        )?;
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].0.len(), 2);
+        assert_eq!(
+            closed[0].1,
+            Pane {
+                timing: FiringTiming::OnTime,
+                seq:    1,
+            }
+        );
+
+        // The new window of the bidder times out in processing time.
+        assert_eq!(
+            find_timeout_tumbling_windows(&windows, window_size)?,
+            vec![7]
+        );
+
+        Ok(())
+    }
+}
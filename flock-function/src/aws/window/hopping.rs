@@ -11,24 +11,32 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use super::{is_distributed, send_partitions};
 use crate::actor::*;
 use crate::{consistent_hash_context, ConsistentHashContext, CONSISTENT_HASH_CONTEXT};
 use chrono::Utc;
-use flock::aws::lambda;
 use flock::prelude::*;
+use flock::stream::pane::{merge_panes, Panes};
 use log::{info, warn};
 use std::sync::Arc;
 
 /// Generate hopping windows workloads for the benchmark on cloud
 /// function services.
 ///
+/// In the distributed mode, the first stage of the query is executed once per
+/// pane of `gcd(window_size, hop_size)` seconds, and the partial aggregates of
+/// the panes are merged by the next stage when a window fires. Otherwise, the
+/// raw events of the whole window are sent to a single function.
+///
 /// # Arguments
+/// * `ctx` - The execution context of the function.
 /// * `payload` - The payload of the function.
 /// * `stream` - the source stream of events.
 /// * `seconds` - the total number of seconds to generate workloads.
 /// * `window_size` - the size of the window in seconds.
 /// * `hop_size` - the size of the hop in seconds.
 pub async fn launch_tasks(
+    ctx: &mut ExecutionContext,
     payload: Payload,
     stream: Arc<dyn DataStream>,
     seconds: usize,
@@ -48,6 +56,10 @@ pub async fn launch_tasks(
         FLOCK_LAMBDA_ASYNC_CALL.to_string()
     };
 
+    if is_distributed(ctx) {
+        return launch_pane_tasks(ctx, payload, stream, seconds, window_size, hop_size).await;
+    }
+
     let (ring, group_name) = consistent_hash_context!();
     let mut window: Box<Vec<(RelationPartitions, RelationPartitions)>> = Box::new(vec![]);
 
@@ -113,7 +125,8 @@ pub async fn launch_tasks(
                     function_name,
                     payload.len()
                 );
-                lambda::invoke_function(&function_name, &invocation_type, Some(payload.into()))
+                ctx.invoker
+                    .invoke(&function_name, &invocation_type, payload.into())
                     .await?;
                 eid += 1;
             }
@@ -122,3 +135,98 @@ pub async fn launch_tasks(
 
     Ok(())
 }
+
+/// Computes the partial aggregates of each pane once, and sends the partial
+/// aggregates of all the panes in a window to the next stage when the window
+/// fires.
+async fn launch_pane_tasks(
+    ctx: &mut ExecutionContext,
+    payload: Payload,
+    stream: Arc<dyn DataStream>,
+    seconds: usize,
+    window_size: usize,
+    hop_size: usize,
+) -> Result<()> {
+    let query_number = payload.query_number;
+    let metadata = payload.metadata;
+    let sync = infer_invocation_type(&metadata)?;
+    let invocation_type = if sync {
+        FLOCK_LAMBDA_SYNC_CALL.to_string()
+    } else {
+        FLOCK_LAMBDA_ASYNC_CALL.to_string()
+    };
+
+    let mut panes = Panes::new(window_size, hop_size)?;
+    let pane_size = panes.pane_size();
+
+    for start in (0..seconds).step_by(pane_size) {
+        let end = start + pane_size;
+        if end > seconds {
+            break;
+        }
+
+        let mut input1 = vec![];
+        let mut input2 = vec![];
+        for t in start..end {
+            let (r1, r2) = stream.select_event_to_batches(t, 0, query_number, sync)?;
+            if !r1.is_empty() {
+                input1.push(r1);
+            }
+            if !r2.is_empty() {
+                input2.push(r2);
+            }
+        }
+        let mut input = vec![];
+        if !input1.is_empty() {
+            input.push(input1.into_iter().flatten().collect());
+        }
+        if !input2.is_empty() {
+            input.push(input2.into_iter().flatten().collect());
+        }
+
+        // The range boundaries of `ORDER BY` are sampled from the whole window,
+        // so the records of the panes are kept until their windows fire.
+        let records = if ctx.range_sampler.is_some() {
+            input.clone()
+        } else {
+            vec![]
+        };
+
+        // Computes the partial aggregates of the pane.
+        let output = if input.is_empty() {
+            vec![]
+        } else {
+            ctx.feed_data_sources(input).await?;
+            let output = ctx.execute_partitioned().await?;
+            ctx.clean_data_sources().await?;
+            output
+        };
+
+        if let Some(window) = panes.push((records, output)) {
+            let output = merge_panes(&window.iter().map(|(_, o)| o).collect::<Vec<_>>());
+            if output.is_empty() {
+                continue;
+            }
+            let records = merge_panes(&window.iter().map(|(r, _)| r).collect::<Vec<_>>());
+            let mut metadata = metadata.clone();
+            ctx.sample_range_boundaries(&records, &mut metadata)?;
+            info!(
+                "[OK] Send the partial aggregates of {} panes from a window (epoch: {}-{}).",
+                window.len(),
+                end - window_size,
+                end
+            );
+            send_partitions(
+                ctx,
+                Arc::new(output),
+                start / pane_size,
+                metadata,
+                &invocation_type,
+                sync,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
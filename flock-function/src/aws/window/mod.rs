@@ -14,14 +14,26 @@
 //! The time window types for the stream queries.
 
 pub mod elementwise;
+pub mod eventtime;
 pub mod global;
 pub mod hopping;
 pub mod session;
+pub mod sliding;
+pub mod stagger;
+pub mod triggered;
 pub mod tumbling;
 
+use crate::actor::*;
+use crate::{consistent_hash_context, ConsistentHashContext, CONSISTENT_HASH_CONTEXT};
+use chrono::Utc;
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::physical_plan::empty::EmptyExec;
 use flock::prelude::*;
+use flock::stream::tvf::append_window_columns;
+use flock::stream::{EventTimeWindow, Firing, Pane};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
 
 /// This function is used to coalesce smaller session windows or global windows
 /// to bigger ones so that the number of events in each payload is greater than
@@ -55,6 +67,30 @@ fn coalesce_windows(
     Ok(res)
 }
 
+/// Coalesces the windows of the same pane, so that each payload carries the
+/// firing of all its windows in the metadata.
+fn coalesce_panes(
+    windows: Vec<(Vec<Vec<RecordBatch>>, Pane)>,
+    granule_size: usize,
+) -> Result<Vec<(Vec<Vec<RecordBatch>>, Pane)>> {
+    let mut panes: Vec<(Pane, Vec<Vec<Vec<RecordBatch>>>)> = vec![];
+    for (window, pane) in windows {
+        match panes.iter_mut().find(|(p, _)| *p == pane) {
+            Some((_, group)) => group.push(window),
+            None => panes.push((pane, vec![window])),
+        }
+    }
+    let mut res = vec![];
+    for (pane, windows) in panes {
+        res.extend(
+            coalesce_windows(windows, granule_size)?
+                .into_iter()
+                .map(|window| (window, pane)),
+        );
+    }
+    Ok(res)
+}
+
 /// Is distributed execution enabled?
 fn is_distributed(ctx: &ExecutionContext) -> bool {
     ctx.plan.execution_plans[0]
@@ -62,3 +98,244 @@ fn is_distributed(ctx: &ExecutionContext) -> bool {
         .downcast_ref::<EmptyExec>()
         .is_none()
 }
+
+/// Launches the windows whose records are assigned by event time.
+///
+/// # Arguments
+/// * `ctx` - The execution context of the function.
+/// * `payload` - The payload of the function.
+/// * `stream` - the source stream of events.
+/// * `seconds` - the total number of seconds to generate workloads.
+/// * `window` - the tumbling, hopping, sliding or stagger window.
+/// * `event_time` - the event-time settings of the window.
+/// * `firing` - the early and late firings of the tumbling or hopping window.
+pub async fn launch_event_time_tasks(
+    ctx: &ExecutionContext,
+    payload: Payload,
+    stream: Arc<dyn DataStream>,
+    seconds: usize,
+    window: Window,
+    event_time: EventTime,
+    firing: Option<Firing>,
+) -> Result<()> {
+    match window {
+        Window::Tumbling(Schedule::Seconds(size)) => {
+            eventtime::launch_tasks(
+                ctx,
+                payload,
+                stream,
+                seconds,
+                (size, size),
+                event_time,
+                firing,
+            )
+            .await
+        }
+        Window::Hopping((size, hop)) => {
+            eventtime::launch_tasks(
+                ctx,
+                payload,
+                stream,
+                seconds,
+                (size, hop),
+                event_time,
+                firing,
+            )
+            .await
+        }
+        _ if firing.is_some() => Err(FlockError::Execution(format!(
+            "Early and late firings don't support the window: {:?}",
+            window
+        ))),
+        Window::Sliding((size, slide)) => {
+            sliding::launch_tasks(ctx, payload, stream, seconds, size, slide, event_time).await
+        }
+        Window::Stagger(size) => {
+            stagger::launch_tasks(ctx, payload, stream, seconds, size, event_time).await
+        }
+        _ => Err(FlockError::Execution(format!(
+            "Event time doesn't support the window: {:?}",
+            window
+        ))),
+    }
+}
+
+/// Sends the data of a fired window to a single function execution environment.
+/// If `columns` is true, the `window_start` and `window_end` columns are
+/// appended to the records of the window.
+async fn send_window(
+    ctx: &ExecutionContext,
+    mut window: EventTimeWindow,
+    invocation_type: &str,
+    sync: bool,
+    columns: bool,
+) -> Result<()> {
+    info!(
+        "[OK] Send a window (event time: {}ms-{}ms, firing: {} #{}).",
+        window.start, window.end, window.pane.timing, window.pane.seq
+    );
+    if columns {
+        window.relations = window
+            .relations
+            .iter()
+            .map(|r| append_window_columns(r, window.start, window.end))
+            .collect::<Result<Vec<_>>>()?;
+    }
+    let mut metadata = HashMap::new();
+    metadata.insert("window_start".to_string(), window.start.to_string());
+    metadata.insert("window_end".to_string(), window.end.to_string());
+    window.pane.to_metadata(&mut metadata);
+    send_relations(ctx, &window.relations, metadata, invocation_type, sync).await
+}
+
+/// Sends the data partitions of the relations in a window to a single function
+/// execution environment.
+///
+/// # Arguments
+/// * `ctx` - The execution context of the function.
+/// * `relations` - The data partitions of the relations in the window.
+/// * `metadata` - The metadata of the window, added to each payload.
+/// * `invocation_type` - The invocation type of the function.
+/// * `sync` - Whether the function is invoked synchronously.
+async fn send_relations(
+    ctx: &ExecutionContext,
+    relations: &[RelationPartitions],
+    metadata: HashMap<String, String>,
+    invocation_type: &str,
+    sync: bool,
+) -> Result<()> {
+    let (ring, group_name) = consistent_hash_context!();
+    let none = vec![];
+    let a = relations.first().unwrap_or(&none);
+    let b = relations.get(1).unwrap_or(&none);
+    let size = a.len().max(b.len());
+    if size == 0 {
+        return Ok(());
+    }
+
+    // The range boundaries of `ORDER BY` are sampled from the whole window.
+    let mut metadata = Some(metadata);
+    ctx.sample_range_boundaries(relations, &mut metadata)?;
+    let metadata = metadata.unwrap_or_default();
+
+    let mut uuid_builder = UuidBuilder::new_with_ts(group_name, Utc::now().timestamp(), size);
+    let function_name = ring
+        .get(&uuid_builder.qid)
+        .expect("hash ring failure.")
+        .to_string();
+
+    info!("[OK] Send a window to function: {}.", function_name);
+
+    let empty = vec![];
+    for i in 0..size {
+        let mut payload = to_payload(
+            if i < a.len() { &a[i] } else { &empty },
+            if i < b.len() { &b[i] } else { &empty },
+            uuid_builder.next_uuid(),
+            sync,
+        );
+        let mut window_metadata = payload.metadata.take().unwrap_or_else(HashMap::new);
+        window_metadata.extend(metadata.clone());
+        payload.metadata = Some(window_metadata);
+
+        let bytes = serde_json::to_vec(&payload)?;
+        info!(
+            "[OK] Event {} - {} function's payload bytes: {}",
+            i,
+            function_name,
+            bytes.len()
+        );
+        ctx.invoker
+            .invoke(&function_name, invocation_type, bytes.into())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Sends the output partitions of the current stage to the next stage of the
+/// query. The `i`-th partition of each relation goes to the same function
+/// execution environment of the group.
+///
+/// The windows with the same index in the data source functions of all the
+/// leaf stages share the same query id, see [`UuidBuilder::new_with_window`].
+///
+/// # Arguments
+/// * `ctx` - The execution context of the function.
+/// * `output` - The output partitions of the relations.
+/// * `window` - The index of the window in the query execution.
+/// * `metadata` - The metadata of the payloads.
+/// * `invocation_type` - The invocation type of the function.
+/// * `sync` - Whether the function is invoked synchronously.
+async fn send_partitions(
+    ctx: &ExecutionContext,
+    output: Arc<Vec<RelationPartitions>>,
+    window: usize,
+    metadata: Option<HashMap<String, String>>,
+    invocation_type: &str,
+    sync: bool,
+) -> Result<()> {
+    let (_, group_name) = consistent_hash_context!();
+    let size = output.first().map_or(0, |partitions| partitions.len());
+    let mut uuid_builder = UuidBuilder::new_with_window(group_name, &metadata, window, size)?;
+
+    // Prepares the namespace of the current query in the state backend.
+    ctx.state_backend.prepare_query(&uuid_builder.qid).await?;
+
+    let tasks = (0..size)
+        .map(|i| {
+            let data = output.clone();
+            let function_name = group_name.clone();
+            let meta = metadata.clone();
+            let invoke_type = invocation_type.to_string();
+            let invoker = ctx.invoker.clone();
+            let uuid = uuid_builder.next_uuid();
+            tokio::spawn(async move {
+                let mut payload = to_payload(
+                    &data[0][i],
+                    if data.len() == 1 { &[] } else { &data[1][i] },
+                    uuid,
+                    sync,
+                );
+                payload.metadata = meta;
+
+                let bytes = serde_json::to_vec(&payload)?;
+                info!(
+                    "[OK] {} function's payload bytes: {}",
+                    function_name,
+                    bytes.len()
+                );
+                invoker
+                    .invoke(&function_name, &invoke_type, bytes.into())
+                    .await
+                    .map(|_| ())
+            })
+        })
+        .collect::<Vec<tokio::task::JoinHandle<Result<()>>>>();
+    futures::future::join_all(tasks).await;
+    Ok(())
+}
+
+/// Writes the late records of the relation to the side output of the window.
+async fn write_late_records(
+    ctx: &ExecutionContext,
+    relation: usize,
+    late: Vec<RecordBatch>,
+    late_sink: &DataSinkType,
+) -> Result<()> {
+    let num_rows = late.iter().map(|b| b.num_rows()).sum::<usize>();
+    if num_rows == 0 {
+        return Ok(());
+    }
+    warn!(
+        "[OK] {} late records of relation {} arrive after their windows fire.",
+        num_rows, relation
+    );
+    if DataSinkType::Blackhole != *late_sink {
+        let name = format!("{}-late-{}", ctx.name, relation);
+        DataSink::new(name, late, Encoding::default())
+            .write(late_sink.clone(), DataSinkFormat::SerdeBinary)
+            .await?;
+    }
+    Ok(())
+}
@@ -11,20 +11,22 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use super::coalesce_windows;
+use super::coalesce_panes;
 use crate::actor::*;
 use crate::{consistent_hash_context, ConsistentHashContext, CONSISTENT_HASH_CONTEXT};
-use chrono::{DateTime, NaiveDateTime, Utc};
-use datafusion::arrow::array::{Int32Array, TimestampMillisecondArray, UInt64Array};
+use chrono::Utc;
+use datafusion::arrow::array::UInt64Array;
 use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::array_value_to_string;
 use datafusion::datasource::MemTable;
 use datafusion::execution::context::ExecutionContext as DataFusionExecutionContext;
 use datafusion::logical_plan::{col, count_distinct};
 use datafusion::physical_plan::expressions::col as expr_col;
 use datafusion::physical_plan::Partitioning::{HashDiff, RoundRobinBatch};
-use flock::aws::lambda;
-use flock::datasource::nexmark::config::BASE_TIME;
 use flock::prelude::*;
+use flock::stream::tvf::append_window_columns;
+use flock::stream::watermark::event_times;
+use flock::stream::{Firing, FiringStates, Pane, Watermark};
 use log::{info, warn};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -52,130 +54,159 @@ fn get_input_from_registered_table(
     })
 }
 
+/// A session window.
+#[derive(Debug)]
+struct Session {
+    /// The event time in milliseconds of the first record.
+    start:      i64,
+    /// The event time in milliseconds of the last record.
+    last:       i64,
+    /// The data partitions of the session. In the discarding mode, only the
+    /// partitions since the previous firing are kept.
+    partitions: Vec<Vec<RecordBatch>>,
+}
+
+/// The open session windows indexed by the session key.
+type Sessions = HashMap<String, Session>;
+
+/// Returns the session key of the partition. After the hash repartitioning,
+/// all the records in a partition share the same key, so the key is read from
+/// the first record. The key is rendered as a string so that any hashable
+/// Arrow type can be used.
+fn session_key(partition: &[RecordBatch], key: &str) -> Result<Option<String>> {
+    match partition.iter().find(|b| b.num_rows() > 0) {
+        Some(batch) => {
+            let index = batch.schema().index_of(key)?;
+            Ok(Some(array_value_to_string(batch.column(index), 0)?))
+        }
+        None => Ok(None),
+    }
+}
+
 /// Add each unique partition to a distinct session window.
 ///
 /// # Arguments
 /// * `partitions` - the partitions to be added.
 /// * `windows` - the session windows.
-/// * `timeouts` - the session timeouts.
+/// * `panes` - the firing states of the session windows.
+/// * `watermark` - the watermark of the event time.
+/// * `key` - the name of the session key column.
+/// * `time` - the name of the timestamp column.
+/// * `timeout` - the session timeout in seconds.
 ///
 /// # Return
-/// The updated session windows.
+/// The closed session windows with their on-time panes.
 fn add_partitions_to_session_windows(
     partitions: Vec<Vec<RecordBatch>>,
-    windows: &mut HashMap<usize, Vec<Vec<RecordBatch>>>,
+    windows: &mut Sessions,
+    panes: &mut FiringStates<String>,
+    watermark: &mut Watermark,
+    key: &str,
+    time: &str,
     timeout: usize,
-) -> Result<Vec<Vec<Vec<RecordBatch>>>> {
-    Ok(partitions
-        .into_iter()
-        .filter(|p| !p.is_empty())
-        .map(|p| {
-            let mut session = vec![];
-            let bidder = p[0]
-                .column(1 /* bidder field */)
-                .as_any()
-                .downcast_ref::<Int32Array>()
-                .unwrap()
-                .value(0);
-            let current_timestamp = p[0]
-                .column(3 /* b_date_time field */)
-                .as_any()
-                .downcast_ref::<TimestampMillisecondArray>()
-                .unwrap()
-                .value(0);
-            let curr_date_time = DateTime::<Utc>::from_utc(
-                NaiveDateTime::from_timestamp(current_timestamp / 1000, 0),
-                Utc,
-            );
-
-            if !windows.contains_key(&(bidder as usize)) {
-                windows
-                    .entry(bidder as usize)
-                    .or_insert_with(Vec::new)
-                    .push(p);
-            } else {
-                // get the last batch in the window.
-                let batch = windows
-                    .get(&(bidder as usize))
-                    .unwrap()
-                    .last()
-                    .unwrap()
-                    .last()
-                    .unwrap();
-                // get the last bid record's timestamp.
-                let last_timestamp = batch
-                    .column(3 /* b_date_time field */)
-                    .as_any()
-                    .downcast_ref::<TimestampMillisecondArray>()
-                    .unwrap()
-                    .value(batch.num_rows() - 1);
-                let last_date_time = DateTime::<Utc>::from_utc(
-                    NaiveDateTime::from_timestamp(last_timestamp / 1000, 0),
-                    Utc,
-                );
-
-                // If the current date time isn't 10 seconds later than the last date time,
-                // then we can add the current batch to the window . Otherwise, we have a new
-                // session window.
-                if curr_date_time.signed_duration_since(last_date_time)
-                    > chrono::Duration::seconds(timeout as i64)
-                {
-                    session = windows.remove(&(bidder as usize)).unwrap();
+) -> Result<Vec<(Session, Pane)>> {
+    let mut sessions = vec![];
+    for p in partitions {
+        let session_key = match session_key(&p, key)? {
+            Some(session_key) => session_key,
+            None => continue,
+        };
+        let mut times = vec![];
+        for batch in &p {
+            times.append(&mut event_times(batch, time)?);
+        }
+        watermark.observe(&times);
+        let first = *times.iter().min().unwrap();
+        let last = *times.iter().max().unwrap();
+
+        // If the first record isn't `timeout` seconds later than the last record
+        // of the session, then we can add the partition to the session window.
+        // Otherwise, the session is closed and we have a new session window.
+        if let Some(session) = windows.get(&session_key) {
+            if first - session.last > timeout as i64 * 1000 {
+                let session = windows.remove(&session_key).unwrap();
+                if let Some(pane) = panes.close(&session_key) {
+                    sessions.push((session, pane));
                 }
-                windows
-                    .entry(bidder as usize)
-                    .or_insert_with(Vec::new)
-                    .push(p);
             }
-            session
+        }
+        panes.observe(&session_key, times.len());
+        let session = windows.entry(session_key).or_insert(Session {
+            start: first,
+            last,
+            partitions: vec![],
+        });
+        session.start = session.start.min(first);
+        session.last = session.last.max(last);
+        session.partitions.push(p);
+    }
+    Ok(sessions)
+}
+
+/// Fires the open session windows early by the firing policy.
+///
+/// # Arguments
+/// * `windows` - the session windows.
+/// * `panes` - the firing states of the session windows.
+/// * `now` - the processing time in milliseconds.
+///
+/// # Return
+/// The early panes of the session windows.
+fn fire_early_session_windows(
+    windows: &mut Sessions,
+    panes: &mut FiringStates<String>,
+    now: i64,
+) -> Vec<(Session, Pane)> {
+    let mode = panes.mode();
+    panes
+        .fire_early(now)
+        .into_iter()
+        .filter_map(|(key, pane)| {
+            let session = windows.get_mut(&key)?;
+            let partitions = mode.pane_records(&mut session.partitions);
+            Some((
+                Session {
+                    partitions,
+                    ..*session
+                },
+                pane,
+            ))
         })
-        .collect::<Vec<Vec<Vec<RecordBatch>>>>())
+        .collect()
+}
+
+/// Appends the window columns to the session window. The session starts at its
+/// first record, and ends at the timeout after its last record.
+fn append_session_columns(session: &Session, timeout: usize) -> Result<Vec<Vec<RecordBatch>>> {
+    append_window_columns(
+        &session.partitions,
+        session.start,
+        session.last + timeout as i64 * 1000,
+    )
 }
 
 /// Find new session windows after timeout.
 ///
 /// # Arguments
 /// * `windows` - the session windows.
-/// * `timeout` - the session timeout.
-/// * `epoch_count` - the number of epochs have been processed.
+/// * `timeout` - the session timeout in seconds.
+/// * `watermark` - the watermark of the event time.
 ///
 /// # Return
 /// The keys of the new session windows.
 fn find_timeout_session_windows(
-    windows: &HashMap<usize, Vec<Vec<RecordBatch>>>,
+    windows: &Sessions,
     timeout: usize,
-    epoch_count: usize,
-) -> Result<Vec<usize>> {
-    let mut to_remove = vec![];
-
-    windows.iter().for_each(|(bidder, batches)| {
-        // Get the last batch
-        let batch = batches.last().unwrap().last().unwrap();
-
-        // Get the last bid's date time
-        let last_timestamp = batch
-            .column(3 /* b_date_time field */)
-            .as_any()
-            .downcast_ref::<TimestampMillisecondArray>()
-            .unwrap()
-            .value(batch.num_rows() - 1);
-
-        let last_date_time =
-            DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(last_timestamp / 1000, 0), Utc);
-
-        let epoch_gap_time = DateTime::<Utc>::from_utc(
-            NaiveDateTime::from_timestamp(BASE_TIME as i64 / 1000 + epoch_count as i64, 0),
-            Utc,
-        );
-
-        if epoch_gap_time.signed_duration_since(last_date_time)
-            > chrono::Duration::seconds(timeout as i64)
-        {
-            to_remove.push(bidder.to_owned());
-        }
-    });
-
-    Ok(to_remove)
+    watermark: &Watermark,
+) -> Vec<String> {
+    match watermark.current() {
+        Some(now) => windows
+            .iter()
+            .filter(|(_, session)| now - session.last > timeout as i64 * 1000)
+            .map(|(key, _)| key.to_owned())
+            .collect(),
+        None => vec![],
+    }
 }
 
 /// Session windows group events that arrive at similar times, filtering out
@@ -183,17 +214,29 @@ fn find_timeout_session_windows(
 /// first event occurs. If another event occurs within the specified timeout
 /// from the last ingested event, then the window extends to include the new
 /// event. Otherwise if no events occur within the timeout, then the window is
-/// closed at the timeout.
+/// closed at the timeout. With a firing policy, the open sessions also send
+/// their early panes.
+///
+/// # Arguments
+/// * `ctx` - The execution context of the function.
+/// * `payload` - The payload of the function.
+/// * `stream` - the source stream of events.
+/// * `seconds` - the total number of seconds to generate workloads.
+/// * `timeout` - the session timeout in seconds.
+/// * `firing` - the early firings of the session windows.
 pub async fn launch_tasks(
+    ctx: &ExecutionContext,
     payload: Payload,
     stream: Arc<dyn DataStream>,
     seconds: usize,
     timeout: usize,
+    firing: Option<Firing>,
 ) -> Result<()> {
     if seconds < timeout {
         warn!("seconds: {} is less than timeout: {}", seconds, timeout);
     }
     let sync = infer_invocation_type(&payload.metadata)?;
+    let columns = infer_window_columns(&payload.metadata);
     let (group_key, table_name) = infer_session_keys(&payload.metadata)?;
     let (ring, group_name) = consistent_hash_context!();
 
@@ -206,8 +249,12 @@ pub async fn launch_tasks(
         )
     };
 
+    let function_ctx = ctx;
+    let invoker = ctx.invoker.clone();
     let mut ctx = DataFusionExecutionContext::new();
-    let mut windows: HashMap<usize, Vec<Vec<RecordBatch>>> = HashMap::new();
+    let mut windows: Sessions = HashMap::new();
+    let mut panes = FiringStates::new(firing);
+    let mut watermark = Watermark::default();
 
     let events = (0..seconds)
         .map(|t| {
@@ -224,6 +271,7 @@ pub async fn launch_tasks(
         .collect::<Vec<Vec<Vec<RecordBatch>>>>();
 
     let schema = events[0][0][0].schema();
+    let time_column = infer_session_time(&payload.metadata, &schema)?;
 
     for (time, batches) in events.into_iter().enumerate() {
         info!("Processing events in epoch: {}", time);
@@ -254,20 +302,56 @@ pub async fn launch_tasks(
         .await?;
 
         // Update the window.
-        let mut sessions = add_partitions_to_session_windows(partitions, &mut windows, timeout)?;
-        let to_remove = find_timeout_session_windows(&windows, timeout, time)?;
-        to_remove.iter().for_each(|bidder| {
-            sessions.push(windows.remove(bidder).unwrap());
+        let mut sessions = add_partitions_to_session_windows(
+            partitions,
+            &mut windows,
+            &mut panes,
+            &mut watermark,
+            &group_key,
+            &time_column,
+            timeout,
+        )?;
+        let to_remove = find_timeout_session_windows(&windows, timeout, &watermark);
+        to_remove.iter().for_each(|key| {
+            let session = windows.remove(key).unwrap();
+            if let Some(pane) = panes.close(key) {
+                sessions.push((session, pane));
+            }
         });
+        sessions.append(&mut fire_early_session_windows(
+            &mut windows,
+            &mut panes,
+            Utc::now().timestamp_millis(),
+        ));
+        let sessions = sessions
+            .into_iter()
+            .map(|(session, pane)| {
+                if columns {
+                    Ok((append_session_columns(&session, timeout)?, pane))
+                } else {
+                    Ok((session.partitions, pane))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        let tasks = coalesce_windows(sessions, granule_size)?
+        let tasks = coalesce_panes(sessions, granule_size)?
             .into_iter()
-            .filter(|session| !session.is_empty())
-            .map(|session| {
+            .filter(|(session, _)| !session.is_empty())
+            .map(|(session, pane)| -> Result<tokio::task::JoinHandle<Result<()>>> {
+                // The firing sequence number lets the sinks upsert the results.
+                let mut metadata = HashMap::new();
+                pane.to_metadata(&mut metadata);
+
+                // The range boundaries of `ORDER BY` are sampled from the whole window.
+                let mut metadata = Some(metadata);
+                function_ctx
+                    .sample_range_boundaries(std::slice::from_ref(&session), &mut metadata)?;
+
                 let function_group = group_name.clone();
                 let invoke_type = invocation_type.clone();
+                let invoker = invoker.clone();
 
-                let query_code = group_name.split('-').next().unwrap();
+                let query_code = FunctionId::query_code_of(group_name);
                 let timestamp = Utc::now().timestamp();
                 let rand_id = uuid::Uuid::new_v4().as_u128();
                 let qid = format!("{}-{}-{}", query_code, timestamp, rand_id);
@@ -276,7 +360,7 @@ pub async fn launch_tasks(
                 let function_name = ring.get(&qid).expect("hash ring failure.").to_string();
                 info!("Session window -> function name: {}", function_name);
 
-                tokio::spawn(async move {
+                Ok(tokio::spawn(async move {
                     let output = repartition(session, RoundRobinBatch(1)).await?;
                     let window = coalesce_batches(output, granule_size * 2).await?;
                     let size = window[0].len();
@@ -285,30 +369,29 @@ pub async fn launch_tasks(
 
                     // Call the next stage of the dataflow graph.
                     info!(
-                        "[OK] Send {} events from a session window to function: {}.",
-                        size, function_name
+                        "[OK] Send {} events from a session window (firing: {} #{}) to function: {}.",
+                        size, pane.timing, pane.seq, function_name
                     );
 
                     for (eid, partition) in window.iter().enumerate() {
-                        let payload = serde_json::to_vec(&to_payload(
-                            partition,
-                            &[],
-                            uuid_builder.next_uuid(),
-                            sync,
-                        ))?;
+                        let mut payload =
+                            to_payload(partition, &[], uuid_builder.next_uuid(), sync);
+                        payload.metadata = metadata.clone();
+                        let payload = serde_json::to_vec(&payload)?;
                         info!(
                             "[OK] Event {} - {} function's payload bytes: {}",
                             eid,
                             function_name,
                             payload.len()
                         );
-                        lambda::invoke_function(&function_name, &invoke_type, Some(payload.into()))
+                        invoker
+                            .invoke(&function_name, &invoke_type, payload.into())
                             .await?;
                     }
                     Ok(())
-                })
+                }))
             })
-            .collect::<Vec<tokio::task::JoinHandle<Result<()>>>>();
+            .collect::<Result<Vec<tokio::task::JoinHandle<Result<()>>>>>()?;
         futures::future::join_all(tasks).await;
 
         let elapsed = now.elapsed().as_millis() as u64;
@@ -319,3 +402,153 @@ pub async fn launch_tasks(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{StringArray, TimestampMicrosecondArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+
+    fn clicks(user: &str, times: &[i64]) -> Result<Vec<RecordBatch>> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("user_id", DataType::Utf8, false),
+            Field::new(
+                "click_time",
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                false,
+            ),
+        ]));
+        Ok(vec![RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![user; times.len()])),
+                Arc::new(TimestampMicrosecondArray::from(times.to_vec())),
+            ],
+        )?])
+    }
+
+    #[tokio::test]
+    async fn generic_session_windows() -> Result<()> {
+        let mut windows: Sessions = HashMap::new();
+        let mut panes = FiringStates::new(None);
+        let mut watermark = Watermark::default();
+        let timeout = 2;
+        let mut add = |partitions, windows: &mut Sessions| {
+            add_partitions_to_session_windows(
+                partitions,
+                windows,
+                &mut panes,
+                &mut watermark,
+                "user_id",
+                "click_time",
+                timeout,
+            )
+        };
+
+        let sessions = add(
+            vec![
+                clicks("alice", &[0, 1_000_000])?,
+                clicks("bob", &[500_000])?,
+            ],
+            &mut windows,
+        )?;
+        assert!(sessions.is_empty());
+        assert_eq!(windows.len(), 2);
+        assert_eq!(windows["alice"].last, 1_000);
+
+        // Alice's next click extends the session, while Bob's session closes
+        // because of a gap longer than the timeout.
+        let sessions = add(
+            vec![clicks("alice", &[2_500_000])?, clicks("bob", &[3_000_000])?],
+            &mut windows,
+        )?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].0.partitions.len(), 1);
+        assert_eq!(sessions[0].1, Pane::default());
+        assert_eq!(windows["alice"].partitions.len(), 2);
+        assert_eq!(windows["bob"].last, 3_000);
+
+        let sessions = add(vec![clicks("carol", &[6_000_000])?], &mut windows)?;
+        assert!(sessions.is_empty());
+        let mut expired = find_timeout_session_windows(&windows, timeout, &watermark);
+        expired.sort();
+        assert_eq!(expired, vec!["alice".to_string(), "bob".to_string()]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn session_window_firings() -> Result<()> {
+        use flock::stream::{AccumulationMode, EarlyFiring, FiringTiming};
+
+        let mut windows: Sessions = HashMap::new();
+        let mut panes = FiringStates::new(Some(Firing {
+            early: vec![EarlyFiring::Elements(2)],
+            late:  false,
+            mode:  AccumulationMode::Discarding,
+        }));
+        let mut watermark = Watermark::default();
+        let timeout = 2;
+        let mut add = |partitions, windows: &mut Sessions, panes: &mut FiringStates<String>| {
+            add_partitions_to_session_windows(
+                partitions,
+                windows,
+                panes,
+                &mut watermark,
+                "user_id",
+                "click_time",
+                timeout,
+            )
+        };
+
+        // The session fires early once it has two new clicks.
+        add(
+            vec![clicks("alice", &[0, 500_000])?],
+            &mut windows,
+            &mut panes,
+        )?;
+        let fired = fire_early_session_windows(&mut windows, &mut panes, 0);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].0.partitions.len(), 1);
+        assert_eq!(
+            fired[0].1,
+            Pane {
+                timing: FiringTiming::Early,
+                seq:    0,
+            }
+        );
+
+        // In the discarding mode, the early pane takes the clicks out of the
+        // session, which still spans from its first click.
+        assert!(windows["alice"].partitions.is_empty());
+        add(
+            vec![clicks("alice", &[1_000_000])?],
+            &mut windows,
+            &mut panes,
+        )?;
+        assert!(fire_early_session_windows(&mut windows, &mut panes, 0).is_empty());
+
+        // The on-time pane only has the click since the early firing.
+        let sessions = add(
+            vec![clicks("alice", &[5_000_000])?],
+            &mut windows,
+            &mut panes,
+        )?;
+        assert_eq!(sessions.len(), 1);
+        let (session, pane) = &sessions[0];
+        assert_eq!((session.start, session.last), (0, 1_000));
+        assert_eq!(session.partitions.len(), 1);
+        assert_eq!(
+            *pane,
+            Pane {
+                timing: FiringTiming::OnTime,
+                seq:    1,
+            }
+        );
+        let output = append_session_columns(session, timeout)?;
+        assert_eq!(output[0][0].num_rows(), 1);
+        assert_eq!(windows["alice"].start, 5_000);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use super::send_relations;
+use crate::actor::*;
+use chrono::Utc;
+use flock::prelude::*;
+use flock::stream::TriggeredWindows;
+use log::info;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Generate the workloads of the windows fired by a rate, cron or row-count
+/// schedule for the benchmark on cloud function services.
+///
+/// The rate and cron schedules fire the window by the wall clock, so each
+/// epoch of the stream takes at least one second. The row-count schedule fires
+/// the window every `n` records, per key if the `session_key` column is given
+/// in the payload's metadata, otherwise globally.
+///
+/// # Arguments
+/// * `ctx` - The execution context of the function.
+/// * `payload` - The payload of the function.
+/// * `stream` - the source stream of events.
+/// * `seconds` - the total number of seconds to generate workloads.
+/// * `schedule` - the schedule of the window.
+pub async fn launch_tasks(
+    ctx: &ExecutionContext,
+    payload: Payload,
+    stream: Arc<dyn DataStream>,
+    seconds: usize,
+    schedule: &Schedule,
+) -> Result<()> {
+    let sync = infer_invocation_type(&payload.metadata)?;
+    let invocation_type = if sync {
+        FLOCK_LAMBDA_SYNC_CALL.to_string()
+    } else {
+        FLOCK_LAMBDA_ASYNC_CALL.to_string()
+    };
+
+    let key = payload
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get("session_key"))
+        .cloned();
+    let trigger = schedule.trigger()?;
+    let wall_clock = trigger.is_wall_clock();
+    let mut windows = TriggeredWindows::new(trigger, key, 2, Utc::now());
+
+    for time in 0..seconds {
+        info!("Processing events in epoch: {}", time);
+        let now = Instant::now();
+
+        if let Some(window) = windows.tick(Utc::now()) {
+            let mut metadata = HashMap::new();
+            metadata.insert(
+                "window_end".to_string(),
+                Utc::now().timestamp_millis().to_string(),
+            );
+            send_relations(ctx, &window, metadata, &invocation_type, sync).await?;
+        }
+
+        let (r1, r2) = stream.select_event_to_batches(
+            time,
+            0, // generator id
+            payload.query_number,
+            sync,
+        )?;
+        for (relation, partitions) in [r1, r2].iter().enumerate() {
+            for window in windows.insert(relation, partitions)? {
+                send_relations(ctx, &window, HashMap::new(), &invocation_type, sync).await?;
+            }
+        }
+
+        // The wall-clock triggers need the real time between the epochs.
+        if wall_clock {
+            let elapsed = now.elapsed().as_millis() as u64;
+            if elapsed < 1000 {
+                std::thread::sleep(std::time::Duration::from_millis(1000 - elapsed));
+            }
+        }
+    }
+
+    // The end of the stream closes all the open windows.
+    for window in windows.flush() {
+        send_relations(ctx, &window, HashMap::new(), &invocation_type, sync).await?;
+    }
+
+    Ok(())
+}
@@ -11,11 +11,10 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use super::is_distributed;
+use super::{is_distributed, send_partitions};
 use crate::actor::*;
 use crate::{consistent_hash_context, ConsistentHashContext, CONSISTENT_HASH_CONTEXT};
 use chrono::Utc;
-use flock::aws::{lambda, s3};
 use flock::prelude::*;
 use log::{info, warn};
 use std::sync::Arc;
@@ -61,7 +60,7 @@ pub async fn launch_tasks(
             let mut input1 = vec![];
             let mut input2 = vec![];
             for t in start..end {
-                let (r1, r2) = stream.select_event_to_batches(t, 0, None, sync)?;
+                let (r1, r2) = stream.select_event_to_batches(t, 0, payload.query_number, sync)?;
                 if !r1.is_empty() {
                     input1.push(r1);
                 }
@@ -77,51 +76,13 @@ pub async fn launch_tasks(
                 input.push(input2.into_iter().flatten().collect());
             }
 
+            // The range boundaries of `ORDER BY` are sampled from the whole window.
+            let mut metadata = metadata.clone();
+            ctx.sample_range_boundaries(&input, &mut metadata)?;
+            ctx.feed_range_boundaries(&metadata).await?;
             ctx.feed_data_sources(input).await?;
             let output = Arc::new(ctx.execute_partitioned().await?);
-            let size = output[0].len();
-            let mut uuid_builder =
-                UuidBuilder::new_with_ts(group_name, Utc::now().timestamp(), size);
-
-            // Creates the S3 bucket for the current query if state backend is S3.
-            if ctx
-                .state_backend
-                .as_any()
-                .downcast_ref::<S3StateBackend>()
-                .is_some()
-            {
-                s3::create_bucket(&uuid_builder.qid).await?;
-            }
-
-            let tasks = (0..size)
-                .map(|i| {
-                    let data = output.clone();
-                    let function_name = group_name.clone();
-                    let meta = metadata.clone();
-                    let invoke_type = invocation_type.clone();
-                    let uuid = uuid_builder.next_uuid();
-                    tokio::spawn(async move {
-                        let mut payload = to_payload(
-                            &data[0][i],
-                            if data.len() == 1 { &[] } else { &data[1][i] },
-                            uuid,
-                            sync,
-                        );
-                        payload.metadata = meta;
-
-                        let bytes = serde_json::to_vec(&payload)?;
-                        info!(
-                            "[OK] {} function's payload bytes: {}",
-                            function_name,
-                            bytes.len()
-                        );
-                        lambda::invoke_function(&function_name, &invoke_type, Some(bytes.into()))
-                            .await
-                            .map(|_| ())
-                    })
-                })
-                .collect::<Vec<tokio::task::JoinHandle<Result<()>>>>();
-            futures::future::join_all(tasks).await;
+            send_partitions(ctx, output, time, metadata, &invocation_type, sync).await?;
             ctx.clean_data_sources().await?;
         } else {
             // Update the tumbling window, and generate the next batch of data.
@@ -176,7 +137,8 @@ pub async fn launch_tasks(
                         function_name,
                         payload.len()
                     );
-                    lambda::invoke_function(&function_name, &invocation_type, Some(payload.into()))
+                    ctx.invoker
+                        .invoke(&function_name, &invocation_type, payload.into())
                         .await?;
                     eid += 1;
                 }
@@ -54,10 +54,36 @@ pub async fn handler(ctx: &mut ExecutionContext, payload: Payload) -> Result<Val
     info!("{:?}", source);
     info!("[OK] Generate YSB events.");
 
-    if let Window::Tumbling(Schedule::Seconds(window_size)) = source.window {
-        tumbling::launch_tasks(ctx, payload, events, sec, window_size).await?;
-    } else {
-        unreachable!();
+    match source.window {
+        Window::Tumbling(Schedule::Seconds(window_size)) => {
+            tumbling::launch_tasks(ctx, payload, events, sec, window_size).await?;
+        }
+        Window::Sliding(..) | Window::Stagger(..) => {
+            launch_event_time_tasks(
+                ctx,
+                payload,
+                events,
+                sec,
+                source.window,
+                EventTime::default(),
+                None,
+            )
+            .await?;
+        }
+        Window::EventTime(..) | Window::Firing(..) => {
+            let (window, event_time, firing) = source.window.into_parts();
+            let event_time = event_time.unwrap_or_default();
+            launch_event_time_tasks(ctx, payload, events, sec, window, event_time, firing).await?;
+        }
+        Window::Tumbling(ref schedule) => {
+            triggered::launch_tasks(ctx, payload, events, sec, schedule).await?;
+        }
+        ref window => {
+            return Err(FlockError::NotImplemented(format!(
+                "The window {:?} is not supported in the YSB benchmark.",
+                window
+            )));
+        }
     }
 
     Ok(json!({"name": &ctx.name, "type": "ysb_bench".to_string()}))
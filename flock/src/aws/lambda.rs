@@ -15,15 +15,73 @@
 
 use crate::configs::*;
 use crate::error::{FlockError, Result};
+use crate::invoker::RetryPolicy;
 use crate::runtime::context::ExecutionContext;
+use async_trait::async_trait;
 use bytes::Bytes;
-use log::{debug, info};
-use rand::Rng;
+use log::info;
 use rusoto_lambda::{
     CreateFunctionRequest, GetFunctionRequest, InvocationRequest, InvocationResponse, Lambda,
     PutFunctionConcurrencyRequest, UpdateFunctionCodeRequest,
 };
-use std::time::Duration;
+use std::fmt::Debug;
+
+/// The AWS Lambda operations used to deploy and run a query.
+///
+/// The launcher talks to AWS Lambda through this trait, so that the
+/// deployment and the execution of a query can be tested against a mocked
+/// Lambda service.
+#[async_trait]
+pub trait LambdaService: Debug + Send + Sync {
+    /// Creates a single lambda function. See [`create_function`].
+    async fn create_function(
+        &self,
+        ctx: &ExecutionContext,
+        memory_size: i64,
+        architecture: &str,
+    ) -> Result<String>;
+
+    /// Sets the lambda function's concurrency. See [`set_concurrency`].
+    async fn set_concurrency(&self, function_name: &str, concurrency: i64) -> Result<()>;
+
+    /// Invokes the lambda function with the given payload. See
+    /// [`invoke_function`].
+    async fn invoke_function(
+        &self,
+        function_name: &str,
+        invocation_type: &str,
+        payload: Option<Bytes>,
+    ) -> Result<InvocationResponse>;
+}
+
+/// The AWS Lambda service backed by `FLOCK_LAMBDA_CLIENT`.
+#[derive(Debug, Default, Clone)]
+pub struct AwsLambdaService;
+
+#[async_trait]
+impl LambdaService for AwsLambdaService {
+    async fn create_function(
+        &self,
+        ctx: &ExecutionContext,
+        memory_size: i64,
+        architecture: &str,
+    ) -> Result<String> {
+        create_function(ctx, memory_size, architecture).await
+    }
+
+    async fn set_concurrency(&self, function_name: &str, concurrency: i64) -> Result<()> {
+        set_concurrency(function_name, concurrency).await
+    }
+
+    async fn invoke_function(
+        &self,
+        function_name: &str,
+        invocation_type: &str,
+        payload: Option<Bytes>,
+    ) -> Result<InvocationResponse> {
+        invoke_function(function_name, invocation_type, payload).await
+    }
+}
 
 /// Sets the lambda function's concurrency.
 ///
@@ -60,6 +118,32 @@ pub async fn invoke_function(
     function_name: &str,
     invocation_type: &str,
     payload: Option<Bytes>,
+) -> Result<InvocationResponse> {
+    invoke_function_with_retry(
+        function_name,
+        invocation_type,
+        payload,
+        &RetryPolicy::default(),
+    )
+    .await
+}
+
+/// Invokes the lambda function with the given payload and retry policy.
+/// Only synchronous invocations are retried.
+///
+/// # Arguments
+/// * `function_name` - The name of the lambda function.
+/// * `invocation_type` - The invocation type of the lambda function.
+/// * `payload` - The payload to be passed to the lambda function.
+/// * `retry` - The retry policy of synchronous invocations.
+///
+/// # Returns
+/// The result of the invocation.
+pub async fn invoke_function_with_retry(
+    function_name: &str,
+    invocation_type: &str,
+    payload: Option<Bytes>,
+    retry: &RetryPolicy,
 ) -> Result<InvocationResponse> {
     let request = InvocationRequest {
         function_name: function_name.to_owned(),
@@ -75,55 +159,27 @@ pub async fn invoke_function(
             .map_err(|e| FlockError::AWS(e.to_string()))?;
         Ok(response)
     } else {
-        // Error retry uses linear random backoff algorithm
-        // min(50 * increase_factor + random_milliseconds, max_backoff)
-        let mut retries = 0;
-        let mut increase_factor = 0;
-        loop {
-            match FLOCK_LAMBDA_CLIENT
-                .invoke(request.clone())
-                .await
-                .map_err(|e| FlockError::AWS(e.to_string()))
-            {
-                Ok(response) => {
-                    if response.function_error.is_none() {
-                        return Ok(response);
-                    } else {
+        retry
+            .run(function_name, || async {
+                let response = FLOCK_LAMBDA_CLIENT
+                    .invoke(request.clone())
+                    .await
+                    .map_err(|e| FlockError::AWS(e.to_string()))?;
+                match response.function_error {
+                    None => Ok(response),
+                    Some(function_error) => {
                         info!(
                             "Function execution error: {}, details: {:?}",
-                            response.function_error.unwrap(),
-                            serde_json::from_slice::<serde_json::Value>(&response.payload.unwrap())
+                            function_error,
+                            serde_json::from_slice::<serde_json::Value>(
+                                &response.payload.unwrap_or_default()
+                            )
                         );
+                        Err(FlockError::AWS(function_error))
                     }
                 }
-                Err(e) => {
-                    debug!("Function invocation error: {}", e);
-                }
-            }
-
-            debug!(
-                "Retrying #{}: {} function invocation...",
-                retries, function_name
-            );
-            retries += 1;
-
-            increase_factor = std::cmp::min(increase_factor, 9) + 1;
-
-            let random_milliseconds = rand::thread_rng().gen_range(0..100);
-            let backoff = Duration::from_millis(std::cmp::min(
-                50 * increase_factor + random_milliseconds,
-                *FLOCK_LAMBDA_MAX_BACKOFF,
-            ));
-
-            tokio::time::sleep(backoff).await;
-
-            if retries as usize > *FLOCK_LAMBDA_MAX_RETRIES {
-                return Err(FlockError::AWS(format!(
-                    "Sync invocation failed after {} retries",
-                    *FLOCK_LAMBDA_MAX_RETRIES
-                )));
-            }
-        }
+            })
+            .await
     }
 }
 
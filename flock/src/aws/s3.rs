@@ -18,8 +18,9 @@ use crate::error::{FlockError, Result};
 use rayon::prelude::*;
 use rusoto_core::ByteStream;
 use rusoto_s3::{
-    CreateBucketRequest, Delete, DeleteBucketRequest, DeleteObjectsRequest, GetObjectRequest,
-    HeadBucketRequest, ListObjectsV2Request, ObjectIdentifier, PutObjectRequest, S3,
+    CreateBucketRequest, Delete, DeleteBucketRequest, DeleteObjectRequest, DeleteObjectsRequest,
+    GetObjectRequest, HeadBucketRequest, HeadObjectRequest, ListObjectsV2Request, ObjectIdentifier,
+    PutObjectRequest, S3,
 };
 use std::io::Read;
 
@@ -139,6 +140,30 @@ pub async fn get_object(bucket: &str, key: &str) -> Result<Vec<u8>> {
     .expect("failed to load object from S3"))
 }
 
+/// Gets the size and the ETag of an object from AWS S3 without reading its
+/// body.
+///
+/// # Arguments
+/// * `bucket` - The name of the bucket of the object.
+/// * `key` - The key of the object.
+///
+/// # Returns
+/// The size of the object in bytes and its ETag.
+pub async fn head_object(bucket: &str, key: &str) -> Result<(usize, String)> {
+    let output = FLOCK_S3_CLIENT
+        .head_object(HeadObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| FlockError::AWS(e.to_string()))?;
+    Ok((
+        output.content_length.unwrap_or_default() as usize,
+        output.e_tag.unwrap_or_default(),
+    ))
+}
+
 /// Checks if a bucket exists in AWS S3.
 ///
 /// # Arguments
@@ -296,6 +321,23 @@ pub async fn get_matched_keys(bucket: &str, prefix: &str) -> Result<Vec<String>>
     Ok(keys)
 }
 
+/// Deletes an object from AWS S3.
+///
+/// # Arguments
+/// * `bucket` - The name of the bucket to delete the object from.
+/// * `key` - The key of the object to delete.
+pub async fn delete_object(bucket: &str, key: &str) -> Result<()> {
+    FLOCK_S3_CLIENT
+        .delete_object(DeleteObjectRequest {
+            bucket: bucket.to_owned(),
+            key: key.to_owned(),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| FlockError::AWS(e.to_string()))
+        .map(|_| ())
+}
+
 /// Deletes all objects in a bucket.
 pub async fn delete_all_objects(bucket: &str) -> Result<()> {
     if bucket_exists(bucket).await? {
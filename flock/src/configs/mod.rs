@@ -41,8 +41,18 @@ lazy_static! {
     pub static ref FLOCK_LAMBDA_MAX_BACKOFF: u64 = FLOCK_CONF["lambda"]["max_backoff"].parse::<u64>().unwrap();
     /// AWS Lambda function timeout.
     pub static ref FLOCK_LAMBDA_TIMEOUT: i64 = FLOCK_CONF["lambda"]["timeout"].parse::<i64>().unwrap();
+    /// The maximum size of the encoded execution context in the environment variables.
+    pub static ref FLOCK_CONTEXT_SIZE_LIMIT: usize = FLOCK_CONF["lambda"]["context_size_limit"].parse::<usize>().unwrap();
     /// AWS Lambda function concurrency.
     pub static ref FLOCK_FUNCTION_CONCURRENCY: usize = FLOCK_CONF["lambda"]["concurrency"].parse::<usize>().unwrap();
+    /// The maximum number of partial aggregates that a function merges, so that its input stays under
+    /// the aggregate threshold.
+    pub static ref FLOCK_AGGREGATE_FAN_IN: usize = FLOCK_CONF["lambda"]["aggregate_threshold"].parse::<usize>().unwrap()
+        / FLOCK_CONF["lambda"]["payload_batch_size"].parse::<usize>().unwrap();
+    /// AWS Lambda function memory size for the regular functions.
+    pub static ref FLOCK_REGULAR_MEMORY_SIZE: i64 = FLOCK_CONF["lambda"]["regular_memory_size"].parse::<i64>().unwrap();
+    /// AWS Lambda function memory size for the aggregate functions in the function group.
+    pub static ref FLOCK_AGGREGATE_MEMORY_SIZE: i64 = FLOCK_CONF["lambda"]["realtime_aggreate_memory_size"].parse::<i64>().unwrap();
 
     /// Flock sync invocation granularity.
     pub static ref FLOCK_SYNC_GRANULE_SIZE: usize = FLOCK_CONF["lambda"]["sync_granule"].parse::<usize>().unwrap();
@@ -53,8 +63,14 @@ lazy_static! {
     pub static ref FLOCK_S3_X86_64_KEY: String = FLOCK_CONF["s3"]["x86_64_key"].to_string();
     /// Flock Arm_64 binary S3 key prefix.
     pub static ref FLOCK_S3_ARM_64_KEY: String = FLOCK_CONF["s3"]["arm_64_key"].to_string();
+    /// Flock execution plan S3 key prefix.
+    pub static ref FLOCK_S3_PLAN_KEY: String = FLOCK_CONF["s3"]["plan_key"].to_string();
     /// Flock S3 bucket name.
     pub static ref FLOCK_S3_BUCKET: String = FLOCK_CONF["s3"]["bucket"].to_string();
+    /// Flock object store backend.
+    pub static ref FLOCK_OBJECT_STORE_BACKEND: String = FLOCK_CONF["object_store"]["backend"].to_string();
+    /// Flock local object store root directory.
+    pub static ref FLOCK_OBJECT_STORE_LOCAL_ROOT: String = FLOCK_CONF["object_store"]["local_root"].to_string();
     /// Flock availablity zone.
     pub static ref FLOCK_AVAILABILITY_ZONE: String = FLOCK_CONF["aws"]["availability_zone"].to_string();
     /// Flock subnet id.
@@ -62,6 +78,24 @@ lazy_static! {
     /// Flock security group id.
     pub static ref FLOCK_SECURITY_GROUP_ID: String = FLOCK_CONF["aws"]["security_group_id"].to_string();
 
+    /// Flock arena window timeout in seconds.
+    pub static ref FLOCK_ARENA_WINDOW_TIMEOUT: u64 = FLOCK_CONF["arena"]["window_timeout"].parse::<u64>().unwrap();
+    /// Flock arena memory budget in bytes.
+    pub static ref FLOCK_ARENA_MEMORY_BUDGET: usize = FLOCK_CONF["arena"]["memory_budget"].parse::<usize>().unwrap();
+    /// Flock arena spill threshold in bytes.
+    pub static ref FLOCK_ARENA_SPILL_THRESHOLD: usize = FLOCK_CONF["arena"]["spill_threshold"].parse::<usize>().unwrap();
+    /// Flock arena expiry policy.
+    pub static ref FLOCK_ARENA_EXPIRY_POLICY: String = FLOCK_CONF["arena"]["expiry_policy"].to_string();
+    /// Flock arena dead-letter key prefix.
+    pub static ref FLOCK_ARENA_DEAD_LETTER_KEY: String = FLOCK_CONF["arena"]["dead_letter_key"].to_string();
+    /// Flock arena processed window retention in seconds.
+    pub static ref FLOCK_ARENA_PROCESSED_RETENTION: u64 = FLOCK_CONF["arena"]["processed_retention"].parse::<u64>().unwrap();
+
+    /// The maximum size in bytes of a side input that is broadcast to the functions that join it.
+    pub static ref FLOCK_SIDE_INPUT_BROADCAST_THRESHOLD: usize = FLOCK_CONF["side_input"]["broadcast_threshold"].parse::<usize>().unwrap();
+    /// The time in seconds after which a warm function revalidates a loaded side input.
+    pub static ref FLOCK_SIDE_INPUT_REFRESH_INTERVAL: u64 = FLOCK_CONF["side_input"]["refresh_interval"].parse::<u64>().unwrap();
+
     /// Flock EFS creation token.
     pub static ref FLOCK_EFS_CREATION_TOKEN: String = FLOCK_CONF["efs"]["creation_token"].to_string();
     /// Flock EFS Posix user ID.
@@ -14,10 +14,11 @@
 //! This module provides different data sinks for the Flock runtime to write
 //! data to.
 
-use crate::aws::s3;
 use crate::configs::*;
 use crate::encoding::Encoding;
 use crate::error::{FlockError, Result};
+use crate::objectstore::object_store;
+use crate::runtime::function::FunctionId;
 use crate::runtime::payload::DataFrame;
 use crate::transmute::*;
 use datafusion::arrow::csv;
@@ -115,6 +116,11 @@ pub struct DataSink {
     /// The last actor in the dag that wrote to the data sink.
     /// Client can use this to fetch the logs for AWS WatchLogs.
     pub function_name:  String,
+    /// The partition of the results if the last stage is a function group,
+    /// such as a sorted range of `ORDER BY`. The partitions are concatenated
+    /// in order when the results are read.
+    #[serde(default)]
+    pub partition:      Option<usize>,
 }
 
 impl DataSink {
@@ -155,23 +161,61 @@ impl DataSink {
     }
 
     /// Read the record batches from the data sink.
+    ///
+    /// The results of the SQS and S3 sinks are keyed by the query code, which
+    /// is parsed from the function name, see [`DataSink::read_by_query_code`].
     pub async fn read(
         function_name: String,
         sink_type: DataSinkType,
         sink_format: DataSinkFormat,
+    ) -> Result<DataSink> {
+        match sink_type {
+            DataSinkType::EFS => DataSink::read_from_efs(function_name, sink_format).await,
+            _ => {
+                let query_code = FunctionId::query_code_of(&function_name);
+                DataSink::read_by_query_code(query_code, sink_type, sink_format).await
+            }
+        }
+    }
+
+    /// Read the record batches of a query from the data sink.
+    ///
+    /// Unlike [`DataSink::read`], the query code is used as is, since a query
+    /// code such as `q-2` is also a valid function name.
+    pub async fn read_by_query_code(
+        query_code: String,
+        sink_type: DataSinkType,
+        sink_format: DataSinkFormat,
     ) -> Result<DataSink> {
         match sink_type {
             DataSinkType::Blackhole => Ok(DataSink {
-                function_name,
+                function_name: query_code,
                 ..Default::default()
             }),
-            DataSinkType::SQS => DataSink::read_from_sqs(function_name).await,
-            DataSinkType::S3 => DataSink::read_from_s3(function_name).await,
-            DataSinkType::EFS => DataSink::read_from_efs(function_name, sink_format).await,
+            DataSinkType::SQS => DataSink::read_from_sqs(query_code).await,
+            DataSinkType::S3 => DataSink::read_from_s3(query_code).await,
+            DataSinkType::EFS => DataSink::read_from_efs(query_code, sink_format).await,
             _ => unimplemented!(),
         }
     }
 
+    /// Remove the results of the previous executions of a query from the data
+    /// sink. The query code is derived from the query, so the partitions of a
+    /// re-run would otherwise be read together with the stale ones.
+    pub async fn clear_by_query_code(query_code: &str, sink_type: DataSinkType) -> Result<()> {
+        if sink_type == DataSinkType::S3 {
+            let store = object_store();
+            for key in store
+                .list(&FLOCK_S3_BUCKET, &format!("{}/", query_code))
+                .await?
+            {
+                store.delete(&FLOCK_S3_BUCKET, &key).await?;
+            }
+            store.delete(&FLOCK_S3_BUCKET, query_code).await?;
+        }
+        Ok(())
+    }
+
     /// This is an internal function that is used to decode `self.encoded_data`
     /// to `self.record_batches` for future use.
     fn decode_record_batches(&mut self) -> Result<()> {
@@ -234,7 +278,7 @@ impl DataSink {
         // A queue name can have up to 80 characters.
         // Valid values: alphanumeric characters, hyphens (-), and underscores (_).
         // A FIFO queue name must end with the .fifo suffix.
-        let queue_name = self.function_name.split('-').next().unwrap();
+        let queue_name = FunctionId::query_code_of(&self.function_name);
 
         let mut attrs = HashMap::new();
         // The length of time, in seconds, for which Amazon SQS retains a message.
@@ -272,8 +316,14 @@ impl DataSink {
     async fn write_to_s3(&mut self) -> Result<()> {
         self.encode_record_batches();
 
-        let s3_key = self.function_name.split('-').next().unwrap();
-        s3::put_object(&FLOCK_S3_BUCKET, s3_key, serde_json::to_vec(&self)?).await?;
+        let query_code = FunctionId::query_code_of(&self.function_name);
+        let s3_key = match self.partition {
+            Some(partition) => format!("{}/{}", query_code, partition),
+            None => query_code,
+        };
+        object_store()
+            .put(&FLOCK_S3_BUCKET, &s3_key, serde_json::to_vec(&self)?)
+            .await?;
 
         Ok(())
     }
@@ -315,8 +365,7 @@ impl DataSink {
         Ok(())
     }
 
-    async fn read_from_sqs(function_name: String) -> Result<DataSink> {
-        let queue_name = function_name.split('-').next().unwrap();
+    async fn read_from_sqs(queue_name: String) -> Result<DataSink> {
         let queue_url = FLOCK_SQS_CLIENT
             .get_queue_url(GetQueueUrlRequest {
                 queue_name: format!("{}.fifo", queue_name),
@@ -349,13 +398,40 @@ impl DataSink {
         Ok(data)
     }
 
-    async fn read_from_s3(function_name: String) -> Result<DataSink> {
-        let s3_key = function_name.split('-').next().unwrap();
-        let body = s3::get_object(&FLOCK_S3_BUCKET, s3_key).await?;
-        let mut data: DataSink = serde_json::from_slice(&body)?;
-        data.decode_record_batches()?;
+    async fn read_from_s3(s3_key: String) -> Result<DataSink> {
+        let store = object_store();
+        let partitions = store
+            .list(&FLOCK_S3_BUCKET, &format!("{}/", s3_key))
+            .await?;
+        if partitions.is_empty() {
+            let body = store.get(&FLOCK_S3_BUCKET, &s3_key).await?;
+            let mut data: DataSink = serde_json::from_slice(&body)?;
+            data.decode_record_batches()?;
+            return Ok(data);
+        }
 
-        Ok(data)
+        let mut sinks = vec![];
+        for key in partitions {
+            let body = store.get(&FLOCK_S3_BUCKET, &key).await?;
+            let mut data: DataSink = serde_json::from_slice(&body)?;
+            data.decode_record_batches()?;
+            sinks.push(data);
+        }
+        Ok(DataSink::concat(sinks))
+    }
+
+    /// Concatenates the partitions of the results in order.
+    pub fn concat(mut sinks: Vec<DataSink>) -> DataSink {
+        sinks.sort_by_key(|s| s.partition);
+        let function_name = sinks
+            .first()
+            .map(|s| s.function_name.clone())
+            .unwrap_or_default();
+        DataSink {
+            record_batches: sinks.into_iter().flat_map(|s| s.record_batches).collect(),
+            function_name,
+            ..Default::default()
+        }
     }
 
     async fn read_from_efs(function_name: String, sink_format: DataSinkFormat) -> Result<DataSink> {
@@ -414,3 +490,124 @@ impl DataSink {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_batches_eq;
+    use crate::test_util::init_in_memory_object_store;
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::datatypes::{DataType, Field};
+
+    #[tokio::test]
+    async fn s3_data_sink() -> Result<()> {
+        init_in_memory_object_store();
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![1, 2, 3]))])?;
+
+        let function_name = "datasinktest-0".to_string();
+        DataSink::new(function_name.clone(), vec![batch], Encoding::default())
+            .write(DataSinkType::S3, DataSinkFormat::SerdeBinary)
+            .await?;
+
+        let sink =
+            DataSink::read(function_name, DataSinkType::S3, DataSinkFormat::default()).await?;
+
+        let expected = vec!["+---+", "| a |", "+---+", "| 1 |", "| 2 |", "| 3 |", "+---+"];
+        assert_batches_eq!(&expected, &sink.record_batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_data_sink_by_query_code() -> Result<()> {
+        init_in_memory_object_store();
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![1, 2, 3]))])?;
+
+        // The query code looks like a function name of the query `datasink`.
+        let query_code = "datasink-2".to_string();
+        let function_name = FunctionId::try_new(&query_code, 0)?.to_cloud_name();
+        DataSink::new(function_name, vec![batch], Encoding::default())
+            .write(DataSinkType::S3, DataSinkFormat::SerdeBinary)
+            .await?;
+
+        assert!(DataSink::read(
+            query_code.clone(),
+            DataSinkType::S3,
+            DataSinkFormat::default()
+        )
+        .await
+        .is_err());
+
+        let sink =
+            DataSink::read_by_query_code(query_code, DataSinkType::S3, DataSinkFormat::default())
+                .await?;
+
+        let expected = vec!["+---+", "| a |", "+---+", "| 1 |", "| 2 |", "| 3 |", "+---+"];
+        assert_batches_eq!(&expected, &sink.record_batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_partitioned_data_sink() -> Result<()> {
+        init_in_memory_object_store();
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let ranges = vec![vec![1, 2], vec![3], vec![4, 5]];
+
+        // The sorted ranges are written by different functions in any order.
+        let group = FunctionId::try_new("datasink-ranges", 3)?;
+        for (i, range) in ranges.into_iter().enumerate().rev() {
+            let batch =
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(range))])?;
+            let mut sink = DataSink::new(
+                group.member(i).to_cloud_name(),
+                vec![batch],
+                Encoding::default(),
+            );
+            sink.partition = Some(i);
+            sink.write(DataSinkType::S3, DataSinkFormat::SerdeBinary)
+                .await?;
+        }
+
+        let sink = DataSink::read(
+            group.to_cloud_name(),
+            DataSinkType::S3,
+            DataSinkFormat::default(),
+        )
+        .await?;
+
+        let expected = vec![
+            "+---+", "| a |", "+---+", "| 1 |", "| 2 |", "| 3 |", "| 4 |", "| 5 |", "+---+",
+        ];
+        assert_batches_eq!(&expected, &sink.record_batches);
+
+        // A re-run of the query doesn't read the partitions of the previous run.
+        DataSink::clear_by_query_code(&group.query_code, DataSinkType::S3).await?;
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(Int32Array::from(vec![6]))])?;
+        let mut sink = DataSink::new(
+            group.member(1).to_cloud_name(),
+            vec![batch],
+            Encoding::default(),
+        );
+        sink.partition = Some(1);
+        sink.write(DataSinkType::S3, DataSinkFormat::SerdeBinary)
+            .await?;
+
+        let sink = DataSink::read(
+            group.to_cloud_name(),
+            DataSinkType::S3,
+            DataSinkFormat::default(),
+        )
+        .await?;
+
+        let expected = vec!["+---+", "| a |", "+---+", "| 6 |", "+---+"];
+        assert_batches_eq!(&expected, &sink.record_batches);
+
+        Ok(())
+    }
+}
@@ -121,6 +121,18 @@ impl DataSource {
     pub fn kinesis() -> Self {
         DataSource::KinesisEvent(KinesisSource::default())
     }
+
+    /// Returns the number of data fragments that the data source sends per
+    /// window, i.e., one fragment per generator of the benchmarks.
+    pub fn fragments(&self) -> usize {
+        match self {
+            DataSource::NEXMarkEvent(source) | DataSource::S3(source) => {
+                source.config.get_as_or("threads", 1)
+            }
+            DataSource::YSBEvent(source) => source.config.get_as_or("threads", 1),
+            _ => 1,
+        }
+    }
 }
 
 pub mod config;
@@ -128,5 +140,6 @@ pub mod epoch;
 pub mod kafka;
 pub mod kinesis;
 pub mod nexmark;
+pub mod side_input;
 pub mod tpch;
 pub mod ysb;
@@ -0,0 +1,509 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! A side input is a table in the object store that is joined with the data
+//! streams, such as a lookup table. Unlike the data streams, it isn't sent by
+//! the data source, but read by the functions themselves.
+//!
+//! A side input is loaded once per function execution environment, and kept
+//! in memory across invocations. After the refresh interval, the next
+//! invocation revalidates it by its ETag, so that the table can change while
+//! the query is running.
+
+use crate::configs::{FLOCK_S3_BUCKET, FLOCK_SIDE_INPUT_REFRESH_INTERVAL};
+use crate::error::{FlockError, Result};
+use crate::objectstore::object_store;
+use datafusion::arrow::csv;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::error::Result as ArrowResult;
+use datafusion::arrow::ipc::reader::{FileReader, StreamReader};
+use datafusion::arrow::json;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use datafusion::parquet::file::serialized_reader::SerializedFileReader;
+use datafusion::parquet::util::cursor::SliceableCursor;
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::ExecutionPlan;
+use lazy_static::lazy_static;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// The number of rows in each record batch of a side input.
+const SIDE_INPUT_BATCH_SIZE: usize = 1024;
+
+/// The magic bytes at the beginning of the Arrow IPC file format.
+const ARROW_FILE_MAGIC: &[u8] = b"ARROW1";
+
+/// The key of the field metadata that marks each column of a side input with
+/// the name of its table.
+pub const SIDE_INPUT_TABLE: &str = "flock_side_input";
+
+lazy_static! {
+    /// The side inputs loaded by the current process, keyed by their bucket
+    /// and key.
+    static ref LOADED_SIDE_INPUTS: RwLock<HashMap<(String, String), LoadedSideInput>> =
+        RwLock::new(HashMap::new());
+}
+
+/// A side input in the memory of the current process.
+#[derive(Debug, Clone)]
+struct LoadedSideInput {
+    /// The ETag of the object that the record batches are decoded from.
+    etag:         String,
+    /// The last time that the ETag was checked against the object store.
+    validated_at: Instant,
+    /// The content of the side input.
+    batches:      Vec<RecordBatch>,
+}
+
+/// The file format of a side input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SideInputFormat {
+    /// Comma-separated values with a header line.
+    Csv,
+    /// Line-delimited JSON objects.
+    Json,
+    /// Apache Parquet.
+    Parquet,
+    /// Arrow IPC, either in the file format or in the streaming format.
+    ArrowIpc,
+}
+
+impl FromStr for SideInputFormat {
+    type Err = FlockError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(SideInputFormat::Csv),
+            "json" => Ok(SideInputFormat::Json),
+            "parquet" => Ok(SideInputFormat::Parquet),
+            "arrow" | "ipc" => Ok(SideInputFormat::ArrowIpc),
+            _ => Err(FlockError::NotImplemented(format!(
+                "Unsupported side input format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// A table in the object store that is joined with the data streams.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SideInput {
+    /// The name of the table in the query.
+    pub table:            String,
+    /// The bucket of the object.
+    pub bucket:           String,
+    /// The key of the object.
+    pub key:              String,
+    /// The file format of the object.
+    pub format:           SideInputFormat,
+    /// The schema of the table.
+    pub schema:           Schema,
+    /// The time in seconds after which a loaded side input is revalidated.
+    pub refresh_interval: u64,
+}
+
+impl SideInput {
+    /// Creates a new side input in the Flock bucket, which is revalidated
+    /// every `FLOCK_SIDE_INPUT_REFRESH_INTERVAL` seconds.
+    ///
+    /// # Arguments
+    /// * `table` - The name of the table in the query.
+    /// * `key` - The key of the object in the Flock bucket.
+    /// * `format` - The file format of the object.
+    /// * `schema` - The schema of the table.
+    pub fn new<T, K>(table: T, key: K, format: SideInputFormat, schema: Schema) -> Self
+    where
+        T: Into<String>,
+        K: Into<String>,
+    {
+        Self {
+            table: table.into(),
+            bucket: FLOCK_S3_BUCKET.clone(),
+            key: key.into(),
+            format,
+            schema,
+            refresh_interval: *FLOCK_SIDE_INPUT_REFRESH_INTERVAL,
+        }
+    }
+
+    /// Reads the side input from another bucket.
+    pub fn with_bucket<T: Into<String>>(mut self, bucket: T) -> Self {
+        self.bucket = bucket.into();
+        self
+    }
+
+    /// Sets the time after which a loaded side input is revalidated. With a
+    /// zero interval, the side input is revalidated on every load.
+    pub fn with_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval.as_secs();
+        self
+    }
+
+    /// Returns the schema of the table. Each column is marked with the name of
+    /// the table, so that the planner tells the scans of the side input apart
+    /// from the scans of the data streams, see [`Self::is_scanned_by`].
+    pub fn schema(&self) -> SchemaRef {
+        let fields = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| {
+                let mut metadata = field.metadata().clone().unwrap_or_default();
+                metadata.insert(SIDE_INPUT_TABLE.to_owned(), self.table.clone());
+                let mut field = field.clone();
+                field.set_metadata(Some(metadata));
+                field
+            })
+            .collect();
+        Arc::new(Schema::new_with_metadata(
+            fields,
+            self.schema.metadata().clone(),
+        ))
+    }
+
+    /// Returns the size of the object in bytes.
+    pub async fn size(&self) -> Result<usize> {
+        Ok(object_store().head(&self.bucket, &self.key).await?.size)
+    }
+
+    /// Returns the content of the side input.
+    ///
+    /// The side input is read from the object store on the first load. Later
+    /// loads return the record batches in memory, unless the refresh interval
+    /// has passed since they were validated. In that case, the ETag of the
+    /// object is checked, and the object is read again only if it has
+    /// changed.
+    pub async fn load(&self) -> Result<Vec<RecordBatch>> {
+        let location = (self.bucket.clone(), self.key.clone());
+        let loaded = LOADED_SIDE_INPUTS.read().unwrap().get(&location).cloned();
+        if let Some(loaded) = &loaded {
+            if loaded.validated_at.elapsed() < Duration::from_secs(self.refresh_interval) {
+                return Ok(loaded.batches.clone());
+            }
+        }
+
+        let etag = object_store().head(&self.bucket, &self.key).await?.etag;
+        let batches = match loaded {
+            Some(loaded) if loaded.etag == etag => loaded.batches,
+            _ => {
+                info!(
+                    "Loading the side input {} from {}/{}.",
+                    self.table, self.bucket, self.key
+                );
+                // If the object is overwritten in between, the new body is kept
+                // with the old ETag, and read again on the next revalidation.
+                self.decode(object_store().get(&self.bucket, &self.key).await?)?
+            }
+        };
+
+        LOADED_SIDE_INPUTS.write().unwrap().insert(
+            location,
+            LoadedSideInput {
+                etag,
+                validated_at: Instant::now(),
+                batches: batches.clone(),
+            },
+        );
+        Ok(batches)
+    }
+
+    /// Decodes the body of the object into record batches of the table.
+    ///
+    /// # Arguments
+    /// * `body` - The body of the object in the format of the side input.
+    pub fn decode(&self, body: Vec<u8>) -> Result<Vec<RecordBatch>> {
+        let schema = self.schema();
+        let batches = match self.format {
+            SideInputFormat::Csv => csv::ReaderBuilder::new()
+                .with_schema(schema.clone())
+                .has_header(true)
+                .with_batch_size(SIDE_INPUT_BATCH_SIZE)
+                .build(Cursor::new(body))?
+                .collect::<ArrowResult<Vec<_>>>()?,
+            SideInputFormat::Json => {
+                let mut reader = json::Reader::new(
+                    Cursor::new(body),
+                    schema.clone(),
+                    SIDE_INPUT_BATCH_SIZE,
+                    None,
+                );
+                let mut batches = vec![];
+                while let Some(batch) = reader.next()? {
+                    batches.push(batch);
+                }
+                batches
+            }
+            SideInputFormat::Parquet => {
+                let reader = SerializedFileReader::new(SliceableCursor::new(body))?;
+                ParquetFileArrowReader::new(Arc::new(reader))
+                    .get_record_reader(SIDE_INPUT_BATCH_SIZE)?
+                    .collect::<ArrowResult<Vec<_>>>()?
+            }
+            SideInputFormat::ArrowIpc => {
+                if body.starts_with(ARROW_FILE_MAGIC) {
+                    FileReader::try_new(Cursor::new(body))?.collect::<ArrowResult<Vec<_>>>()?
+                } else {
+                    StreamReader::try_new(Cursor::new(body))?.collect::<ArrowResult<Vec<_>>>()?
+                }
+            }
+        };
+
+        // Parquet and Arrow IPC carry their own schemas, which must have the
+        // same columns as the table.
+        batches
+            .into_iter()
+            .map(|batch| {
+                if batch.num_columns() != schema.fields().len()
+                    || batch
+                        .schema()
+                        .fields()
+                        .iter()
+                        .zip(schema.fields())
+                        .any(|(a, b)| a.name() != b.name())
+                {
+                    return Err(FlockError::Execution(format!(
+                        "The side input {} doesn't match the schema of the table.",
+                        self.table
+                    )));
+                }
+                Ok(RecordBatch::try_new(
+                    schema.clone(),
+                    batch.columns().to_vec(),
+                )?)
+            })
+            .collect()
+    }
+
+    /// Returns true if the plan scans the table of the side input, i.e., it is
+    /// a `MemoryExec` whose columns are all marked with the name of the table.
+    pub fn is_scanned_by(&self, plan: &Arc<dyn ExecutionPlan>) -> bool {
+        if !plan.as_any().is::<MemoryExec>() {
+            return false;
+        }
+        let schema = plan.schema();
+        !schema.fields().is_empty()
+            && schema.fields().iter().all(|f| {
+                f.metadata()
+                    .as_ref()
+                    .and_then(|metadata| metadata.get(SIDE_INPUT_TABLE))
+                    .map_or(false, |table| *table == self.table)
+            })
+    }
+}
+
+/// Returns the schema without the side input marks on its columns.
+///
+/// # Arguments
+/// * `schema` - The schema of a plan that may scan a side input.
+pub fn unmark_side_inputs(schema: &Schema) -> Schema {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let mut field = field.clone();
+            let metadata = field
+                .metadata()
+                .clone()
+                .map(|mut metadata| {
+                    metadata.remove(SIDE_INPUT_TABLE);
+                    metadata
+                })
+                .filter(|metadata| !metadata.is_empty());
+            field.set_metadata(metadata);
+            field
+        })
+        .collect();
+    Schema::new_with_metadata(fields, schema.metadata().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_batches_eq;
+    use crate::test_util::init_in_memory_object_store;
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::datatypes::{DataType, Field};
+    use datafusion::arrow::ipc::writer::{FileWriter, StreamWriter};
+    use datafusion::parquet::arrow::ArrowWriter;
+    use datafusion::parquet::file::writer::InMemoryWriteableCursor;
+
+    fn lookup_table(values: Vec<i32>) -> Result<(SchemaRef, RecordBatch)> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Int32, false),
+            Field::new("value", DataType::Int32, false),
+        ]));
+        let keys = (0..values.len() as i32).collect::<Vec<_>>();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(keys)),
+                Arc::new(Int32Array::from(values)),
+            ],
+        )?;
+        Ok((schema, batch))
+    }
+
+    fn num_rows(batches: &[RecordBatch]) -> usize {
+        batches.iter().map(|b| b.num_rows()).sum()
+    }
+
+    fn to_csv(batch: &RecordBatch) -> Result<Vec<u8>> {
+        let mut body = vec![];
+        csv::Writer::new(&mut body).write(batch)?;
+        Ok(body)
+    }
+
+    #[tokio::test]
+    async fn side_input_formats() -> Result<()> {
+        init_in_memory_object_store();
+        let (schema, batch) = lookup_table(vec![10, 20, 30])?;
+
+        let mut json_lines = vec![];
+        {
+            let mut writer = json::LineDelimitedWriter::new(&mut json_lines);
+            writer.write_batches(&[batch.clone()])?;
+            writer.finish()?;
+        }
+
+        let parquet = InMemoryWriteableCursor::default();
+        let mut writer = ArrowWriter::try_new(parquet.clone(), schema.clone(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        let mut ipc_file = vec![];
+        {
+            let mut writer = FileWriter::try_new(&mut ipc_file, &schema)?;
+            writer.write(&batch)?;
+            writer.finish()?;
+        }
+        let mut ipc_stream = vec![];
+        {
+            let mut writer = StreamWriter::try_new(&mut ipc_stream, &schema)?;
+            writer.write(&batch)?;
+            writer.finish()?;
+        }
+
+        let expected = vec![
+            "+-----+-------+",
+            "| key | value |",
+            "+-----+-------+",
+            "| 0   | 10    |",
+            "| 1   | 20    |",
+            "| 2   | 30    |",
+            "+-----+-------+",
+        ];
+
+        for (name, format, body) in [
+            ("csv", SideInputFormat::Csv, to_csv(&batch)?),
+            ("json", SideInputFormat::Json, json_lines),
+            ("parquet", SideInputFormat::Parquet, parquet.data()),
+            ("arrow", SideInputFormat::ArrowIpc, ipc_file),
+            ("arrows", SideInputFormat::ArrowIpc, ipc_stream),
+        ] {
+            let key = format!("side_input_formats/lookup.{}", name);
+            object_store().put(&FLOCK_S3_BUCKET, &key, body).await?;
+            let side_input = SideInput::new("lookup", key, format, schema.as_ref().clone());
+            assert_batches_eq!(&expected, &side_input.load().await?);
+        }
+
+        // The columns of the object must match the table.
+        let other = Schema::new(vec![
+            Field::new("k", DataType::Int32, false),
+            Field::new("value", DataType::Int32, false),
+        ]);
+        let side_input = SideInput::new("lookup", "", SideInputFormat::ArrowIpc, other);
+        let body = object_store()
+            .get(&FLOCK_S3_BUCKET, "side_input_formats/lookup.arrow")
+            .await?;
+        assert!(side_input.decode(body).is_err());
+
+        assert_eq!("ipc".parse::<SideInputFormat>()?, SideInputFormat::ArrowIpc);
+        assert!("avro".parse::<SideInputFormat>().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn side_input_refresh() -> Result<()> {
+        init_in_memory_object_store();
+        let key = "side_input_refresh/lookup.csv";
+        let (schema, batch) = lookup_table(vec![10])?;
+        object_store()
+            .put(&FLOCK_S3_BUCKET, key, to_csv(&batch)?)
+            .await?;
+
+        let cached = SideInput::new("lookup", key, SideInputFormat::Csv, schema.as_ref().clone())
+            .with_refresh_interval(Duration::from_secs(3600));
+        let fresh = cached.clone().with_refresh_interval(Duration::from_secs(0));
+        assert_eq!(num_rows(&cached.load().await?), 1);
+        assert_eq!(cached.size().await?, to_csv(&batch)?.len());
+
+        // The loaded side input isn't revalidated before the refresh interval.
+        let (_, new_batch) = lookup_table(vec![10, 20])?;
+        object_store()
+            .put(&FLOCK_S3_BUCKET, key, to_csv(&new_batch)?)
+            .await?;
+        assert_eq!(num_rows(&cached.load().await?), 1);
+
+        // The changed object is read again once the ETag is checked.
+        assert_eq!(num_rows(&fresh.load().await?), 2);
+        assert_eq!(num_rows(&cached.load().await?), 2);
+        assert_eq!(num_rows(&fresh.load().await?), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn side_input_scans() -> Result<()> {
+        let (schema, _) = lookup_table(vec![])?;
+        let side_input = SideInput::new(
+            "lookup",
+            "lookup.csv",
+            SideInputFormat::Csv,
+            schema.as_ref().clone(),
+        );
+        let scan =
+            |schema: SchemaRef, projection: Option<Vec<usize>>| -> Result<Arc<dyn ExecutionPlan>> {
+                Ok(Arc::new(MemoryExec::try_new(&[], schema, projection)?))
+            };
+
+        // The projected scans of the side input are marked with its table.
+        assert!(side_input.is_scanned_by(&scan(side_input.schema(), None)?));
+        assert!(side_input.is_scanned_by(&scan(side_input.schema(), Some(vec![0]))?));
+
+        // A stream or another side input with the same columns isn't the side input.
+        let other = SideInput::new(
+            "other",
+            "other.csv",
+            SideInputFormat::Csv,
+            schema.as_ref().clone(),
+        );
+        let stream = Arc::new(Schema::new(vec![Field::new("key", DataType::Int32, false)]));
+        assert!(!side_input.is_scanned_by(&scan(schema.clone(), None)?));
+        assert!(!side_input.is_scanned_by(&scan(stream, None)?));
+        assert!(!side_input.is_scanned_by(&scan(other.schema(), None)?));
+
+        // The output of a stage isn't the side input either.
+        let output = Arc::new(unmark_side_inputs(&side_input.schema()));
+        assert_eq!(output, schema);
+        assert!(!side_input.is_scanned_by(&scan(output, None)?));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! A partitioned hash join shuffles both of its inputs by the join keys, so
+//! it needs a stage of its own. If the build side is a small side input, every
+//! function can read the whole side input instead, and the join runs in the
+//! same stage as its probe side.
+//!
+//! `BroadcastJoinExec` is a hash join that collects the side input on the
+//! build side, and streams the probe side without shuffling it.
+
+use crate::datasource::side_input::SideInput;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::Result;
+use datafusion::logical_plan::JoinType;
+use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::expressions::Column;
+use datafusion::physical_plan::hash_join::{HashJoinExec, PartitionMode};
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, LambdaExecPlan, Partitioning, PhysicalExpr,
+    SendableRecordBatchStream, Statistics,
+};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::sync::Arc;
+
+/// `BroadcastJoinExec` joins its probe side with a side input, which every
+/// function reads as a whole.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastJoinExec {
+    /// The hash join in the `CollectLeft` mode, whose build side scans the
+    /// side input.
+    join:       Arc<dyn ExecutionPlan>,
+    /// The side input on the build side.
+    side_input: SideInput,
+}
+
+impl BroadcastJoinExec {
+    /// Returns the side input on the build side.
+    pub fn side_input(&self) -> &SideInput {
+        &self.side_input
+    }
+}
+
+#[async_trait]
+impl LambdaExecPlan for BroadcastJoinExec {
+    fn feed_batches(&mut self, _partitions: Vec<Vec<RecordBatch>>) {
+        unimplemented!();
+    }
+}
+
+#[async_trait]
+#[typetag::serde(name = "broadcast_join_exec")]
+impl ExecutionPlan for BroadcastJoinExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_mut_any(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.join.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.join.output_partitioning()
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        self.join.children()
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(BroadcastJoinExec {
+            join:       self.join.with_new_children(children)?,
+            side_input: self.side_input.clone(),
+        }))
+    }
+
+    async fn execute(&self, partition: usize) -> Result<SendableRecordBatchStream> {
+        // The hash join keeps the collected build side for the later
+        // executions, so a fresh join is built for each execution. Otherwise,
+        // a warm function would never see the reloaded side input.
+        self.join
+            .with_new_children(self.join.children())?
+            .execute(partition)
+            .await
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                write!(
+                    f,
+                    "BroadcastJoinExec: side_input={}, ",
+                    self.side_input.table
+                )?;
+                self.join.fmt_as(t, f)
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.join.statistics()
+    }
+}
+
+/// Rewrites the partitioned hash joins of the plan whose build side scans one
+/// of the side inputs into broadcast joins.
+///
+/// Since the build side is collected in every function, only inner joins and
+/// the outer joins that keep the unmatched rows of the probe side are
+/// broadcast. If the side input is on the right of the join, the inputs are
+/// swapped, and the columns are projected back into their original order.
+///
+/// # Arguments
+/// * `plan` - The query plan.
+/// * `side_inputs` - The side inputs that are small enough to be broadcast.
+pub fn broadcast_joins(
+    plan: Arc<dyn ExecutionPlan>,
+    side_inputs: &[SideInput],
+) -> Result<Arc<dyn ExecutionPlan>> {
+    if side_inputs.is_empty() || plan.children().is_empty() {
+        return Ok(plan);
+    }
+
+    let children = plan
+        .children()
+        .into_iter()
+        .map(|child| broadcast_joins(child, side_inputs))
+        .collect::<Result<Vec<_>>>()?;
+    let plan = plan.with_new_children(children)?;
+
+    if let Some(join) = plan.as_any().downcast_ref::<HashJoinExec>() {
+        if let Some(broadcast) = broadcast_join(join, side_inputs)? {
+            return Ok(broadcast);
+        }
+    }
+    Ok(plan)
+}
+
+/// Returns the broadcast join for the hash join, or none if neither input of
+/// the join can be broadcast.
+fn broadcast_join(
+    join: &HashJoinExec,
+    side_inputs: &[SideInput],
+) -> Result<Option<Arc<dyn ExecutionPlan>>> {
+    if !matches!(join.partition_mode(), PartitionMode::Partitioned) {
+        return Ok(None);
+    }
+
+    let left = without_exchange(join.left());
+    if let Some(side_input) = side_inputs.iter().find(|s| s.is_scanned_by(&left)) {
+        if matches!(join.join_type(), JoinType::Inner | JoinType::Right) {
+            let hash_join = HashJoinExec::try_new(
+                left,
+                without_shuffle(join.right()),
+                join.on().to_vec(),
+                join.join_type(),
+                PartitionMode::CollectLeft,
+                join.null_equals_null(),
+            )?;
+            return Ok(Some(Arc::new(BroadcastJoinExec {
+                join:       Arc::new(hash_join),
+                side_input: side_input.clone(),
+            })));
+        }
+    }
+
+    let right = without_exchange(join.right());
+    if let Some(side_input) = side_inputs.iter().find(|s| s.is_scanned_by(&right)) {
+        let join_type = match join.join_type() {
+            JoinType::Inner => JoinType::Inner,
+            JoinType::Left => JoinType::Right,
+            _ => return Ok(None),
+        };
+        let probe = without_shuffle(join.left());
+        let (probe_columns, build_columns) =
+            (probe.schema().fields().len(), right.schema().fields().len());
+        let hash_join = HashJoinExec::try_new(
+            right,
+            probe,
+            join.on()
+                .iter()
+                .map(|(l, r)| (r.clone(), l.clone()))
+                .collect(),
+            &join_type,
+            PartitionMode::CollectLeft,
+            join.null_equals_null(),
+        )?;
+        let broadcast = Arc::new(BroadcastJoinExec {
+            join:       Arc::new(hash_join),
+            side_input: side_input.clone(),
+        });
+
+        // The columns of the build side come first in the swapped join.
+        let expr = join
+            .schema()
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let index = if i < probe_columns {
+                    build_columns + i
+                } else {
+                    i - probe_columns
+                };
+                (
+                    Arc::new(Column::new(field.name(), index)) as Arc<dyn PhysicalExpr>,
+                    field.name().to_owned(),
+                )
+            })
+            .collect();
+        return Ok(Some(Arc::new(ProjectionExec::try_new(expr, broadcast)?)));
+    }
+
+    Ok(None)
+}
+
+/// Returns the input of the join without the operators that only
+/// redistribute its records, which are useless on the build side of a
+/// broadcast join.
+fn without_exchange(plan: &Arc<dyn ExecutionPlan>) -> Arc<dyn ExecutionPlan> {
+    let any = plan.as_any();
+    if any.is::<RepartitionExec>()
+        || any.is::<CoalesceBatchesExec>()
+        || any.is::<CoalescePartitionsExec>()
+    {
+        without_exchange(&plan.children()[0])
+    } else {
+        plan.clone()
+    }
+}
+
+/// Returns the input of the join without the hash repartition that shuffles
+/// it by the join keys.
+fn without_shuffle(plan: &Arc<dyn ExecutionPlan>) -> Arc<dyn ExecutionPlan> {
+    if let Some(repartition) = plan.as_any().downcast_ref::<RepartitionExec>() {
+        if matches!(repartition.partitioning(), Partitioning::Hash(_, _)) {
+            return repartition.input().clone();
+        }
+    } else if plan.as_any().is::<CoalesceBatchesExec>() {
+        let input = without_shuffle(&plan.children()[0]);
+        if !Arc::ptr_eq(&input, &plan.children()[0]) {
+            return input;
+        }
+    }
+    plan.clone()
+}
+
+/// Returns the side inputs that the functions of a query stage read.
+///
+/// The side inputs on the build side of the broadcast joins are read by the
+/// stage of the joins. The other side inputs are only read by the leaf stages
+/// that scan them, along with the data streams.
+///
+/// # Arguments
+/// * `stage` - The subplans of the query stage.
+/// * `is_leaf` - Whether the stage reads from the data source.
+/// * `side_inputs` - The side inputs of the query.
+pub fn stage_side_inputs(
+    stage: &[Arc<dyn ExecutionPlan>],
+    is_leaf: bool,
+    side_inputs: &[SideInput],
+) -> Vec<SideInput> {
+    fn visit(
+        plan: &Arc<dyn ExecutionPlan>,
+        is_leaf: bool,
+        side_inputs: &[SideInput],
+        found: &mut Vec<SideInput>,
+    ) {
+        let scanned = match plan.as_any().downcast_ref::<BroadcastJoinExec>() {
+            Some(broadcast) => vec![broadcast.side_input().clone()],
+            None if is_leaf && plan.children().is_empty() => side_inputs
+                .iter()
+                .filter(|s| s.is_scanned_by(plan))
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        for side_input in scanned {
+            if !found.contains(&side_input) {
+                found.push(side_input);
+            }
+        }
+        plan.children()
+            .iter()
+            .for_each(|child| visit(child, is_leaf, side_inputs, found));
+    }
+
+    let mut found = vec![];
+    stage
+        .iter()
+        .for_each(|plan| visit(plan, is_leaf, side_inputs, &mut found));
+    found
+}
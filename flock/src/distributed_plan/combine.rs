@@ -0,0 +1,411 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! A `Final` aggregate merges the partial aggregates of all the data fragments
+//! of a window in a single cloud function. With many fragments per window, the
+//! function becomes the bottleneck, and its input can exceed the payload
+//! limits.
+//!
+//! `CombineAggregateExec` merges some of the partial aggregates into a new
+//! partial aggregate, rather than the final results. The combiners form a tree
+//! in front of the `Final` aggregate: each level of the tree merges at most
+//! `fan_in` partial aggregates per function, and forwards the merged partial
+//! aggregates to the next level.
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{Array, ArrayRef, UInt32Array};
+use datafusion::arrow::compute::{concat, take};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::physical_plan::hash_aggregate::HashAggregateExec;
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::{
+    collect, AggregateExpr, DisplayFormatType, ExecutionPlan, LambdaExecPlan, Partitioning,
+    SendableRecordBatchStream, Statistics,
+};
+use datafusion::scalar::ScalarValue;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// `CombineAggregateExec` merges the partial aggregates of its input into
+/// partial aggregates with the same schema, one row per group.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CombineAggregateExec {
+    /// The input plan, which outputs partial aggregates.
+    input:     Arc<dyn ExecutionPlan>,
+    /// The number of the grouping columns at the front of the input.
+    groups:    usize,
+    /// The aggregate expressions whose states follow the grouping columns.
+    aggr_expr: Vec<Arc<dyn AggregateExpr>>,
+    /// The maximum number of partial aggregates merged by each function.
+    fan_in:    usize,
+}
+
+impl CombineAggregateExec {
+    /// Create a new combiner for the partial aggregates of a `Final`
+    /// aggregate.
+    ///
+    /// # Arguments
+    /// * `aggregate` - The `Final` aggregate that consumes the combined partial
+    ///   aggregates.
+    /// * `input` - The input plan, which outputs partial aggregates.
+    /// * `fan_in` - The maximum number of partial aggregates merged by each
+    ///   function.
+    pub fn try_new(
+        aggregate: &HashAggregateExec,
+        input: Arc<dyn ExecutionPlan>,
+        fan_in: usize,
+    ) -> Result<Self> {
+        if fan_in < 2 {
+            return Err(DataFusionError::Plan(
+                "CombineAggregateExec requires a fan-in of at least two.".to_owned(),
+            ));
+        }
+        Ok(Self {
+            input,
+            groups: aggregate.group_expr().len(),
+            aggr_expr: aggregate.aggr_expr().to_vec(),
+            fan_in,
+        })
+    }
+
+    /// Returns the input plan.
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+
+    /// Returns the maximum number of partial aggregates merged by each
+    /// function.
+    pub fn fan_in(&self) -> usize {
+        self.fan_in
+    }
+
+    /// Merges the partial aggregates in the record batches.
+    ///
+    /// # Arguments
+    /// * `batches` - The partial aggregates to merge.
+    ///
+    /// # Returns
+    /// The merged partial aggregates, or no record batch if the input is
+    /// empty.
+    pub fn combine(&self, batches: &[RecordBatch]) -> Result<Vec<RecordBatch>> {
+        let batches = batches
+            .iter()
+            .filter(|b| b.num_rows() > 0)
+            .collect::<Vec<_>>();
+        if batches.is_empty() {
+            return Ok(vec![]);
+        }
+        let columns = (0..self.schema().fields().len())
+            .map(|i| {
+                let arrays = batches
+                    .iter()
+                    .map(|b| b.column(i).as_ref())
+                    .collect::<Vec<_>>();
+                concat(&arrays)
+            })
+            .collect::<std::result::Result<Vec<ArrayRef>, _>>()?;
+
+        // The row indices of each group, in the order of their first rows.
+        let mut groups: Vec<Vec<u32>> = vec![];
+        let mut index: HashMap<Vec<ScalarValue>, usize> = HashMap::new();
+        for row in 0..columns[0].len() {
+            let key = columns[..self.groups]
+                .iter()
+                .map(|c| ScalarValue::try_from_array(c, row))
+                .collect::<Result<Vec<_>>>()?;
+            let group = *index.entry(key).or_insert_with(|| {
+                groups.push(vec![]);
+                groups.len() - 1
+            });
+            groups[group].push(row as u32);
+        }
+
+        let first_rows = UInt32Array::from(groups.iter().map(|g| g[0]).collect::<Vec<_>>());
+        let mut output = columns[..self.groups]
+            .iter()
+            .map(|c| take(c.as_ref(), &first_rows, None))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let mut offset = self.groups;
+        for expr in &self.aggr_expr {
+            let fields = expr.state_fields()?.len();
+            let mut states = vec![vec![]; fields];
+            for rows in &groups {
+                let rows = UInt32Array::from(rows.clone());
+                let partials = columns[offset..offset + fields]
+                    .iter()
+                    .map(|c| take(c.as_ref(), &rows, None))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                let mut accumulator = expr.create_accumulator()?;
+                accumulator.merge_batch(&partials)?;
+                for (i, state) in accumulator.state()?.into_iter().enumerate() {
+                    states[i].push(state.to_array_of_size(1));
+                }
+            }
+            for state in states {
+                output.push(concat(
+                    &state.iter().map(|a| a.as_ref()).collect::<Vec<_>>(),
+                )?);
+            }
+            offset += fields;
+        }
+
+        Ok(vec![RecordBatch::try_new(self.schema(), output)?])
+    }
+}
+
+#[async_trait]
+impl LambdaExecPlan for CombineAggregateExec {
+    fn feed_batches(&mut self, _partitions: Vec<Vec<RecordBatch>>) {
+        unimplemented!();
+    }
+}
+
+#[async_trait]
+#[typetag::serde(name = "combine_aggregate_exec")]
+impl ExecutionPlan for CombineAggregateExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_mut_any(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        match children.len() {
+            1 => Ok(Arc::new(CombineAggregateExec {
+                input:     children[0].clone(),
+                groups:    self.groups,
+                aggr_expr: self.aggr_expr.clone(),
+                fan_in:    self.fan_in,
+            })),
+            _ => Err(DataFusionError::Internal(
+                "CombineAggregateExec wrong number of children".to_owned(),
+            )),
+        }
+    }
+
+    async fn execute(&self, partition: usize) -> Result<SendableRecordBatchStream> {
+        if partition != 0 {
+            return Err(DataFusionError::Execution(format!(
+                "CombineAggregateExec invalid partition {}",
+                partition
+            )));
+        }
+        let batches = self.combine(&collect(self.input.clone()).await?)?;
+        MemoryExec::try_new(&[batches], self.schema(), None)?
+            .execute(0)
+            .await
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                let aggr: Vec<&str> = self.aggr_expr.iter().map(|e| e.name()).collect();
+                write!(
+                    f,
+                    "CombineAggregateExec: fan_in={}, aggr=[{}]",
+                    self.fan_in,
+                    aggr.join(", ")
+                )
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+}
+
+/// Returns the fan-in of each level of the combiner tree.
+///
+/// The tree has as few levels as possible, so that the `Final` aggregate
+/// merges at most `max_fan_in` partial aggregates. The fan-in is then balanced
+/// across the levels, so that no function merges more partial aggregates than
+/// needed.
+///
+/// # Arguments
+/// * `fragments` - The number of data fragments in each window, i.e., the
+///   number of partial aggregates to merge.
+/// * `max_fan_in` - The maximum number of partial aggregates merged by each
+///   function.
+///
+/// # Returns
+/// The fan-in of each level from the bottom up, or no level at all if the
+/// `Final` aggregate can merge all the partial aggregates.
+pub fn combiner_fan_ins(fragments: usize, max_fan_in: usize) -> Vec<usize> {
+    let max_fan_in = max_fan_in.max(2);
+    let mut levels = 0;
+    let mut capacity = max_fan_in;
+    while capacity < fragments {
+        capacity = capacity.saturating_mul(max_fan_in);
+        levels += 1;
+    }
+    if levels == 0 {
+        return vec![];
+    }
+
+    let mut fan_in = 2;
+    while fan_in.saturating_pow(levels as u32 + 1) < fragments {
+        fan_in += 1;
+    }
+    vec![fan_in; levels]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_batches_sorted_eq;
+    use datafusion::arrow::array::{Float64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::datasource::MemTable;
+    use datafusion::execution::context::{ExecutionConfig, ExecutionContext};
+    use datafusion::physical_plan::hash_aggregate::AggregateMode;
+
+    #[tokio::test]
+    async fn combiner_tree_fan_ins() -> Result<()> {
+        assert!(combiner_fan_ins(1, 20).is_empty());
+        assert!(combiner_fan_ins(20, 20).is_empty());
+        assert_eq!(combiner_fan_ins(64, 20), vec![8]);
+        assert_eq!(combiner_fan_ins(400, 20), vec![20]);
+        assert_eq!(combiner_fan_ins(500, 20), vec![8, 8]);
+        assert_eq!(combiner_fan_ins(5, 1), vec![2, 2]);
+        Ok(())
+    }
+
+    /// Returns the aggregates of the plan from the top down.
+    fn aggregates(plan: &Arc<dyn ExecutionPlan>) -> Vec<Arc<dyn ExecutionPlan>> {
+        let mut aggregates = vec![];
+        if plan.as_any().is::<HashAggregateExec>() {
+            aggregates.push(plan.clone());
+        }
+        plan.children()
+            .iter()
+            .for_each(|child| aggregates.extend(aggregates(child)));
+        aggregates
+    }
+
+    /// Executes the aggregate on the given partial aggregates.
+    async fn aggregate(
+        aggregate: &Arc<dyn ExecutionPlan>,
+        partials: Vec<RecordBatch>,
+    ) -> Result<Vec<RecordBatch>> {
+        let schema = aggregate.children()[0].schema();
+        let input = Arc::new(MemoryExec::try_new(&[partials], schema, None)?);
+        collect(aggregate.with_new_children(vec![input])?).await
+    }
+
+    #[tokio::test]
+    async fn combine_partial_aggregates() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("k", DataType::Utf8, true),
+            Field::new("v", DataType::Float64, false),
+        ]));
+        let batches = (0..6)
+            .map(|i| {
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![
+                        Arc::new(StringArray::from(vec![
+                            Some("a"),
+                            if i % 2 == 0 { None } else { Some("b") },
+                            Some(["a", "c", "d"][i % 3]),
+                        ])),
+                        Arc::new(Float64Array::from(vec![
+                            i as f64,
+                            i as f64 * 1.5,
+                            i as f64 * 2.0,
+                        ])),
+                    ],
+                )
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let config = ExecutionConfig::new().with_target_partitions(1);
+        let mut ctx = ExecutionContext::with_config(config);
+        let table = MemTable::try_new(schema.clone(), vec![batches.clone()])?;
+        ctx.register_table("t", Arc::new(table))?;
+        let sql = "SELECT k, COUNT(v), SUM(v), MIN(v), AVG(v) FROM t GROUP BY k";
+        let plan = ctx.create_logical_plan(sql)?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+
+        let aggregates = aggregates(&plan);
+        assert_eq!(aggregates.len(), 2);
+        let final_aggregate = aggregates[0]
+            .as_any()
+            .downcast_ref::<HashAggregateExec>()
+            .unwrap();
+        assert!(!matches!(final_aggregate.mode(), AggregateMode::Partial));
+        let partial = &aggregates[1];
+        let partial_schema = partial.schema();
+
+        // Each data fragment is aggregated by its own function.
+        let mut partials = vec![];
+        for batch in batches {
+            let input = Arc::new(MemoryExec::try_new(&[vec![batch]], schema.clone(), None)?);
+            partials.push(collect(partial.with_new_children(vec![input])?).await?);
+        }
+        let expected = aggregate(&aggregates[0], partials.concat()).await?;
+        let expected = pretty_format_batches(&expected)?.to_string();
+        let expected = expected.trim().lines().collect::<Vec<_>>();
+
+        // The partial aggregates are merged by a tree of combiners.
+        let mut level = partials;
+        while level.len() > 1 {
+            let mut next = vec![];
+            for fragments in level.chunks(4) {
+                let input = Arc::new(MemoryExec::try_new(
+                    &[fragments.concat()],
+                    partial_schema.clone(),
+                    None,
+                )?);
+                let combiner = CombineAggregateExec::try_new(final_aggregate, input, 4)?;
+                let combined = collect(Arc::new(combiner)).await?;
+                assert_eq!(combined.len(), 1);
+                next.push(combined);
+            }
+            level = next;
+        }
+        let results = aggregate(&aggregates[0], level.concat()).await?;
+        assert_batches_sorted_eq!(expected, &results);
+
+        // An empty input has no partial aggregates to merge.
+        let empty = Arc::new(MemoryExec::try_new(&[vec![]], partial_schema, None)?);
+        let combiner = CombineAggregateExec::try_new(final_aggregate, empty, 4)?;
+        assert!(collect(Arc::new(combiner)).await?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! `EXPLAIN DISTRIBUTED` shows how a query is split into query stages, which
+//! cloud functions run each stage, and how the data is routed between them.
+//!
+//! For each stage, the explanation contains the cloud function type, the
+//! function names, the reserved concurrency, the next hop, the size of the
+//! encoded execution context, the estimated payload sizes and the physical
+//! plan. It is rendered either as text or as a Graphviz DOT graph, in which
+//! each stage is a node and each edge is labeled with its routing.
+
+use crate::configs::*;
+use crate::distributed_plan::stage::QueryDag;
+use crate::encoding::Encoding;
+use crate::error::{FlockError, Result};
+use crate::runtime::context::{marshal, CloudFunction, CloudFunctionType};
+use daggy::NodeIndex;
+use datafusion::arrow::datatypes::{DataType, Schema};
+use datafusion::physical_plan::displayable;
+use std::str::FromStr;
+
+/// The estimated size in bytes of a value of variable width, such as a string.
+const VARIABLE_WIDTH_ESTIMATE: usize = 32;
+
+/// The output format of `EXPLAIN DISTRIBUTED`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExplainFormat {
+    /// Human-readable text.
+    Text,
+    /// Graphviz DOT, see <https://graphviz.org>.
+    Dot,
+}
+
+impl FromStr for ExplainFormat {
+    type Err = FlockError;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.to_lowercase().as_str() {
+            "text" => Ok(ExplainFormat::Text),
+            "dot" => Ok(ExplainFormat::Dot),
+            _ => Err(FlockError::Plan(format!(
+                "Unknown explain format: {}",
+                format
+            ))),
+        }
+    }
+}
+
+/// The explanation of a query stage.
+#[derive(Debug, Clone)]
+struct StageExplanation {
+    /// The stage id.
+    stage:        usize,
+    /// The stage id of the next stage, if any.
+    next_stage:   Option<usize>,
+    /// The cloud function type.
+    function:     CloudFunctionType,
+    /// The function name, or the first and the last member names of the group.
+    names:        String,
+    /// The number of functions that run the stage.
+    num_funcs:    usize,
+    /// The next hop of the output.
+    next:         String,
+    /// The size of the encoded execution context in bytes.
+    context_size: usize,
+    /// Whether the plan is (or must be) placed in the object store.
+    placed:       bool,
+    /// The estimated size in bytes of an output row.
+    row_size:     usize,
+    /// The physical plan of the stage.
+    plan:         String,
+}
+
+impl StageExplanation {
+    /// Returns the estimated payload sizes of the sync and async invocations.
+    fn payload(&self) -> String {
+        format!(
+            "~{} sync, ~{} async",
+            format_bytes(self.row_size * *FLOCK_SYNC_GRANULE_SIZE),
+            format_bytes(self.row_size * *FLOCK_ASYNC_GRANULE_SIZE)
+        )
+    }
+
+    /// Returns the reserved concurrency of each function.
+    fn concurrency(&self) -> &'static str {
+        match self.function {
+            CloudFunctionType::Lambda => "unreserved",
+            CloudFunctionType::Group => "1 per function",
+        }
+    }
+
+    /// Returns the lines of the stage description, without the plan.
+    fn lines(&self) -> Vec<String> {
+        let mut context = format!("{} bytes", self.context_size);
+        if self.placed {
+            context.push_str(" (the plan is placed in the object store)");
+        }
+        vec![
+            format!("Stage {}: {:?}", self.stage, self.function),
+            format!("Functions:   {} ({})", self.names, self.num_funcs),
+            format!("Concurrency: {}", self.concurrency()),
+            format!("Next:        {}", self.next),
+            format!("Context:     {}", context),
+            format!(
+                "Payload:     ~{} bytes per row, {}",
+                self.row_size,
+                self.payload()
+            ),
+        ]
+    }
+}
+
+impl QueryDag {
+    /// Explains how the query is executed by the cloud functions.
+    ///
+    /// The cloud contexts must be created beforehand, see
+    /// `AwsLambdaLauncher::create_cloud_contexts`.
+    ///
+    /// # Arguments
+    /// * `format` - The output format of the explanation.
+    pub fn explain(&self, format: ExplainFormat) -> Result<String> {
+        let stages = self.explain_stages()?;
+        Ok(match format {
+            ExplainFormat::Text => explain_text(&stages),
+            ExplainFormat::Dot => explain_dot(&stages),
+        })
+    }
+
+    /// Explains the query stages in the order of the data flow.
+    fn explain_stages(&self) -> Result<Vec<StageExplanation>> {
+        (0..self.node_count())
+            .rev()
+            .map(|i| self.explain_stage(NodeIndex::new(i)))
+            .collect()
+    }
+
+    /// Explains the query stage of the given node.
+    fn explain_stage(&self, node: NodeIndex) -> Result<StageExplanation> {
+        let stage = self.get_node(node).unwrap();
+        let ctx = stage.context.as_ref().ok_or_else(|| {
+            FlockError::Plan("The cloud contexts are not created yet.".to_owned())
+        })?;
+
+        let (names, num_funcs) = match (stage.get_function_type(), self.group_size(node)) {
+            (CloudFunctionType::Group, size) if size > 1 => (
+                format!(
+                    "{} .. {}",
+                    ctx.group_member(0)?.name,
+                    ctx.group_member(size - 1)?.name
+                ),
+                size,
+            ),
+            (CloudFunctionType::Group, _) => (ctx.group_member(0)?.name, 1),
+            (CloudFunctionType::Lambda, _) => (ctx.name.clone(), 1),
+        };
+
+        let next = match &ctx.next {
+            CloudFunction::Lambda(name) => format!("Lambda({})", name),
+            CloudFunction::Group((name, size)) => match (ctx.fan_in, ctx.next_input) {
+                (Some(fan_in), _) => format!("Group({}, {}) with fan-in {}", name, size, fan_in),
+                (None, Some((index, inputs))) => {
+                    format!("Group({}, {}) as input {} of {}", name, size, index, inputs)
+                }
+                (None, None) => format!("Group({}, {})", name, size),
+            },
+            CloudFunction::Sink(sink) => format!("Sink({:?})", sink),
+        };
+
+        let context_size = marshal(ctx, Encoding::default())?.len();
+        Ok(StageExplanation {
+            stage: ctx.stage()?,
+            next_stage: self
+                .get_parent(node)
+                .map(|p| self.node_count() - 1 - p.index()),
+            function: stage.get_function_type(),
+            names,
+            num_funcs,
+            next,
+            context_size,
+            placed: ctx.plan.object_storage.is_some() || context_size > *FLOCK_CONTEXT_SIZE_LIMIT,
+            row_size: stage
+                .iter()
+                .map(|plan| estimate_row_size(&plan.schema()))
+                .max()
+                .unwrap_or(0),
+            plan: stage
+                .iter()
+                .map(|plan| format!("{}", displayable(plan.as_ref()).indent()))
+                .collect(),
+        })
+    }
+
+    /// Returns the number of functions in the function group of the given
+    /// node. The group size is defined by the former stage of the dataflow.
+    fn group_size(&self, node: NodeIndex) -> usize {
+        self.get_inputs(node)
+            .into_iter()
+            .find_map(
+                |input| match &self.get_node(input)?.context.as_ref()?.next {
+                    CloudFunction::Group((_, size)) => Some(*size),
+                    _ => None,
+                },
+            )
+            .unwrap_or(1)
+    }
+}
+
+/// Renders the query stages as text.
+fn explain_text(stages: &[StageExplanation]) -> String {
+    let mut text = String::new();
+    for stage in stages {
+        for line in stage.lines() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text.push_str("Plan:\n");
+        for line in stage.plan.lines() {
+            text.push_str(&format!("  {}\n", line));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Renders the query stages as a Graphviz DOT graph.
+fn explain_dot(stages: &[StageExplanation]) -> String {
+    let mut dot = String::from("digraph {\n  node[shape=box fontname=\"monospace\"]\n");
+    for stage in stages {
+        let mut label = stage.lines();
+        label.push("Plan:".to_owned());
+        label.extend(stage.plan.lines().map(|line| format!("  {}", line)));
+        dot.push_str(&format!(
+            "  {}[label=\"{}\\l\"]\n",
+            stage.stage,
+            escape_dot(&label.join("\n"))
+        ));
+
+        let target = match stage.next_stage {
+            Some(next) => next.to_string(),
+            None => {
+                dot.push_str(&format!(
+                    "  sink[shape=oval label=\"{}\"]\n",
+                    escape_dot(&stage.next)
+                ));
+                "sink".to_owned()
+            }
+        };
+        dot.push_str(&format!(
+            "  {} -> {}[label=\"{}\"]\n",
+            stage.stage,
+            target,
+            escape_dot(&format!("{}\n{}", stage.next, stage.payload()))
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Escapes the text in a DOT label. Each line is left-justified.
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\l")
+}
+
+/// Returns the estimated size in bytes of a row of the schema.
+fn estimate_row_size(schema: &Schema) -> usize {
+    schema
+        .fields()
+        .iter()
+        .map(|field| estimate_value_size(field.data_type()))
+        .sum()
+}
+
+/// Returns the estimated size in bytes of a value of the data type.
+fn estimate_value_size(data_type: &DataType) -> usize {
+    match data_type {
+        DataType::Null => 0,
+        DataType::Boolean | DataType::Int8 | DataType::UInt8 => 1,
+        DataType::Int16 | DataType::UInt16 | DataType::Float16 => 2,
+        DataType::Int32
+        | DataType::UInt32
+        | DataType::Float32
+        | DataType::Date32
+        | DataType::Time32(_) => 4,
+        DataType::Decimal(_, _) => 16,
+        DataType::Utf8
+        | DataType::LargeUtf8
+        | DataType::Binary
+        | DataType::LargeBinary
+        | DataType::List(_)
+        | DataType::LargeList(_) => VARIABLE_WIDTH_ESTIMATE,
+        DataType::FixedSizeBinary(size) => *size as usize,
+        DataType::Struct(fields) => fields
+            .iter()
+            .map(|field| estimate_value_size(field.data_type()))
+            .sum(),
+        _ => 8,
+    }
+}
+
+/// Formats the size in bytes in a human-readable unit.
+fn format_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasink::DataSinkType;
+    use crate::datasource::DataSource;
+    use crate::query::{Query, QueryType, Table};
+    use crate::runtime::function::FunctionId;
+    use crate::state::HashMapStateBackend;
+    use datafusion::arrow::datatypes::Field;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn explain_distributed() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("k", DataType::Utf8, false),
+            Field::new("v", DataType::Int64, false),
+        ]));
+        let query = Query::new(
+            "SELECT k, COUNT(*), SUM(v) FROM t GROUP BY k",
+            vec![Table::new("t", schema)],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            Some("explain"),
+            QueryType::OLAP,
+            Arc::new(HashMapStateBackend::new()),
+        );
+
+        // The partial aggregation runs in a lambda function, and the final
+        // aggregation in a function group.
+        let text = query.explain_distributed(ExplainFormat::Text).await?;
+        println!("{}", text);
+        let group_size = *FLOCK_FUNCTION_CONCURRENCY;
+        let last = FunctionId::try_new("explain", 1)?.member(group_size - 1);
+        for line in [
+            "Stage 0: Lambda".to_owned(),
+            "Functions:   explain-0 (1)".to_owned(),
+            "Concurrency: unreserved".to_owned(),
+            format!("Next:        Group(explain-1, {})", group_size),
+            "Stage 1: Group".to_owned(),
+            format!("Functions:   explain-1_0 .. {} ({})", last, group_size),
+            "Concurrency: 1 per function".to_owned(),
+            "Next:        Sink(Blackhole)".to_owned(),
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing line: {}", line);
+        }
+        assert!(text.contains("HashAggregateExec: mode=Partial"));
+        assert!(text.contains("HashAggregateExec: mode=Final"));
+
+        let dot = query.explain_distributed(ExplainFormat::Dot).await?;
+        println!("{}", dot);
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains(&format!(
+            "  0 -> 1[label=\"Group(explain-1, {})\\l",
+            group_size
+        )));
+        assert!(dot.contains("  sink[shape=oval label=\"Sink(Blackhole)\"]\n"));
+        assert!(dot.contains("  1 -> sink[label=\"Sink(Blackhole)\\l"));
+
+        // A string is estimated at 32 bytes, and an integer at 8 bytes.
+        assert_eq!(
+            estimate_row_size(&Schema::new(vec![
+                Field::new("k", DataType::Utf8, false),
+                Field::new("v", DataType::Int64, false),
+            ])),
+            40
+        );
+        assert_eq!(format_bytes(40 * 3072), "120.0 KB");
+        assert_eq!("DOT".parse::<ExplainFormat>()?, ExplainFormat::Dot);
+        assert!("json".parse::<ExplainFormat>().is_err());
+
+        Ok(())
+    }
+}
@@ -16,8 +16,16 @@
 //! to split their query into multiple functions, and execute them in
 //! distributed fashion on cloud environments.
 
+pub mod broadcast;
+pub mod combine;
+pub mod explain;
 pub mod planner;
+pub mod range;
 pub mod stage;
 
+pub use broadcast::BroadcastJoinExec;
+pub use combine::CombineAggregateExec;
+pub use explain::ExplainFormat;
 pub use planner::DistributedPlanner;
+pub use range::{RangePartitionExec, RangeSampler};
 pub use stage::{QueryDag, QueryStage};
@@ -14,21 +14,48 @@
 //! Distributed plnner is a unified API for users to split their query plan into
 //! multiple functions, and execute them in distributed fashion on cloud.
 
+use crate::configs::FLOCK_SIDE_INPUT_BROADCAST_THRESHOLD;
+use crate::datasource::side_input::SideInput;
+use crate::distributed_plan::broadcast::broadcast_joins;
 use crate::distributed_plan::stage::{self, QueryDag};
 use crate::error::Result;
 use datafusion::physical_plan::ExecutionPlan;
-use log::debug;
+use log::{debug, info};
 use std::sync::Arc;
 
 /// Distributed Planer deals with the physical plan and convert it into
 /// distributed plan.
 #[derive(Debug)]
-pub struct DistributedPlanner {}
+pub struct DistributedPlanner {
+    /// The number of data fragments in each window of the data source.
+    fragments:   usize,
+    /// The side inputs of the query.
+    side_inputs: Vec<SideInput>,
+}
 
 impl DistributedPlanner {
     /// Create a new distributed planner.
     pub fn new() -> Self {
-        DistributedPlanner {}
+        Self::with_fragments(1)
+    }
+
+    /// Create a new distributed planner for the data source that sends the
+    /// given number of data fragments per window. The planner merges their
+    /// partial aggregates by a tree of combiners if needed.
+    pub fn with_fragments(fragments: usize) -> Self {
+        DistributedPlanner {
+            fragments,
+            side_inputs: vec![],
+        }
+    }
+
+    /// Sets the side inputs of the query. The hash joins with a side input of
+    /// at most `FLOCK_SIDE_INPUT_BROADCAST_THRESHOLD` bytes on one side are
+    /// planned as broadcast joins, see
+    /// [`BroadcastJoinExec`](crate::distributed_plan::BroadcastJoinExec).
+    pub fn with_side_inputs(mut self, side_inputs: Vec<SideInput>) -> Self {
+        self.side_inputs = side_inputs;
+        self
     }
 }
 
@@ -46,7 +73,24 @@ impl DistributedPlanner {
         execution_plan: Arc<dyn ExecutionPlan>,
     ) -> Result<QueryDag> {
         debug!("planning query stages\n");
-        Ok(self.plan_query_stages_internal(execution_plan)?)
+        let plan = broadcast_joins(execution_plan, &self.broadcast_side_inputs().await?)?;
+        Ok(self.plan_query_stages_internal(plan)?)
+    }
+
+    /// Returns the side inputs that are small enough to be broadcast.
+    async fn broadcast_side_inputs(&self) -> Result<Vec<SideInput>> {
+        let mut side_inputs = vec![];
+        for side_input in &self.side_inputs {
+            let size = side_input.size().await?;
+            if size <= *FLOCK_SIDE_INPUT_BROADCAST_THRESHOLD {
+                info!(
+                    "Broadcasting the side input {} ({} bytes).",
+                    side_input.table, size
+                );
+                side_inputs.push(side_input.clone());
+            }
+        }
+        Ok(side_inputs)
     }
 
     /// Returns a potentially DAG of the execution plan.
@@ -54,15 +98,22 @@ impl DistributedPlanner {
         &self,
         execution_plan: Arc<dyn ExecutionPlan>,
     ) -> Result<QueryDag> {
-        stage::build_query_dag(execution_plan)
+        stage::build_query_dag_with_fragments(execution_plan, self.fragments)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::configs::FLOCK_S3_BUCKET;
     use crate::datasource::nexmark::*;
+    use crate::datasource::side_input::SideInputFormat;
     use crate::datasource::ysb::*;
+    use crate::distributed_plan::broadcast::stage_side_inputs;
+    use crate::objectstore::object_store;
+    use crate::test_util::init_in_memory_object_store;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::MemTable;
     use datafusion::physical_plan::displayable;
 
     #[tokio::test]
@@ -258,6 +309,59 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn nexmark_q13_broadcast_join() -> Result<()> {
+        init_in_memory_object_store();
+        let key = "nexmark_q13_broadcast_join/side_input.csv";
+        object_store()
+            .put(&FLOCK_S3_BUCKET, key, b"key,value\n1,2\n".to_vec())
+            .await?;
+        let side_input =
+            SideInput::new("side_input", key, SideInputFormat::Csv, side_input_schema());
+
+        // The side input is registered with its marked columns, as the query does.
+        let mut ctx = register_nexmark_tables().await?;
+        let schema = side_input.schema();
+        let table = MemTable::try_new(schema.clone(), vec![vec![RecordBatch::new_empty(schema)]])?;
+        ctx.register_table("side_input", Arc::new(table))?;
+        let df = ctx
+            .sql(include_str!(
+                "../../../benchmarks/src/nexmark/query/q13.sql"
+            ))
+            .await?;
+
+        let plan = df.to_logical_plan();
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+
+        // Without the side input, both inputs of the join are shuffled.
+        let dag = DistributedPlanner::new()
+            .plan_query_stages(plan.clone())
+            .await?;
+        assert_eq!(2, dag.get_all_stages().len());
+
+        let dag = DistributedPlanner::new()
+            .with_side_inputs(vec![side_input.clone()])
+            .plan_query_stages(plan)
+            .await?;
+        let stages = &dag.get_all_stages();
+        for (i, stage) in stages.iter().enumerate() {
+            println!("=== Stage {} ===\n{}\n", i, stage.get_plan_str());
+        }
+        assert_eq!(1, stages.len());
+
+        let plan = stages[0].get_plan_str();
+        assert!(plan.contains("BroadcastJoinExec: side_input=side_input"));
+        assert!(plan.contains("mode=CollectLeft"));
+        assert!(!plan.contains("partitioning=Hash"));
+        assert_eq!(
+            stage_side_inputs(&stages[0].stage, true, &[side_input.clone()]),
+            vec![side_input]
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn show_nexmark_distributed_plans() -> Result<()> {
         let mut ctx = register_nexmark_tables().await?;
@@ -0,0 +1,620 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! A sort on a single function funnels all the records of the window into one
+//! cloud function. `RangePartitionExec` splits its input into the ranges of
+//! the sort keys instead, so that each function of a group sorts one range,
+//! and the sorted ranges are concatenated in order.
+//!
+//! `RangePartitionExec` runs on top of each function of the stage below the
+//! sort, and every function sends its ranges straight to the sorters. All of
+//! them have to split their outputs at the same boundaries, so the boundaries
+//! are picked once per window by [`RangeSampler`] from the window of the data
+//! source, and passed down the dataflow pipeline in the payload metadata.
+//!
+//! If the sort keys are computed by the query, such as an aggregate, they
+//! can't be sampled from the data source. The stage below the sort then runs
+//! on a single function, which samples the whole window by itself.
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{build_compare, Array, ArrayRef, DynComparator, UInt32Array};
+use datafusion::arrow::compute::{concat, lexsort_to_indices, take, SortColumn};
+use datafusion::arrow::datatypes::{Field, Schema, SchemaRef};
+use datafusion::arrow::error::Result as ArrowResult;
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::physical_plan::expressions::{Column, PhysicalSortExpr};
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::{
+    collect, DisplayFormatType, ExecutionPlan, LambdaExecPlan, Partitioning,
+    SendableRecordBatchStream, Statistics,
+};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::cmp::Ordering;
+use std::io::Cursor;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// The number of sampled rows per range to pick the range boundaries.
+const SAMPLES_PER_RANGE: usize = 100;
+
+/// The metadata key of the range boundaries of the window.
+pub const RANGE_BOUNDARIES: &str = "range_boundaries";
+
+/// `RangePartitionExec` splits its input into the ranges of the sort keys.
+/// The `i`-th output partition contains the records of the `i`-th range, and
+/// all the records of a range sort before the records of the next range.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangePartitionExec {
+    /// The input plan.
+    input:      Arc<dyn ExecutionPlan>,
+    /// The sort expressions that define the ranges.
+    expr:       Vec<PhysicalSortExpr>,
+    /// The number of ranges.
+    partitions: usize,
+    /// The range boundaries of the current window, which are fed at runtime.
+    /// The `i`-th row is the upper bound of the `i`-th range.
+    #[serde(skip)]
+    boundaries: Option<RecordBatch>,
+    /// The ranges of the current window. The input is executed once for all
+    /// the ranges, and the execution of each range takes its own records.
+    #[serde(skip)]
+    ranges:     Arc<Mutex<Vec<Option<Vec<RecordBatch>>>>>,
+}
+
+impl RangePartitionExec {
+    /// Create a new range partitioner.
+    ///
+    /// # Arguments
+    /// * `expr` - The sort expressions that define the ranges.
+    /// * `input` - The input plan.
+    /// * `partitions` - The number of ranges.
+    pub fn try_new(
+        expr: Vec<PhysicalSortExpr>,
+        input: Arc<dyn ExecutionPlan>,
+        partitions: usize,
+    ) -> Result<Self> {
+        if partitions == 0 {
+            return Err(DataFusionError::Plan(
+                "RangePartitionExec requires at least one range.".to_owned(),
+            ));
+        }
+        Ok(Self {
+            input,
+            expr,
+            partitions,
+            boundaries: None,
+            ranges: Arc::default(),
+        })
+    }
+
+    /// Returns the input plan.
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+
+    /// Returns the sort expressions that define the ranges.
+    pub fn expr(&self) -> &[PhysicalSortExpr] {
+        &self.expr
+    }
+
+    /// Returns the number of ranges.
+    pub fn partitions(&self) -> usize {
+        self.partitions
+    }
+
+    /// Sets the range boundaries of the current window, see [`RangeSampler`].
+    pub fn set_boundaries(&mut self, boundaries: Option<RecordBatch>) {
+        self.boundaries = boundaries;
+        self.ranges = Arc::default();
+    }
+
+    /// Executes the input once and splits it into all the ranges.
+    ///
+    /// Without the range boundaries of the window, such as the sort keys that
+    /// are computed by the query rather than read from the data source, the
+    /// boundaries are sampled from the input. The planner only distributes
+    /// such a sort if the input is the whole window, see the module docs.
+    pub async fn execute_ranges(&self) -> Result<Vec<Vec<RecordBatch>>> {
+        let batches = collect(self.input.clone()).await?;
+        let boundaries = match self.boundaries.clone() {
+            Some(boundaries) => boundaries,
+            None => {
+                debug!("No range boundaries for the window, the input is sampled instead.");
+                let input = batches.iter().collect::<Vec<_>>();
+                match sample_boundaries(&input, &self.expr, self.partitions)? {
+                    Some(boundaries) => boundaries,
+                    None => return Ok(vec![vec![]; self.partitions]),
+                }
+            }
+        };
+        split_ranges(&batches, &self.expr, &boundaries, self.partitions)
+    }
+}
+
+/// `RangeSampler` picks the range boundaries of a distributed sort from the
+/// window of the data source, before the window is sent to the query stages.
+///
+/// The sort keys are bound by name to the columns of the first relation that
+/// contains all of them, so the boundaries reflect the distribution of the
+/// keys in the window, even though the records are sorted by a later stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangeSampler {
+    /// The sort expressions that define the ranges.
+    expr:       Vec<PhysicalSortExpr>,
+    /// The number of ranges.
+    partitions: usize,
+}
+
+impl RangeSampler {
+    /// Create a new sampler for the range partitioner.
+    pub fn new(range: &RangePartitionExec) -> Self {
+        Self {
+            expr:       range.expr.clone(),
+            partitions: range.partitions,
+        }
+    }
+
+    /// Picks the range boundaries from the relations of the data source.
+    ///
+    /// # Arguments
+    /// * `sources` - The partitions of each relation in the window.
+    ///
+    /// # Returns
+    /// The range boundaries, or `None` if no relation contains all the sort
+    /// keys, or the window is empty.
+    pub fn sample(&self, sources: &[Vec<Vec<RecordBatch>>]) -> Result<Option<RecordBatch>> {
+        for relation in sources {
+            let batches = relation
+                .iter()
+                .flatten()
+                .filter(|b| b.num_rows() > 0)
+                .collect::<Vec<_>>();
+            if let Some(expr) = batches.first().and_then(|b| self.bind(&b.schema())) {
+                return sample_boundaries(&batches, &expr, self.partitions);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns true if the sort keys can be sampled from a relation with the
+    /// given schema.
+    pub fn binds(&self, schema: &Schema) -> bool {
+        self.bind(schema).is_some()
+    }
+
+    /// Binds the sort keys to the columns of the relation with the same names.
+    fn bind(&self, schema: &Schema) -> Option<Vec<PhysicalSortExpr>> {
+        self.expr
+            .iter()
+            .map(|e| {
+                let column = e.expr.as_any().downcast_ref::<Column>()?;
+                let index = schema.index_of(column.name()).ok()?;
+                Some(PhysicalSortExpr {
+                    expr:    Arc::new(Column::new(column.name(), index)),
+                    options: e.options,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Encodes the range boundaries as a string of the payload metadata.
+pub fn encode_boundaries(boundaries: &RecordBatch) -> Result<String> {
+    let mut bytes = vec![];
+    {
+        let mut writer = StreamWriter::try_new(&mut bytes, &boundaries.schema())?;
+        writer.write(boundaries)?;
+        writer.finish()?;
+    }
+    Ok(base64::encode(bytes))
+}
+
+/// Decodes the range boundaries from a string of the payload metadata.
+pub fn decode_boundaries(boundaries: &str) -> Result<RecordBatch> {
+    let bytes =
+        base64::decode(boundaries).map_err(|e| DataFusionError::Execution(e.to_string()))?;
+    StreamReader::try_new(Cursor::new(bytes))?
+        .collect::<ArrowResult<Vec<_>>>()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| DataFusionError::Execution("The range boundaries are missing.".to_owned()))
+}
+
+#[async_trait]
+impl LambdaExecPlan for RangePartitionExec {
+    fn feed_batches(&mut self, _partitions: Vec<Vec<RecordBatch>>) {
+        unimplemented!();
+    }
+}
+
+#[async_trait]
+#[typetag::serde(name = "range_partition_exec")]
+impl ExecutionPlan for RangePartitionExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_mut_any(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.partitions)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        match children.len() {
+            1 => {
+                let mut range = RangePartitionExec::try_new(
+                    self.expr.clone(),
+                    children[0].clone(),
+                    self.partitions,
+                )?;
+                range.set_boundaries(self.boundaries.clone());
+                Ok(Arc::new(range))
+            }
+            _ => Err(DataFusionError::Internal(
+                "RangePartitionExec wrong number of children".to_owned(),
+            )),
+        }
+    }
+
+    async fn execute(&self, partition: usize) -> Result<SendableRecordBatchStream> {
+        if partition >= self.partitions {
+            return Err(DataFusionError::Execution(format!(
+                "RangePartitionExec invalid partition {} of {}",
+                partition, self.partitions
+            )));
+        }
+        let batches = {
+            let mut ranges = self.ranges.lock().await;
+            // The ranges are computed again once this range has been taken,
+            // since the input is fed with the next window.
+            if ranges.get(partition).map_or(true, |range| range.is_none()) {
+                *ranges = self.execute_ranges().await?.into_iter().map(Some).collect();
+            }
+            ranges[partition].take().unwrap_or_default()
+        };
+        MemoryExec::try_new(&[batches], self.schema(), None)?
+            .execute(0)
+            .await
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                let expr: Vec<String> = self.expr.iter().map(|e| e.to_string()).collect();
+                write!(
+                    f,
+                    "RangePartitionExec: partitions={}, expr=[{}]",
+                    self.partitions,
+                    expr.join(", ")
+                )
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.input.statistics()
+    }
+}
+
+/// Splits the record batches into the ranges of the sort keys at the range
+/// boundaries.
+///
+/// # Arguments
+/// * `batches` - The record batches to split.
+/// * `expr` - The sort expressions that define the ranges.
+/// * `boundaries` - The range boundaries, one column per sort expression.
+/// * `partitions` - The number of ranges.
+///
+/// # Returns
+/// The record batches of each range, in the sort order of the ranges.
+pub fn split_ranges(
+    batches: &[RecordBatch],
+    expr: &[PhysicalSortExpr],
+    boundaries: &RecordBatch,
+    partitions: usize,
+) -> Result<Vec<Vec<RecordBatch>>> {
+    if boundaries.num_columns() != expr.len() || boundaries.num_rows() + 1 != partitions {
+        return Err(DataFusionError::Execution(format!(
+            "The range boundaries {:?} don't split the sort keys into {} ranges.",
+            boundaries.schema(),
+            partitions
+        )));
+    }
+
+    let mut ranges = vec![vec![]; partitions];
+    let batches = batches
+        .iter()
+        .filter(|b| b.num_rows() > 0)
+        .collect::<Vec<_>>();
+    if batches.is_empty() {
+        return Ok(ranges);
+    }
+
+    let keys = sort_keys(&batches, expr)?;
+    let boundaries = boundaries.columns();
+    let comparators = keys
+        .iter()
+        .zip(boundaries.iter())
+        .map(|(key, boundary)| build_compare(key.values.as_ref(), boundary.as_ref()))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let bounds = (0..partitions - 1).collect::<Vec<_>>();
+
+    let mut offset = 0;
+    for batch in batches {
+        let mut indices = vec![vec![]; partitions];
+        for row in 0..batch.num_rows() {
+            // Rows equal to a boundary belong to the lower range.
+            let range = bounds.partition_point(|b| {
+                compare_row(&keys, boundaries, &comparators, offset + row, *b) == Ordering::Greater
+            });
+            indices[range].push(row as u32);
+        }
+        offset += batch.num_rows();
+
+        for (range, indices) in indices.into_iter().enumerate() {
+            if !indices.is_empty() {
+                let indices = UInt32Array::from(indices);
+                let columns = batch
+                    .columns()
+                    .iter()
+                    .map(|c| take(c.as_ref(), &indices, None))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                ranges[range].push(RecordBatch::try_new(batch.schema(), columns)?);
+            }
+        }
+    }
+
+    Ok(ranges)
+}
+
+/// Picks the range boundaries from the sort keys of the record batches.
+///
+/// # Returns
+/// The range boundaries, one column per sort expression, or `None` if the
+/// record batches are empty.
+fn sample_boundaries(
+    batches: &[&RecordBatch],
+    expr: &[PhysicalSortExpr],
+    partitions: usize,
+) -> Result<Option<RecordBatch>> {
+    if batches.iter().all(|b| b.num_rows() == 0) {
+        return Ok(None);
+    }
+    let keys = sort_keys(batches, expr)?;
+    let boundaries = range_boundaries(&keys, partitions)?;
+    let schema = Schema::new(
+        expr.iter()
+            .zip(boundaries.iter())
+            .map(|(e, b)| Field::new(&e.expr.to_string(), b.data_type().clone(), true))
+            .collect(),
+    );
+    Ok(Some(RecordBatch::try_new(Arc::new(schema), boundaries)?))
+}
+
+/// Returns the sort keys of all the rows in the record batches.
+fn sort_keys(batches: &[&RecordBatch], expr: &[PhysicalSortExpr]) -> Result<Vec<SortColumn>> {
+    expr.iter()
+        .map(|e| {
+            let arrays = batches
+                .iter()
+                .map(|b| Ok(e.expr.evaluate(b)?.into_array(b.num_rows())))
+                .collect::<Result<Vec<ArrayRef>>>()?;
+            Ok(SortColumn {
+                values:  concat(&arrays.iter().map(|a| a.as_ref()).collect::<Vec<_>>())?,
+                options: Some(e.options),
+            })
+        })
+        .collect()
+}
+
+/// Picks `partitions - 1` boundaries from an evenly spaced sample of the sort
+/// keys. The `i`-th boundary is the upper bound of the `i`-th range.
+fn range_boundaries(keys: &[SortColumn], partitions: usize) -> Result<Vec<ArrayRef>> {
+    let num_rows = keys[0].values.len();
+    let step = (num_rows / (partitions * SAMPLES_PER_RANGE)).max(1);
+    let sample = UInt32Array::from(
+        (0..num_rows)
+            .step_by(step)
+            .map(|i| i as u32)
+            .collect::<Vec<_>>(),
+    );
+    let sample = keys
+        .iter()
+        .map(|key| {
+            Ok(SortColumn {
+                values:  take(key.values.as_ref(), &sample, None)?,
+                options: key.options,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let order = lexsort_to_indices(&sample, None)?;
+    let picks = UInt32Array::from(
+        (1..partitions)
+            .map(|i| order.value(i * order.len() / partitions))
+            .collect::<Vec<_>>(),
+    );
+    Ok(sample
+        .iter()
+        .map(|key| take(key.values.as_ref(), &picks, None))
+        .collect::<std::result::Result<Vec<_>, _>>()?)
+}
+
+/// Compares the sort keys of a row with a range boundary.
+fn compare_row(
+    keys: &[SortColumn],
+    boundaries: &[ArrayRef],
+    comparators: &[DynComparator],
+    row: usize,
+    boundary: usize,
+) -> Ordering {
+    for ((key, bound), cmp) in keys.iter().zip(boundaries).zip(comparators) {
+        let options = key.options.unwrap_or_default();
+        let ordering = match (key.values.is_valid(row), bound.is_valid(boundary)) {
+            (false, false) => Ordering::Equal,
+            (false, true) if options.nulls_first => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (true, false) if options.nulls_first => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (true, true) if options.descending => cmp(row, boundary).reverse(),
+            (true, true) => cmp(row, boundary),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::compute::SortOptions;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::physical_plan::collect_partitioned;
+    use datafusion::physical_plan::expressions::col;
+    use datafusion::physical_plan::sort::SortExec;
+
+    fn values(batches: &[RecordBatch]) -> Vec<Option<i64>> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let array = b.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+                (0..array.len())
+                    .map(|i| array.is_valid(i).then(|| array.value(i)))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn range_partitioned_sort() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int64, true),
+            Field::new("b", DataType::Utf8, false),
+        ]));
+        let batches = (0..10)
+            .map(|i| {
+                let a = (0..100)
+                    .map(|j| match (i * 100 + j) * 7919 % 1000 {
+                        v if v % 97 == 0 => None,
+                        v => Some(v % 300),
+                    })
+                    .collect::<Vec<_>>();
+                let b = a.iter().map(|v| format!("{:?}", v)).collect::<Vec<_>>();
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![
+                        Arc::new(Int64Array::from(a)),
+                        Arc::new(StringArray::from(b)),
+                    ],
+                )
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let expr = vec![PhysicalSortExpr {
+            expr:    col("a", &schema)?,
+            options: SortOptions {
+                descending:  true,
+                nulls_first: false,
+            },
+        }];
+        let input = Arc::new(MemoryExec::try_new(
+            &[batches.clone()],
+            schema.clone(),
+            None,
+        )?);
+        let mut exec = RangePartitionExec::try_new(expr.clone(), input.clone(), 4)?;
+
+        // Without the range boundaries, the input is sampled by itself.
+        let sampled = exec.execute_ranges().await?;
+        assert_eq!(sampled.len(), 4);
+        assert!(sampled.iter().all(|r| !r.is_empty()));
+
+        // The range boundaries are sampled from the window of the data source,
+        // and passed down in the payload metadata.
+        let boundaries = RangeSampler::new(&exec)
+            .sample(&[vec![batches.clone()]])?
+            .unwrap();
+        assert_eq!(boundaries.num_rows(), 3);
+        let boundaries = decode_boundaries(&encode_boundaries(&boundaries)?)?;
+        exec.set_boundaries(Some(boundaries.clone()));
+        let ranges = exec.execute_ranges().await?;
+        assert_eq!(ranges.len(), 4);
+        for (range, sampled) in ranges.iter().zip(sampled.iter()) {
+            assert_eq!(values(range), values(sampled));
+        }
+
+        // Each output partition is the same as the range split at once, and
+        // the ranges are split again once they have all been taken.
+        let exec = Arc::new(exec);
+        for _ in 0..2 {
+            let partitions = collect_partitioned(exec.clone()).await?;
+            for (range, partition) in ranges.iter().zip(partitions.iter()) {
+                assert_eq!(values(range), values(partition));
+            }
+        }
+
+        // Each function splits its own output at the same boundaries, so the
+        // ranges of all the functions are the same as the ranges of the window.
+        let (left, right) = batches.split_at(5);
+        let left = split_ranges(left, &expr, &boundaries, 4)?;
+        let right = split_ranges(right, &expr, &boundaries, 4)?;
+        for (i, range) in ranges.iter().enumerate() {
+            let mut expected = values(range);
+            let mut actual = values(&left[i]);
+            actual.extend(values(&right[i]));
+            expected.sort_unstable();
+            actual.sort_unstable();
+            assert_eq!(actual, expected);
+        }
+
+        // Sorting the ranges locally and concatenating them in order is the
+        // same as sorting all the records.
+        let mut sorted = vec![];
+        for (left, right) in left.into_iter().zip(right.into_iter()) {
+            let range = left
+                .into_iter()
+                .chain(right.into_iter())
+                .collect::<Vec<_>>();
+            let range = Arc::new(MemoryExec::try_new(&[range], schema.clone(), None)?);
+            sorted.extend(collect(Arc::new(SortExec::try_new(expr.clone(), range)?)).await?);
+        }
+        let expected = collect(Arc::new(SortExec::try_new(expr, input)?)).await?;
+        assert_eq!(values(&sorted), values(&expected));
+        assert_eq!(values(&sorted).len(), 1000);
+        assert_eq!(values(&sorted).last(), Some(&None));
+
+        Ok(())
+    }
+}
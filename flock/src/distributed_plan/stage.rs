@@ -15,13 +15,25 @@
 //! query statement.
 
 extern crate daggy;
-use crate::error::{FlockError, Result};
+use crate::configs::{FLOCK_AGGREGATE_FAN_IN, FLOCK_FUNCTION_CONCURRENCY};
+use crate::datasource::side_input::unmark_side_inputs;
+use crate::distributed_plan::combine::{combiner_fan_ins, CombineAggregateExec};
+use crate::distributed_plan::range::{RangePartitionExec, RangeSampler};
+use crate::error::Result;
 use crate::runtime::context::{CloudFunctionType, ExecutionContext};
 use daggy::{Dag, NodeIndex, Walker};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::cross_join::CrossJoinExec;
 use datafusion::physical_plan::displayable;
+use datafusion::physical_plan::hash_aggregate::{AggregateMode, HashAggregateExec};
+use datafusion::physical_plan::hash_join::HashJoinExec;
+use datafusion::physical_plan::limit::{GlobalLimitExec, LocalLimitExec};
 use datafusion::physical_plan::memory::MemoryExec;
-use datafusion::physical_plan::ExecutionPlan;
-use serde_json::Value;
+use datafusion::physical_plan::sort::SortExec;
+use datafusion::physical_plan::windows::WindowAggExec;
+use datafusion::physical_plan::{Distribution, ExecutionPlan};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
@@ -205,18 +217,57 @@ impl QueryDag {
         children
     }
 
-    /// Return all nodes in the dag. The order is topological.
-    pub fn get_all_stages(&self) -> Vec<&QueryStage> {
-        let mut stages = vec![];
-        let count = self.node_count();
-        stages.push(self.get_node(NodeIndex::new(count - 1)).unwrap());
-
+    /// Return the stage that consumes the output of the given node, or none if
+    /// the node is the root stage of the query.
+    pub fn get_parent(&self, node: NodeIndex) -> Option<NodeIndex> {
         self.dag
-            .recursive_walk(NodeIndex::new(count - 1), |g, n| g.parents(n).walk_next(g))
+            .parents(node)
             .iter(&self.dag)
-            .for_each(|(_, n)| stages.push(self.dag.node_weight(n).unwrap()));
+            .map(|(_, n)| n)
+            .next()
+    }
+
+    /// Return the input stages of the given node, from left to right.
+    pub fn get_inputs(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut inputs = self
+            .dag
+            .children(node)
+            .iter(&self.dag)
+            .map(|(_, n)| n)
+            .collect::<Vec<_>>();
+        inputs.sort();
+        inputs
+    }
+
+    /// Return the leaf stages of the dag, which read from the data sources.
+    pub fn get_leaves(&self) -> Vec<NodeIndex> {
+        (0..self.node_count())
+            .map(NodeIndex::new)
+            .filter(|n| self.get_inputs(*n).is_empty())
+            .collect()
+    }
 
-        stages
+    /// Return all nodes in the dag. The order is topological: each stage comes
+    /// after all of its input stages, and the root stage is the last one.
+    pub fn get_all_stages(&self) -> Vec<&QueryStage> {
+        let mut order = vec![];
+        if self.node_count() > 0 {
+            self.post_order(NodeIndex::new(0), &mut order);
+        }
+        order
+            .into_iter()
+            .map(|n| self.get_node(n).unwrap())
+            .collect()
+    }
+
+    /// Visit the input stages of the given node before the node itself.
+    fn post_order(&self, node: NodeIndex, order: &mut Vec<NodeIndex>) {
+        for input in self.get_inputs(node) {
+            self.post_order(input, order);
+        }
+        if !order.contains(&node) {
+            order.push(node);
+        }
     }
 
     /// Return the internal daggy.
@@ -225,16 +276,20 @@ impl QueryDag {
     }
 
     /// Add a new node to the `QueryDag`.
+    ///
+    /// The runtime feeds the data sources of a stage in place, so each stage
+    /// gets its own copy of the subplans instead of sharing the operators with
+    /// the query plan.
     fn insert(
         &mut self,
         parent: NodeIndex,
-        nodes: Vec<Value>,
+        plans: Vec<Arc<dyn ExecutionPlan>>,
         function_type: CloudFunctionType,
     ) -> Result<NodeIndex> {
-        let stage = nodes
-            .into_iter()
-            .map(|node| serde_json::from_value(node).unwrap())
-            .collect();
+        let stage = plans
+            .iter()
+            .map(|plan| Ok(serde_json::from_value(serde_json::to_value(plan)?)?))
+            .collect::<Result<Vec<Arc<dyn ExecutionPlan>>>>()?;
         if parent == NodeIndex::end() {
             Ok(self.add_node(QueryStage {
                 stage,
@@ -257,113 +312,333 @@ impl QueryDag {
 
 /// Build a DAG from a query plan.
 ///
+/// The plan is cut at the exchange operators, where the data has to be
+/// redistributed across the cloud functions:
+///
+/// - `HashAggregateExec` in the final mode and `SortExec` gather their inputs
+///   into a function group.
+/// - `WindowAggExec` gathers its input into a function group, unless its input
+///   is already sorted by a `SortExec` of the stage below.
+/// - `HashJoinExec` and `CrossJoinExec` read both of their inputs from the
+///   stage below. If the inputs are computed by stages of their own, the join
+///   gathers them into a function group instead.
+/// - `BroadcastJoinExec` runs in the same stage as its probe side, since the
+///   side input on its build side is read by the functions of the stage.
+/// - `CombineAggregateExec` merges some of the partial aggregates of its input
+///   in a function group.
+///
+/// All the other operators, such as `CoalescePartitionsExec`,
+/// `RepartitionExec`, `RangePartitionExec` and `UnionExec`, run in the same
+/// stage as their inputs.
+///
+/// The `ORDER BY` of the query is distributed before the plan is cut, see
+/// [`distribute_sort`].
+///
 /// # Arguments
 /// * `plan` - The query plan.
 ///
 /// # Returns
 /// * `QueryDag` - the DAG representation of the query plan.
 pub fn build_query_dag(plan: Arc<dyn ExecutionPlan>) -> Result<QueryDag> {
-    build_query_dag_from_serde_json(plan)
+    build_query_dag_with_fragments(plan, 1)
 }
 
-fn build_query_dag_from_serde_json(plan: Arc<dyn ExecutionPlan>) -> Result<QueryDag> {
+/// Build the DAG of the query stages for the data source that sends the given
+/// number of data fragments per window.
+///
+/// If a `Final` aggregate can't merge the partial aggregates of all the data
+/// fragments in a single function, the partial aggregates are first merged by
+/// a tree of combiners, see [`combine_aggregates`].
+///
+/// # Arguments
+/// * `plan` - The query plan.
+/// * `fragments` - The number of data fragments in each window.
+///
+/// # Returns
+/// * `QueryDag` - the DAG representation of the query plan.
+pub fn build_query_dag_with_fragments(
+    plan: Arc<dyn ExecutionPlan>,
+    fragments: usize,
+) -> Result<QueryDag> {
     let mut dag = QueryDag::new();
-    let mut root = serde_json::to_value(&plan).unwrap();
-    let mut json = &mut root;
-    let mut leaf = NodeIndex::end();
-    let mut curr = plan.clone();
-    loop {
-        match json["execution_plan"].as_str() {
-            Some("hash_aggregate_exec") => match json["mode"].as_str() {
-                Some("Final") | Some("FinalPartitioned") => {
-                    // Split the plan into two subplans
-                    let object = (*json["input"].take().as_object().ok_or_else(|| {
-                        FlockError::QueryStage(
-                            "Failed to parse input for HashAggregateExec".to_string(),
-                        )
-                    })?)
-                    .clone();
-                    // Add a input for the new subplan
-                    let input: Arc<dyn ExecutionPlan> = Arc::new(MemoryExec::try_new(
-                        &[],
-                        curr.children()[0].schema().clone(),
-                        None,
-                    )?);
-                    json["input"] = serde_json::to_value(input)?;
-                    // Add the new subplan to DAG
-                    leaf = dag.insert(leaf, vec![root], CloudFunctionType::Group)?;
-                    // Point to the next subplan
-                    root = Value::Object(object);
-                    json = &mut root;
-                }
-                Some("Partial") => json = &mut json["input"],
-                _ => {
-                    println!("{:?}", json["aggregate_mode"].as_str());
-                    return Err(FlockError::QueryStage(
-                        "Failed to parse aggregate mode for HashAggregateExec".to_string(),
-                    ));
-                }
-            },
-            Some("hash_join_exec") => {
-                let left_obj = (*json["left"].take().as_object().ok_or_else(|| {
-                    FlockError::QueryStage(
-                        "Failed to parse left input for HashJoinExec".to_string(),
-                    )
-                })?)
-                .clone();
-                let right_obj = (*json["right"].take().as_object().ok_or_else(|| {
-                    FlockError::QueryStage(
-                        "Failed to parse right input for HashJoinExec".to_string(),
-                    )
-                })?)
-                .clone();
-
-                let left: Arc<dyn ExecutionPlan> =
-                    Arc::new(MemoryExec::try_new(&[], curr.children()[0].schema(), None)?);
-                let right: Arc<dyn ExecutionPlan> =
-                    Arc::new(MemoryExec::try_new(&[], curr.children()[1].schema(), None)?);
-
-                json["left"] = serde_json::to_value(left)?;
-                json["right"] = serde_json::to_value(right)?;
-
-                leaf = dag.insert(leaf, vec![root], CloudFunctionType::Lambda)?;
-                dag.insert(
-                    leaf,
-                    vec![Value::Object(left_obj), Value::Object(right_obj)],
-                    CloudFunctionType::Lambda,
-                )?;
-                return Ok(dag);
+    let fan_ins = combiner_fan_ins(fragments, *FLOCK_AGGREGATE_FAN_IN);
+    let plan = combine_aggregates(plan, &fan_ins)?;
+    let plan = distribute_sort(plan, *FLOCK_FUNCTION_CONCURRENCY)?;
+    build_query_stage(&mut dag, NodeIndex::end(), vec![plan])?;
+    assert!(dag.node_count() >= 1);
+    Ok(dag)
+}
+
+/// Rewrites the `ORDER BY` at the root of the plan, so that the records are
+/// no longer sorted by a single function.
+///
+/// - `ORDER BY ... LIMIT k`: each partition of the input stage keeps its own
+///   top `k` records, and the final stage merges them into the global top `k`.
+/// - `ORDER BY`: each function of the input stage splits its own output into
+///   `ranges` ranges of the sort keys, and sends them straight to the final
+///   stage. Each function of the final stage sorts one range, and the sink
+///   concatenates the sorted ranges in order. The range boundaries are shared
+///   by all the functions, see
+///   [`RangeSampler`](crate::distributed_plan::RangeSampler).
+///
+/// If the sort keys can't be sampled from the data source, the input stage
+/// samples the boundaries by itself, which is only correct if the input stage
+/// runs on a single function. Otherwise, the sort is left as it is.
+///
+/// The rewritten plan of a range sort is only executed stage by stage, since
+/// the `SortExec` of each function reads a single range.
+fn distribute_sort(plan: Arc<dyn ExecutionPlan>, ranges: usize) -> Result<Arc<dyn ExecutionPlan>> {
+    let any = plan.as_any();
+    if let Some(limit) = any.downcast_ref::<GlobalLimitExec>() {
+        if let Some(sort) = limit.input().as_any().downcast_ref::<SortExec>() {
+            let top_k = Arc::new(CoalescePartitionsExec::new(Arc::new(LocalLimitExec::new(
+                Arc::new(SortExec::new_with_partitioning(
+                    sort.expr().to_vec(),
+                    partitioned_input(sort.input()),
+                    true,
+                )),
+                limit.limit(),
+            ))));
+            let sort = limit.input().with_new_children(vec![top_k])?;
+            return Ok(plan.with_new_children(vec![sort])?);
+        }
+    } else if let Some(sort) = any.downcast_ref::<SortExec>() {
+        if ranges > 1 && is_exchange_sort(&plan) {
+            let input = partitioned_input(sort.input());
+            let range = RangePartitionExec::try_new(sort.expr().to_vec(), input.clone(), ranges)?;
+            let sampler = RangeSampler::new(&range);
+            if source_schemas(&input).iter().any(|s| sampler.binds(s))
+                || stage_function_type(&input) != Some(CloudFunctionType::Group)
+            {
+                return Ok(plan.with_new_children(vec![Arc::new(range)])?);
             }
-            Some("sort_exec") => {
-                let object = (*json["input"].take().as_object().ok_or_else(|| {
-                    FlockError::QueryStage("Failed to parse input for SortExec".to_string())
-                })?)
-                .clone();
-                // Add a input for the new subplan
-                let input: Arc<dyn ExecutionPlan> = Arc::new(MemoryExec::try_new(
-                    &[],
-                    curr.children()[0].schema().clone(),
-                    None,
-                )?);
-                json["input"] = serde_json::to_value(input)?;
-                // Add the new subplan to DAG
-                leaf = dag.insert(leaf, vec![root], CloudFunctionType::Group)?;
-                // Point to the next subplan
-                root = Value::Object(object);
-                json = &mut root;
+        }
+    }
+    Ok(plan)
+}
+
+/// Returns the schemas of the data sources of the plan.
+fn source_schemas(plan: &Arc<dyn ExecutionPlan>) -> Vec<SchemaRef> {
+    let children = plan.children();
+    if children.is_empty() {
+        vec![plan.schema()]
+    } else {
+        children.iter().flat_map(source_schemas).collect()
+    }
+}
+
+/// Returns the cloud function type of the query stage that computes the
+/// operator, or none if the operator is computed by the leaf stage, see
+/// [`cut_query_stage`].
+fn stage_function_type(plan: &Arc<dyn ExecutionPlan>) -> Option<CloudFunctionType> {
+    let children = plan.children();
+    match exchange_type(plan) {
+        Some(_) if children.len() > 1 && children.iter().any(has_stage_boundary) => {
+            Some(CloudFunctionType::Group)
+        }
+        Some(exchange) => Some(exchange),
+        None => children.iter().find_map(stage_function_type),
+    }
+}
+
+/// Inserts a tree of combiners below each `Final` aggregate of the plan. The
+/// `i`-th combiner from the bottom up merges `fan_ins[i]` partial aggregates
+/// per function, and each combiner runs in its own function group.
+///
+/// `FinalPartitioned` aggregates are left as they are, since their input is
+/// already shuffled across a function group.
+fn combine_aggregates(
+    plan: Arc<dyn ExecutionPlan>,
+    fan_ins: &[usize],
+) -> Result<Arc<dyn ExecutionPlan>> {
+    if fan_ins.is_empty() || plan.children().is_empty() {
+        return Ok(plan);
+    }
+
+    let children = plan
+        .children()
+        .into_iter()
+        .map(|child| combine_aggregates(child, fan_ins))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(aggregate) = plan.as_any().downcast_ref::<HashAggregateExec>() {
+        if matches!(aggregate.mode(), AggregateMode::Final) {
+            let mut input = children[0].clone();
+            for fan_in in fan_ins {
+                input = Arc::new(CombineAggregateExec::try_new(aggregate, input, *fan_in)?);
             }
-            _ => json = &mut json["input"],
+            return Ok(plan.with_new_children(vec![input])?);
         }
-        if !json.is_object() {
-            break;
+    }
+
+    // Only the operators above a `Final` aggregate are rebuilt.
+    if plan
+        .children()
+        .iter()
+        .zip(children.iter())
+        .all(|(old, new)| Arc::ptr_eq(old, new))
+    {
+        Ok(plan)
+    } else {
+        Ok(plan.with_new_children(children)?)
+    }
+}
+
+/// Returns the input of a sort without merging its partitions.
+fn partitioned_input(input: &Arc<dyn ExecutionPlan>) -> Arc<dyn ExecutionPlan> {
+    if input.as_any().is::<CoalescePartitionsExec>() {
+        input.children()[0].clone()
+    } else {
+        input.clone()
+    }
+}
+
+/// Returns true if the sort merges all the partitions of its input, rather
+/// than sorting each partition on its own.
+fn is_exchange_sort(plan: &Arc<dyn ExecutionPlan>) -> bool {
+    matches!(
+        plan.required_child_distribution(),
+        Distribution::SinglePartition
+    )
+}
+
+/// The subplans of a query stage.
+type SubPlans = Vec<Arc<dyn ExecutionPlan>>;
+
+/// Adds a query stage for the given subplans to the DAG, and then adds the
+/// stages of its inputs as its children, from left to right.
+fn build_query_stage(dag: &mut QueryDag, parent: NodeIndex, plans: SubPlans) -> Result<()> {
+    let mut inputs = vec![];
+    let mut function_type = None;
+    let stage = plans
+        .iter()
+        .map(|plan| cut_query_stage(plan, &mut inputs, &mut function_type))
+        .collect::<Result<Vec<_>>>()?;
+
+    let node = dag.insert(
+        parent,
+        stage,
+        function_type.unwrap_or(CloudFunctionType::Lambda),
+    )?;
+    for input in inputs {
+        build_query_stage(dag, node, input)?;
+    }
+    Ok(())
+}
+
+/// Returns the input of a query stage, which is fed with the output of the
+/// former stage at runtime. It doesn't scan a side input, even if the former
+/// stage only outputs the columns of one.
+fn stage_input(child: &Arc<dyn ExecutionPlan>) -> Result<Arc<dyn ExecutionPlan>> {
+    let schema = Arc::new(unmark_side_inputs(&child.schema()));
+    Ok(Arc::new(MemoryExec::try_new(&[], schema, None)?))
+}
+
+/// Returns the cloud function type of the stage that ends with the operator, or
+/// none if the operator doesn't need to exchange its inputs across functions.
+fn exchange_type(plan: &Arc<dyn ExecutionPlan>) -> Option<CloudFunctionType> {
+    let any = plan.as_any();
+    if let Some(aggregate) = any.downcast_ref::<HashAggregateExec>() {
+        match aggregate.mode() {
+            AggregateMode::Partial => None,
+            _ => Some(CloudFunctionType::Group),
         }
-        curr = curr.children()[0].clone();
+    } else if any.is::<SortExec>() {
+        if is_exchange_sort(plan) {
+            Some(CloudFunctionType::Group)
+        } else {
+            None
+        }
+    } else if any.is::<CombineAggregateExec>() {
+        Some(CloudFunctionType::Group)
+    } else if any.is::<WindowAggExec>() {
+        if is_sorted(&plan.children()[0]) {
+            None
+        } else {
+            Some(CloudFunctionType::Group)
+        }
+    } else if any.is::<HashJoinExec>() || any.is::<CrossJoinExec>() {
+        Some(CloudFunctionType::Lambda)
+    } else {
+        None
     }
+}
 
-    dag.insert(leaf, vec![root], CloudFunctionType::Lambda)?;
-    assert!(dag.node_count() >= 1);
+/// Returns true if the subplan is sorted by a `SortExec` that only has
+/// partition-preserving operators above it.
+fn is_sorted(plan: &Arc<dyn ExecutionPlan>) -> bool {
+    let any = plan.as_any();
+    if any.is::<SortExec>() {
+        true
+    } else if any.is::<CoalescePartitionsExec>() || any.is::<CoalesceBatchesExec>() {
+        is_sorted(&plan.children()[0])
+    } else {
+        false
+    }
+}
 
-    Ok(dag)
+/// Returns true if the subplan needs to be split into more than one stage.
+fn has_stage_boundary(plan: &Arc<dyn ExecutionPlan>) -> bool {
+    exchange_type(plan).is_some() || plan.children().iter().any(has_stage_boundary)
+}
+
+/// Cuts the subplan at the boundaries of the query stage.
+///
+/// The inputs of an exchange operator are replaced with the stage inputs, and
+/// pushed to `inputs` as the subplans of the next stages. The function type
+/// of the stage is decided by the first exchange operator.
+///
+/// A join whose inputs are computed by different stages is a function group:
+/// each input stage sends its output separately, so the outputs have to be
+/// gathered in the same window before the join fires.
+fn cut_query_stage(
+    plan: &Arc<dyn ExecutionPlan>,
+    inputs: &mut Vec<SubPlans>,
+    function_type: &mut Option<CloudFunctionType>,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let children = plan.children();
+    if children.is_empty() {
+        return Ok(plan.clone());
+    }
+
+    let children = match exchange_type(plan) {
+        Some(exchange) => {
+            if children.len() > 1 && !children.iter().any(has_stage_boundary) {
+                // All the inputs are computed in the same stage.
+                function_type.get_or_insert(exchange);
+                inputs.push(children.clone());
+            } else {
+                // Each input is computed by its own stages.
+                function_type.get_or_insert(if children.len() > 1 {
+                    CloudFunctionType::Group
+                } else {
+                    exchange
+                });
+                children
+                    .iter()
+                    .for_each(|child| inputs.push(vec![child.clone()]));
+            }
+            children
+                .iter()
+                .map(stage_input)
+                .collect::<Result<Vec<_>>>()?
+        }
+        None => children
+            .iter()
+            .map(|child| cut_query_stage(child, inputs, function_type))
+            .collect::<Result<Vec<_>>>()?,
+    };
+
+    match plan.as_any().downcast_ref::<SortExec>() {
+        // `SortExec::with_new_children` always merges the partitions of its
+        // input, so the per-partition sort of a top-k is rebuilt as is.
+        Some(sort) if !is_exchange_sort(plan) => Ok(Arc::new(SortExec::new_with_partitioning(
+            sort.expr().to_vec(),
+            children[0].clone(),
+            true,
+        ))),
+        _ => Ok(plan.with_new_children(children)?),
+    }
 }
 
 #[cfg(test)]
@@ -383,6 +658,10 @@ mod tests {
     use std::sync::Arc;
 
     async fn quick_init(sql: &str) -> Result<QueryDag> {
+        QueryDag::from(quick_plan(sql).await?)
+    }
+
+    async fn quick_plan(sql: &str) -> Result<Arc<dyn ExecutionPlan>> {
         let schema = Arc::new(Schema::new(vec![
             Field::new("c1", DataType::Int64, false),
             Field::new("c2", DataType::Float64, false),
@@ -433,7 +712,7 @@ mod tests {
             displayable(physical_plan.as_ref()).indent()
         );
 
-        QueryDag::from(physical_plan)
+        Ok(physical_plan)
     }
 
     // Mem -> Proj
@@ -569,7 +848,7 @@ mod tests {
 
     #[tokio::test]
     async fn aggregate_query_no_group_by() -> Result<()> {
-        let sql = concat!("SELECT MIN(c1), AVG(c4), COUNT(c3) FROM test_table");
+        let sql = "SELECT MIN(c1), AVG(c4), COUNT(c3) FROM test_table";
         #[rustfmt::skip]
         // === Physical Plan ===
         // ProjectionExec: expr=[MIN(test_table.c1)@0 as MIN(test_table.c1), AVG(test_table.c4)@1 as AVG(test_table.c4), COUNT(test_table.c3)@2 as COUNT(test_table.c3)]
@@ -582,6 +861,41 @@ mod tests {
         Ok(())
     }
 
+    // Aggregate + Combiner Tree
+    // Mem -> HashAgg -> Combine -> Combine -> HashAgg -> Proj
+    #[tokio::test]
+    async fn aggregate_query_combiner_tree() -> Result<()> {
+        let sql = "SELECT MIN(c1), AVG(c4), COUNT(c3) FROM test_table";
+        let plan = quick_plan(sql).await?;
+
+        // The final aggregate merges all the partial aggregates.
+        let dag = build_query_dag_with_fragments(plan.clone(), *FLOCK_AGGREGATE_FAN_IN)?;
+        assert_eq!(2, dag.node_count());
+
+        // Two levels of combiners merge the partial aggregates first.
+        let fragments = FLOCK_AGGREGATE_FAN_IN.pow(2) + 1;
+        let dag = build_query_dag_with_fragments(plan, fragments)?;
+        assert_eq!(4, dag.node_count());
+
+        let fan_in = combiner_fan_ins(fragments, *FLOCK_AGGREGATE_FAN_IN)[0];
+        let stages = dag.get_all_stages();
+        assert!(stages[0]
+            .get_plan_str()
+            .contains("HashAggregateExec: mode=Partial"));
+        for stage in &stages[1..3] {
+            let plan = stage.get_plan_str();
+            assert!(plan.starts_with(&format!("CombineAggregateExec: fan_in={}", fan_in)));
+            assert!(plan.contains("MemoryExec"));
+            assert_eq!(stage.get_function_type(), CloudFunctionType::Group);
+        }
+        assert!(stages[3]
+            .get_plan_str()
+            .contains("HashAggregateExec: mode=Final"));
+        assert!(!stages[3].get_plan_str().contains("CombineAggregateExec"));
+
+        Ok(())
+    }
+
     // Aggregate + Group By
     // Mem -> HashAgg -> HashAgg -> Proj
     #[tokio::test]
@@ -633,7 +947,74 @@ mod tests {
         //     ProjectionExec: expr=[c1@0 as c1, c2@1 as c2, c3@2 as c3]
         //       RepartitionExec: partitioning=RoundRobinBatch(16)
         //         MemoryExec: partitions=1, partition_sizes=[1]
-        quick_init(sql).await?;
+        let dag = quick_init(sql).await?;
+
+        // The sort is distributed across a function group by ranges.
+        assert_eq!(2, dag.node_count());
+        let root = NodeIndex::new(0);
+        let plan = dag.get_plan_str(root);
+        assert!(plan.starts_with("SortExec"));
+        assert!(!plan.contains("RangePartitionExec"));
+        assert_eq!(
+            dag.get_node(root).unwrap().get_function_type(),
+            CloudFunctionType::Group
+        );
+
+        // Each function of the input stage splits its own output into the
+        // ranges, so no function gathers all the records before the sort.
+        let input = dag.get_inputs(root)[0];
+        let plan = dag.get_plan_str(input);
+        assert!(plan.starts_with(&format!(
+            "RangePartitionExec: partitions={}",
+            *FLOCK_FUNCTION_CONCURRENCY
+        )));
+        assert!(plan.contains("ProjectionExec"));
+        assert!(!plan.contains("SortExec"));
+        assert_eq!(
+            dag.get_node(input).unwrap().get_function_type(),
+            CloudFunctionType::Lambda
+        );
+
+        Ok(())
+    }
+
+    // Sort by the keys that are computed by the query
+    #[tokio::test]
+    async fn sort_by_computed_key() -> Result<()> {
+        // The input stage runs on a single function, so it samples the computed
+        // keys of the whole window by itself.
+        let sql = "SELECT c1, c2 * 2 AS d FROM test_table ORDER BY d";
+        let dag = quick_init(sql).await?;
+        assert_eq!(2, dag.node_count());
+        let input = dag.get_inputs(NodeIndex::new(0))[0];
+        assert!(dag.get_plan_str(input).starts_with("RangePartitionExec"));
+        assert_eq!(
+            dag.get_node(input).unwrap().get_function_type(),
+            CloudFunctionType::Lambda
+        );
+
+        // The aggregates are computed by a function group, and no function of
+        // the group sees the whole window, so the sort isn't distributed.
+        let sql = "SELECT c3, COUNT(c1) AS n FROM test_table GROUP BY c3 ORDER BY n";
+        let dag = quick_init(sql).await?;
+        for i in 0..dag.node_count() {
+            assert!(!dag
+                .get_plan_str(NodeIndex::new(i))
+                .contains("RangePartitionExec"));
+        }
+        assert!(dag.get_plan_str(NodeIndex::new(0)).starts_with("SortExec"));
+
+        // The group keys are read from the data source, so they are sampled by
+        // the data source instead.
+        let sql = "SELECT c3, COUNT(c1) AS n FROM test_table GROUP BY c3 ORDER BY c3";
+        let dag = quick_init(sql).await?;
+        let input = dag.get_inputs(NodeIndex::new(0))[0];
+        assert!(dag.get_plan_str(input).starts_with("RangePartitionExec"));
+        assert_eq!(
+            dag.get_node(input).unwrap().get_function_type(),
+            CloudFunctionType::Group
+        );
+
         Ok(())
     }
 
@@ -654,7 +1035,26 @@ mod tests {
         //       ProjectionExec: expr=[c1@0 as c1, c2@1 as c2, c3@2 as c3]
         //         RepartitionExec: partitioning=RoundRobinBatch(16)
         //           MemoryExec: partitions=1, partition_sizes=[1]
-        quick_init(sql).await?;
+        let dag = quick_init(sql).await?;
+
+        // The final stage merges the top 4 records of each partition.
+        assert_eq!(2, dag.node_count());
+        let root = NodeIndex::new(0);
+        let plan = dag.get_plan_str(root);
+        assert!(plan.starts_with("GlobalLimitExec: limit=4"));
+        assert!(plan.contains("SortExec"));
+        assert!(!plan.contains("LocalLimitExec"));
+
+        let input = dag.get_inputs(root)[0];
+        let plan = dag.get_plan_str(input);
+        assert!(plan.contains("LocalLimitExec: limit=4"));
+        assert!(plan.contains("SortExec"));
+        assert!(plan.contains("ProjectionExec"));
+        assert_eq!(
+            dag.get_node(input).unwrap().get_function_type(),
+            CloudFunctionType::Lambda
+        );
+
         Ok(())
     }
 
@@ -800,6 +1200,118 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn union_all() -> Result<()> {
+        let sql = concat!(
+            "SELECT c1 FROM test_table WHERE c2 < 95 ",
+            "UNION ALL ",
+            "SELECT c1 FROM test_table WHERE c2 > 99"
+        );
+        let dag = quick_init(sql).await?;
+
+        // The union runs in the same stage as its inputs.
+        assert_eq!(1, dag.node_count());
+        let plan = dag.get_plan_str(NodeIndex::new(0));
+        assert!(plan.contains("UnionExec"));
+        assert_eq!(plan.matches("FilterExec").count(), 2);
+        assert_eq!(plan.matches("MemoryExec").count(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn union_aggregates() -> Result<()> {
+        let sql = concat!(
+            "SELECT c3, COUNT(c1) FROM test_table GROUP BY c3 ",
+            "UNION ALL ",
+            "SELECT c5, COUNT(c1) FROM test_table GROUP BY c5"
+        );
+        let dag = quick_init(sql).await?;
+
+        // Each input of the union is aggregated by its own stages.
+        assert_eq!(3, dag.node_count());
+        let root = NodeIndex::new(0);
+        let plan = dag.get_plan_str(root);
+        assert!(plan.contains("UnionExec"));
+        assert_eq!(
+            plan.matches("HashAggregateExec: mode=FinalPartitioned")
+                .count(),
+            2
+        );
+        assert_eq!(
+            dag.get_node(root).unwrap().get_function_type(),
+            CloudFunctionType::Group
+        );
+
+        let inputs = dag.get_inputs(root);
+        assert_eq!(inputs.len(), 2);
+        for input in inputs {
+            let plan = dag.get_plan_str(input);
+            assert!(plan.contains("HashAggregateExec: mode=Partial"));
+            assert!(!plan.contains("UnionExec"));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cross_join() -> Result<()> {
+        let sql = concat!(
+            "SELECT a.c1, b.c3 ",
+            "FROM test_table AS a CROSS JOIN test_table AS b"
+        );
+        let dag = quick_init(sql).await?;
+
+        // Both inputs of the cross join are computed in the same stage.
+        assert_eq!(2, dag.node_count());
+        let root = NodeIndex::new(0);
+        let plan = dag.get_plan_str(root);
+        assert!(plan.contains("CrossJoinExec"));
+        assert_eq!(plan.matches("MemoryExec").count(), 2);
+        assert_eq!(
+            dag.get_node(root).unwrap().get_function_type(),
+            CloudFunctionType::Lambda
+        );
+
+        let inputs = dag.get_inputs(root);
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(dag.get_node(inputs[0]).unwrap().len(), 2);
+        assert!(!dag.get_plan_str(inputs[0]).contains("CrossJoinExec"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn window_aggregate() -> Result<()> {
+        // The window function sorts its input by the partition keys.
+        let sql = "SELECT c3, ROW_NUMBER() OVER (PARTITION BY c3 ORDER BY c1) FROM test_table";
+        let dag = quick_init(sql).await?;
+        assert_eq!(2, dag.node_count());
+        let root = NodeIndex::new(0);
+        let plan = dag.get_plan_str(root);
+        assert!(plan.contains("WindowAggExec"));
+        assert!(plan.contains("SortExec"));
+        assert_eq!(
+            dag.get_node(root).unwrap().get_function_type(),
+            CloudFunctionType::Group
+        );
+
+        // The window function reads all the records in a single partition.
+        let sql = "SELECT c3, COUNT(c1) OVER () FROM test_table";
+        let dag = quick_init(sql).await?;
+        assert_eq!(2, dag.node_count());
+        let root = NodeIndex::new(0);
+        assert!(dag.get_plan_str(root).contains("WindowAggExec"));
+        assert_eq!(
+            dag.get_node(root).unwrap().get_function_type(),
+            CloudFunctionType::Group
+        );
+        let inputs = dag.get_inputs(root);
+        assert!(!dag.get_plan_str(inputs[0]).contains("WindowAggExec"));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn simple_join() -> Result<()> {
         let schema1 = Arc::new(Schema::new(vec![
@@ -948,4 +1460,173 @@ mod tests {
 
         Ok(())
     }
+
+    async fn multi_join_init(sql: &str) -> Result<(QueryDag, Vec<RecordBatch>)> {
+        let mut ctx = ExecutionContext::new();
+        for (table, key, value) in [("t1", "a", "b"), ("t2", "c", "d"), ("t3", "e", "f")] {
+            let schema = Arc::new(Schema::new(vec![
+                Field::new(key, DataType::Utf8, false),
+                Field::new(value, DataType::Int32, false),
+            ]));
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(StringArray::from(vec!["a", "b", "c", "d"])),
+                    Arc::new(Int32Array::from(vec![1, 10, 10, 100])),
+                ],
+            )?;
+            let provider = MemTable::try_new(schema, vec![vec![batch]])?;
+            ctx.register_table(table, Arc::new(provider))?;
+        }
+
+        let plan = ctx.create_logical_plan(sql)?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+        println!(
+            "=== Physical Plan ===\n{}\n",
+            displayable(plan.as_ref()).indent()
+        );
+
+        let dag = QueryDag::from(plan.clone())?;
+        for (i, stage) in dag.get_all_stages().iter().enumerate() {
+            println!("=== Query Stage {} ===\n{}", i, stage.get_plan_str());
+        }
+        Ok((dag, collect(plan).await?))
+    }
+
+    #[tokio::test]
+    async fn multi_way_join() -> Result<()> {
+        let sql = concat!(
+            "SELECT a, b, d, f ",
+            "FROM t1 JOIN t2 ON a = c ",
+            "JOIN t3 ON c = e"
+        );
+        let (dag, batches) = multi_join_init(sql).await?;
+
+        // The upper join reads from the lower join and the third table:
+        //
+        //                 +-----------+
+        //                 |  join(0)  |
+        //                 +-----+-----+
+        //                       |
+        //            +----------+----------+
+        //            |                     |
+        //      +-----v-----+         +-----v-----+
+        //      |  join(1)  |         |  scan(3)  |
+        //      +-----+-----+         +-----------+
+        //            |
+        //      +-----v-----+
+        //      | scans(2)  |
+        //      +-----------+
+        assert_eq!(4, dag.node_count());
+        assert_eq!(3, dag.edge_count());
+
+        let root = NodeIndex::new(0);
+        let plan = dag.get_plan_str(root);
+        assert!(plan.matches("HashJoinExec").count() == 1);
+        assert!(plan.matches("MemoryExec").count() == 2);
+        assert_eq!(dag.get_parent(root), None);
+
+        // The upper join gathers the outputs of its input stages.
+        assert_eq!(
+            dag.get_node(root).unwrap().get_function_type(),
+            CloudFunctionType::Group
+        );
+
+        // Both inputs of the upper join are stages of their own.
+        let inputs = dag.get_inputs(root);
+        assert_eq!(inputs.len(), 2);
+        let (join, scan) = if dag.get_plan_str(inputs[0]).contains("HashJoinExec") {
+            (inputs[0], inputs[1])
+        } else {
+            (inputs[1], inputs[0])
+        };
+        assert!(!dag.get_plan_str(scan).contains("HashJoinExec"));
+        assert!(dag.get_plan_str(scan).matches("MemoryExec").count() == 1);
+        assert_eq!(dag.get_parent(join), Some(root));
+        assert_eq!(dag.get_parent(scan), Some(root));
+
+        // The inputs of the lower join are computed in the same stage.
+        assert_eq!(
+            dag.get_node(join).unwrap().get_function_type(),
+            CloudFunctionType::Lambda
+        );
+        let scans = dag.get_inputs(join);
+        assert_eq!(scans.len(), 1);
+        assert_eq!(dag.get_node(scans[0]).unwrap().len(), 2);
+        assert_eq!(dag.get_parent(scans[0]), Some(join));
+
+        let mut leaves = dag.get_leaves();
+        leaves.sort();
+        let mut expected = vec![scans[0], scan];
+        expected.sort();
+        assert_eq!(leaves, expected);
+
+        // The root stage comes after all the other stages.
+        let stages = dag.get_all_stages();
+        assert_eq!(stages.len(), 4);
+        assert_eq!(stages[3].get_plan_str(), plan);
+
+        crate::assert_batches_sorted_eq!(
+            &[
+                "+---+-----+-----+-----+",
+                "| a | b   | d   | f   |",
+                "+---+-----+-----+-----+",
+                "| a | 1   | 1   | 1   |",
+                "| b | 10  | 10  | 10  |",
+                "| c | 10  | 10  | 10  |",
+                "| d | 100 | 100 | 100 |",
+                "+---+-----+-----+-----+",
+            ],
+            &batches
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_with_aggregate_input() -> Result<()> {
+        let sql = concat!(
+            "SELECT a, b, cnt ",
+            "FROM t1 JOIN (SELECT e, COUNT(f) AS cnt FROM t3 GROUP BY e) AS q ON a = e"
+        );
+        let (dag, _) = multi_join_init(sql).await?;
+
+        // The aggregation below the join is split into its own stages.
+        assert_eq!(4, dag.node_count());
+        assert_eq!(3, dag.edge_count());
+
+        let root = NodeIndex::new(0);
+        assert!(dag.get_plan_str(root).contains("HashJoinExec"));
+        assert_eq!(
+            dag.get_node(root).unwrap().get_function_type(),
+            CloudFunctionType::Group
+        );
+
+        let inputs = dag.get_inputs(root);
+        assert_eq!(inputs.len(), 2);
+        let (agg, scan) = if dag.get_plan_str(inputs[0]).contains("HashAggregateExec") {
+            (inputs[0], inputs[1])
+        } else {
+            (inputs[1], inputs[0])
+        };
+        assert!(dag
+            .get_plan_str(agg)
+            .contains("HashAggregateExec: mode=FinalPartitioned"));
+        assert_eq!(
+            dag.get_node(agg).unwrap().get_function_type(),
+            CloudFunctionType::Group
+        );
+        assert!(dag.get_inputs(scan).is_empty());
+
+        let partial = dag.get_inputs(agg);
+        assert_eq!(partial.len(), 1);
+        assert!(dag
+            .get_plan_str(partial[0])
+            .contains("HashAggregateExec: mode=Partial"));
+        assert_eq!(dag.get_parent(partial[0]), Some(agg));
+        assert_eq!(dag.get_leaves().len(), 2);
+
+        Ok(())
+    }
 }
@@ -17,6 +17,9 @@
 //! For example, it can be used to reduce the size of the payload sent between
 //! the cloud functions, and reduce the size of all environment variables to
 //! less than 4KB as well.
+//!
+//! [`content_hash`] encodes the content into a short code, which names the
+//! cloud functions of a query and the execution plans in the object store.
 
 use super::error::{FlockError, Result};
 use lz4::block::CompressionMode;
@@ -99,6 +102,38 @@ impl Encoding {
     }
 }
 
+/// The offset basis of the 128-bit FNV-1a hash.
+const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+
+/// The prime of the 128-bit FNV-1a hash.
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// The digits of the content hash, which are all valid in cloud function names
+/// and object keys.
+const BASE62: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// The length of the content hash. 22 base62 digits hold a 128-bit hash.
+pub const CONTENT_HASH_LEN: usize = 22;
+
+/// Hashes the content with the 128-bit FNV-1a, and encodes the hash in
+/// [`CONTENT_HASH_LEN`] base62 digits. Unlike the `DefaultHasher` of the
+/// standard library, the hash doesn't depend on the toolchain or the platform.
+pub fn content_hash(content: &[u8]) -> String {
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in content {
+        hash ^= *byte as u128;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    (0..CONTENT_HASH_LEN)
+        .map(|_| {
+            let digit = BASE62[(hash % 62) as usize] as char;
+            hash /= 62;
+            digit
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -186,4 +221,14 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn stable_content_hash() -> Result<()> {
+        // The hash names the deployed functions, so it must never change
+        // across toolchains and releases.
+        assert_eq!(content_hash(b""), "PI26E71IyrnBRphtFh1WI3");
+        assert_eq!(content_hash(b"flock"), "ej2QMBepMcMCfj5phOwGI1");
+        assert_ne!(content_hash(b"flock"), content_hash(b"Flock"));
+        Ok(())
+    }
 }
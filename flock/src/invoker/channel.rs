@@ -0,0 +1,187 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The in-process function invoker.
+
+use super::{is_sync_call, FunctionInvoker, RetryPolicy};
+use crate::error::{FlockError, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::{mpsc, oneshot};
+
+/// A function invocation received by the in-process function. The response
+/// channel is only provided for synchronous invocations.
+pub type Invocation = (Bytes, Option<oneshot::Sender<Result<Bytes>>>);
+
+lazy_static! {
+    /// The in-process functions, keyed by the namespace and the function name.
+    static ref CHANNELS: Mutex<HashMap<(String, String), mpsc::UnboundedSender<Invocation>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Invokes the functions running in the same process through tokio channels.
+///
+/// The functions are registered in a process-wide registry. The namespace
+/// isolates the functions of different queries or tests running in the same
+/// process.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelInvoker {
+    /// The namespace of the functions.
+    pub namespace: String,
+    /// The retry policy of synchronous invocations.
+    pub retry:     RetryPolicy,
+}
+
+#[async_trait]
+#[typetag::serde(name = "channel_invoker")]
+impl FunctionInvoker for ChannelInvoker {
+    fn name(&self) -> String {
+        "ChannelInvoker".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn invoke(
+        &self,
+        function_name: &str,
+        invocation_type: &str,
+        payload: Bytes,
+    ) -> Result<Bytes> {
+        if !is_sync_call(invocation_type) {
+            self.send(function_name, (payload, None))?;
+            return Ok(Bytes::new());
+        }
+
+        self.retry
+            .run(function_name, || async {
+                let (tx, rx) = oneshot::channel();
+                self.send(function_name, (payload.clone(), Some(tx)))?;
+                rx.await.map_err(|e| FlockError::Internal(e.to_string()))?
+            })
+            .await
+    }
+}
+
+impl ChannelInvoker {
+    /// Creates a new ChannelInvoker in the given namespace.
+    pub fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_owned(),
+            retry:     RetryPolicy::default(),
+        }
+    }
+
+    /// Registers a function in the namespace and returns the receiver of its
+    /// invocations. The function is served by the caller, which must reply to
+    /// the synchronous invocations.
+    pub fn register(&self, function_name: &str) -> mpsc::UnboundedReceiver<Invocation> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        CHANNELS
+            .lock()
+            .unwrap()
+            .insert((self.namespace.clone(), function_name.to_owned()), tx);
+        rx
+    }
+
+    /// Removes all the functions in the namespace.
+    pub fn unregister_all(&self) {
+        CHANNELS
+            .lock()
+            .unwrap()
+            .retain(|(namespace, _), _| *namespace != self.namespace);
+    }
+
+    fn send(&self, function_name: &str, invocation: Invocation) -> Result<()> {
+        CHANNELS
+            .lock()
+            .unwrap()
+            .get(&(self.namespace.clone(), function_name.to_owned()))
+            .ok_or_else(|| {
+                FlockError::Internal(format!("Function {} doesn't exist.", function_name))
+            })?
+            .send(invocation)
+            .map_err(|e| FlockError::Internal(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configs::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn channel_invoker_sync_and_async() -> Result<()> {
+        let invoker = ChannelInvoker::new("channel_invoker_sync_and_async");
+        let mut rx = invoker.register("echo");
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some((payload, tx)) = rx.recv().await {
+                match tx {
+                    Some(tx) => tx.send(Ok(payload)).unwrap(),
+                    None => done_tx.send(payload).unwrap(),
+                }
+            }
+        });
+
+        let response = invoker
+            .invoke("echo", &FLOCK_LAMBDA_SYNC_CALL, Bytes::from("hello"))
+            .await?;
+        assert_eq!(response, Bytes::from("hello"));
+
+        let response = invoker
+            .invoke("echo", &FLOCK_LAMBDA_ASYNC_CALL, Bytes::from("world"))
+            .await?;
+        assert!(response.is_empty());
+        assert_eq!(done_rx.recv().await.unwrap(), Bytes::from("world"));
+
+        // The functions in other namespaces are invisible.
+        let other = ChannelInvoker::new("other");
+        assert!(other
+            .invoke("echo", &FLOCK_LAMBDA_ASYNC_CALL, Bytes::new())
+            .await
+            .is_err());
+
+        invoker.unregister_all();
+        assert!(invoker
+            .invoke("echo", &FLOCK_LAMBDA_ASYNC_CALL, Bytes::new())
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn channel_invoker_serde() -> Result<()> {
+        let invoker: Arc<dyn FunctionInvoker> = Arc::new(ChannelInvoker::new("serde"));
+        let json = serde_json::to_string(&invoker)?;
+        let invoker: Arc<dyn FunctionInvoker> = serde_json::from_str(&json)?;
+        assert_eq!(invoker.name(), "ChannelInvoker");
+        assert_eq!(
+            invoker
+                .as_any()
+                .downcast_ref::<ChannelInvoker>()
+                .unwrap()
+                .namespace,
+            "serde"
+        );
+        Ok(())
+    }
+}
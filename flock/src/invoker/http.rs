@@ -0,0 +1,273 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The local HTTP endpoint function invoker.
+
+use super::{is_sync_call, FunctionInvoker, RetryPolicy};
+use crate::error::{FlockError, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+lazy_static! {
+    /// The HTTP client shared by all the HTTP invokers.
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// Invokes the functions served by a local HTTP endpoint.
+///
+/// The endpoint exposes the same invocation API as AWS Lambda:
+///
+/// POST {endpoint}/2015-03-31/functions/{function_name}/invocations
+///
+/// with the invocation type in the `X-Amz-Invocation-Type` header, such as
+/// the AWS Lambda Runtime Interface Emulator or LocalStack.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpInvoker {
+    /// The base URL of the endpoint, e.g. `http://localhost:9000`.
+    pub endpoint: String,
+    /// The retry policy of synchronous invocations.
+    pub retry:    RetryPolicy,
+}
+
+#[async_trait]
+#[typetag::serde(name = "http_invoker")]
+impl FunctionInvoker for HttpInvoker {
+    fn name(&self) -> String {
+        "HttpInvoker".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn invoke(
+        &self,
+        function_name: &str,
+        invocation_type: &str,
+        payload: Bytes,
+    ) -> Result<Bytes> {
+        if !is_sync_call(invocation_type) {
+            return self.post(function_name, invocation_type, payload).await;
+        }
+
+        self.retry
+            .run(function_name, || {
+                self.post(function_name, invocation_type, payload.clone())
+            })
+            .await
+    }
+}
+
+impl HttpInvoker {
+    /// Creates a new HttpInvoker for the given endpoint.
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            retry:    RetryPolicy::default(),
+        }
+    }
+
+    /// Returns the invocation URL of the function.
+    pub fn url(&self, function_name: &str) -> String {
+        format!(
+            "{}/2015-03-31/functions/{}/invocations",
+            self.endpoint, function_name
+        )
+    }
+
+    async fn post(
+        &self,
+        function_name: &str,
+        invocation_type: &str,
+        payload: Bytes,
+    ) -> Result<Bytes> {
+        let response = HTTP_CLIENT
+            .post(self.url(function_name))
+            .header("X-Amz-Invocation-Type", invocation_type)
+            .body(payload)
+            .send()
+            .await
+            .map_err(|e| FlockError::Execution(e.to_string()))?;
+
+        let status = response.status();
+        // The function error is reported in the header by AWS Lambda.
+        let function_error = response.headers().get("X-Amz-Function-Error").cloned();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| FlockError::Execution(e.to_string()))?;
+
+        if !status.is_success() || function_error.is_some() {
+            return Err(FlockError::Execution(format!(
+                "Function {} failed with status {}: {}",
+                function_name,
+                status,
+                String::from_utf8_lossy(&body)
+            )));
+        }
+
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configs::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// A request received by the mock endpoint: the request line, the
+    /// invocation type and the body.
+    type Request = (String, String, Vec<u8>);
+
+    /// Serves the given responses (status line, extra header and body) one
+    /// per connection, and returns the endpoint and the received requests.
+    fn mock_endpoint(
+        responses: Vec<(&'static str, &'static str, &'static str)>,
+    ) -> (String, mpsc::Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (status, header, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut invocation_type = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap();
+                    match name.to_lowercase().as_str() {
+                        "x-amz-invocation-type" => invocation_type = value.trim().to_owned(),
+                        "content-length" => content_length = value.trim().parse().unwrap(),
+                        _ => {}
+                    }
+                }
+                let mut payload = vec![0; content_length];
+                reader.read_exact(&mut payload).unwrap();
+                tx.send((request_line.trim_end().to_owned(), invocation_type, payload))
+                    .unwrap();
+
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    header,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (endpoint, rx)
+    }
+
+    fn invoker(endpoint: &str) -> HttpInvoker {
+        let mut invoker = HttpInvoker::new(&format!("{}/", endpoint));
+        invoker.retry = RetryPolicy {
+            max_retries: 1,
+            max_backoff: 1,
+        };
+        invoker
+    }
+
+    #[tokio::test]
+    async fn http_invoker_sync_call() -> Result<()> {
+        let (endpoint, requests) = mock_endpoint(vec![
+            ("500 Internal Server Error", "", "busy"),
+            ("200 OK", "", "world"),
+        ]);
+        let invoker = invoker(&endpoint);
+        assert_eq!(invoker.endpoint, endpoint);
+
+        let response = invoker
+            .invoke("echo", &FLOCK_LAMBDA_SYNC_CALL, Bytes::from("hello"))
+            .await?;
+        assert_eq!(response, Bytes::from("world"));
+
+        // The failed invocation is retried with the same request.
+        for _ in 0..2 {
+            let (request_line, invocation_type, payload) = requests.recv().unwrap();
+            assert_eq!(
+                request_line,
+                "POST /2015-03-31/functions/echo/invocations HTTP/1.1"
+            );
+            assert_eq!(invocation_type, *FLOCK_LAMBDA_SYNC_CALL);
+            assert_eq!(payload, b"hello");
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn http_invoker_function_error() -> Result<()> {
+        let (endpoint, requests) = mock_endpoint(vec![
+            ("200 OK", "X-Amz-Function-Error: Unhandled\r\n", "oops"),
+            ("200 OK", "X-Amz-Function-Error: Unhandled\r\n", "oops"),
+        ]);
+
+        match invoker(&endpoint)
+            .invoke("echo", &FLOCK_LAMBDA_SYNC_CALL, Bytes::from("hello"))
+            .await
+        {
+            Err(FlockError::Execution(e)) => {
+                assert!(e.starts_with("Sync invocation failed after 1 retries"));
+                assert!(e.ends_with("oops"));
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert_eq!(requests.iter().count(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn http_invoker_async_call() -> Result<()> {
+        let (endpoint, requests) = mock_endpoint(vec![
+            ("202 Accepted", "", ""),
+            ("500 Internal Server Error", "", "busy"),
+        ]);
+        let invoker = invoker(&endpoint);
+
+        let response = invoker
+            .invoke("echo", &FLOCK_LAMBDA_ASYNC_CALL, Bytes::from("hello"))
+            .await?;
+        assert!(response.is_empty());
+        let (_, invocation_type, payload) = requests.recv().unwrap();
+        assert_eq!(invocation_type, *FLOCK_LAMBDA_ASYNC_CALL);
+        assert_eq!(payload, b"hello");
+
+        // The asynchronous invocations are not retried.
+        assert!(invoker
+            .invoke("echo", &FLOCK_LAMBDA_ASYNC_CALL, Bytes::new())
+            .await
+            .is_err());
+        requests.recv().unwrap();
+        assert!(requests.recv().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The AWS Lambda function invoker.
+
+use super::{FunctionInvoker, RetryPolicy};
+use crate::aws::lambda;
+use crate::error::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+/// Invokes AWS Lambda functions through the AWS Lambda client.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct LambdaInvoker {
+    /// The retry policy of synchronous invocations.
+    pub retry: RetryPolicy,
+}
+
+#[async_trait]
+#[typetag::serde(name = "lambda_invoker")]
+impl FunctionInvoker for LambdaInvoker {
+    fn name(&self) -> String {
+        "LambdaInvoker".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn invoke(
+        &self,
+        function_name: &str,
+        invocation_type: &str,
+        payload: Bytes,
+    ) -> Result<Bytes> {
+        let response = lambda::invoke_function_with_retry(
+            function_name,
+            invocation_type,
+            Some(payload),
+            &self.retry,
+        )
+        .await?;
+        Ok(response.payload.unwrap_or_default())
+    }
+}
+
+impl LambdaInvoker {
+    /// Creates a new LambdaInvoker.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Function invokers forward payloads from one cloud function to the next
+//! one in the dataflow pipeline.
+//!
+//! Out of the box, Flock bundles these invokers:
+//!
+//! - `LambdaInvoker`: invokes AWS Lambda functions through the AWS Lambda
+//!   client. This is the default invoker on the cloud.
+//!
+//! - `ChannelInvoker`: invokes functions running in the same process through
+//!   tokio channels. It is used to run the dataflow pipeline on the local
+//!   machine.
+//!
+//! - `HttpInvoker`: invokes functions served by a local HTTP endpoint with the
+//!   same API as AWS Lambda, such as the AWS Lambda Runtime Interface Emulator.
+//!
+//! All invokers support both the synchronous (`RequestResponse`) and the
+//! asynchronous (`Event`) invocations. Synchronous invocations are retried
+//! with the linear random backoff algorithm defined by `RetryPolicy`.
+
+mod channel;
+pub use channel::{ChannelInvoker, Invocation};
+
+mod http;
+pub use http::HttpInvoker;
+
+mod lambda;
+pub use lambda::LambdaInvoker;
+
+use crate::configs::*;
+use crate::error::{FlockError, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::debug;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The function invoker trait defines the interface for function invocations.
+#[async_trait]
+#[typetag::serde(tag = "invoker")]
+pub trait FunctionInvoker: Debug + Send + Sync {
+    /// The type of the function invoker.
+    fn name(&self) -> String;
+    /// Returns the function invoker as [`Any`](std::any::Any) so that it can
+    /// be downcast to a specific implementation.
+    fn as_any(&self) -> &dyn Any;
+    /// Invokes the function with the given payload.
+    ///
+    /// # Arguments
+    /// * `function_name` - The name of the function.
+    /// * `invocation_type` - The invocation type of the function.
+    ///   - `Event`: Asynchronous invocation.
+    ///   - `RequestResponse`: Synchronous invocation.
+    /// * `payload` - The payload to be passed to the function.
+    ///
+    /// # Returns
+    /// The response of the function for synchronous invocations, otherwise an
+    /// empty response.
+    async fn invoke(
+        &self,
+        function_name: &str,
+        invocation_type: &str,
+        payload: Bytes,
+    ) -> Result<Bytes>;
+}
+
+/// Returns the default function invoker, which invokes AWS Lambda functions.
+pub fn default_invoker() -> Arc<dyn FunctionInvoker> {
+    Arc::new(LambdaInvoker::new())
+}
+
+/// Returns true if the invocation type is synchronous.
+pub fn is_sync_call(invocation_type: &str) -> bool {
+    invocation_type != *FLOCK_LAMBDA_ASYNC_CALL
+}
+
+/// The retry policy of synchronous invocations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// The maximum number of retries.
+    pub max_retries: usize,
+    /// The maximum backoff in milliseconds.
+    pub max_backoff: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: *FLOCK_LAMBDA_MAX_RETRIES,
+            max_backoff: *FLOCK_LAMBDA_MAX_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs the invocation until it succeeds or the retries are exhausted.
+    ///
+    /// Error retry uses linear random backoff algorithm
+    /// min(50 * increase_factor + random_milliseconds, max_backoff)
+    ///
+    /// # Arguments
+    /// * `function_name` - The name of the function for logging.
+    /// * `invocation` - A closure that returns a new invocation attempt.
+    pub async fn run<T, F, Fut>(&self, function_name: &str, mut invocation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retries = 0;
+        let mut increase_factor = 0;
+        loop {
+            let error = match invocation().await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+            debug!("Function invocation error: {}", error);
+
+            if retries >= self.max_retries {
+                return Err(exhausted(error, self.max_retries));
+            }
+
+            debug!(
+                "Retrying #{}: {} function invocation...",
+                retries, function_name
+            );
+            retries += 1;
+
+            increase_factor = std::cmp::min(increase_factor, 9) + 1;
+
+            let random_milliseconds = rand::thread_rng().gen_range(0..100);
+            let backoff = Duration::from_millis(std::cmp::min(
+                50 * increase_factor + random_milliseconds,
+                self.max_backoff,
+            ));
+
+            tokio::time::sleep(backoff).await;
+        }
+    }
+}
+
+/// Adds the number of retries to the error of the last invocation attempt.
+/// The error variant is preserved, so that the callers can still tell the
+/// AWS Lambda failures from the other errors.
+fn exhausted(error: FlockError, max_retries: usize) -> FlockError {
+    let message = |e: String| {
+        format!(
+            "Sync invocation failed after {} retries: {}",
+            max_retries, e
+        )
+    };
+    match error {
+        FlockError::AWS(e) => FlockError::AWS(message(e)),
+        FlockError::Execution(e) => FlockError::Execution(message(e)),
+        FlockError::Internal(e) => FlockError::Internal(message(e)),
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Returns an invocation that fails `failures` times before it succeeds.
+    fn flaky(
+        attempts: &AtomicUsize,
+        failures: usize,
+    ) -> impl FnMut() -> std::future::Ready<Result<usize>> + '_ {
+        move || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            std::future::ready(if attempt > failures {
+                Ok(attempt)
+            } else {
+                Err(FlockError::AWS(format!("attempt {}", attempt)))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_until_success() -> Result<()> {
+        let retry = RetryPolicy {
+            max_retries: 3,
+            max_backoff: 1,
+        };
+
+        let attempts = AtomicUsize::new(0);
+        assert_eq!(retry.run("f", flaky(&attempts, 0)).await?, 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let attempts = AtomicUsize::new(0);
+        assert_eq!(retry.run("f", flaky(&attempts, 3)).await?, 4);
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_exhausted() -> Result<()> {
+        let retry = RetryPolicy {
+            max_retries: 2,
+            max_backoff: 1,
+        };
+
+        let attempts = AtomicUsize::new(0);
+        match retry.run("f", flaky(&attempts, 3)).await {
+            Err(FlockError::AWS(e)) => {
+                assert_eq!(e, "Sync invocation failed after 2 retries: attempt 3")
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // Without retries, the invocation is only attempted once.
+        let retry = RetryPolicy {
+            max_retries: 0,
+            max_backoff: 1,
+        };
+        let attempts = AtomicUsize::new(0);
+        assert!(retry.run("f", flaky(&attempts, 1)).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // The other errors are returned as is.
+        let error = retry
+            .run("f", || async {
+                Err::<(), _>(FlockError::NotImplemented("f".to_owned()))
+            })
+            .await;
+        assert!(matches!(error, Err(FlockError::NotImplemented(e)) if e == "f"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_backoff() -> Result<()> {
+        // The backoff is capped by `max_backoff`.
+        let retry = RetryPolicy {
+            max_retries: 2,
+            max_backoff: 20,
+        };
+        let attempts = AtomicUsize::new(0);
+        let start = std::time::Instant::now();
+        retry.run("f", flaky(&attempts, 2)).await?;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(40));
+        assert!(elapsed < Duration::from_millis(1000));
+        Ok(())
+    }
+}
@@ -14,23 +14,28 @@
 //! This crate responsibles for executing queries on AWS Lambda Functions.
 
 extern crate daggy;
+use crate::aws::lambda::{AwsLambdaService, LambdaService};
 use crate::configs::*;
-use crate::datasink::DataSinkType;
+use crate::datasink::{DataSink, DataSinkFormat, DataSinkType};
+use crate::datasource::side_input::SideInput;
+use crate::datasource::DataSource;
+use crate::distributed_plan::broadcast::stage_side_inputs;
 use crate::distributed_plan::DistributedPlanner;
-use crate::distributed_plan::QueryDag;
-use crate::error::Result;
+use crate::distributed_plan::{CombineAggregateExec, QueryDag, RangePartitionExec, RangeSampler};
+use crate::error::{FlockError, Result};
 use crate::launcher::{ExecutionMode, Launcher};
 use crate::query::Query;
 use crate::runtime::context::*;
+use crate::runtime::function::FunctionId;
+use crate::runtime::payload::{Payload, UuidBuilder, WINDOW_ID};
 use crate::runtime::plan::CloudExecutionPlan;
 use crate::state::*;
 use async_trait::async_trait;
 use daggy::NodeIndex;
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::physical_plan::ExecutionPlan;
-use log::debug;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use log::{debug, info};
+use std::collections::HashMap;
 use std::sync::Arc;
 
 /// AwsLambdaLauncher defines the interface for deploying and executing
@@ -38,16 +43,33 @@ use std::sync::Arc;
 #[derive(Debug)]
 pub struct AwsLambdaLauncher {
     /// The first component of the function name.
-    pub query_code:    Option<String>,
+    pub query_code:      Option<String>,
     /// The DAG of a given query.
-    pub dag:           QueryDag,
+    pub dag:             QueryDag,
+    /// The data source of a given query.
+    pub datasource:      DataSource,
     /// The data sink type of a given query.
-    pub sink_type:     DataSinkType,
+    pub sink_type:       DataSinkType,
     /// The entire execution plan. This can be used to execute the query
     /// in a single Lambda function.
-    pub plan:          Arc<dyn ExecutionPlan>,
+    pub plan:            Arc<dyn ExecutionPlan>,
     /// The state backend to use.
-    pub state_backend: Arc<dyn StateBackend>,
+    pub state_backend:   Arc<dyn StateBackend>,
+    /// The execution context of the data source function in centralized
+    /// mode.
+    pub data_source_ctx: Option<ExecutionContext>,
+    /// The execution context of the worker functions in centralized mode.
+    pub worker_ctx:      Option<ExecutionContext>,
+    /// The instruction set architecture of the lambda functions.
+    pub architecture:    String,
+    /// The memory size of the worker functions in MB. If not set, the regular
+    /// functions use `FLOCK_REGULAR_MEMORY_SIZE` and the members of the
+    /// function groups use `FLOCK_AGGREGATE_MEMORY_SIZE`.
+    pub memory_size:     Option<i64>,
+    /// The AWS Lambda service to deploy and invoke the functions.
+    pub lambda:          Arc<dyn LambdaService>,
+    /// The side inputs of a given query.
+    pub side_inputs:     Vec<SideInput>,
 }
 
 #[async_trait]
@@ -59,30 +81,36 @@ impl Launcher for AwsLambdaLauncher {
         let plan = query.plan()?;
         let sink_type = query.datasink();
 
-        let planner = DistributedPlanner::new();
+        let planner = DistributedPlanner::with_fragments(query.datasource().fragments())
+            .with_side_inputs(query.side_inputs().clone());
         let dag = planner.plan_query_stages(plan.clone()).await?;
 
-        let mut query_code = query.query_code();
-        if query_code.is_none() {
-            let mut hasher = DefaultHasher::new();
-            query.sql().hash(&mut hasher);
-            query_code = Some(hasher.finish().to_string());
-        }
+        let query_code = Some(query.code()?);
 
         let state_backend = query.state_backend();
 
         Ok(AwsLambdaLauncher {
             plan,
             dag,
+            datasource: query.datasource(),
             sink_type,
             query_code,
             state_backend,
+            data_source_ctx: None,
+            worker_ctx: None,
+            architecture: "x86_64".to_owned(),
+            memory_size: None,
+            lambda: Arc::new(AwsLambdaService),
+            side_inputs: query.side_inputs().clone(),
         })
     }
 
-    fn deploy(&mut self) -> Result<()> {
+    /// Create the cloud functions for both the centralized and the
+    /// distributed execution modes.
+    async fn deploy(&mut self) -> Result<()> {
         self.create_cloud_contexts(*FLOCK_FUNCTION_CONCURRENCY)?;
-        self.create_cloud_functions()?;
+        self.place_plans(*FLOCK_CONTEXT_SIZE_LIMIT).await?;
+        self.create_cloud_functions().await?;
         Ok(())
     }
 
@@ -96,41 +124,120 @@ impl Launcher for AwsLambdaLauncher {
     /// the results to the next lambda function. This greatly simplifies the
     /// code size and complexity of the distributed query engine. Meanwhile, the
     /// latency is significantly reduced.
-    async fn execute(&self, _: ExecutionMode) -> Result<Vec<RecordBatch>> {
-        unimplemented!();
+    ///
+    /// The entry function is invoked synchronously, and all the following
+    /// functions are invoked synchronously as well, so the results can be read
+    /// from the data sink once the invocation returns.
+    async fn execute(&self, mode: ExecutionMode) -> Result<Vec<RecordBatch>> {
+        let query_code = self.query_code.as_ref().expect("query code not set");
+
+        let mut metadata = HashMap::new();
+        metadata.insert("invocation_type".to_string(), "sync".to_string());
+
+        let function_names = match mode {
+            ExecutionMode::Centralized => {
+                let ctx = self.data_source_ctx.as_ref().ok_or_else(|| {
+                    FlockError::Internal("The query is not deployed yet.".to_owned())
+                })?;
+                // The data source function is shared by all queries, so the
+                // worker functions are passed in the metadata of the payload.
+                metadata.insert("workers".to_string(), serde_json::to_string(&ctx.next)?);
+                vec![ctx.name.clone()]
+            }
+            ExecutionMode::Distributed => {
+                // Each leaf stage reads its own relations from the data source,
+                // and the windows of all the leaf stages share the same query
+                // ids, so that a join gathers its inputs in the same window.
+                metadata.insert(WINDOW_ID.to_string(), UuidBuilder::window_id());
+                self.dag
+                    .get_leaves()
+                    .into_iter()
+                    .map(|leaf| {
+                        self.dag
+                            .get_node(leaf)
+                            .and_then(|node| node.context.as_ref())
+                            .map(|ctx| ctx.name.clone())
+                            .ok_or_else(|| {
+                                FlockError::Internal("The query is not deployed yet.".to_owned())
+                            })
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            }
+        };
+
+        let payload = serde_json::to_vec(&Payload {
+            datasource: self.datasource.clone(),
+            metadata: Some(metadata),
+            ..Default::default()
+        })?;
+
+        // The results of the previous runs of the same query are removed, so
+        // that they are not read together with the results of this run.
+        DataSink::clear_by_query_code(query_code, self.sink_type.clone()).await?;
+
+        for function_name in function_names {
+            info!("Invoking the entry function: {}", function_name);
+            self.lambda
+                .invoke_function(
+                    &function_name,
+                    &FLOCK_LAMBDA_SYNC_CALL,
+                    Some(payload.clone().into()),
+                )
+                .await?;
+        }
+
+        Ok(DataSink::read_by_query_code(
+            query_code.clone(),
+            self.sink_type.clone(),
+            DataSinkFormat::default(),
+        )
+        .await?
+        .record_batches)
     }
 }
 
 impl AwsLambdaLauncher {
     /// Create a new `AwsLambdaLauncher` instance.
+    ///
+    /// The side inputs that are small enough are broadcast to the joins that
+    /// read them, see [`DistributedPlanner::with_side_inputs`].
     pub async fn try_new<T>(
         query_code: T,
         plan: Arc<dyn ExecutionPlan>,
         sink_type: DataSinkType,
         state_backend: Arc<dyn StateBackend>,
+        side_inputs: Vec<SideInput>,
     ) -> Result<Self>
     where
         T: Into<String>,
     {
-        let planner = DistributedPlanner::new();
+        let planner = DistributedPlanner::new().with_side_inputs(side_inputs.clone());
         let dag = planner.plan_query_stages(plan.clone()).await?;
         Ok(AwsLambdaLauncher {
             query_code: Some(query_code.into()),
             plan,
             dag,
+            datasource: DataSource::default(),
             sink_type,
             state_backend,
+            data_source_ctx: None,
+            worker_ctx: None,
+            architecture: "x86_64".to_owned(),
+            memory_size: None,
+            lambda: Arc::new(AwsLambdaService),
+            side_inputs,
         })
     }
 
     /// Initialize the query code for the query.
-    pub fn set_query_code(&mut self, query: &Query) {
-        self.query_code = query.query_code();
-        if self.query_code.is_none() {
-            let mut hasher = DefaultHasher::new();
-            query.sql().hash(&mut hasher);
-            self.query_code = Some(hasher.finish().to_string());
-        }
+    pub fn set_query_code(&mut self, query: &Query) -> Result<()> {
+        self.query_code = Some(query.code()?);
+        Ok(())
+    }
+
+    /// Set the AWS Lambda service to deploy and invoke the functions.
+    pub fn set_lambda_service(&mut self, lambda: Arc<dyn LambdaService>) {
+        self.lambda = lambda;
     }
 
     /// Create the cloud contexts for the query.
@@ -143,65 +250,215 @@ impl AwsLambdaLauncher {
         {
             let dag = &mut self.dag;
             let count = dag.node_count();
-            assert!(count < 100);
+            let query_code = self.query_code.as_ref().expect("query code not set");
 
             let func_types = (0..count)
                 .map(|i| dag.get_node(NodeIndex::new(i)).unwrap().get_function_type())
                 .collect::<Vec<CloudFunctionType>>();
 
-            (0..count).rev().for_each(|i| {
-                let node = dag.get_node_mut(NodeIndex::new(i)).unwrap();
-                let query_code = self.query_code.as_ref().expect("query code not set");
-
-                let next = if i == 0 {
-                    CloudFunction::Sink(self.sink_type.clone())
-                } else if func_types[i - 1 /* follower stage */] == CloudFunctionType::Group {
-                    CloudFunction::Group((
-                        format!("{}-{:02}", query_code, count - 1 - (i - 1)),
-                        group_size,
-                    ))
-                } else {
-                    CloudFunction::Lambda(format!("{}-{:02}", query_code, count - 1 - (i - 1)))
+            // The fan-in of each stage that combines the partial aggregates.
+            let fan_ins = (0..count)
+                .map(|i| {
+                    dag.get_node(NodeIndex::new(i)).unwrap().stage[0]
+                        .as_any()
+                        .downcast_ref::<CombineAggregateExec>()
+                        .map(|combiner| combiner.fan_in())
+                })
+                .collect::<Vec<Option<usize>>>();
+
+            // The data source samples the range boundaries of the distributed sort
+            // for all the range partitioners, see `RangeSampler`.
+            let range_sampler = (0..count).find_map(|i| {
+                dag.get_node(NodeIndex::new(i)).unwrap().stage[0]
+                    .as_any()
+                    .downcast_ref::<RangePartitionExec>()
+                    .map(RangeSampler::new)
+            });
+
+            // The data flows from the leaves of the DAG to the root, so the stage id
+            // of the root is the largest one.
+            let function_id = |i: usize| FunctionId::try_new(query_code, count - 1 - i);
+
+            for i in (0..count).rev() {
+                // The follower stage consumes the output of the current stage.
+                let next = match dag.get_parent(NodeIndex::new(i)) {
+                    None => CloudFunction::Sink(self.sink_type.clone()),
+                    Some(p) if func_types[p.index()] == CloudFunctionType::Group => {
+                        CloudFunction::Group((function_id(p.index())?.to_cloud_name(), group_size))
+                    }
+                    Some(p) => CloudFunction::Lambda(function_id(p.index())?.to_cloud_name()),
                 };
 
+                let fan_in = dag
+                    .get_parent(NodeIndex::new(i))
+                    .and_then(|p| fan_ins[p.index()]);
+
+                // The function group that gathers the outputs of several input stages
+                // tells them apart by the index of the input stage.
+                let next_input = dag.get_parent(NodeIndex::new(i)).and_then(|p| {
+                    let inputs = dag.get_inputs(p);
+                    if inputs.len() > 1 && func_types[p.index()] == CloudFunctionType::Group {
+                        inputs
+                            .iter()
+                            .position(|n| n.index() == i)
+                            .map(|index| (index, inputs.len()))
+                    } else {
+                        None
+                    }
+                });
+
+                // Only the leaf stages read from the data source.
+                let is_leaf = dag.get_inputs(NodeIndex::new(i)).is_empty();
+                let node = dag.get_node_mut(NodeIndex::new(i)).unwrap();
+                let id = function_id(i)?;
+
                 let ctx = ExecutionContext {
                     plan: CloudExecutionPlan::new(node.stage.clone(), None),
-                    name: format!("{}-{:02}", query_code, count - 1 - i),
+                    name: id.to_cloud_name(),
+                    id: Some(id),
                     next,
+                    fan_in,
+                    next_input,
                     state_backend: self.state_backend.clone(),
+                    side_inputs: stage_side_inputs(&node.stage, is_leaf, &self.side_inputs),
+                    range_sampler: range_sampler.clone().filter(|_| is_leaf),
+                    ..Default::default()
                 };
 
                 node.context = Some(ctx);
-            });
+            }
         }
 
         // Creates the cloud contexts for centralized mode
         {
             let query_code = self.query_code.as_ref().expect("query code not set");
-            let _data_source_ctx = ExecutionContext {
+            let worker_id = FunctionId::try_new(query_code, 0)?;
+            self.data_source_ctx = Some(ExecutionContext {
                 plan:          CloudExecutionPlan::new(vec![FLOCK_EMPTY_PLAN.clone()], None),
                 name:          FLOCK_DATA_SOURCE_FUNC_NAME.clone(),
-                next:          CloudFunction::Group((
-                    format!("{}-{:02}", query_code, 0),
-                    *FLOCK_FUNCTION_CONCURRENCY,
-                )),
+                next:          CloudFunction::Group((worker_id.to_cloud_name(), group_size)),
                 state_backend: self.state_backend.clone(),
-            };
-            let _worker_ctx = ExecutionContext {
-                // TODO: add option to store the execution plan in S3.
+                ..Default::default()
+            });
+            self.worker_ctx = Some(ExecutionContext {
                 plan:          CloudExecutionPlan::new(vec![self.plan.clone()], None),
-                name:          format!("{}-{:02}", query_code, 0),
+                name:          worker_id.to_cloud_name(),
+                id:            Some(worker_id),
                 next:          CloudFunction::Sink(self.sink_type.clone()),
                 state_backend: self.state_backend.clone(),
-            };
+                side_inputs:   self.side_inputs.clone(),
+                ..Default::default()
+            });
         }
 
         Ok(())
     }
 
+    /// Places the execution plans of the cloud contexts in the object store if
+    /// the encoded contexts exceed the size limit of the cloud environment.
+    ///
+    /// # Arguments
+    /// * `size_limit` - The maximum size of an encoded context in bytes.
+    pub async fn place_plans(&mut self, size_limit: usize) -> Result<()> {
+        if let Some(ctx) = self.worker_ctx.as_mut() {
+            ctx.place_plan(size_limit).await?;
+        }
+        for i in 0..self.dag.node_count() {
+            let node = self.dag.get_node_mut(NodeIndex::new(i)).unwrap();
+            if let Some(ctx) = node.context.as_mut() {
+                ctx.place_plan(size_limit).await?;
+            }
+        }
+        Ok(())
+    }
+
     /// Create the cloud functions for the query.
-    fn create_cloud_functions(&self) -> Result<()> {
-        unimplemented!();
+    ///
+    /// Lambda-type functions use the regular memory size with the default
+    /// concurrency. Each member of a function group uses the aggregate memory
+    /// size with a concurrency of *1*, so that all data packets of the same
+    /// window are routed to the same function execution environment.
+    pub async fn create_cloud_functions(&self) -> Result<()> {
+        let (data_source_ctx, worker_ctx) =
+            match (self.data_source_ctx.as_ref(), self.worker_ctx.as_ref()) {
+                (Some(d), Some(w)) => (d, w),
+                _ => {
+                    return Err(FlockError::Internal(
+                        "The cloud contexts are not created yet.".to_owned(),
+                    ))
+                }
+            };
+
+        // Creates the cloud functions for centralized mode
+        info!("Creating lambda function: {}", data_source_ctx.name);
+        self.lambda
+            .create_function(data_source_ctx, *FLOCK_REGULAR_MEMORY_SIZE, &self.architecture)
+            .await?;
+        if let CloudFunction::Group((_, group_size)) = &data_source_ctx.next {
+            self.create_function_group(worker_ctx, *group_size).await?;
+        }
+
+        // Creates the cloud functions for the distributed mode
+        let count = self.dag.node_count();
+        for i in (0..count).rev() {
+            let node = self.dag.get_node(NodeIndex::new(i)).unwrap();
+            let ctx = node.context.as_ref().ok_or_else(|| {
+                FlockError::Internal("The cloud contexts are not created yet.".to_owned())
+            })?;
+            if node.get_function_type() == CloudFunctionType::Group {
+                // The group size is defined by the former stage of the dataflow.
+                let former = self
+                    .dag
+                    .get_node(self.dag.get_inputs(NodeIndex::new(i))[0])
+                    .unwrap();
+                match &former.context.as_ref().unwrap().next {
+                    CloudFunction::Group((_, group_size)) => {
+                        self.create_function_group(ctx, *group_size).await?
+                    }
+                    _ => unreachable!(),
+                }
+            } else {
+                info!("Creating lambda function: {}", ctx.name);
+                let memory_size = self.memory_size.unwrap_or(*FLOCK_REGULAR_MEMORY_SIZE);
+                self.lambda
+                    .create_function(ctx, memory_size, &self.architecture)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create the function group for the given context.
+    ///
+    /// # Arguments
+    /// * `ctx` - The execution context shared by the group members.
+    /// * `group_size` - The number of functions in the group.
+    async fn create_function_group(&self, ctx: &ExecutionContext, group_size: usize) -> Result<()> {
+        info!("Creating lambda function group: ({}, {})", ctx.name, group_size);
+        let tasks = (0..group_size)
+            .map(|i| {
+                let member = ctx.group_member(i);
+                let lambda = self.lambda.clone();
+                let architecture = self.architecture.clone();
+                let memory_size = self.memory_size.unwrap_or(*FLOCK_AGGREGATE_MEMORY_SIZE);
+                tokio::spawn(async move {
+                    let member = member?;
+                    lambda
+                        .create_function(&member, memory_size, &architecture)
+                        .await?;
+                    lambda.set_concurrency(&member.name, 1).await
+                })
+            })
+            .collect::<Vec<tokio::task::JoinHandle<Result<()>>>>();
+
+        futures::future::join_all(tasks)
+            .await
+            .into_iter()
+            .map(|r| r.map_err(|e| FlockError::Internal(e.to_string()))?)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(())
     }
 }
 
@@ -216,15 +473,77 @@ mod tests {
     use crate::datasource::ysb::event::{AdEvent, Campaign};
     use crate::datasource::ysb::YSBSource;
     use crate::datasource::DataSource;
+    use crate::encoding::Encoding;
     use crate::launcher::LocalLauncher;
     use crate::query::Table;
     use crate::query::{QueryType, StreamType};
+    use crate::runtime::payload::{Uuid, UuidBuilder};
     use crate::stream::{Schedule, Window};
+    use crate::test_util::init_in_memory_object_store;
     use crate::transmute::event_bytes_to_batch;
     use datafusion::arrow::array::*;
     use datafusion::arrow::datatypes::{DataType, Field, Schema};
     use datafusion::arrow::util::pretty::pretty_format_batches;
     use indoc::indoc;
+    use rusoto_lambda::InvocationResponse;
+    use std::sync::Mutex;
+
+    /// A mocked AWS Lambda service that records the deployed functions and
+    /// the invocations instead of talking to AWS.
+    #[derive(Debug, Default)]
+    struct MockLambdaService {
+        /// The function name, its memory size and its reserved concurrency.
+        functions:   Mutex<HashMap<String, (i64, Option<i64>)>>,
+        /// The function name and the payload of each invocation.
+        invocations: Mutex<Vec<(String, Payload)>>,
+    }
+
+    #[async_trait]
+    impl LambdaService for MockLambdaService {
+        async fn create_function(
+            &self,
+            ctx: &ExecutionContext,
+            memory_size: i64,
+            _architecture: &str,
+        ) -> Result<String> {
+            // All environment variables of a function share 4 KB.
+            if marshal(ctx, Encoding::default())?.len() > *FLOCK_CONTEXT_SIZE_LIMIT {
+                return Err(FlockError::AWS(format!(
+                    "The environment of {} exceeds 4 KB",
+                    ctx.name
+                )));
+            }
+            self.functions
+                .lock()
+                .unwrap()
+                .insert(ctx.name.clone(), (memory_size, None));
+            Ok(ctx.name.clone())
+        }
+
+        async fn set_concurrency(&self, function_name: &str, concurrency: i64) -> Result<()> {
+            self.functions
+                .lock()
+                .unwrap()
+                .get_mut(function_name)
+                .ok_or_else(|| FlockError::AWS(format!("{} not found", function_name)))?
+                .1 = Some(concurrency);
+            Ok(())
+        }
+
+        async fn invoke_function(
+            &self,
+            function_name: &str,
+            _invocation_type: &str,
+            payload: Option<bytes::Bytes>,
+        ) -> Result<InvocationResponse> {
+            let payload: Payload = serde_json::from_slice(&payload.unwrap())?;
+            self.invocations
+                .lock()
+                .unwrap()
+                .push((function_name.to_owned(), payload));
+            Ok(InvocationResponse::default())
+        }
+    }
 
     fn init_query() -> Result<Query> {
         let table1 = "t1".to_owned();
@@ -272,6 +591,171 @@ mod tests {
             println!("Function Type: {:?}\n\n", stage.get_function_type());
         }
 
+        // Function groups are not limited to 100 members.
+        launcher.create_cloud_contexts(128)?;
+        for stage in launcher.dag.get_all_stages() {
+            let ctx = stage.context.as_ref().unwrap();
+            assert!(!ctx.is_aggregate());
+
+            let member = ctx.group_member(127)?;
+            assert!(member.is_aggregate());
+            assert_eq!(member.stage()?, ctx.stage()?);
+            assert_eq!(FunctionId::from_cloud_name(&member.name)?, member.id.unwrap());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aws_launcher_plan_placement() -> Result<()> {
+        init_in_memory_object_store();
+        let query = init_query()?;
+        let mut launcher = AwsLambdaLauncher::new(&query).await?;
+        launcher.create_cloud_contexts(*FLOCK_FUNCTION_CONCURRENCY)?;
+
+        let contexts = |launcher: &AwsLambdaLauncher| {
+            let mut contexts = vec![launcher.worker_ctx.clone().unwrap()];
+            contexts.extend(
+                launcher
+                    .dag
+                    .get_all_stages()
+                    .into_iter()
+                    .map(|stage| stage.context.clone().unwrap()),
+            );
+            contexts
+        };
+        let originals = contexts(&launcher);
+
+        // The contexts that fit in the environment keep their plans.
+        launcher.place_plans(usize::MAX).await?;
+        assert_eq!(contexts(&launcher), originals);
+
+        // Otherwise, the plans are placed in the object store.
+        launcher.place_plans(0).await?;
+        for (placed, original) in contexts(&launcher).iter().zip(originals) {
+            let (bucket, key) = placed.plan.object_storage.clone().unwrap();
+            assert_eq!(bucket, *FLOCK_S3_BUCKET);
+            assert!(key.starts_with(&format!("{}/", *FLOCK_S3_PLAN_KEY)));
+
+            // The function loads the plans from the object store.
+            let mut ctx = unmarshal(marshal(placed, Encoding::default())?)?;
+            assert_eq!(
+                serde_json::to_string(&ctx.plan().await?)?,
+                serde_json::to_string(&original.plan.execution_plans)?
+            );
+
+            // The key is derived from the plans, so the same plans are placed
+            // only once.
+            let mut ctx = original.clone();
+            ctx.place_plan(0).await?;
+            assert_eq!(ctx.plan.object_storage, placed.plan.object_storage);
+        }
+
+        // The placed contexts fit in the environment of the functions.
+        launcher.set_lambda_service(Arc::new(MockLambdaService::default()));
+        launcher.create_cloud_functions().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aws_launcher_deploy_and_execute() -> Result<()> {
+        init_in_memory_object_store();
+        let query = init_query()?;
+        let mut launcher = AwsLambdaLauncher::new(&query).await?;
+        let lambda = Arc::new(MockLambdaService::default());
+        launcher.set_lambda_service(lambda.clone());
+        launcher.deploy().await?;
+
+        let query_code = launcher.query_code.clone().unwrap();
+        let group_size = *FLOCK_FUNCTION_CONCURRENCY;
+        let functions = lambda.functions.lock().unwrap().clone();
+
+        // Centralized mode: the data source function and the worker group.
+        assert_eq!(
+            functions[&*FLOCK_DATA_SOURCE_FUNC_NAME],
+            (*FLOCK_REGULAR_MEMORY_SIZE, None)
+        );
+        let worker = FunctionId::try_new(&query_code, 0)?;
+        worker.members(group_size).for_each(|member| {
+            assert_eq!(
+                functions[&member.to_cloud_name()],
+                (*FLOCK_AGGREGATE_MEMORY_SIZE, Some(1))
+            );
+        });
+
+        // Distributed mode: one function per stage, or one function group per
+        // aggregate stage.
+        let count = launcher.dag.node_count();
+        let mut expected_num = 1 + group_size;
+        for i in 0..count {
+            let node = launcher.dag.get_node(NodeIndex::new(i)).unwrap();
+            let id = FunctionId::try_new(&query_code, count - 1 - i)?;
+            if node.get_function_type() == CloudFunctionType::Group {
+                id.members(group_size).for_each(|member| {
+                    assert_eq!(
+                        functions[&member.to_cloud_name()],
+                        (*FLOCK_AGGREGATE_MEMORY_SIZE, Some(1))
+                    );
+                });
+                expected_num += group_size;
+            } else {
+                assert_eq!(
+                    functions[&id.to_cloud_name()],
+                    (*FLOCK_REGULAR_MEMORY_SIZE, None)
+                );
+                expected_num += 1;
+            }
+        }
+        assert_eq!(functions.len(), expected_num);
+
+        // The blackhole sink doesn't return any results.
+        assert!(launcher.execute(ExecutionMode::Centralized).await?.is_empty());
+        assert!(launcher.execute(ExecutionMode::Distributed).await?.is_empty());
+
+        let invocations = lambda.invocations.lock().unwrap().clone();
+        assert_eq!(invocations.len(), 2);
+
+        let (name, payload) = &invocations[0];
+        assert_eq!(name, &*FLOCK_DATA_SOURCE_FUNC_NAME);
+        assert_eq!(payload.datasource, DataSource::Memory);
+        let workers: CloudFunction =
+            serde_json::from_str(&payload.metadata.as_ref().unwrap()["workers"])?;
+        assert_eq!(
+            workers,
+            CloudFunction::Group((worker.to_cloud_name(), group_size))
+        );
+
+        let (name, payload) = &invocations[1];
+        assert_eq!(name, &worker.to_cloud_name());
+        assert!(!payload.metadata.as_ref().unwrap().contains_key("workers"));
+        // The data source functions of all the leaf stages share the window id.
+        assert!(payload.metadata.as_ref().unwrap().contains_key(WINDOW_ID));
+        assert!(!invocations[0].1.metadata.as_ref().unwrap().contains_key(WINDOW_ID));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aws_launcher_memory_size() -> Result<()> {
+        init_in_memory_object_store();
+        let query = init_query()?;
+        let mut launcher = AwsLambdaLauncher::new(&query).await?;
+        let lambda = Arc::new(MockLambdaService::default());
+        launcher.set_lambda_service(lambda.clone());
+        launcher.memory_size = Some(256);
+        launcher.deploy().await?;
+
+        // The data source function keeps the regular memory size.
+        let functions = lambda.functions.lock().unwrap().clone();
+        functions.iter().for_each(|(name, (memory_size, _))| {
+            if name == &*FLOCK_DATA_SOURCE_FUNC_NAME {
+                assert_eq!(*memory_size, *FLOCK_REGULAR_MEMORY_SIZE);
+            } else {
+                assert_eq!(*memory_size, 256);
+            }
+        });
+
         Ok(())
     }
 
@@ -404,11 +888,12 @@ mod tests {
         //           MemoryExec: partitions=0, partition_sizes=[]
         //
         // === Stage 1 ===
-        // ProjectionExec: expr=[name@4 as name, city@5 as city, state@6 as state, a_id@0 as a_id]
-        //   CoalesceBatchesExec: target_batch_size=4096
-        //     HashJoinExec: mode=Partitioned, join_type=Inner, on=[(Column { name: "seller", index: 1 }, Column { name: "p_id", index: 0 })]
-        //       MemoryExec: partitions=0, partition_sizes=[]
-        //       MemoryExec: partitions=0, partition_sizes=[]
+        // RangePartitionExec: partitions=16, expr=[a_id@3 ASC NULLS LAST]
+        //   ProjectionExec: expr=[name@4 as name, city@5 as city, state@6 as state, a_id@0 as a_id]
+        //     CoalesceBatchesExec: target_batch_size=4096
+        //       HashJoinExec: mode=Partitioned, join_type=Inner, on=[(Column { name: "seller", index: 1 }, Column { name: "p_id", index: 0 })]
+        //         MemoryExec: partitions=0, partition_sizes=[]
+        //         MemoryExec: partitions=0, partition_sizes=[]
         //
         // === Stage 2 ===
         // SortExec: [a_id@3 ASC NULLS LAST]
@@ -423,9 +908,14 @@ mod tests {
         let input = vec![vec![auctions_batches], vec![person_batches]];
 
         // === Query Stage 0 ===
+        // The data source samples the range boundaries of the window.
         let mut ctx = stages[0].context.clone().unwrap();
+        assert!(ctx.range_sampler.is_some());
         assert!(ctx.is_shuffling().await?);
         assert!(!ctx.is_last_stage().await?);
+        let mut metadata = None;
+        ctx.sample_range_boundaries(&input, &mut metadata)?;
+        assert!(metadata.is_some());
         ctx.feed_data_sources(input.clone()).await?;
         // We **MUST USE** execute_partitioned() instead of execute() here.
         let output = ctx.execute_partitioned().await?;
@@ -433,30 +923,45 @@ mod tests {
         assert_eq!(output[0].len(), output[1].len());
 
         // === Query Stage 1 ===
+        // Each function splits its own output into the ranges with the same
+        // boundaries, and sends the ranges straight to the sorters.
         let num_partitions = output[0].len();
         let mut ctx = stages[1].context.clone().unwrap();
-        assert!(!ctx.is_shuffling().await?);
+        assert!(ctx.range_sampler.is_none());
+        assert!(ctx.is_shuffling().await?);
+        assert!(ctx.is_range_partitioning().await?);
         assert!(!ctx.is_last_stage().await?);
-        let mut output1 = vec![];
+        let mut ranges = vec![vec![]; *FLOCK_FUNCTION_CONCURRENCY];
         for i in 0..num_partitions {
+            ctx.feed_range_boundaries(&metadata).await?;
             ctx.feed_data_sources(vec![vec![output[0][i].clone()], vec![output[1][i].clone()]])
                 .await?;
-            let sliced_output = ctx.execute().await?;
+            let sliced_output = ctx.execute_partitioned().await?;
             ctx.clean_data_sources().await?;
             assert!(sliced_output.len() == 1);
-            output1.push(sliced_output.into_iter().next().unwrap());
+            let sliced_output = sliced_output.into_iter().next().unwrap();
+            assert_eq!(sliced_output.len(), *FLOCK_FUNCTION_CONCURRENCY);
+            for (range, batches) in sliced_output.into_iter().enumerate() {
+                ranges[range].extend(batches);
+            }
         }
 
         // === Query Stage 2 ===
+        // Each range is sorted by its own function, and the sorted ranges are
+        // concatenated in order.
         let mut ctx = stages[2].context.clone().unwrap();
         assert!(!ctx.is_shuffling().await?);
         assert!(ctx.is_last_stage().await?);
-        let output1 = output1.into_iter().flatten().collect::<Vec<_>>();
-        ctx.feed_data_sources(vec![vec![output1]]).await?;
-        let result = ctx.execute().await?;
-        ctx.clean_data_sources().await?;
+        let mut result = vec![];
+        for range in ranges {
+            if range.is_empty() {
+                continue;
+            }
+            ctx.feed_data_sources(vec![vec![range]]).await?;
+            result.extend(ctx.execute().await?.into_iter().flatten());
+            ctx.clean_data_sources().await?;
+        }
 
-        let result = result.into_iter().flatten().collect::<Vec<_>>();
         let formatted = pretty_format_batches(&result).unwrap().to_string();
         let expected: Vec<&str> = formatted.trim().lines().collect();
 
@@ -470,6 +975,107 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn aws_launcher_combiner_tree() -> Result<()> {
+        let bid_schema = Arc::new(Bid::schema());
+        let query = Query::new(
+            "SELECT COUNT(*), SUM(price), MIN(price), AVG(price) FROM bid",
+            vec![Table("bid".to_string(), bid_schema.clone())],
+            DataSource::NEXMarkEvent(NEXMarkSource::new(1, 64, 1000, Window::ElementWise)),
+            DataSinkType::Blackhole,
+            None,
+            QueryType::Streaming(StreamType::NEXMarkBench),
+            Arc::new(HashMapStateBackend::new()),
+        );
+
+        // === Stage 0 ===
+        // CoalescePartitionsExec
+        //   HashAggregateExec: mode=Partial, gby=[], aggr=[COUNT(UInt8(1)), SUM(bid.price), MIN(bid.price), AVG(bid.price)]
+        //     RepartitionExec: partitioning=RoundRobinBatch(16)
+        //       MemoryExec: partitions=0, partition_sizes=[]
+        //
+        // === Stage 1 ===
+        // CombineAggregateExec: fan_in=8, aggr=[COUNT(UInt8(1)), SUM(bid.price), MIN(bid.price), AVG(bid.price)]
+        //   MemoryExec: partitions=0, partition_sizes=[]
+        //
+        // === Stage 2 ===
+        // ProjectionExec: expr=[COUNT(UInt8(1))@0 as COUNT(UInt8(1)), SUM(bid.price)@1 as SUM(bid.price), MIN(bid.price)@2 as MIN(bid.price), AVG(bid.price)@3 as AVG(bid.price)]
+        //   HashAggregateExec: mode=Final, gby=[], aggr=[COUNT(UInt8(1)), SUM(bid.price), MIN(bid.price), AVG(bid.price)]
+        //     MemoryExec: partitions=0, partition_sizes=[]
+        let mut launcher = AwsLambdaLauncher::new(&query).await?;
+        launcher.create_cloud_contexts(*FLOCK_FUNCTION_CONCURRENCY)?;
+        assert_eq!(launcher.dag.node_count(), 3);
+
+        let stages = launcher.dag.get_all_stages();
+        for (i, stage) in stages.iter().enumerate() {
+            println!("=== Stage {} ===\n{}", i, stage.get_plan_str());
+        }
+
+        let nexmark_source = NEXMarkSource::new(1, 1, 1000, Window::ElementWise);
+        let stream = nexmark_source.generate_data()?;
+        let (events, _) = stream.select(0, 0).expect("Failed to select event.");
+        let bids = event_bytes_to_batch(&events.bids, bid_schema, 64);
+        assert!(bids.len() > 8);
+
+        // === Query Stage 0 ===
+        // Each data fragment of the window is aggregated by its own function, and
+        // sent to one of the combiners.
+        let mut ctx = stages[0].context.clone().unwrap();
+        assert_eq!(ctx.fan_in, Some(8));
+        let mut uuid_builder = UuidBuilder::new_with_ts(&ctx.name, 0, bids.len());
+        let mut metadata = None;
+        let mut windows: HashMap<usize, Vec<(Uuid, Vec<RecordBatch>)>> = HashMap::new();
+        for batch in bids.iter() {
+            ctx.feed_data_sources(vec![vec![vec![batch.clone()]]]).await?;
+            let partial = ctx.execute().await?.into_iter().flatten().collect();
+            ctx.clean_data_sources().await?;
+            let (uuid, shuffle_id) =
+                ctx.next_fragment(uuid_builder.next_uuid(), None, &mut metadata)?;
+            windows.entry(shuffle_id.unwrap()).or_default().push((uuid, partial));
+        }
+        assert_eq!(windows.len(), (bids.len() + 7) / 8);
+
+        // === Query Stage 1 ===
+        // Each combiner merges the partial aggregates in its own window.
+        let mut ctx = stages[1].context.clone().unwrap();
+        assert!(ctx.is_combining());
+        assert_eq!(ctx.fan_in, None);
+        let mut partials = vec![];
+        for (shuffle_id, window) in windows {
+            assert!(window.iter().all(|(uuid, _)| uuid.seq_len == window.len()));
+            let uuid = window[0].0.clone();
+            let input = window.into_iter().flat_map(|(_, p)| p).collect();
+            ctx.feed_data_sources(vec![vec![input]]).await?;
+            partials.extend(ctx.execute().await?.into_iter().flatten());
+            ctx.clean_data_sources().await?;
+
+            let (uuid, next_shuffle_id) =
+                ctx.next_fragment(uuid, Some(shuffle_id), &mut metadata.clone())?;
+            assert_eq!(next_shuffle_id, None);
+            assert_eq!(uuid.seq_num, shuffle_id);
+            assert_eq!(uuid.seq_len, (bids.len() + 7) / 8);
+        }
+
+        // === Query Stage 2 ===
+        let mut ctx = stages[2].context.clone().unwrap();
+        assert!(ctx.is_last_stage().await?);
+        ctx.feed_data_sources(vec![vec![partials]]).await?;
+        let result = ctx.execute().await?.into_iter().flatten().collect::<Vec<_>>();
+        ctx.clean_data_sources().await?;
+
+        let formatted = pretty_format_batches(&result).unwrap().to_string();
+        let expected: Vec<&str> = formatted.trim().lines().collect();
+
+        // Local execution mode
+        let mut launcher = LocalLauncher::new(&query).await?;
+        launcher.feed_data_sources(vec![vec![bids]]);
+        let batches = launcher.collect().await?;
+
+        assert_batches_eq!(expected, &batches);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn aws_launcher_nexmark_q4_shuffle() -> Result<()> {
         let auction_schema = Arc::new(Auction::schema());
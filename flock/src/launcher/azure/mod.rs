@@ -32,7 +32,7 @@ impl Launcher for AzureLauncher {
         Ok(AzureLauncher {})
     }
 
-    fn deploy(&mut self) -> Result<()> {
+    async fn deploy(&mut self) -> Result<()> {
         unimplemented!();
     }
 
@@ -32,7 +32,7 @@ impl Launcher for GCPLauncher {
         Ok(GCPLauncher {})
     }
 
-    fn deploy(&mut self) -> Result<()> {
+    async fn deploy(&mut self) -> Result<()> {
         unimplemented!();
     }
 
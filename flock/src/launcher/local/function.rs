@@ -0,0 +1,201 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! An in-process cloud function that runs the same function actor as the
+//! cloud. Each local function owns its execution context and memory arena, and
+//! forwards its results to the next functions through the channel invoker of
+//! the function registry.
+
+use crate::configs::*;
+use crate::datasink::DataSinkType;
+use crate::encoding::Encoding;
+use crate::error::{FlockError, Result};
+use crate::invoker::{ChannelInvoker, FunctionInvoker, Invocation, RetryPolicy};
+use crate::runtime::actor::{self, ResultSink, Router};
+use crate::runtime::arena::Arena;
+use crate::runtime::context::{self, ExecutionContext};
+use crate::runtime::function::FunctionId;
+use crate::runtime::payload::Payload;
+use async_trait::async_trait;
+use bytes::Bytes;
+use datafusion::arrow::record_batch::RecordBatch;
+use log::error;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// The registry of all in-process cloud functions of a query.
+#[derive(Debug)]
+pub struct FunctionRegistry {
+    /// The invoker of the functions in the registry's namespace.
+    invoker: ChannelInvoker,
+    /// The results written to the data sink by the last stage, by partition.
+    sink:    Mutex<BTreeMap<usize, Vec<RecordBatch>>>,
+}
+
+impl Default for FunctionRegistry {
+    fn default() -> Self {
+        // Each registry has its own namespace, so queries running in the same
+        // process never invoke each other's functions.
+        let mut invoker = ChannelInvoker::new(&uuid::Uuid::new_v4().to_string());
+        // The local functions are deterministic, retrying a failed invocation
+        // only hides the error.
+        invoker.retry = RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        };
+        Self {
+            invoker,
+            sink: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Drop for FunctionRegistry {
+    fn drop(&mut self) {
+        self.invoker.unregister_all();
+    }
+}
+
+impl FunctionRegistry {
+    /// Create a new function registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new function and return the receiver of its invocations.
+    pub fn register(&self, name: &str) -> mpsc::UnboundedReceiver<Invocation> {
+        self.invoker.register(name)
+    }
+
+    /// Return the invoker of the functions in the registry.
+    pub fn invoker(&self) -> Arc<dyn FunctionInvoker> {
+        Arc::new(self.invoker.clone())
+    }
+
+    /// Invoke the function synchronously. The call returns once the function
+    /// and all functions invoked by it have finished.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the function to invoke.
+    /// * `payload` - The payload of the invocation.
+    pub async fn invoke(&self, name: &str, mut payload: Payload) -> Result<()> {
+        payload.function = FunctionId::from_cloud_name(name).ok();
+        let bytes = serde_json::to_vec(&payload)?;
+        self.invoker
+            .invoke(name, &FLOCK_LAMBDA_SYNC_CALL, bytes.into())
+            .await
+            .map(|_| ())
+    }
+
+    /// Write the record batches to the data sink.
+    ///
+    /// # Arguments
+    /// * `partition` - The partition of the results, such as a sorted range of
+    ///   `ORDER BY`.
+    /// * `batches` - The record batches to write.
+    pub fn sink(&self, partition: usize, batches: Vec<RecordBatch>) {
+        self.sink
+            .lock()
+            .unwrap()
+            .entry(partition)
+            .or_default()
+            .extend(batches);
+    }
+
+    /// Take all the record batches in the data sink, concatenated in the
+    /// order of their partitions.
+    pub fn take_sink(&self) -> Vec<RecordBatch> {
+        std::mem::take(&mut *self.sink.lock().unwrap())
+            .into_values()
+            .flatten()
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ResultSink for FunctionRegistry {
+    async fn write(
+        &self,
+        _ctx: &ExecutionContext,
+        _sink_type: &DataSinkType,
+        partition: Option<usize>,
+        batches: Vec<RecordBatch>,
+    ) -> Result<Value> {
+        self.sink(partition.unwrap_or(0), batches);
+        Ok(Value::Null)
+    }
+}
+
+/// Create a deep copy of the execution context so that each function owns
+/// its execution plan, as if the context was unmarshalled from the cloud
+/// environment.
+fn clone_context(ctx: &ExecutionContext) -> Result<ExecutionContext> {
+    context::unmarshal(context::marshal(ctx, Encoding::None)?)
+}
+
+/// An in-process cloud function.
+pub struct LocalFunction {
+    /// The execution context of the function.
+    ctx:    ExecutionContext,
+    /// The memory arena to collect the data fragments of the windows.
+    arena:  Arena,
+    /// The router to the next function(s), or to the data sink of the
+    /// registry.
+    router: Router,
+}
+
+impl LocalFunction {
+    /// Create a new local function.
+    ///
+    /// # Arguments
+    /// * `ctx` - The execution context of the function.
+    /// * `registry` - The registry of all the functions of the query.
+    pub fn try_new(ctx: &ExecutionContext, registry: Arc<FunctionRegistry>) -> Result<Self> {
+        let mut ctx = clone_context(ctx)?;
+        ctx.invoker = registry.invoker();
+        let router = Router::new(&ctx.next, registry)?;
+        Ok(Self {
+            ctx,
+            arena: Arena::new(),
+            router,
+        })
+    }
+
+    /// Serve the invocations one at a time until the registry is dropped.
+    /// Functions in a group have a concurrency of *1* on the cloud, so do
+    /// the local functions.
+    ///
+    /// The invocations are handled by the same function actor as the cloud
+    /// functions, see [`actor::handler`].
+    pub async fn run(mut self, mut invocations: mpsc::UnboundedReceiver<Invocation>) {
+        if let Err(e) = actor::init_arena(&self.ctx, &mut self.arena).await {
+            error!("Failed to initialize the function {}: {}", self.ctx.name, e);
+            return;
+        }
+        while let Some((bytes, tx)) = invocations.recv().await {
+            let result = match serde_json::from_slice::<Payload>(&bytes) {
+                Ok(payload) => {
+                    actor::handler(&mut self.ctx, &mut self.arena, &self.router, payload)
+                        .await
+                        .map(|_| Bytes::new())
+                }
+                Err(e) => Err(FlockError::from(e)),
+            };
+            if let Some(tx) = tx {
+                let _ = tx.send(result);
+            }
+        }
+    }
+}
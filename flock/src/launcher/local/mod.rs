@@ -0,0 +1,818 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! This crate responsibles for executing queries on the local machine.
+
+mod function;
+pub use function::{FunctionRegistry, LocalFunction};
+
+use crate::configs::*;
+use crate::datasource::DataStream;
+use crate::error::{FlockError, Result};
+use crate::launcher::{AwsLambdaLauncher, ExecutionMode, Launcher};
+use crate::query::Query;
+use crate::runtime::actor::Router;
+use crate::runtime::context::{CloudFunction, CloudFunctionType};
+use crate::runtime::payload::UuidBuilder;
+use crate::stream::{Schedule, TriggeredWindows};
+use crate::transmute::to_payload;
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use daggy::NodeIndex;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::physical_plan::collect;
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::ExecutionPlan;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// LocalLauncher executes the query locally.
+pub struct LocalLauncher {
+    /// The physical plan of the query.
+    execution_plan: Arc<dyn ExecutionPlan>,
+    /// The query to execute.
+    query:          Query,
+    /// The data sources fed to the query.
+    sources:        Vec<Vec<Vec<RecordBatch>>>,
+    /// The number of functions in each function group in distributed mode.
+    group_size:     usize,
+}
+
+#[async_trait]
+impl Launcher for LocalLauncher {
+    async fn new(query: &Query) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(LocalLauncher {
+            execution_plan: query.plan().unwrap(),
+            query:          query.clone(),
+            sources:        vec![],
+            group_size:     *FLOCK_FUNCTION_CONCURRENCY,
+        })
+    }
+
+    async fn deploy(&mut self) -> Result<()> {
+        Err(FlockError::Internal(
+            "Local execution doesn't require a deployment.".to_owned(),
+        ))
+    }
+
+    /// Execute the query on the local machine.
+    ///
+    /// In centralized mode, the entire physical plan is executed at once. In
+    /// distributed mode, the query stages of the DAG are executed by
+    /// in-process functions that forward payloads to each other in the same
+    /// way as the cloud functions do.
+    async fn execute(&self, mode: ExecutionMode) -> Result<Vec<RecordBatch>> {
+        match mode {
+            ExecutionMode::Centralized => self.collect().await,
+            ExecutionMode::Distributed => self.collect_distributed().await,
+        }
+    }
+}
+
+impl LocalLauncher {
+    /// Compare two execution plans' schemas.
+    /// Returns true if they are belong to the same plan node.
+    ///
+    /// # Arguments
+    /// * `schema1` - The first schema.
+    /// * `schema2` - The second schema.
+    ///
+    /// # Returns
+    /// * `true` - If the schemas belong to the same plan node.
+    fn compare_schema(schema1: SchemaRef, schema2: SchemaRef) -> bool {
+        let (superset, subset) = if schema1.fields().len() >= schema2.fields().len() {
+            (schema1, schema2)
+        } else {
+            (schema2, schema1)
+        };
+
+        let fields = superset
+            .fields()
+            .iter()
+            .map(|f| f.name())
+            .collect::<HashSet<_>>();
+
+        subset.fields().iter().all(|f| fields.contains(&f.name()))
+    }
+
+    /// Feeds the query with data.
+    ///
+    /// # Arguments
+    /// * `sources` - A list of data sources.
+    pub fn feed_data_sources(&mut self, mut sources: Vec<Vec<Vec<RecordBatch>>>) {
+        self.sources = sources.clone();
+
+        // Breadth-first search
+        let mut queue = VecDeque::new();
+        queue.push_back(self.execution_plan.clone());
+
+        let mut found = false;
+        let mut index = 0xFFFFFFFF;
+        while !queue.is_empty() {
+            let mut plan = queue.pop_front().unwrap();
+            if plan.children().is_empty() {
+                for (i, partition) in sources.iter().enumerate() {
+                    let mut schema = Arc::new(Schema::new(vec![]));
+                    let mut flag = false;
+                    for p in partition.iter().filter(|p| !p.is_empty()) {
+                        if let Some(b) = p.iter().next() {
+                            schema = b.schema();
+                            flag = true;
+                            break;
+                        }
+                    }
+                    if !flag {
+                        continue;
+                    }
+
+                    if LocalLauncher::compare_schema(plan.schema(), schema) {
+                        index = i;
+                        found = true;
+                        break;
+                    }
+                }
+
+                if found {
+                    unsafe {
+                        Arc::get_mut_unchecked(&mut plan)
+                            .as_mut_any()
+                            .downcast_mut::<MemoryExec>()
+                            .unwrap()
+                            .set_partitions(sources.remove(index));
+                        index = 0xFFFFFFFF;
+                        found = false;
+                    }
+                }
+            }
+
+            plan.children()
+                .iter()
+                .enumerate()
+                .for_each(|(i, _)| queue.push_back(plan.children()[i].clone()));
+        }
+    }
+
+    /// Collects the results of the query.
+    pub async fn collect(&self) -> Result<Vec<RecordBatch>> {
+        collect(self.execution_plan.clone())
+            .await
+            .map_err(|e| FlockError::Execution(e.to_string()))
+    }
+
+    /// Collects the results of the query on the windows fired by the schedule.
+    ///
+    /// The local runtime simulates the wall clock of the stream: the `t`-th
+    /// epoch of the stream arrives at `t` seconds after the Unix epoch, so the
+    /// rate and cron schedules fire deterministically.
+    ///
+    /// # Arguments
+    /// * `stream` - The source stream of events.
+    /// * `seconds` - The total number of epochs to read from the stream.
+    /// * `query_number` - The query number of the benchmark, if any.
+    /// * `schedule` - The rate, cron, seconds or row-count schedule.
+    /// * `key` - The key column of the row-count windows, if any.
+    ///
+    /// # Returns
+    /// The results of the query for each fired window.
+    pub async fn collect_windows(
+        &mut self,
+        stream: &dyn DataStream,
+        seconds: usize,
+        query_number: Option<usize>,
+        schedule: &Schedule,
+        key: Option<&str>,
+    ) -> Result<Vec<Vec<RecordBatch>>> {
+        let clock = |t: usize| Utc.timestamp(t as i64, 0);
+        let mut windows =
+            TriggeredWindows::new(schedule.trigger()?, key.map(|k| k.to_owned()), 2, clock(0));
+
+        let mut fired = vec![];
+        for time in 0..seconds {
+            fired.extend(windows.tick(clock(time)));
+            let (r1, r2) = stream.select_event_to_batches(time, 0, query_number, true)?;
+            for (relation, partitions) in [r1, r2].iter().enumerate() {
+                fired.extend(windows.insert(relation, partitions)?);
+            }
+        }
+        fired.extend(windows.tick(clock(seconds)));
+        fired.extend(windows.flush());
+
+        // The relations without records in a window are fed with empty batches,
+        // so that they don't keep the data of the previous window.
+        let mut schemas: Vec<Option<SchemaRef>> = vec![None; 2];
+        let mut results = vec![];
+        for window in fired {
+            let sources = window
+                .into_iter()
+                .enumerate()
+                .map(|(i, partitions)| {
+                    match partitions.iter().flatten().next() {
+                        Some(batch) => schemas[i] = Some(batch.schema()),
+                        None => {
+                            if let Some(schema) = &schemas[i] {
+                                return vec![vec![RecordBatch::new_empty(schema.clone())]];
+                            }
+                        }
+                    }
+                    partitions
+                })
+                .collect::<Vec<_>>();
+            self.feed_data_sources(sources);
+            results.push(self.collect().await?);
+        }
+        Ok(results)
+    }
+
+    /// Returns the data sources scanned by the query stage, which are the
+    /// sources whose schema matches one of the scans of the stage.
+    ///
+    /// # Arguments
+    /// * `stage` - The execution plans of the query stage.
+    fn stage_sources(&self, stage: &[Arc<dyn ExecutionPlan>]) -> Vec<Vec<Vec<RecordBatch>>> {
+        // Breadth-first search
+        let mut queue = stage.iter().cloned().collect::<VecDeque<_>>();
+        let mut schemas = vec![];
+        while let Some(plan) = queue.pop_front() {
+            if plan.children().is_empty() {
+                schemas.push(plan.schema());
+            }
+            queue.extend(plan.children());
+        }
+
+        self.sources
+            .iter()
+            .filter(|relation| {
+                relation.iter().flatten().next().map_or(false, |batch| {
+                    schemas
+                        .iter()
+                        .any(|schema| LocalLauncher::compare_schema(schema.clone(), batch.schema()))
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Collects the results of the query in distributed mode.
+    ///
+    /// Each query stage is executed by a tokio task that simulates a lambda
+    /// function, or by `group_size` tokio tasks if the stage is a function
+    /// group. The launcher itself only plays the role of the data source
+    /// function: it sends the `i`-th partitions of the data sources scanned by
+    /// each leaf stage to the leaf function as the `i`-th data fragment of the
+    /// window.
+    ///
+    /// The windows of all the leaf stages share the same query id, so that a
+    /// function group that gathers the outputs of several input stages, such
+    /// as a join, collects them in the same window.
+    pub async fn collect_distributed(&self) -> Result<Vec<RecordBatch>> {
+        if self.sources.is_empty() {
+            return Err(FlockError::Execution(
+                "No data sources are fed to the query.".to_owned(),
+            ));
+        }
+
+        let mut launcher = AwsLambdaLauncher::new(&self.query).await?;
+        launcher.create_cloud_contexts(self.group_size)?;
+        let dag = &launcher.dag;
+
+        // Register all the functions before any of them starts to run.
+        let registry = FunctionRegistry::new();
+        let mut functions = vec![];
+        for i in (0..dag.node_count()).map(NodeIndex::new) {
+            let node = dag.get_node(i).unwrap();
+            let ctx = node.context.as_ref().unwrap();
+            if node.get_function_type() == CloudFunctionType::Group {
+                for j in 0..self.group_size {
+                    let member = ctx.group_member(j)?;
+                    functions.push((registry.register(&member.name), member));
+                }
+            } else {
+                functions.push((registry.register(&ctx.name), ctx.clone()));
+            }
+        }
+
+        let registry = Arc::new(registry);
+        let handles = functions
+            .into_iter()
+            .map(|(invocations, ctx)| {
+                LocalFunction::try_new(&ctx, registry.clone())
+                    .map(|function| tokio::spawn(function.run(invocations)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let timestamp = Utc::now().timestamp();
+        let window = uuid::Uuid::new_v4().as_u128();
+        let mut invocations = vec![];
+        for leaf in dag.get_leaves() {
+            let node = dag.get_node(leaf).unwrap();
+            let ctx = node.context.as_ref().unwrap();
+            let relations = self.stage_sources(&node.stage);
+            if relations.len() > 2 {
+                return Err(FlockError::Execution(format!(
+                    "The stage {} scans {} data sources, but a payload carries at most two.",
+                    ctx.name,
+                    relations.len()
+                )));
+            }
+
+            // The data source function samples the range boundaries of the
+            // whole window, so that all the fragments are split at the same
+            // boundaries.
+            let mut metadata = None;
+            ctx.sample_range_boundaries(&relations, &mut metadata)?;
+
+            let next = match node.get_function_type() {
+                CloudFunctionType::Group => {
+                    CloudFunction::Group((ctx.name.clone(), self.group_size))
+                }
+                _ => CloudFunction::Lambda(ctx.name.clone()),
+            };
+            let router = Router::new(&next, registry.clone())?;
+
+            let empty = vec![];
+            let partitions = |r: usize, i: usize| {
+                relations
+                    .get(r)
+                    .and_then(|relation| relation.get(i))
+                    .unwrap_or(&empty)
+            };
+            let size = relations.iter().map(|r| r.len()).max().unwrap_or(1);
+            let mut uuid_builder =
+                UuidBuilder::new_with_ts_uuid(&ctx.name, timestamp, window, size);
+            for i in 0..size {
+                let uuid = uuid_builder.next_uuid();
+                let name = router.group_function(&uuid, None);
+                let mut payload = to_payload(partitions(0, i), partitions(1, i), uuid, true);
+                payload.metadata = metadata.clone();
+                let registry = registry.clone();
+                invocations.push(async move { registry.invoke(&name, payload).await });
+            }
+        }
+
+        let result = futures::future::join_all(invocations)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>();
+        handles.iter().for_each(|h| h.abort());
+        result?;
+
+        Ok(registry.take_sink())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_batches_eq;
+    use crate::assert_batches_sorted_eq;
+    use crate::configs::FLOCK_S3_BUCKET;
+    use crate::datasink::DataSinkType;
+    use crate::datasource::nexmark::event::{side_input_schema, Auction, Bid, Person};
+    use crate::datasource::nexmark::NEXMarkSource;
+    use crate::datasource::side_input::{SideInput, SideInputFormat};
+    use crate::datasource::ysb::event::{AdEvent, Campaign};
+    use crate::datasource::ysb::YSBSource;
+    use crate::datasource::{DataSource, RelationPartitions};
+    use crate::objectstore::object_store;
+    use crate::query::Table;
+    use crate::query::{QueryType, StreamType};
+    use crate::state::*;
+    use crate::stream::{Schedule, Window};
+    use crate::test_util::init_in_memory_object_store;
+    use crate::transmute::event_bytes_to_batch;
+    use datafusion::arrow::array::*;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use indoc::indoc;
+
+    #[tokio::test]
+    async fn version_check() -> Result<()> {
+        let manifest = cargo_toml::Manifest::from_str(include_str!("../../../Cargo.toml")).unwrap();
+        assert_eq!(env!("CARGO_PKG_VERSION"), manifest.package.unwrap().version);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_launcher() -> Result<()> {
+        let table_name = "test_table".to_owned();
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("c1", DataType::Int64, false),
+            Field::new("c2", DataType::Float64, false),
+            Field::new("c3", DataType::Utf8, false),
+            Field::new("c4", DataType::UInt64, false),
+            Field::new("c5", DataType::Utf8, false),
+            Field::new("neg", DataType::Int64, false),
+        ]));
+        let sql = "SELECT MIN(c1), AVG(c4), COUNT(c3) FROM test_table";
+        let query = Query::new(
+            sql,
+            vec![Table(table_name, schema.clone())],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::OLAP,
+            Arc::new(HashMapStateBackend::new()),
+        );
+
+        let mut launcher = LocalLauncher::new(&query).await?;
+
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![90, 90, 91, 101, 92, 102, 93, 103])),
+                Arc::new(Float64Array::from(vec![
+                    92.1, 93.2, 95.3, 96.4, 98.5, 99.6, 100.7, 101.8,
+                ])),
+                Arc::new(StringArray::from(vec![
+                    "a", "a", "d", "b", "b", "d", "c", "c",
+                ])),
+                Arc::new(UInt64Array::from(vec![33, 1, 54, 33, 12, 75, 2, 87])),
+                Arc::new(StringArray::from(vec![
+                    "rapport",
+                    "pedantic",
+                    "mimesis",
+                    "haptic",
+                    "baksheesh",
+                    "amok",
+                    "devious",
+                    "c",
+                ])),
+                Arc::new(Int64Array::from(vec![
+                    -90, -90, -91, -101, -92, -102, -93, -103,
+                ])),
+            ],
+        )?;
+
+        launcher.feed_data_sources(vec![vec![vec![batch]]]);
+        let batches = launcher.collect().await?;
+
+        let expected = vec![
+            "+--------------------+--------------------+----------------------+",
+            "| MIN(test_table.c1) | AVG(test_table.c4) | COUNT(test_table.c3) |",
+            "+--------------------+--------------------+----------------------+",
+            "| 90                 | 37.125             | 8                    |",
+            "+--------------------+--------------------+----------------------+",
+        ];
+
+        assert_batches_eq!(&expected, &batches);
+
+        let batches = launcher.execute(ExecutionMode::Distributed).await?;
+        assert_batches_eq!(&expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_launcher_distributed_nexmark_q3() -> Result<()> {
+        let auction_schema = Arc::new(Auction::schema());
+        let person_schema = Arc::new(Person::schema());
+
+        let query = Query::new(
+            indoc! {"
+                SELECT  name,
+                        city,
+                        state,
+                        a_id
+                FROM    auction
+                        INNER JOIN person
+                                ON seller = p_id
+                WHERE  category = 10
+                        AND ( state = 'or'
+                                OR state = 'id'
+                                OR state = 'ca' )
+                ORDER BY a_id ASC
+            "},
+            vec![
+                Table("auction".to_string(), auction_schema.clone()),
+                Table("person".to_string(), person_schema.clone()),
+            ],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::Streaming(StreamType::NEXMarkBench),
+            Arc::new(HashMapStateBackend::new()),
+        );
+
+        let nexmark_source = NEXMarkSource::new(1, 1, 10_000, Window::ElementWise);
+        let stream = nexmark_source.generate_data()?;
+        let (events, _) = stream.select(0, 0).expect("Failed to select event.");
+        let auctions_batches = event_bytes_to_batch(&events.auctions, auction_schema, 1024);
+        let person_batches = event_bytes_to_batch(&events.persons, person_schema, 1024);
+
+        let mut launcher = LocalLauncher::new(&query).await?;
+        launcher.feed_data_sources(vec![vec![auctions_batches], vec![person_batches]]);
+
+        let batches = launcher.execute(ExecutionMode::Centralized).await?;
+        let formatted = pretty_format_batches(&batches).unwrap().to_string();
+        let expected: Vec<&str> = formatted.trim().lines().collect();
+
+        // Each function in the group sorts a range of the results, and the
+        // sink concatenates the sorted ranges in order.
+        let batches = launcher.execute(ExecutionMode::Distributed).await?;
+        assert_batches_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_launcher_distributed_nexmark_q4() -> Result<()> {
+        let auction_schema = Arc::new(Auction::schema());
+        let bid_schema = Arc::new(Bid::schema());
+
+        let query = Query::new(
+            indoc! {"
+                SELECT category,
+                    Avg(final)
+                FROM   (SELECT Max(price) AS final,
+                            category
+                        FROM   auction
+                            INNER JOIN bid
+                                    ON a_id = auction
+                        WHERE  b_date_time BETWEEN a_date_time AND expires
+                        GROUP  BY a_id,
+                                category) AS Q
+                GROUP  BY category;
+            "},
+            vec![
+                Table("auction".to_string(), auction_schema.clone()),
+                Table("bid".to_string(), bid_schema.clone()),
+            ],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::Streaming(StreamType::NEXMarkBench),
+            Arc::new(HashMapStateBackend::new()),
+        );
+
+        let nexmark_source = NEXMarkSource::new(1, 1, 1000, Window::ElementWise);
+        let stream = nexmark_source.generate_data()?;
+        let (events, _) = stream.select(0, 0).expect("Failed to select event.");
+        let auctions_batches = event_bytes_to_batch(&events.auctions, auction_schema, 1024);
+        let bids_batches = event_bytes_to_batch(&events.bids, bid_schema, 1024);
+
+        let mut launcher = LocalLauncher::new(&query).await?;
+        launcher.feed_data_sources(vec![vec![auctions_batches], vec![bids_batches]]);
+
+        let batches = launcher.execute(ExecutionMode::Centralized).await?;
+        let formatted = pretty_format_batches(&batches).unwrap().to_string();
+        let expected: Vec<&str> = formatted.trim().lines().collect();
+
+        // Two function groups are involved: the first one shuffles the results
+        // of the partial aggregation to the second one.
+        let batches = launcher.execute(ExecutionMode::Distributed).await?;
+        assert_batches_sorted_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_launcher_distributed_nexmark_q13() -> Result<()> {
+        init_in_memory_object_store();
+        let key = "local_launcher_distributed_nexmark_q13/side_input.csv";
+        let csv = (0..10_000).fold("key,value\n".to_owned(), |csv, i| {
+            csv + &format!("{},{}\n", i, i % 100)
+        });
+        object_store()
+            .put(&FLOCK_S3_BUCKET, key, csv.into_bytes())
+            .await?;
+
+        let bid_schema = Arc::new(Bid::schema());
+        let side_input =
+            SideInput::new("side_input", key, SideInputFormat::Csv, side_input_schema());
+        let query = Query::new(
+            include_str!("../../../../benchmarks/src/nexmark/query/q13.sql"),
+            vec![Table("bid".to_string(), bid_schema.clone())],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::Streaming(StreamType::NEXMarkBench),
+            Arc::new(HashMapStateBackend::new()),
+        )
+        .with_side_inputs(vec![side_input.clone()]);
+
+        let nexmark_source = NEXMarkSource::new(1, 1, 1000, Window::ElementWise);
+        let stream = nexmark_source.generate_data()?;
+        let (events, _) = stream.select(0, 0).expect("Failed to select event.");
+        let bids_batches = event_bytes_to_batch(&events.bids, bid_schema, 1024);
+
+        // The functions load the side input from the object store themselves.
+        let mut launcher = LocalLauncher::new(&query).await?;
+        launcher.feed_data_sources(vec![vec![bids_batches.clone()]]);
+        let batches = launcher.execute(ExecutionMode::Distributed).await?;
+
+        launcher.feed_data_sources(vec![vec![bids_batches], vec![side_input.load().await?]]);
+        let expected = launcher.execute(ExecutionMode::Centralized).await?;
+        let formatted = pretty_format_batches(&expected).unwrap().to_string();
+        let expected: Vec<&str> = formatted.trim().lines().collect();
+        assert_batches_sorted_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_launcher_distributed_ysb() -> Result<()> {
+        let ad_event_schema = Arc::new(AdEvent::schema());
+        let campaign_schema = Arc::new(Campaign::schema());
+
+        let query = Query::new(
+            indoc! {"
+                SELECT  campaign_id,
+                        Count(*)
+                FROM    ad_event
+                        INNER JOIN campaign
+                                ON ad_id = c_ad_id
+                WHERE   event_type = 'view'
+                GROUP   BY campaign_id;
+            "},
+            vec![
+                Table("ad_event".to_string(), ad_event_schema.clone()),
+                Table("campaign".to_string(), campaign_schema.clone()),
+            ],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::Streaming(StreamType::YSBBench),
+            Arc::new(HashMapStateBackend::new()),
+        );
+
+        let ysb_source = YSBSource::new(1, 1, 1000, Window::Tumbling(Schedule::Seconds(10)));
+        let stream = ysb_source.generate_data()?;
+        let (events, _) = stream.select(0, 0).expect("Failed to select event.");
+        let ad_events_batches = event_bytes_to_batch(&events.ad_events, ad_event_schema, 1024);
+        let campaigns_batches = event_bytes_to_batch(&stream.campaigns.0, campaign_schema, 1024);
+
+        let mut launcher = LocalLauncher::new(&query).await?;
+        launcher.feed_data_sources(vec![vec![ad_events_batches], vec![campaigns_batches]]);
+
+        let batches = launcher.execute(ExecutionMode::Centralized).await?;
+        let formatted = pretty_format_batches(&batches).unwrap().to_string();
+        let expected: Vec<&str> = formatted.trim().lines().collect();
+
+        let batches = launcher.execute(ExecutionMode::Distributed).await?;
+        assert_batches_sorted_eq!(expected, &batches);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_launcher_distributed_multi_leaf_join() -> Result<()> {
+        let mut tables = vec![];
+        let mut sources = vec![];
+        for (table, key, value) in [("t1", "a", "b"), ("t2", "c", "d"), ("t3", "e", "f")] {
+            let schema = Arc::new(Schema::new(vec![
+                Field::new(key, DataType::Utf8, false),
+                Field::new(value, DataType::Int32, false),
+            ]));
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(StringArray::from(vec!["a", "b", "c", "d", "d"])),
+                    Arc::new(Int32Array::from(vec![1, 10, 10, 100, 1000])),
+                ],
+            )?;
+            tables.push(Table(table.to_owned(), schema));
+            sources.push(vec![vec![batch]]);
+        }
+
+        // Each input of the upper join is computed by its own stages, so the
+        // join gathers the outputs of two leaf stages.
+        let sqls = [
+            "SELECT a, b, d, f FROM t1 JOIN t2 ON a = c JOIN t3 ON c = e",
+            "SELECT a, b, cnt FROM t1 JOIN (SELECT e, COUNT(f) AS cnt FROM t3 GROUP BY e) AS q \
+             ON a = e",
+        ];
+        for sql in sqls {
+            let query = Query::new(
+                sql,
+                tables.clone(),
+                DataSource::Memory,
+                DataSinkType::Blackhole,
+                None,
+                QueryType::OLAP,
+                Arc::new(HashMapStateBackend::new()),
+            );
+
+            let mut launcher = LocalLauncher::new(&query).await?;
+            launcher.feed_data_sources(sources.clone());
+
+            let batches = launcher.execute(ExecutionMode::Centralized).await?;
+            let formatted = pretty_format_batches(&batches).unwrap().to_string();
+            let expected: Vec<&str> = formatted.trim().lines().collect();
+            assert!(expected.len() > 4);
+
+            let batches = launcher.execute(ExecutionMode::Distributed).await?;
+            assert_batches_sorted_eq!(expected, &batches);
+        }
+
+        Ok(())
+    }
+
+    /// A stream of one record per epoch, whose key alternates between 0 and 1.
+    struct EpochStream(SchemaRef);
+
+    impl DataStream for EpochStream {
+        fn select_event_to_payload(
+            &self,
+            _time: usize,
+            _generator: usize,
+            _query_number: Option<usize>,
+            _uuid: crate::runtime::payload::Uuid,
+            _sync: bool,
+        ) -> Result<crate::runtime::payload::Payload> {
+            unimplemented!()
+        }
+
+        fn select_event_to_batches(
+            &self,
+            time: usize,
+            _generator: usize,
+            _query_number: Option<usize>,
+            _sync: bool,
+        ) -> Result<(RelationPartitions, RelationPartitions)> {
+            let batch = RecordBatch::try_new(
+                self.0.clone(),
+                vec![
+                    Arc::new(StringArray::from(vec![format!("{}", time % 2)])),
+                    Arc::new(Int64Array::from(vec![time as i64])),
+                ],
+            )?;
+            Ok((vec![vec![batch]], vec![]))
+        }
+    }
+
+    #[tokio::test]
+    async fn local_launcher_scheduled_windows() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        let query = Query::new(
+            "SELECT COUNT(value), MIN(value) FROM epochs",
+            vec![Table("epochs".to_owned(), schema.clone())],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::OLAP,
+            Arc::new(HashMapStateBackend::new()),
+        );
+        let stream = EpochStream(schema);
+
+        let windows = |results: Vec<Vec<RecordBatch>>| {
+            results
+                .iter()
+                .map(|batches| {
+                    let count = batches[0].column(0);
+                    let min = batches[0].column(1);
+                    (
+                        count
+                            .as_any()
+                            .downcast_ref::<UInt64Array>()
+                            .unwrap()
+                            .value(0),
+                        min.as_any().downcast_ref::<Int64Array>().unwrap().value(0),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut launcher = LocalLauncher::new(&query).await?;
+        let schedule = Schedule::Rate("rate(1 minute)".to_owned());
+        let results = launcher
+            .collect_windows(&stream, 150, None, &schedule, None)
+            .await?;
+        assert_eq!(windows(results), vec![(60, 0), (60, 60), (30, 120)]);
+
+        let schedule = Schedule::Cron("cron(0/2 * * * ? *)".to_owned());
+        let results = launcher
+            .collect_windows(&stream, 300, None, &schedule, None)
+            .await?;
+        assert_eq!(windows(results), vec![(120, 0), (120, 120), (60, 240)]);
+
+        let schedule = Schedule::Rows(50);
+        let results = launcher
+            .collect_windows(&stream, 120, None, &schedule, Some("key"))
+            .await?;
+        assert_eq!(
+            windows(results),
+            vec![(50, 0), (50, 1), (10, 100), (10, 101)]
+        );
+
+        Ok(())
+    }
+}
@@ -34,7 +34,9 @@ pub mod distributed_plan;
 pub mod driver;
 pub mod encoding;
 pub mod error;
+pub mod invoker;
 pub mod launcher;
+pub mod objectstore;
 pub mod prelude;
 pub mod query;
 pub mod runtime;
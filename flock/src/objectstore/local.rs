@@ -0,0 +1,244 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The local directory object store.
+
+use super::{ObjectMeta, ObjectStore};
+use crate::error::{FlockError, Result};
+use async_trait::async_trait;
+use std::any::Any;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+
+/// LocalObjectStore stores the objects in a local directory.
+///
+/// The object `key` in the `bucket` is stored at `<root>/<bucket>/<key>`. The
+/// slashes in the key are mapped to subdirectories, so the keys that share a
+/// prefix such as `<plan index>/<shuffle id>/` are listed efficiently. The
+/// buckets are created on the first write.
+///
+/// The file operations go through `tokio::fs`, so they don't block the
+/// threads of the async runtime.
+#[derive(Debug, Clone)]
+pub struct LocalObjectStore {
+    /// The root directory of the object store.
+    pub root: PathBuf,
+}
+
+#[async_trait]
+impl ObjectStore for LocalObjectStore {
+    fn name(&self) -> String {
+        "LocalObjectStore".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn create_bucket(&self, bucket: &str) -> Result<()> {
+        fs::create_dir_all(self.path(bucket, "")?).await?;
+        Ok(())
+    }
+
+    async fn delete_bucket(&self, bucket: &str) -> Result<()> {
+        not_found_as_none(fs::remove_dir_all(self.path(bucket, "")?).await)?;
+        Ok(())
+    }
+
+    async fn put(&self, bucket: &str, key: &str, body: Vec<u8>) -> Result<()> {
+        let path = self.path(bucket, key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // Write to a temporary file first, so the readers never see a
+        // partially written object.
+        let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        fs::write(&tmp, body).await?;
+        fs::rename(tmp, path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path(bucket, key)?).await?)
+    }
+
+    async fn head(&self, bucket: &str, key: &str) -> Result<ObjectMeta> {
+        let metadata = fs::metadata(self.path(bucket, key)?).await?;
+        // The objects are replaced by renaming, so a new body always comes
+        // with a new modification time.
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| FlockError::Internal(e.to_string()))?;
+        Ok(ObjectMeta {
+            size: metadata.len() as usize,
+            etag: format!("{:x}-{:x}", metadata.len(), modified.as_nanos()),
+        })
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<()> {
+        if self.exists(bucket, key).await? {
+            not_found_as_none(fs::remove_file(self.path(bucket, key)?).await)?;
+        }
+        Ok(())
+    }
+
+    async fn list(&self, bucket: &str, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = collect_keys(&self.path(bucket, "")?).await?;
+        keys.retain(|key| key.starts_with(prefix) && !key.contains(".tmp-"));
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn exists(&self, bucket: &str, key: &str) -> Result<bool> {
+        let metadata = not_found_as_none(fs::metadata(self.path(bucket, key)?).await)?;
+        Ok(metadata.map_or(false, |m| m.is_file()))
+    }
+}
+
+impl LocalObjectStore {
+    /// Creates a new LocalObjectStore in the root directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the file path of the object. The bucket and the key must be
+    /// relative paths that stay inside the root directory.
+    fn path(&self, bucket: &str, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(bucket).join(key);
+        if bucket.is_empty()
+            || relative
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(FlockError::Internal(format!(
+                "Invalid object path: {}/{}",
+                bucket, key
+            )));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+/// Collects the keys of all files in the bucket directory and its
+/// subdirectories. A missing bucket has no keys.
+async fn collect_keys(bucket_dir: &Path) -> Result<Vec<String>> {
+    let mut keys = vec![];
+    let mut dirs = vec![bucket_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match not_found_as_none(fs::read_dir(dir).await)? {
+            Some(entries) => entries,
+            None => continue,
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let key = path
+                .strip_prefix(bucket_dir)
+                .map_err(|e| FlockError::Internal(e.to_string()))?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/");
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
+/// Returns `None` if the file or the directory doesn't exist.
+fn not_found_as_none<T>(result: std::io::Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objectstore::InMemoryObjectStore;
+    use std::sync::Arc;
+
+    async fn check_object_store(store: Arc<dyn ObjectStore>) -> Result<()> {
+        let bucket = "flock-object-store-test";
+        store.create_bucket(bucket).await?;
+        store.put(bucket, "01/01/1", b"a".to_vec()).await?;
+        store.put(bucket, "01/01/-2", b"b".to_vec()).await?;
+        store.put(bucket, "01/02/1", b"c".to_vec()).await?;
+        store.put(bucket, "plan", b"d".to_vec()).await?;
+
+        assert_eq!(store.get(bucket, "01/01/-2").await?, b"b".to_vec());
+        assert_eq!(
+            store.list(bucket, "01/01/").await?,
+            vec!["01/01/-2".to_string(), "01/01/1".to_string()]
+        );
+        assert_eq!(store.list(bucket, "").await?.len(), 4);
+        assert!(store.list("flock-missing-bucket", "").await?.is_empty());
+        assert!(store.get(bucket, "missing").await.is_err());
+
+        // The existing object is not modified.
+        store.put_if_missing(bucket, "plan", b"e".to_vec()).await?;
+        assert_eq!(store.get(bucket, "plan").await?, b"d".to_vec());
+        store.put(bucket, "plan", b"e".to_vec()).await?;
+        assert_eq!(store.get(bucket, "plan").await?, b"e".to_vec());
+        assert!(store.exists(bucket, "plan").await?);
+        assert!(!store.exists(bucket, "pla").await?);
+
+        // The entity tag changes with the body.
+        let meta = store.head(bucket, "plan").await?;
+        assert_eq!(meta.size, 1);
+        assert_eq!(store.head(bucket, "plan").await?, meta);
+        store.put(bucket, "plan", b"ef".to_vec()).await?;
+        let new_meta = store.head(bucket, "plan").await?;
+        assert_eq!(new_meta.size, 2);
+        assert_ne!(new_meta.etag, meta.etag);
+        store.put(bucket, "plan", b"e".to_vec()).await?;
+        assert!(store.head(bucket, "missing").await.is_err());
+
+        store.delete(bucket, "plan").await?;
+        store.delete(bucket, "plan").await?;
+        assert!(!store.exists(bucket, "plan").await?);
+
+        store.delete_bucket(bucket).await?;
+        assert!(store.list(bucket, "").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_object_store() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("flock-{}", uuid::Uuid::new_v4()));
+        check_object_store(Arc::new(LocalObjectStore::new(&root))).await?;
+
+        let store = LocalObjectStore::new(&root);
+        assert!(store.put("bucket", "../escape", vec![]).await.is_err());
+        assert!(store.put("", "key", vec![]).await.is_err());
+
+        fs::remove_dir_all(root).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn in_memory_object_store() -> Result<()> {
+        check_object_store(Arc::new(InMemoryObjectStore::new())).await
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The in-memory object store.
+
+use super::ObjectStore;
+use crate::error::{FlockError, Result};
+use async_trait::async_trait;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+/// InMemoryObjectStore stores the objects in the process memory. The buckets
+/// are created on the first write.
+#[derive(Default, Debug)]
+pub struct InMemoryObjectStore {
+    buckets: Mutex<HashMap<String, BTreeMap<String, Vec<u8>>>>,
+}
+
+#[async_trait]
+impl ObjectStore for InMemoryObjectStore {
+    fn name(&self) -> String {
+        "InMemoryObjectStore".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn create_bucket(&self, bucket: &str) -> Result<()> {
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(bucket.to_owned())
+            .or_default();
+        Ok(())
+    }
+
+    async fn delete_bucket(&self, bucket: &str) -> Result<()> {
+        self.buckets.lock().unwrap().remove(bucket);
+        Ok(())
+    }
+
+    async fn put(&self, bucket: &str, key: &str, body: Vec<u8>) -> Result<()> {
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(bucket.to_owned())
+            .or_default()
+            .insert(key.to_owned(), body);
+        Ok(())
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<Vec<u8>> {
+        self.buckets
+            .lock()
+            .unwrap()
+            .get(bucket)
+            .and_then(|objects| objects.get(key))
+            .cloned()
+            .ok_or_else(|| {
+                FlockError::Internal(format!("Object {}/{} doesn't exist.", bucket, key))
+            })
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<()> {
+        if let Some(objects) = self.buckets.lock().unwrap().get_mut(bucket) {
+            objects.remove(key);
+        }
+        Ok(())
+    }
+
+    async fn list(&self, bucket: &str, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .buckets
+            .lock()
+            .unwrap()
+            .get(bucket)
+            .map(|objects| {
+                objects
+                    .range(prefix.to_owned()..)
+                    .take_while(|(key, _)| key.starts_with(prefix))
+                    .map(|(key, _)| key.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+impl InMemoryObjectStore {
+    /// Creates a new InMemoryObjectStore.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Object stores keep the query plans, side inputs, query states and query
+//! results that are too large to be passed through the function payloads.
+//!
+//! Out of the box, Flock bundles these object stores:
+//!
+//! - `S3ObjectStore`: stores the objects in Amazon S3. This is the default
+//!   object store on the cloud.
+//!
+//! - `LocalObjectStore`: stores the objects in a local directory. Each bucket
+//!   is a subdirectory of the root directory, and each key is a file path
+//!   relative to the bucket directory.
+//!
+//! - `InMemoryObjectStore`: stores the objects in the process memory. It is
+//!   used to test the object store dependent features without a network.
+//!
+//! The object store is selected by the `object_store` section in the Flock
+//! configuration, and can be replaced at runtime by `set_object_store`.
+
+mod local;
+pub use local::LocalObjectStore;
+
+mod memory;
+pub use memory::InMemoryObjectStore;
+
+mod s3;
+pub use self::s3::S3ObjectStore;
+
+use crate::configs::*;
+use crate::encoding::content_hash;
+use crate::error::{FlockError, Result};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::any::Any;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+lazy_static! {
+    /// The object store of the current process.
+    static ref OBJECT_STORE: RwLock<Arc<dyn ObjectStore>> =
+        RwLock::new(from_config().expect("Failed to create the object store."));
+}
+
+/// The metadata of an object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectMeta {
+    /// The size of the object in bytes.
+    pub size: usize,
+    /// The entity tag of the object, which changes whenever the object is
+    /// overwritten with a different body.
+    pub etag: String,
+}
+
+/// The object store trait defines the interface for the object storage.
+#[async_trait]
+pub trait ObjectStore: Debug + Send + Sync {
+    /// The type of the object store.
+    fn name(&self) -> String;
+    /// Returns the object store as [`Any`](std::any::Any) so that it can be
+    /// downcast to a specific implementation.
+    fn as_any(&self) -> &dyn Any;
+    /// Creates a new bucket if it does not exist.
+    async fn create_bucket(&self, bucket: &str) -> Result<()>;
+    /// Deletes the bucket and all the objects in it.
+    async fn delete_bucket(&self, bucket: &str) -> Result<()>;
+    /// Puts an object to the bucket. If the object exists, it is overwritten.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket to put the object in.
+    /// * `key` - The key of the object to put.
+    /// * `body` - The body of the object to put.
+    async fn put(&self, bucket: &str, key: &str, body: Vec<u8>) -> Result<()>;
+    /// Gets an object from the bucket.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket to get the object from.
+    /// * `key` - The key of the object to get.
+    ///
+    /// # Returns
+    /// The body of the object.
+    async fn get(&self, bucket: &str, key: &str) -> Result<Vec<u8>>;
+    /// Returns the metadata of an object without reading its body, if the
+    /// object store supports it. By default, the body is read and hashed by
+    /// [`content_hash`].
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket of the object.
+    /// * `key` - The key of the object.
+    ///
+    /// # Returns
+    /// The size and the entity tag of the object.
+    async fn head(&self, bucket: &str, key: &str) -> Result<ObjectMeta> {
+        let body = self.get(bucket, key).await?;
+        Ok(ObjectMeta {
+            size: body.len(),
+            etag: content_hash(&body),
+        })
+    }
+    /// Deletes an object from the bucket. Deleting a missing object is not an
+    /// error.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket to delete the object from.
+    /// * `key` - The key of the object to delete.
+    async fn delete(&self, bucket: &str, key: &str) -> Result<()>;
+    /// Returns the keys in the bucket that begin with the prefix.
+    ///
+    /// # Arguments
+    /// * `bucket` - The name of the bucket to get the keys from.
+    /// * `prefix` - Limits the response to keys that begin with the specified
+    ///   prefix.
+    ///
+    /// # Returns
+    /// A list of keys in the bucket in lexicographical order.
+    async fn list(&self, bucket: &str, prefix: &str) -> Result<Vec<String>>;
+    /// Returns true if the object exists in the bucket.
+    async fn exists(&self, bucket: &str, key: &str) -> Result<bool> {
+        Ok(self.list(bucket, key).await?.iter().any(|k| k == key))
+    }
+    /// Puts an object to the bucket if the object does not exist. If the object
+    /// exists, it isn't modified.
+    async fn put_if_missing(&self, bucket: &str, key: &str, body: Vec<u8>) -> Result<()> {
+        if !self.exists(bucket, key).await? {
+            self.put(bucket, key, body).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the object store specified by the Flock configuration.
+///
+/// | backend  | object store          |
+/// |----------|-----------------------|
+/// | `s3`     | `S3ObjectStore`       |
+/// | `local`  | `LocalObjectStore`    |
+/// | `memory` | `InMemoryObjectStore` |
+pub fn from_config() -> Result<Arc<dyn ObjectStore>> {
+    new_object_store(&FLOCK_OBJECT_STORE_BACKEND)
+}
+
+/// Creates a new object store by the backend name.
+pub fn new_object_store(backend: &str) -> Result<Arc<dyn ObjectStore>> {
+    match backend.to_lowercase().as_str() {
+        "s3" => Ok(Arc::new(S3ObjectStore::new())),
+        "local" => Ok(Arc::new(LocalObjectStore::new(
+            &*FLOCK_OBJECT_STORE_LOCAL_ROOT,
+        ))),
+        "memory" => Ok(Arc::new(InMemoryObjectStore::new())),
+        _ => Err(FlockError::Internal(format!(
+            "Unknown object store backend: {}",
+            backend
+        ))),
+    }
+}
+
+/// Returns the object store of the current process.
+pub fn object_store() -> Arc<dyn ObjectStore> {
+    OBJECT_STORE.read().unwrap().clone()
+}
+
+/// Replaces the object store of the current process.
+pub fn set_object_store(store: Arc<dyn ObjectStore>) {
+    *OBJECT_STORE.write().unwrap() = store;
+}
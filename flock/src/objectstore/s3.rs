@@ -0,0 +1,77 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The Amazon S3 object store.
+
+use super::{ObjectMeta, ObjectStore};
+use crate::aws::s3;
+use crate::error::Result;
+use async_trait::async_trait;
+use std::any::Any;
+
+/// S3ObjectStore stores the objects in Amazon S3 through `FLOCK_S3_CLIENT`.
+#[derive(Default, Debug, Clone)]
+pub struct S3ObjectStore {}
+
+#[async_trait]
+impl ObjectStore for S3ObjectStore {
+    fn name(&self) -> String {
+        "S3ObjectStore".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn create_bucket(&self, bucket: &str) -> Result<()> {
+        s3::create_bucket_if_missing(bucket).await
+    }
+
+    async fn delete_bucket(&self, bucket: &str) -> Result<()> {
+        s3::delete_bucket(bucket).await
+    }
+
+    async fn put(&self, bucket: &str, key: &str, body: Vec<u8>) -> Result<()> {
+        s3::put_object(bucket, key, body).await
+    }
+
+    async fn get(&self, bucket: &str, key: &str) -> Result<Vec<u8>> {
+        s3::get_object(bucket, key).await
+    }
+
+    async fn head(&self, bucket: &str, key: &str) -> Result<ObjectMeta> {
+        let (size, etag) = s3::head_object(bucket, key).await?;
+        Ok(ObjectMeta { size, etag })
+    }
+
+    async fn delete(&self, bucket: &str, key: &str) -> Result<()> {
+        s3::delete_object(bucket, key).await
+    }
+
+    async fn list(&self, bucket: &str, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = s3::get_matched_keys(bucket, prefix).await?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn put_if_missing(&self, bucket: &str, key: &str, body: Vec<u8>) -> Result<()> {
+        s3::put_object_if_missing(bucket, key, body).await
+    }
+}
+
+impl S3ObjectStore {
+    /// Creates a new S3ObjectStore.
+    pub fn new() -> Self {
+        Self {}
+    }
+}
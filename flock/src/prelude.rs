@@ -23,15 +23,22 @@
 
 pub use crate::configs::*;
 pub use crate::datasink::{DataSink, DataSinkFormat, DataSinkType};
+pub use crate::datasource::side_input::{SideInput, SideInputFormat};
 pub use crate::datasource::{nexmark, tpch, ysb, DataSource, DataStream, RelationPartitions};
+pub use crate::distributed_plan::ExplainFormat;
 pub use crate::encoding::Encoding;
 pub use crate::error::{FlockError, Result};
+pub use crate::invoker::*;
 pub use crate::launcher::aws::AwsLambdaLauncher;
+pub use crate::objectstore::{object_store, set_object_store, ObjectStore};
 pub use crate::query::{Query, QueryType, StreamType, Table};
-pub use crate::runtime::arena::{Arena, HashAggregateStatus, WindowSession};
+pub use crate::runtime::arena::{
+    Arena, ArenaCheckpoint, ArenaPolicy, ExpiryPolicy, HashAggregateStatus, WindowSession,
+};
 pub use crate::runtime::context::{self, CloudFunction, CloudFunctionType, ExecutionContext};
+pub use crate::runtime::function::FunctionId;
 pub use crate::runtime::payload::{DataFrame, Payload, Uuid, UuidBuilder};
 pub use crate::runtime::plan::{physical_plan, CloudExecutionPlan};
 pub use crate::state::*;
-pub use crate::stream::{Schedule, Window};
+pub use crate::stream::{EventTime, Schedule, Window};
 pub use crate::transmute::*;
@@ -14,15 +14,24 @@
 //! The query interface is responsible for bringing the underlying query
 //! implementation (streaming and OLAP) into the system backend.
 
+use crate::configs::FLOCK_FUNCTION_CONCURRENCY;
 use crate::datasink::DataSinkType;
+use crate::datasource::side_input::SideInput;
 use crate::datasource::DataSource;
+use crate::distributed_plan::ExplainFormat;
+use crate::encoding::content_hash;
 use crate::error::{FlockError, Result};
+use crate::launcher::{AwsLambdaLauncher, Launcher};
 use crate::state::*;
-use datafusion::arrow::datatypes::SchemaRef;
+use crate::stream::tvf::rewrite_window_functions;
+use crate::stream::{Window, WindowFunction};
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::datasource::MemTable;
 use datafusion::execution::context::{ExecutionConfig, ExecutionContext};
 use datafusion::physical_plan::ExecutionPlan;
+use serde::Serialize;
+use serde_json::Value;
 use std::fmt::Debug;
 use std::sync::Arc;
 
@@ -49,6 +58,50 @@ impl Debug for Table {
     }
 }
 
+/// The content of the query that determines its code.
+#[derive(Serialize)]
+struct QueryContent<'a> {
+    sql:         &'a str,
+    tables:      Vec<(&'a str, &'a Schema)>,
+    window:      Option<Window>,
+    datasource:  &'a DataSource,
+    datasink:    &'a DataSinkType,
+    #[serde(skip_serializing_if = "<[SideInput]>::is_empty")]
+    side_inputs: &'a [SideInput],
+}
+
+/// Writes the JSON value with the keys of every object in sorted order and
+/// without whitespace, so that equal values always have the same text.
+fn write_canonical_json(value: &Value, out: &mut String) {
+    match value {
+        Value::Array(values) => {
+            out.push('[');
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(v, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(k.to_owned()).to_string());
+                out.push(':');
+                write_canonical_json(v, out);
+            }
+            out.push('}');
+        }
+        _ => out.push_str(&value.to_string()),
+    }
+}
+
 /// The stream type for the query.
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
@@ -93,13 +146,19 @@ pub struct Query {
     /// A sink for the output of the query.
     pub datasink:      DataSinkType,
     /// This is used to specify the function name for benchmarking. Otherwise,
-    /// the function name is generated from `sql`. To make the debugging easier,
-    /// we define human-readable function name for benchmarking.
+    /// the function name is derived from the query, see [`Query::code`]. To
+    /// make the debugging easier, we define human-readable function name for
+    /// benchmarking.
     pub query_code:    Option<String>,
     /// The query type.
     pub query_type:    QueryType,
     /// The state backend to use.
     pub state_backend: Arc<dyn StateBackend>,
+    /// The tables that are read from the object store rather than the data
+    /// source, such as lookup tables. The joins with a small side input are
+    /// broadcast, see
+    /// [`BroadcastJoinExec`](crate::distributed_plan::BroadcastJoinExec).
+    pub side_inputs:   Vec<SideInput>,
 }
 
 impl Default for Query {
@@ -112,6 +171,7 @@ impl Default for Query {
             query_code:    None,
             query_type:    QueryType::default(),
             state_backend: Arc::new(HashMapStateBackend::new()),
+            side_inputs:   vec![],
         }
     }
 }
@@ -138,9 +198,22 @@ impl Query {
             query_code: query_code.map(|x| x.into()),
             query_type,
             state_backend,
+            side_inputs: vec![],
         }
     }
 
+    /// Declares the side inputs of the query. A side input whose table isn't
+    /// in `tables` is registered with its own schema.
+    pub fn with_side_inputs(mut self, side_inputs: Vec<SideInput>) -> Self {
+        self.side_inputs = side_inputs;
+        self
+    }
+
+    /// Returns the side inputs of the query.
+    pub fn side_inputs(&self) -> &Vec<SideInput> {
+        &self.side_inputs
+    }
+
     /// Returns a SQL query.
     pub fn sql(&self) -> String {
         self.sql.to_owned()
@@ -170,26 +243,107 @@ impl Query {
     /// Returns the physical plan for a given query.
     pub fn plan(&self) -> Result<Arc<dyn ExecutionPlan>> {
         let mut ctx = ExecutionContext::new();
-        for table in &self.tables {
-            let mem_table = MemTable::try_new(
-                table.1.clone(),
-                vec![vec![RecordBatch::new_empty(table.1.clone())]],
-            )?;
-            ctx.register_table(table.0.as_ref(), Arc::new(mem_table))?;
-        }
-
-        let plan = ctx.create_logical_plan(self.sql.as_ref())?;
+        let sql = self.register_tables(&mut ctx)?;
+        let plan = ctx.create_logical_plan(&sql)?;
         let plan = ctx.optimize(&plan)?;
 
         futures::executor::block_on(ctx.create_physical_plan(&plan))
             .map_err(|e| FlockError::Internal(e.to_string()))
     }
 
+    /// Returns the windowing table-valued function in the SQL, if any.
+    pub fn window_function(&self) -> Result<Option<WindowFunction>> {
+        let (_, mut functions) = rewrite_window_functions(&self.sql)?;
+        if functions.len() > 1 {
+            return Err(FlockError::Plan(
+                "Only one window function is supported in a query.".to_owned(),
+            ));
+        }
+        Ok(functions.pop())
+    }
+
+    /// Returns the window described by the SQL, if any.
+    pub fn window(&self) -> Result<Option<Window>> {
+        Ok(self.window_function()?.map(|f| f.window))
+    }
+
+    /// Registers the tables and the side inputs of the query in the context.
+    /// The table of the window function is extended with the `window_start`
+    /// and `window_end` columns, and the columns of the side inputs are marked
+    /// with their table names, see [`SideInput::schema`].
+    ///
+    /// # Returns
+    /// The SQL without the window function.
+    fn register_tables(&self, ctx: &mut ExecutionContext) -> Result<String> {
+        let function = self.window_function()?;
+        let side_inputs = self
+            .side_inputs
+            .iter()
+            .filter(|s| self.tables.iter().all(|t| t.0 != s.table))
+            .map(|s| Table::new(s.table.clone(), s.schema()))
+            .collect::<Vec<_>>();
+        for table in self.tables.iter().chain(side_inputs.iter()) {
+            let side_input = self.side_inputs.iter().find(|s| s.table == table.0);
+            let schema = match (&function, side_input) {
+                (Some(f), _) if f.table == table.0 => Arc::new(f.extend_schema(&table.1)),
+                (_, Some(side_input)) => side_input.schema(),
+                _ => table.1.clone(),
+            };
+            let mem_table =
+                MemTable::try_new(schema.clone(), vec![vec![RecordBatch::new_empty(schema)]])?;
+            ctx.register_table(table.0.as_ref(), Arc::new(mem_table))?;
+        }
+        Ok(rewrite_window_functions(&self.sql)?.0)
+    }
+
     /// Returns the query code for a given query.
     pub fn query_code(&self) -> Option<String> {
         self.query_code.to_owned()
     }
 
+    /// Returns the code that names the cloud functions of the query.
+    ///
+    /// If `query_code` is not specified, the code is derived from a canonical
+    /// serialization of the SQL, the table schemas, the window, the data
+    /// source, the data sink and the side inputs, and hashed by
+    /// [`content_hash`]. Hence, the same query is always deployed to the
+    /// same functions, and the queries that only differ in their sources or
+    /// sinks are not.
+    pub fn code(&self) -> Result<String> {
+        if let Some(code) = &self.query_code {
+            return Ok(code.to_owned());
+        }
+
+        let content = serde_json::to_value(&QueryContent {
+            sql:         &self.sql,
+            tables:      self
+                .tables
+                .iter()
+                .map(|t| (t.0.as_str(), t.1.as_ref()))
+                .collect(),
+            window:      self.window()?,
+            datasource:  &self.datasource,
+            datasink:    &self.datasink,
+            side_inputs: &self.side_inputs,
+        })?;
+        let mut canonical = String::new();
+        write_canonical_json(&content, &mut canonical);
+
+        Ok(content_hash(canonical.as_bytes()))
+    }
+
+    /// Returns how the query is split into query stages, and how the cloud
+    /// functions of the stages are named and connected, see
+    /// [`QueryDag::explain`](crate::distributed_plan::QueryDag::explain).
+    ///
+    /// # Arguments
+    /// * `format` - The output format of the explanation.
+    pub async fn explain_distributed(&self, format: ExplainFormat) -> Result<String> {
+        let mut launcher = AwsLambdaLauncher::new(self).await?;
+        launcher.create_cloud_contexts(*FLOCK_FUNCTION_CONCURRENCY)?;
+        launcher.dag.explain(format)
+    }
+
     /// Returns the physical plan for a given query.
     ///
     /// # Arguments
@@ -201,18 +355,122 @@ impl Query {
     ) -> Result<Arc<dyn ExecutionPlan>> {
         let config = ExecutionConfig::new().with_target_partitions(shuffle_partitions);
         let mut ctx = ExecutionContext::with_config(config);
-        for table in &self.tables {
-            let mem_table = MemTable::try_new(
-                table.1.clone(),
-                vec![vec![RecordBatch::new_empty(table.1.clone())]],
-            )?;
-            ctx.register_table(table.0.as_ref(), Arc::new(mem_table))?;
-        }
-
-        let plan = ctx.create_logical_plan(self.sql.as_ref())?;
+        let sql = self.register_tables(&mut ctx)?;
+        let plan = ctx.create_logical_plan(&sql)?;
         let plan = ctx.optimize(&plan)?;
 
         futures::executor::block_on(ctx.create_physical_plan(&plan))
             .map_err(|e| FlockError::Internal(e.to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasource::side_input::SideInputFormat;
+    use crate::encoding::CONTENT_HASH_LEN;
+    use crate::runtime::function::{FunctionId, MAX_QUERY_CODE_LEN};
+    use datafusion::arrow::datatypes::{DataType, Field};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[tokio::test]
+    async fn stable_query_code() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("k", DataType::Utf8, false),
+            Field::new("v", DataType::Int64, false),
+        ]));
+        let query = Query::new(
+            "SELECT k, SUM(v) FROM t GROUP BY k",
+            vec![Table::new("t", schema)],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::OLAP,
+            Arc::new(HashMapStateBackend::new()),
+        );
+
+        let code = query.code()?;
+        assert_eq!(code.len(), CONTENT_HASH_LEN);
+        assert!(CONTENT_HASH_LEN <= MAX_QUERY_CODE_LEN);
+        assert!(code.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(FunctionId::try_new(&code, 0)?.query_code, code);
+        assert_eq!(query.clone().code()?, code);
+
+        // The state backend and the query type don't change the functions.
+        let mut same = query.clone();
+        same.query_type = QueryType::Streaming(StreamType::Regular);
+        assert_eq!(same.code()?, code);
+
+        // Queries with the same SQL but different schemas, sources or sinks are
+        // deployed to different functions.
+        let mut other = query.clone();
+        other.tables = vec![Table::new(
+            "t",
+            Arc::new(Schema::new(vec![
+                Field::new("k", DataType::Utf8, false),
+                Field::new("v", DataType::Float64, false),
+            ])),
+        )];
+        assert_ne!(other.code()?, code);
+
+        let mut other = query.clone();
+        other.datasource = DataSource::Json;
+        assert_ne!(other.code()?, code);
+
+        let mut other = query.clone();
+        other.datasink = DataSinkType::S3;
+        assert_ne!(other.code()?, code);
+
+        // The side inputs are read by the functions.
+        let mut other = query.clone();
+        other.side_inputs = vec![SideInput::new(
+            "lookup",
+            "lookup.csv",
+            SideInputFormat::Csv,
+            Schema::new(vec![Field::new("k", DataType::Utf8, false)]),
+        )];
+        assert_ne!(other.code()?, code);
+
+        // The human-readable query code is used as is.
+        let mut other = query.clone();
+        other.query_code = Some("q1".to_owned());
+        assert_eq!(other.code()?, "q1");
+
+        // The code of a fixed query never changes across toolchains and
+        // releases, otherwise the deployed functions would be orphaned.
+        let field = |name: &str, data_type: DataType| {
+            let mut field = Field::new(name, data_type, false);
+            field.set_metadata(Some(BTreeMap::from([(
+                "origin".to_owned(),
+                "nexmark".to_owned(),
+            )])));
+            field
+        };
+        let golden = Query::new(
+            "SELECT k, SUM(v) FROM t GROUP BY k",
+            vec![Table::new(
+                "t",
+                Arc::new(Schema::new_with_metadata(
+                    vec![field("k", DataType::Utf8), field("v", DataType::Int64)],
+                    HashMap::from([("name".to_owned(), "t".to_owned())]),
+                )),
+            )],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::OLAP,
+            Arc::new(HashMapStateBackend::new()),
+        );
+        assert_eq!(golden.code()?, "zb2AWT3xt61yIqzji80616");
+
+        // Object keys are written in sorted order.
+        let mut canonical = String::new();
+        write_canonical_json(
+            &serde_json::json!({"b": [1, {"d": null, "c": "x"}], "a": true}),
+            &mut canonical,
+        );
+        assert_eq!(canonical, r#"{"a":true,"b":[1,{"c":"x","d":null}]}"#);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1055 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The function actor runs the query stage of a cloud function on each
+//! invocation. It collects the data fragments of the windows in the arena,
+//! executes the physical plan once a window is complete, and routes the output
+//! to the next function(s) in the dataflow pipeline or to the data sink.
+//!
+//! The actor is shared by the generic cloud function on AWS Lambda and the
+//! in-process functions of the local launcher, so that both of them route the
+//! data fragments in the same way.
+
+use crate::configs::*;
+use crate::datasink::{DataSink, DataSinkFormat, DataSinkType};
+use crate::encoding::Encoding;
+use crate::error::{FlockError, Result};
+use crate::objectstore::object_store;
+use crate::runtime::arena::{
+    gathered_window_ids, Arena, ExpiryPolicy, HashAggregateStatus, WindowId,
+};
+use crate::runtime::context::{CloudFunction, ExecutionContext, SHUFFLED_PARTITIONS};
+use crate::runtime::function::FunctionId;
+use crate::runtime::payload::{Payload, Uuid, UuidBuilder};
+use crate::transmute::{schema_to_bytes, to_payload};
+use async_trait::async_trait;
+use chrono::Utc;
+use datafusion::arrow::record_batch::RecordBatch;
+use hashring::HashRing;
+use log::{info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+/// The destination of the output of the last query stage.
+#[async_trait]
+pub trait ResultSink: Debug + Send + Sync {
+    /// Writes the output of the function to the data sink.
+    ///
+    /// # Arguments
+    /// * `ctx` - The runtime context of the function.
+    /// * `sink_type` - The data sink type of the query.
+    /// * `partition` - The partition of the results if the last stage is a
+    ///   function group, such as a sorted range of `ORDER BY`.
+    /// * `batches` - The output of the function.
+    ///
+    /// # Returns
+    /// A JSON object that describes the write.
+    async fn write(
+        &self,
+        ctx: &ExecutionContext,
+        sink_type: &DataSinkType,
+        partition: Option<usize>,
+        batches: Vec<RecordBatch>,
+    ) -> Result<Value>;
+}
+
+/// Writes the output to the data sink of the query in the cloud.
+#[derive(Debug, Default)]
+pub struct CloudSink;
+
+#[async_trait]
+impl ResultSink for CloudSink {
+    async fn write(
+        &self,
+        ctx: &ExecutionContext,
+        sink_type: &DataSinkType,
+        partition: Option<usize>,
+        batches: Vec<RecordBatch>,
+    ) -> Result<Value> {
+        info!("[Ok] Sinking data to {:?}", sink_type);
+        if batches.is_empty() || DataSinkType::Blackhole == *sink_type {
+            return Ok(Value::Null);
+        }
+        let mut sink = DataSink::new(ctx.name.clone(), batches, Encoding::default());
+        sink.partition = partition;
+        sink.write(sink_type.clone(), DataSinkFormat::SerdeBinary)
+            .await
+    }
+}
+
+/// Routes the output of the function to the next function(s) in the dataflow
+/// pipeline, or to the data sink after the last query stage.
+pub struct Router {
+    /// The consistent hashing ring of the next function(s).
+    pub ring: HashRing<String>,
+    /// The data sink of the last query stage.
+    pub sink: Arc<dyn ResultSink>,
+}
+
+impl Router {
+    /// Creates a new router to the next function(s) of the current function.
+    ///
+    /// # Arguments
+    /// * `next` - The next function(s) of the current function.
+    /// * `sink` - The data sink of the last query stage.
+    pub fn new(next: &CloudFunction, sink: Arc<dyn ResultSink>) -> Result<Self> {
+        Ok(Self::with_ring(hash_ring(next)?, sink))
+    }
+
+    /// Creates a new router with the given consistent hashing ring.
+    pub fn with_ring(ring: HashRing<String>, sink: Arc<dyn ResultSink>) -> Self {
+        Self { ring, sink }
+    }
+
+    /// Returns the name of the function in the group that receives the data
+    /// partition at the given index for shuffling.
+    ///
+    /// Partitions at the same index position in different functions get the
+    /// same function name, so they can be aggregated in the same function.
+    pub fn shuffle_function(&self, index: usize) -> String {
+        // Function 0: data[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]
+        // Function 1: data[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]
+        // ..
+        // Function n: data[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]
+        //
+        // F0[0], F1[0], F2[0] .. Fn[0] ---> lambda function x
+        // F0[1], F1[1], F2[1] .. Fn[1] ---> lambda function y
+        // ..
+        // F0[n], F1[n], F2[n] .. Fn[n] ---> lambda function v
+        let mut rng = StdRng::seed_from_u64(0xDEAD); // Predictable RNG clutch
+        let mut arr = [0u8; 64];
+        rng.fill(&mut arr);
+        let func_idx = self.ring.get_index(&arr).expect("hash ring failure.");
+        self.ring
+            .get_by_index((func_idx + index) % self.ring.len())
+            .expect("hash ring failure.")
+            .to_string()
+    }
+
+    /// Returns the name of the function in the group that receives the data
+    /// fragment of the window, which is identified by the query id and the
+    /// shuffle id.
+    pub fn group_function(&self, uuid: &Uuid, shuffle_id: Option<usize>) -> String {
+        match shuffle_id {
+            Some(id) => self.ring.get(&(&uuid.qid, id)),
+            None => self.ring.get(&uuid.qid),
+        }
+        .expect("hash ring failure.")
+        .to_string()
+    }
+}
+
+/// Builds the consistent hashing ring for the next function(s).
+///
+/// The *consistent hash* technique distributes the data packets in a time
+/// window to the same function name in the function group. Because each
+/// function in the function group has a concurrency of *1*, all data packets
+/// from the same query can be routed to the same function execution
+/// environment.
+///
+/// # Arguments
+/// * `next` - The next function(s) of the current function.
+pub fn hash_ring(next: &CloudFunction) -> Result<HashRing<String>> {
+    let mut ring: HashRing<String> = HashRing::new();
+    match next {
+        CloudFunction::Lambda(name) => ring.add(name.clone()),
+        CloudFunction::Group((name, group_size)) => FunctionId::from_cloud_name(name)?
+            .members(*group_size)
+            .for_each(|member| ring.add(member.to_cloud_name())),
+        CloudFunction::Sink(..) => {}
+    }
+    Ok(ring)
+}
+
+/// The generic function executor.
+///
+/// This function is invoked by the datafusion runtime. It is responsible for
+/// executing the physical plan. It is also responsible for collecting the
+/// results of the execution. After the execution is finished, the results are
+/// written to the output. The results are written to the output in the form of
+/// Arrow RecordBatch.
+///
+/// ## Arguments
+/// * `ctx` - The runtime context of the function.
+/// * `streams` - The input streams of the function.
+///
+/// ## Returns
+/// The output stream of the function.
+pub async fn collect(
+    ctx: &mut ExecutionContext,
+    streams: Vec<Vec<Vec<RecordBatch>>>,
+) -> Result<Vec<Vec<RecordBatch>>> {
+    info!("Executing the physical plan.");
+    ctx.feed_data_sources(streams).await?;
+    let output = if ctx.is_shuffling().await? {
+        let output = ctx.execute_partitioned().await?;
+        assert!(output.len() == 1);
+        output.into_iter().next().unwrap()
+    } else {
+        ctx.execute().await?
+    };
+    ctx.clean_data_sources().await?;
+    info!("[OK] The execution is finished.");
+
+    info!(
+        "[INFO] The number of rows in the output is {}.",
+        output
+            .par_iter()
+            .map(|s| s.par_iter().map(|b| b.num_rows()).sum::<usize>())
+            .sum::<usize>()
+    );
+
+    Ok(output)
+}
+
+/// Read the payload from the object store via the bucket and the key.
+async fn read_payload_from_s3(bucket: String, key: String) -> Result<Payload> {
+    let body = object_store().get(&bucket, &key).await?;
+    let payload: Payload = serde_json::from_slice(&body)?;
+    Ok(payload)
+}
+
+/// The endpoint for worker function invocations. The worker function
+/// invocations are invoked by the data source generator or the former stage of
+/// the dataflow pipeline.
+///
+/// A window of the aggregate function is committed only after its output is
+/// delivered to the next function(s), so the window is fired at least once. If
+/// the function is recycled after the delivery but before the commit, the
+/// window fires again, and the next function(s) receive its output twice.
+///
+/// # Arguments
+/// * `ctx` - The runtime context of the function.
+/// * `arena` - The global memory arena for the function across invocations.
+/// * `router` - The router to the next function(s) of the function.
+/// * `event` - The payload of the function invocation.
+///
+/// # Returns
+/// A JSON object that contains the return value of the function invocation.
+pub async fn handler(
+    ctx: &mut ExecutionContext,
+    arena: &mut Arena,
+    router: &Router,
+    event: Payload,
+) -> Result<Value> {
+    info!("Receiving a data packet: {:?}", event.uuid);
+    ctx.check_destination(&event)?;
+
+    let query_number = event.query_number;
+    let metadata = event.metadata.clone();
+    let uuid = event.uuid.clone();
+    let shuffle_id = event.shuffle_id;
+    let windows = gathered_window_ids(&event.get_window_id(), event.input);
+
+    if ctx.is_aggregate() {
+        expire_windows(ctx, arena, router).await?;
+    }
+
+    let (input, status) = prepare_data_sources(ctx, arena, event).await?;
+
+    if status == HashAggregateStatus::Processed {
+        info!("[Ok] Function {}: data is already processed.", ctx.name);
+        return Ok(Value::Null);
+    } else if status == HashAggregateStatus::NotReady {
+        info!("[Ok] Function {}: data aggregation is not ready.", ctx.name);
+        return Ok(Value::Null);
+    }
+
+    ctx.feed_range_boundaries(&metadata).await?;
+    let output = collect(ctx, input).await?;
+    let value = invoke_next_functions(
+        ctx,
+        router,
+        query_number,
+        uuid,
+        metadata,
+        shuffle_id,
+        output,
+    )
+    .await?;
+
+    if ctx.is_aggregate() {
+        commit_windows(ctx, arena, &windows).await?;
+    }
+    Ok(value)
+}
+
+/// Records the progress of the window in the state backend. Only the window
+/// changed by the invocation is written instead of the whole arena.
+async fn checkpoint_window(
+    ctx: &ExecutionContext,
+    arena: &Arena,
+    window_id: &WindowId,
+) -> Result<()> {
+    if !ctx.state_backend.is_persistent() {
+        return Ok(());
+    }
+    match arena.checkpoint_window(window_id) {
+        Some(checkpoint) => {
+            ctx.state_backend
+                .save_checkpoint(&ctx.name, &checkpoint)
+                .await
+        }
+        None => {
+            ctx.state_backend
+                .remove_checkpoint(&ctx.name, window_id)
+                .await
+        }
+    }
+}
+
+/// Commits the fired windows once their output is delivered to the next
+/// function(s). Each window is marked as processed and checkpointed, and its
+/// persisted data fragments are removed.
+///
+/// If the delivery fails, the windows are not committed. The redelivered data
+/// fragment then collects the other fragments from the state backend again,
+/// and the windows fire once more.
+async fn commit_windows(
+    ctx: &ExecutionContext,
+    arena: &mut Arena,
+    windows: &[WindowId],
+) -> Result<()> {
+    for window_id in windows {
+        arena.mark_processed(window_id.clone());
+        checkpoint_window(ctx, arena, window_id).await?;
+        if let Err(e) = ctx.state_backend.cleanup(ctx.stage()?, window_id).await {
+            warn!("Failed to clean up the window {:?}: {}", window_id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Applies the expiry policy of the arena to the windows that miss their
+/// deadlines or exceed the memory budget, and prunes the processed windows
+/// older than the retention period.
+///
+/// # Arguments
+/// * `ctx` - The runtime context of the function.
+/// * `arena` - The global memory arena for the function across invocations.
+/// * `router` - The router to the next function(s) of the function.
+async fn expire_windows(
+    ctx: &mut ExecutionContext,
+    arena: &mut Arena,
+    router: &Router,
+) -> Result<()> {
+    for window_id in arena.prune_processed() {
+        ctx.state_backend
+            .remove_checkpoint(&ctx.name, &window_id)
+            .await?;
+    }
+
+    let expired = arena.expired_windows();
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    let expiry = arena
+        .policy()
+        .expiry
+        .with_state_backend(ctx.state_backend.as_ref());
+    for window_id in expired {
+        match expiry {
+            ExpiryPolicy::Fire => fire_window(ctx, arena, router, &window_id, true).await?,
+            ExpiryPolicy::Recover => {
+                let windows = arena.gathered_windows(&window_id);
+                load_persisted_fragments(ctx, arena, &windows).await?;
+                if arena.is_gathered_complete(&window_id) {
+                    fire_window(ctx, arena, router, &window_id, false).await?;
+                } else {
+                    arena.dead_letter(&ctx.name, &window_id).await?;
+                    checkpoint_window(ctx, arena, &window_id).await?;
+                }
+            }
+            ExpiryPolicy::DeadLetter => {
+                arena.dead_letter(&ctx.name, &window_id).await?;
+                checkpoint_window(ctx, arena, &window_id).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fires the window in the arena, together with the windows gathered with it,
+/// and invokes the next functions. The windows are committed once the next
+/// functions are invoked.
+///
+/// # Arguments
+/// * `ctx` - The runtime context of the function.
+/// * `arena` - The global memory arena for the function across invocations.
+/// * `router` - The router to the next function(s) of the function.
+/// * `window_id` - The window to fire.
+/// * `partial` - Whether the window misses some data fragments. If so, the
+///   output payloads are flagged with `partial_window` in the metadata.
+async fn fire_window(
+    ctx: &mut ExecutionContext,
+    arena: &mut Arena,
+    router: &Router,
+    window_id: &WindowId,
+    partial: bool,
+) -> Result<()> {
+    let (uuid, mut metadata) = match arena.get(window_id) {
+        Some(window) => (window.uuid.clone(), window.metadata.clone()),
+        None => return Ok(()),
+    };
+    if partial {
+        warn!("Firing the window {:?} with partial data.", window_id);
+        metadata
+            .get_or_insert_with(HashMap::new)
+            .insert("partial_window".to_owned(), "true".to_owned());
+    }
+
+    let windows = arena.gathered_windows(window_id);
+    let shuffle_id = arena.get(window_id).and_then(|w| w.shuffle_id(window_id));
+    let mut input = take_window(arena, window_id).await?;
+    input.extend(ctx.load_side_inputs().await?);
+
+    ctx.feed_range_boundaries(&metadata).await?;
+    let output = collect(ctx, input).await?;
+    invoke_next_functions(ctx, router, None, uuid, metadata, shuffle_id, output).await?;
+    commit_windows(ctx, arena, &windows).await
+}
+
+/// Takes the window from the arena as the input of the aggregate function.
+/// All data fragments of the window belong to the same partition, since the
+/// plan of the aggregate function is `Final` or `FinalPartitioned`.
+///
+/// If the window is gathered with the windows of the other input stages, such
+/// as the inputs of a join, each input stage is a relation of the input.
+async fn take_window(
+    arena: &mut Arena,
+    window_id: &WindowId,
+) -> Result<Vec<Vec<Vec<RecordBatch>>>> {
+    let windows = arena.gathered_windows(window_id);
+    let gathered = windows.len() > 1;
+    let mut input = vec![];
+    for window_id in windows {
+        let relations = arena
+            .take(&window_id)
+            .await?
+            .into_iter()
+            .map(|relation| vec![relation.into_iter().flatten().collect::<Vec<_>>()]);
+        if gathered {
+            // Each input stage sends a single relation.
+            input.extend(relations.take(1));
+        } else {
+            input.extend(relations);
+        }
+    }
+    Ok(input)
+}
+
+/// Collects the data fragments of the windows that are persisted in the state
+/// backend but haven't reached the function yet.
+///
+/// The aggregation states are not saved by the aggregator itself, but by the
+/// former stage of the dataflow pipeline. Since the aggregator's ancestors are
+/// default Lambda functions with much higher concurrency, all of them can write
+/// the partial aggregation states to the state backend in parallel, and the
+/// aggregator brings the states to the current function directly to reduce the
+/// query's latency.
+async fn load_persisted_fragments(
+    ctx: &ExecutionContext,
+    arena: &mut Arena,
+    windows: &[WindowId],
+) -> Result<()> {
+    let stage = ctx.stage()?;
+    for window_id in windows {
+        if let Some(bitmap) = arena.get_bitmap(window_id) {
+            let keys = ctx
+                .state_backend
+                .list_fragments(stage, window_id, bitmap)
+                .await?;
+            if keys.is_empty() {
+                continue;
+            }
+            // TODO: optimize the performance of this part.
+            // Because the key include a negative sequence number, we don't need
+            // to read its object from the state backend.
+            ctx.state_backend
+                .load_fragments(&window_id.0, keys)
+                .await?
+                .into_iter()
+                .for_each(|payload| {
+                    arena.collect(payload);
+                });
+        }
+    }
+    Ok(())
+}
+
+/// Initializes the arena of the aggregate function. It is called once the
+/// execution context is initialized.
+///
+/// If the state backend is persistent, the arena spills the cold data fragments
+/// to the state backend, and the in-flight windows and the processed windows
+/// are rehydrated from the latest checkpoint, so a window still fires even if
+/// the function is recycled in the middle of the window. The window is fired
+/// at least once rather than exactly once, see [`handler`].
+///
+/// # Arguments
+/// * `ctx` - The runtime context of the function.
+/// * `arena` - The fresh memory arena of the function.
+pub async fn init_arena(ctx: &ExecutionContext, arena: &mut Arena) -> Result<()> {
+    if !ctx.is_aggregate() || !ctx.state_backend.is_persistent() {
+        return Ok(());
+    }
+    let stage = ctx.stage()?;
+    arena.enable_spilling(ctx.state_backend.clone(), stage);
+    if let Some(checkpoint) = ctx.state_backend.load_checkpoint(&ctx.name).await? {
+        info!(
+            "Recovering {} windows from the checkpoint.",
+            checkpoint.windows.len()
+        );
+        arena
+            .recover(&ctx.state_backend, stage, &checkpoint)
+            .await?;
+        arena.spill().await?;
+    }
+    Ok(())
+}
+
+/// Prepare the data sources to the executor in the current function.
+///
+/// # Arguments
+/// * `ctx` - The runtime context of the current function.
+/// * `arena` - The global memory arena for the function across invocations.
+/// * `event` - The payload of the current function invocation.
+///
+/// # Returns
+/// The input data for the executor in the current function.
+async fn prepare_data_sources(
+    ctx: &mut ExecutionContext,
+    arena: &mut Arena,
+    event: Payload,
+) -> Result<(Vec<Vec<Vec<RecordBatch>>>, HashAggregateStatus)> {
+    let metadata = event.metadata.clone();
+    let stage = ctx.stage()?;
+    let window_id = event.get_window_id();
+
+    if arena.is_processed(&window_id) {
+        return Ok((vec![], HashAggregateStatus::Processed));
+    }
+
+    // If all data packets have been received, then the data sources are ready.
+    #[allow(unused_assignments)]
+    let mut status = HashAggregateStatus::NotReady;
+    let mut input = vec![];
+
+    // Read payload from S3 is a baseline for our system.
+    if let Some((bucket, key)) = infer_s3_mode(&metadata) {
+        info!("Reading payload from S3...");
+        let payload = read_payload_from_s3(bucket, key).await?;
+        info!("[OK] Received payload from S3.");
+
+        info!("Parsing payload to input partitions...");
+        let (r1, r2) = payload.to_record_batch();
+        info!("[OK] Parsed payload.");
+
+        input.push(vec![r1]);
+        input.push(vec![r2]);
+        status = HashAggregateStatus::Ready;
+    } else if ctx.is_aggregate() {
+        if ctx.state_backend.is_persistent() && !event.persisted {
+            // The data fragment is persisted before it is collected, so that the
+            // window can be rehydrated if the function is recycled. The functions
+            // of the former stage persist the fragments they send themselves.
+            let bytes = serde_json::to_vec(&event)?;
+            ctx.state_backend
+                .persist_fragment(stage, &event, bytes)
+                .await?;
+        }
+
+        // aggregate incoming data to its specific destination
+        status = arena.collect(event);
+        if status == HashAggregateStatus::Processed && arena.is_complete(&window_id) {
+            // The window was rehydrated completely after a cold start, but the
+            // function was recycled before the window fired.
+            status = HashAggregateStatus::Ready;
+        }
+        if status == HashAggregateStatus::Ready && !arena.is_gathered_complete(&window_id) {
+            // The windows of the other input stages are still collecting.
+            status = HashAggregateStatus::NotReady;
+        }
+        if status == HashAggregateStatus::NotReady {
+            // Aggregation has not yet been completed. We can also check the query
+            // states in the state backend.
+            let windows = arena.gathered_windows(&window_id);
+            load_persisted_fragments(ctx, arena, &windows).await?;
+            if arena.is_gathered_complete(&window_id) {
+                status = HashAggregateStatus::Ready;
+            }
+        }
+        if status == HashAggregateStatus::Ready {
+            info!("Received all data packets for the window: {:?}", window_id);
+            input.extend(take_window(arena, &window_id).await?);
+        }
+
+        if status == HashAggregateStatus::NotReady {
+            let num = arena.spill().await?;
+            if num > 0 {
+                info!("Spilled {} data fragments to the state backend.", num);
+            }
+            for window_id in arena.gathered_windows(&window_id) {
+                if arena.contains_key(&window_id) {
+                    checkpoint_window(ctx, arena, &window_id).await?;
+                }
+            }
+        }
+    } else {
+        // data packet is an individual event for the current function.
+        let (r1, r2) = event.to_record_batch();
+        input.push(vec![r1]);
+        input.push(vec![r2]);
+        status = HashAggregateStatus::Ready;
+    }
+
+    if status == HashAggregateStatus::Ready {
+        // If the data sources are ready, then we can read the side inputs.
+        input.extend(ctx.load_side_inputs().await?);
+    }
+
+    Ok((input, status))
+}
+
+/// Invoke the next functions in the dataflow pipeline.
+///
+/// # Arguments
+/// * `ctx` - The runtime context of the current function.
+/// * `router` - The router to the next function(s) of the current function.
+/// * `query_num` - The query number of the current request (for testing).
+/// * `uuid` - The UUID of the current payload.
+/// * `metadata` - The metadata of the current request.
+/// * `shuffle_id` - The shuffle id of the current payload.
+/// * `output` - The output of the current function.
+///
+/// # Returns
+/// A JSON object that contains the return value of the current function.
+pub async fn invoke_next_functions(
+    ctx: &mut ExecutionContext,
+    router: &Router,
+    query_number: Option<usize>,
+    uuid: Uuid,
+    metadata: Option<HashMap<String, String>>,
+    shuffle_id: Option<usize>,
+    output: Vec<Vec<RecordBatch>>,
+) -> Result<Value> {
+    let sync = infer_invocation_type(&metadata)?;
+    let schema = schema_to_bytes(ctx.schema(0).await?);
+    let input = ctx.next_input;
+    let next_payload =
+        |batches: &[RecordBatch], uuid: Uuid, metadata: Option<HashMap<String, String>>| {
+            let mut payload = to_payload(batches, &[], uuid, sync);
+            payload.schema = schema.clone();
+            payload.query_number = query_number;
+            payload.metadata = metadata;
+            payload.input = input;
+            payload
+        };
+
+    // The function names and the payloads of the next invocations, and the
+    // stage of the next function group that recovers the persisted payloads.
+    let mut invocations = vec![];
+    let mut next_stage = None;
+
+    match &ctx.next {
+        CloudFunction::Sink(sink_type) => {
+            return router
+                .sink
+                .write(
+                    ctx,
+                    sink_type,
+                    // Each function of the group writes its own partition of the
+                    // results, such as a sorted range of `ORDER BY`.
+                    shuffle_id.map(|id| id - 1),
+                    output.into_iter().flatten().collect::<Vec<_>>(),
+                )
+                .await;
+        }
+        CloudFunction::Lambda(function_name) => {
+            if ctx.is_aggregate() {
+                // If the current function is an aggregator, which means its output
+                // can be repartitioned to multiple partitions, and each partition
+                // can be executed by a single lambda function for the next stage of the
+                // dataflow pipeline.
+                let mut uuid_builder =
+                    UuidBuilder::new_with_ts(function_name, Utc::now().timestamp(), output.len());
+                for partition in output.iter() {
+                    invocations.push((
+                        function_name.clone(),
+                        next_payload(partition, uuid_builder.next_uuid(), metadata.clone()),
+                    ));
+                }
+            } else {
+                // If the current function is not an aggregator, which means its
+                // output CANNOT be repartitioned to multiple partitions,
+                // otherwise the future aggregator CANNOT ganuantee the
+                // correctness of the result. Therefore, we have to reuse the
+                // uuid of the current payload to the next function.
+                let output = output.into_iter().flatten().collect::<Vec<_>>();
+                invocations.push((function_name.clone(), next_payload(&output, uuid, metadata)));
+            }
+        }
+        CloudFunction::Group((group_name, _)) => {
+            // The fragments are persisted for the next stage, so that the function
+            // group can recover them.
+            next_stage = Some(FunctionId::from_cloud_name(group_name)?.stage);
+            if !ctx.is_shuffling().await? {
+                // The partial aggregates of a window are spread over the combiners
+                // of the next level, if any.
+                let mut metadata = metadata;
+                let mut uuid = uuid;
+                if ctx.is_aggregate() && shuffle_id.is_none() && !ctx.is_combining() {
+                    // The whole window is fired at once, so its output is the only
+                    // data fragment of the window in the next function group.
+                    uuid.seq_num = 1;
+                    uuid.seq_len = 1;
+                }
+                let (uuid, shuffle_id) = ctx.next_fragment(uuid, shuffle_id, &mut metadata)?;
+                let output = output.into_iter().flatten().collect::<Vec<_>>();
+                let mut payload = next_payload(&output, uuid.clone(), metadata);
+                payload.shuffle_id = shuffle_id;
+                invocations.push((router.group_function(&uuid, shuffle_id), payload));
+            } else {
+                let mut uuid = uuid;
+                let mut metadata = metadata;
+                if ctx.is_range_partitioning().await? {
+                    // Each function of the current stage splits its own output into
+                    // the ranges, so the window of each range in the next function
+                    // group collects one data fragment from each of them.
+                    if let Some(seq_num) = shuffle_id {
+                        uuid.seq_num = seq_num;
+                        uuid.seq_len = metadata
+                            .as_ref()
+                            .and_then(|m| m.get(SHUFFLED_PARTITIONS))
+                            .ok_or_else(|| {
+                                FlockError::Execution(
+                                    "The shuffled window has no shuffled partitions.".to_owned(),
+                                )
+                            })?
+                            .parse::<usize>()
+                            .map_err(|e| FlockError::Execution(e.to_string()))?;
+                    } else if ctx.is_aggregate() {
+                        // The whole window is fired at once.
+                        uuid.seq_num = 1;
+                        uuid.seq_len = 1;
+                    }
+                } else if let Some(seq_num) = shuffle_id {
+                    // This is REALLY important and tricky.
+                    // The shuffle id must be assigned to the new payload's sequence
+                    // number. Otherwise, the next function will not be able to
+                    // distinguish the payloads for aggregation. The window in the
+                    // next function collects one fragment from each function in the
+                    // current group.
+                    uuid.seq_num = seq_num;
+                    uuid.seq_len = output.len();
+                }
+                metadata
+                    .get_or_insert_with(HashMap::new)
+                    .insert(SHUFFLED_PARTITIONS.to_owned(), output.len().to_string());
+                for (i, partition) in output.iter().enumerate() {
+                    let mut payload = next_payload(partition, uuid.clone(), metadata.clone());
+                    // set shuffle id to each data partition since they will be aggregated
+                    // at different functions.
+                    payload.shuffle_id = Some(i + 1); // Starts from 1.
+                    invocations.push((router.shuffle_function(i), payload));
+                }
+            }
+        }
+    }
+
+    let invocation_type = if sync {
+        FLOCK_LAMBDA_SYNC_CALL.to_string()
+    } else {
+        FLOCK_LAMBDA_ASYNC_CALL.to_string()
+    };
+    let tasks = invocations
+        .into_iter()
+        .map(|(function_name, mut payload)| {
+            let invoker = ctx.invoker.clone();
+            let state_backend = ctx.state_backend.clone();
+            let invocation_type = invocation_type.clone();
+            tokio::spawn(async move {
+                payload.function = FunctionId::from_cloud_name(&function_name).ok();
+                let persisted_stage = next_stage.filter(|_| state_backend.is_persistent());
+                // The aggregator doesn't persist the payload again.
+                payload.persisted = persisted_stage.is_some();
+                let bytes = serde_json::to_vec(&payload)?;
+                info!(
+                    "[OK] {} function's payload bytes: {}",
+                    function_name,
+                    bytes.len()
+                );
+
+                if let Some(stage) = persisted_stage {
+                    // The payload is persisted before the invocation, so that the
+                    // aggregator can recover it if the invocation hasn't reached it yet.
+                    state_backend
+                        .persist_fragment(stage, &payload, bytes.clone())
+                        .await?;
+                }
+
+                invoker
+                    .invoke(&function_name, &invocation_type, bytes.into())
+                    .await
+                    .map(|_| ())
+            })
+        })
+        .collect::<Vec<JoinHandle<Result<()>>>>();
+
+    futures::future::join_all(tasks)
+        .await
+        .into_iter()
+        .map(|r| r.map_err(|e| FlockError::Internal(e.to_string()))?)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Value::Null)
+}
+
+/// Infer the invocation mode of the function.
+pub fn infer_invocation_type(metadata: &Option<HashMap<String, String>>) -> Result<bool> {
+    let mut sync = true;
+    if let Some(metadata) = metadata {
+        if let Some(invocation_type) = metadata.get("invocation_type") {
+            if invocation_type.parse::<String>().unwrap() == "async" {
+                sync = false;
+            }
+        }
+    }
+    Ok(sync)
+}
+
+/// Infer the S3 communucation mode of the function.
+pub fn infer_s3_mode(metadata: &Option<HashMap<String, String>>) -> Option<(String, String)> {
+    if let Some(metadata) = metadata {
+        if let (Some(bucket), Some(key)) = (metadata.get("s3_bucket"), metadata.get("s3_key")) {
+            let bucket = bucket.parse::<String>().unwrap();
+            let key = key.parse::<String>().unwrap();
+            if !bucket.is_empty() && !key.is_empty() {
+                return Some((bucket, key));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_batches_eq;
+    use crate::invoker::{ChannelInvoker, Invocation, RetryPolicy};
+    use crate::runtime::arena::{ArenaCheckpoint, Bitmap};
+    use crate::runtime::plan::{physical_plan, CloudExecutionPlan};
+    use crate::state::EfsStateBackend;
+    use bytes::Bytes;
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::datasource::MemTable;
+    use datafusion::physical_plan::ExecutionPlan;
+    use tokio::sync::mpsc;
+
+    /// Serves `n` invocations of the next function and returns their payloads.
+    fn serve(
+        mut invocations: mpsc::UnboundedReceiver<Invocation>,
+        n: usize,
+    ) -> JoinHandle<Vec<Payload>> {
+        tokio::spawn(async move {
+            let mut payloads = vec![];
+            while payloads.len() < n {
+                let (bytes, tx) = invocations.recv().await.unwrap();
+                payloads.push(serde_json::from_slice::<Payload>(&bytes).unwrap());
+                if let Some(tx) = tx {
+                    tx.send(Ok(Bytes::new())).unwrap();
+                }
+            }
+            payloads
+        })
+    }
+
+    fn metadata() -> Option<HashMap<String, String>> {
+        let mut metadata = HashMap::new();
+        metadata.insert("key".to_owned(), "value".to_owned());
+        Some(metadata)
+    }
+
+    /// Builds the execution context of the function `id`, which forwards
+    /// its output to the next stage through the channel invoker.
+    async fn stage_context(
+        namespace: &str,
+        id: FunctionId,
+    ) -> Result<(
+        ExecutionContext,
+        RecordBatch,
+        mpsc::UnboundedReceiver<Invocation>,
+    )> {
+        let schema = Arc::new(Schema::new(vec![Field::new("c1", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from(vec![1, 2, 3, 4]))],
+        )?;
+
+        let mut df = datafusion::execution::context::ExecutionContext::new();
+        let table = MemTable::try_new(schema.clone(), vec![vec![RecordBatch::new_empty(schema)]])?;
+        df.register_table("t", Arc::new(table))?;
+        let plan = physical_plan(&df, "SELECT c1 FROM t WHERE c1 > 1").await?;
+        let plan: Arc<dyn ExecutionPlan> = serde_json::from_str(&serde_json::to_string(&plan)?)?;
+
+        let next = FunctionId::try_new(&id.query_code, id.stage + 1)?.to_cloud_name();
+        let mut invoker = ChannelInvoker::new(namespace);
+        invoker.retry = RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        };
+        let invocations = invoker.register(&next);
+        let ctx = ExecutionContext {
+            plan: CloudExecutionPlan::new(vec![plan], None),
+            name: id.to_cloud_name(),
+            id: Some(id),
+            next: CloudFunction::Lambda(next),
+            invoker: Arc::new(invoker),
+            ..Default::default()
+        };
+        Ok((ctx, batch, invocations))
+    }
+
+    #[tokio::test]
+    async fn actor_forwards_payload() -> Result<()> {
+        let id = FunctionId::try_new("actor0", 0)?;
+        let (mut ctx, batch, invocations) = stage_context("actor_forwards_payload", id).await?;
+        let router = Router::new(&ctx.next, Arc::new(CloudSink))?;
+        let next = serve(invocations, 1);
+
+        let uuid = UuidBuilder::new_with_ts(&ctx.name, Utc::now().timestamp(), 1).next_uuid();
+        let mut event = to_payload(&[batch], &[], uuid.clone(), true);
+        event.function = ctx.id.clone();
+        event.query_number = Some(7);
+        event.metadata = metadata();
+        handler(&mut ctx, &mut Arena::new(), &router, event.clone()).await?;
+
+        let payloads = next.await.unwrap();
+        let payload = payloads[0].clone();
+        assert_eq!(payload.function, Some(FunctionId::try_new("actor0", 1)?));
+        assert_eq!(payload.uuid, uuid);
+        assert_eq!(payload.query_number, Some(7));
+        assert_eq!(payload.metadata, event.metadata);
+
+        let expected = vec![
+            "+----+", "| c1 |", "+----+", "| 2  |", "| 3  |", "| 4  |", "+----+",
+        ];
+        assert_batches_eq!(&expected, &payload.to_record_batch().0);
+
+        // The payload for another function is rejected.
+        event.function = Some(FunctionId::try_new("actor0", 2)?);
+        assert!(handler(&mut ctx, &mut Arena::new(), &router, event)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn actor_fires_complete_window() -> Result<()> {
+        let id = FunctionId::try_new("actor1", 0)?.member(0);
+        let (mut ctx, batch, mut invocations) =
+            stage_context("actor_fires_complete_window", id).await?;
+        let router = Router::new(&ctx.next, Arc::new(CloudSink))?;
+        let mut arena = Arena::new();
+
+        let mut uuid_builder = UuidBuilder::new_with_ts(&ctx.name, Utc::now().timestamp(), 2);
+        let events = (0..2)
+            .map(|_| {
+                let mut event = to_payload(&[batch.clone()], &[], uuid_builder.next_uuid(), true);
+                event.query_number = Some(7);
+                event.metadata = metadata();
+                event
+            })
+            .collect::<Vec<_>>();
+
+        // The window is not fired until all its data fragments are collected.
+        handler(&mut ctx, &mut arena, &router, events[0].clone()).await?;
+        assert!(invocations.try_recv().is_err());
+        let next = serve(invocations, 1);
+        handler(&mut ctx, &mut arena, &router, events[1].clone()).await?;
+
+        let payloads = next.await.unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_ne!(payloads[0].uuid.qid, events[0].uuid.qid);
+        assert_eq!(payloads[0].query_number, Some(7));
+        assert_eq!(payloads[0].metadata, events[0].metadata);
+        let (batches, _) = payloads[0].clone().to_record_batch();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 6);
+
+        // A duplicate data fragment of the fired window is dropped.
+        assert!(arena.is_processed(&events[1].get_window_id()));
+        handler(&mut ctx, &mut arena, &router, events[1].clone()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn actor_refires_undelivered_window() -> Result<()> {
+        let id = FunctionId::try_new("actor2", 0)?.member(0);
+        let (mut ctx, batch, _) = stage_context("actor_refires_undelivered_window", id).await?;
+        let mount_path = std::env::temp_dir().join(format!("flock-efs-{}", uuid::Uuid::new_v4()));
+        ctx.state_backend = Arc::new(EfsStateBackend::with_mount_path(&mount_path));
+        let router = Router::new(&ctx.next, Arc::new(CloudSink))?;
+        let mut arena = Arena::new();
+        init_arena(&ctx, &mut arena).await?;
+
+        let mut uuid_builder = UuidBuilder::new_with_ts(&ctx.name, Utc::now().timestamp(), 2);
+        let events = (0..2)
+            .map(|_| to_payload(&[batch.clone()], &[], uuid_builder.next_uuid(), true))
+            .collect::<Vec<_>>();
+        let window_id = events[0].get_window_id();
+
+        // The next function is unavailable, so the window is not committed.
+        let namespace = ChannelInvoker::new("actor_refires_undelivered_window");
+        namespace.unregister_all();
+        handler(&mut ctx, &mut arena, &router, events[0].clone()).await?;
+        assert!(handler(&mut ctx, &mut arena, &router, events[1].clone())
+            .await
+            .is_err());
+        assert!(!arena.is_processed(&window_id));
+
+        // The redelivered data fragment fires the window again from the data
+        // fragments in the state backend.
+        let next_function = FunctionId::try_new("actor2", 1)?.to_cloud_name();
+        let next = serve(namespace.register(&next_function), 1);
+        handler(&mut ctx, &mut arena, &router, events[1].clone()).await?;
+        let (batches, _) = next.await.unwrap()[0].clone().to_record_batch();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 6);
+
+        // The window is committed after the delivery.
+        assert!(arena.is_processed(&window_id));
+        assert_eq!(
+            ctx.state_backend.load_checkpoint(&ctx.name).await?,
+            Some(ArenaCheckpoint {
+                windows:   vec![],
+                processed: vec![window_id.clone()],
+            })
+        );
+        assert!(ctx
+            .state_backend
+            .list_fragments(0, &window_id, &Bitmap::new(3))
+            .await?
+            .is_empty());
+
+        std::fs::remove_dir_all(mount_path)?;
+        Ok(())
+    }
+}
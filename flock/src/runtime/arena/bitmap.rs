@@ -59,6 +59,13 @@ impl Bitmap {
             bit_util::set_bit_raw(self.bits.as_mut_ptr(), i);
         }
     }
+
+    /// Returns the indices of the set bits in ascending order.
+    pub fn set_indices(&self) -> Vec<usize> {
+        (0..(self.bits.len() << 3))
+            .filter(|i| self.is_set(*i))
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -76,6 +83,7 @@ mod tests {
 
         bitmap.set(100);
         assert!(bitmap.is_set(100));
+        assert_eq!(bitmap.set_indices(), vec![0, 100]);
 
         Ok(())
     }
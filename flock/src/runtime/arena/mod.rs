@@ -18,17 +18,27 @@
 mod bitmap;
 pub use bitmap::Bitmap;
 
+mod policy;
+pub use policy::{ArenaPolicy, ExpiryPolicy};
+
+use crate::configs::*;
 use crate::encoding::Encoding;
 use crate::error::{FlockError, Result};
-use crate::runtime::payload::{DataFrame, Payload};
+use crate::objectstore::object_store;
+use crate::runtime::payload::{DataFrame, Payload, Uuid};
+use crate::state::{fragment_key, StateBackend};
 use crate::transmute::*;
 use datafusion::arrow::datatypes::SchemaRef;
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::arrow_flight::utils::flight_data_to_arrow_batch;
 use datafusion::arrow_flight::FlightData;
 use hashbrown::HashMap;
+use log::warn;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::task::JoinHandle;
 
 type QueryId = String;
@@ -57,7 +67,24 @@ pub enum HashAggregateStatus {
 ///   query time.
 /// * The value is the data frames of the previous stage of dataflow for a given
 ///   query at a given time wrapped by `WindowSession`.
-pub struct Arena(HashMap<WindowId, WindowSession>);
+///
+/// The arena also remembers the processed windows, so that the late data
+/// fragments of a fired window are dropped. Each window has a deadline, and all
+/// windows share a memory budget. See [`ArenaPolicy`] for details.
+///
+/// If spilling is enabled, the cold data fragments are spilled to the state
+/// backend once the in-memory windows exceed the spill threshold, and they are
+/// read back when the window is taken.
+pub struct Arena {
+    /// The in-flight windows.
+    windows:   HashMap<WindowId, WindowSession>,
+    /// The processed windows and the time they were processed.
+    processed: HashMap<WindowId, Instant>,
+    /// The deadlines and the memory budget of the arena.
+    policy:    ArenaPolicy,
+    /// The state backend and the stage index to spill the data fragments to.
+    spill:     Option<(Arc<dyn StateBackend>, usize)>,
+}
 
 /// `WindowSession` is an abstraction of a temporal window that is used to store
 /// the data frames of the previous stage of dataflow to ensure the integrity of
@@ -82,6 +109,22 @@ pub struct WindowSession {
     pub bitmap:         Bitmap,
     /// The compression method.
     pub encoding:       Encoding,
+    /// The uuid of the first data fragment in the window.
+    pub uuid:           Uuid,
+    /// The metadata of the first data fragment in the window.
+    pub metadata:       Option<std::collections::HashMap<String, String>>,
+    /// The sequence numbers of the data fragments in the arrival order.
+    pub seq_nums:       Vec<usize>,
+    /// The keys of the data fragments spilled to the state backend. The
+    /// spilled data fragments are empty in memory.
+    pub spilled:        Vec<String>,
+    /// The encoded byte size of the data fragments in memory.
+    pub bytes:          usize,
+    /// The window expires if not all data fragments arrive before the deadline.
+    pub deadline:       Instant,
+    /// The index of the input stage that the window collects, and the number
+    /// of the input stages gathered by the function, see [`Payload::input`].
+    pub input:          Option<(usize, usize)>,
 }
 
 impl WindowSession {
@@ -102,12 +145,261 @@ impl WindowSession {
             ))
         }
     }
+
+    /// Return the shuffle id of the data fragments in the window.
+    pub fn shuffle_id(&self, window_id: &WindowId) -> Option<usize> {
+        let shuffle_id = match self.input {
+            Some((index, inputs)) => (window_id.1 - index) / inputs,
+            None => window_id.1,
+        };
+        if shuffle_id == 0 {
+            None
+        } else {
+            Some(shuffle_id)
+        }
+    }
+
+    /// Return the checkpoint of the window.
+    pub fn checkpoint(&self, window_id: &WindowId) -> WindowCheckpoint {
+        WindowCheckpoint {
+            window_id: window_id.clone(),
+            size:      self.size,
+            seq_nums:  self.bitmap.set_indices(),
+            processed: false,
+        }
+    }
+}
+
+/// Return the encoded byte size of the data frames.
+fn encoded_size(data: &[DataFrame]) -> usize {
+    data.iter().map(|d| d.header.len() + d.body.len()).sum()
+}
+
+/// The progress of a temporal window in the arena.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowCheckpoint {
+    /// The window identifier.
+    pub window_id: WindowId,
+    /// The number of data fragments in the window.
+    pub size:      usize,
+    /// The sequence numbers of the collected data fragments.
+    pub seq_nums:  Vec<usize>,
+    /// Whether the window has been processed by the function.
+    #[serde(default)]
+    pub processed: bool,
+}
+
+/// `ArenaCheckpoint` is the durable progress of the arena in an aggregate
+/// function. The data fragments themselves are persisted in the state backend
+/// separately, so the checkpoint only records which windows are in flight and
+/// which windows have been fired.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArenaCheckpoint {
+    /// The windows that are still collecting data fragments.
+    pub windows:   Vec<WindowCheckpoint>,
+    /// The windows that have been processed by the function.
+    pub processed: Vec<WindowId>,
+}
+
+impl FromIterator<WindowCheckpoint> for ArenaCheckpoint {
+    /// Assembles the checkpoint of the arena from the checkpoints of its
+    /// windows.
+    fn from_iter<I: IntoIterator<Item = WindowCheckpoint>>(iter: I) -> Self {
+        let (processed, mut windows): (Vec<_>, Vec<_>) =
+            iter.into_iter().partition(|window| window.processed);
+        windows.sort_by(|a, b| a.window_id.cmp(&b.window_id));
+        let mut processed = processed
+            .into_iter()
+            .map(|window| window.window_id)
+            .collect::<Vec<_>>();
+        processed.sort();
+
+        ArenaCheckpoint { windows, processed }
+    }
 }
 
 impl Arena {
-    /// Create a new `Arena`.
+    /// Create a new `Arena` with the policy in the Flock configuration.
     pub fn new() -> Arena {
-        Arena(HashMap::<WindowId, WindowSession>::new())
+        Arena::with_policy(ArenaPolicy::default())
+    }
+
+    /// Create a new `Arena` with the given policy.
+    pub fn with_policy(policy: ArenaPolicy) -> Arena {
+        Arena {
+            windows:   HashMap::new(),
+            processed: HashMap::new(),
+            policy,
+            spill: None,
+        }
+    }
+
+    /// Enable spilling the cold data fragments to the state backend. The state
+    /// backend must be persistent.
+    ///
+    /// # Arguments
+    /// * `state_backend` - The state backend to spill the data fragments to.
+    /// * `stage` - The index of the query stage that owns the windows.
+    pub fn enable_spilling(&mut self, state_backend: Arc<dyn StateBackend>, stage: usize) {
+        assert!(state_backend.is_persistent());
+        self.spill = Some((state_backend, stage));
+    }
+
+    /// Spill the cold data fragments to the state backend until the in-memory
+    /// byte size of the arena is below the spill threshold. The windows with
+    /// the earliest deadlines are the coldest ones.
+    ///
+    /// # Returns
+    /// The number of spilled data fragments.
+    pub async fn spill(&mut self) -> Result<usize> {
+        let (state_backend, stage) = match &self.spill {
+            Some((state_backend, stage)) => (state_backend.clone(), *stage),
+            None => return Ok(0),
+        };
+        let threshold = self.policy.spill_threshold;
+        let mut bytes = self.bytes();
+        if bytes <= threshold {
+            return Ok(0);
+        }
+
+        let mut windows = self
+            .iter()
+            .map(|(window_id, window)| (window.deadline, window_id.clone()))
+            .collect::<Vec<_>>();
+        windows.sort();
+
+        let mut num = 0;
+        for (_, window_id) in windows {
+            let window = self.windows.get_mut(&window_id).unwrap();
+            for i in 0..window.r1_flight_data.len() {
+                if bytes <= threshold {
+                    return Ok(num);
+                }
+                let size = encoded_size(&window.r1_flight_data[i])
+                    + encoded_size(&window.r2_flight_data[i]);
+                if size == 0 {
+                    continue;
+                }
+
+                let payload = Payload {
+                    uuid: Uuid {
+                        qid:     window_id.0.clone(),
+                        seq_num: window.seq_nums[i],
+                        seq_len: window.size,
+                    },
+                    data:     std::mem::take(&mut window.r1_flight_data[i]),
+                    data2:    std::mem::take(&mut window.r2_flight_data[i]),
+                    schema:   window.r1_schema.clone(),
+                    schema2:  window.r2_schema.clone(),
+                    encoding: window.encoding.clone(),
+                    shuffle_id: window.shuffle_id(&window_id),
+                    input: window.input,
+                    ..Default::default()
+                };
+                state_backend
+                    .persist_fragment(stage, &payload, serde_json::to_vec(&payload)?)
+                    .await?;
+                window.spilled.push(fragment_key(stage, &payload));
+                window.bytes -= size;
+                bytes -= size;
+                num += 1;
+            }
+        }
+        Ok(num)
+    }
+
+    /// Return the policy of the arena.
+    pub fn policy(&self) -> &ArenaPolicy {
+        &self.policy
+    }
+
+    /// Return true if the window has been processed.
+    pub fn is_processed(&self, window_id: &WindowId) -> bool {
+        self.processed.contains_key(window_id)
+    }
+
+    /// Mark the window as processed. The late data fragments of the window
+    /// are dropped until the processed window id is pruned.
+    pub fn mark_processed(&mut self, window_id: WindowId) {
+        self.processed.insert(window_id, Instant::now());
+    }
+
+    /// Prune the processed window ids older than the retention period.
+    ///
+    /// # Returns
+    /// The pruned window ids.
+    pub fn prune_processed(&mut self) -> Vec<WindowId> {
+        let retention = self.policy.processed_retention;
+        let pruned = self
+            .processed
+            .iter()
+            .filter(|(_, processed_at)| processed_at.elapsed() >= retention)
+            .map(|(window_id, _)| window_id.clone())
+            .collect::<Vec<_>>();
+        pruned.iter().for_each(|window_id| {
+            self.processed.remove(window_id);
+        });
+        pruned
+    }
+
+    /// Return the encoded byte size of all in-flight windows.
+    pub fn bytes(&self) -> usize {
+        self.values().map(|window| window.bytes).sum()
+    }
+
+    /// Return the windows that expire. A window expires if its deadline has
+    /// passed, or if the arena exceeds the memory budget and the window is one
+    /// of the oldest windows.
+    pub fn expired_windows(&self) -> Vec<WindowId> {
+        let now = Instant::now();
+        let (mut expired, mut alive): (Vec<_>, Vec<_>) =
+            self.iter().partition(|(_, window)| window.deadline <= now);
+
+        let mut bytes = alive.iter().map(|(_, window)| window.bytes).sum::<usize>();
+        alive.sort_by_key(|(_, window)| window.deadline);
+        for (window_id, window) in alive {
+            if bytes <= self.policy.memory_budget {
+                break;
+            }
+            bytes -= window.bytes;
+            expired.push((window_id, window));
+        }
+
+        expired
+            .into_iter()
+            .map(|(window_id, _)| window_id.clone())
+            .collect()
+    }
+
+    /// Remove the window from the arena and report it to the dead-letter
+    /// location. The report is the checkpoint of the window at the key
+    /// `<dead letter key>/<function name>/<query id>/<shuffle id>` in the Flock
+    /// bucket.
+    ///
+    /// # Arguments
+    /// * `function_name` - The name of the aggregate function.
+    /// * `window_id` - The expired window.
+    pub async fn dead_letter(&mut self, function_name: &str, window_id: &WindowId) -> Result<()> {
+        if let Some(window) = self.remove(window_id) {
+            warn!(
+                "The window {:?} expires with {}/{} data fragments.",
+                window_id,
+                window.r1_flight_data.len(),
+                window.size
+            );
+            object_store()
+                .put(
+                    &FLOCK_S3_BUCKET,
+                    &format!(
+                        "{}/{}/{}/{:02}",
+                        self.policy.dead_letter_key, function_name, window_id.0, window_id.1
+                    ),
+                    serde_json::to_vec(&window.checkpoint(window_id))?,
+                )
+                .await?;
+        }
+        self.mark_processed(window_id.clone());
+        Ok(())
     }
 
     /// Take a window from the arena.
@@ -130,7 +422,27 @@ impl Arena {
                 .collect()
         };
 
-        if let Some(window) = (*self).remove(window_id) {
+        if let Some(mut window) = self.windows.remove(window_id) {
+            if !window.spilled.is_empty() {
+                // Read the spilled data fragments back from the state backend.
+                let state_backend = match &self.spill {
+                    Some((state_backend, _)) => state_backend.clone(),
+                    None => {
+                        return Err(FlockError::Internal(
+                            "Spilling is not enabled in the arena.".to_string(),
+                        ))
+                    }
+                };
+                state_backend
+                    .load_fragments(&window_id.0, std::mem::take(&mut window.spilled))
+                    .await?
+                    .into_iter()
+                    .for_each(|payload| {
+                        window.r1_flight_data.push(payload.data);
+                        window.r2_flight_data.push(payload.data2);
+                    });
+            }
+
             let (schema1, schema2) = window.schema()?;
 
             let mut tasks: Vec<JoinHandle<Vec<Vec<RecordBatch>>>> = vec![];
@@ -168,6 +480,87 @@ impl Arena {
         }
     }
 
+    /// Return the checkpoint of the arena.
+    pub fn checkpoint(&self) -> ArenaCheckpoint {
+        let mut windows = self
+            .iter()
+            .map(|(window_id, window)| window.checkpoint(window_id))
+            .collect::<Vec<_>>();
+        windows.sort_by(|a, b| a.window_id.cmp(&b.window_id));
+
+        let mut processed = self.processed.keys().cloned().collect::<Vec<_>>();
+        processed.sort();
+
+        ArenaCheckpoint {
+            windows,
+            processed,
+        }
+    }
+
+    /// Return the checkpoint of a single window, so that the function only
+    /// records the window changed by an invocation instead of the whole arena.
+    ///
+    /// # Returns
+    /// The checkpoint of the window, or `None` if the window is neither in
+    /// flight nor processed.
+    pub fn checkpoint_window(&self, window_id: &WindowId) -> Option<WindowCheckpoint> {
+        if self.is_processed(window_id) {
+            Some(WindowCheckpoint {
+                window_id: window_id.clone(),
+                size:      0,
+                seq_nums:  vec![],
+                processed: true,
+            })
+        } else {
+            self.get(window_id)
+                .map(|window| window.checkpoint(window_id))
+        }
+    }
+
+    /// Rehydrate the in-flight windows and the processed windows of the
+    /// checkpoint from the data fragments persisted in the state backend. It is
+    /// called on a fresh arena after the function is cold started.
+    ///
+    /// # Arguments
+    /// * `state_backend` - The state backend that holds the data fragments.
+    /// * `stage` - The index of the query stage that owns the windows.
+    /// * `checkpoint` - The latest checkpoint of the arena.
+    pub async fn recover(
+        &mut self,
+        state_backend: &Arc<dyn StateBackend>,
+        stage: usize,
+        checkpoint: &ArenaCheckpoint,
+    ) -> Result<()> {
+        for window in &checkpoint.windows {
+            let keys = state_backend
+                .list_fragments(stage, &window.window_id, &Bitmap::new(window.size + 1))
+                .await?;
+            state_backend
+                .load_fragments(&window.window_id.0, keys)
+                .await?
+                .into_iter()
+                .for_each(|payload| {
+                    self.collect(payload);
+                });
+
+            let recovered = self
+                .get_bitmap(&window.window_id)
+                .map(|bitmap| bitmap.set_indices())
+                .unwrap_or_default();
+            if window.seq_nums.iter().any(|i| !recovered.contains(i)) {
+                warn!(
+                    "Some data fragments of the window {:?} are not persisted.",
+                    window.window_id
+                );
+            }
+        }
+        checkpoint
+            .processed
+            .iter()
+            .for_each(|window_id| self.mark_processed(window_id.clone()));
+        Ok(())
+    }
+
     /// Return the Bitmap reference of the temporal window.
     pub fn get_bitmap(&self, window_id: &WindowId) -> Option<&Bitmap> {
         self.get(window_id).map(|window| &window.bitmap)
@@ -180,6 +573,21 @@ impl Arena {
             .unwrap_or(false)
     }
 
+    /// Return the windows that are gathered together with the given window,
+    /// including itself, in the order of their input stages. A window that
+    /// collects the output of a single input stage is gathered alone.
+    pub fn gathered_windows(&self, window_id: &WindowId) -> Vec<WindowId> {
+        gathered_window_ids(window_id, self.get(window_id).and_then(|w| w.input))
+    }
+
+    /// Return true if all the windows gathered together with the given window
+    /// are complete.
+    pub fn is_gathered_complete(&self, window_id: &WindowId) -> bool {
+        self.gathered_windows(window_id)
+            .iter()
+            .all(|window_id| self.is_complete(window_id))
+    }
+
     /// Collect the data fragments for temporal windows.
     ///
     /// # Arguments
@@ -196,6 +604,8 @@ impl Arena {
             Some(window) => {
                 assert!(uuid.seq_len == window.size);
                 if !window.bitmap.is_set(uuid.seq_num) {
+                    window.bytes += encoded_size(&payload.data) + encoded_size(&payload.data2);
+                    window.seq_nums.push(uuid.seq_num);
                     window.r1_flight_data.push(payload.data);
                     window.r2_flight_data.push(payload.data2);
                     assert!(window.r1_flight_data.len() == window.r2_flight_data.len());
@@ -212,12 +622,19 @@ impl Arena {
             None => {
                 let mut window = WindowSession {
                     size:           uuid.seq_len,
+                    bytes:          encoded_size(&payload.data) + encoded_size(&payload.data2),
                     r1_flight_data: vec![payload.data],
                     r2_flight_data: vec![payload.data2],
                     r1_schema:      payload.schema,
                     r2_schema:      payload.schema2,
                     bitmap:         Bitmap::new(uuid.seq_len + 1), // Starts from 1.
                     encoding:       payload.encoding,
+                    seq_nums:       vec![uuid.seq_num],
+                    spilled:        vec![],
+                    uuid:           uuid.clone(),
+                    metadata:       payload.metadata,
+                    deadline:       Instant::now() + self.policy.window_timeout,
+                    input:          payload.input,
                 };
                 // SEQ_NUM is used to indicate the data existence in the window via bitmap.
                 window.bitmap.set(uuid.seq_num);
@@ -232,17 +649,44 @@ impl Arena {
     }
 }
 
+/// Return the windows of all the input stages that the window of an input
+/// stage is gathered with, see [`Payload::get_window_id`]. The windows of the
+/// input stages share the same query id, see
+/// [`UuidBuilder::new_with_window`](crate::runtime::payload::UuidBuilder::new_with_window).
+///
+/// # Arguments
+/// * `window_id` - The window of the input stage.
+/// * `input` - The index of the input stage and the number of the input stages,
+///   if any.
+pub fn gathered_window_ids(window_id: &WindowId, input: Option<(usize, usize)>) -> Vec<WindowId> {
+    match input {
+        Some((index, inputs)) => {
+            let first = window_id.1 - index;
+            (first..first + inputs)
+                .map(|shuffle_id| (window_id.0.clone(), shuffle_id))
+                .collect()
+        }
+        None => vec![window_id.clone()],
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Arena {
+        Arena::new()
+    }
+}
+
 impl Deref for Arena {
     type Target = HashMap<WindowId, WindowSession>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.windows
     }
 }
 
 impl DerefMut for Arena {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.windows
     }
 }
 
@@ -251,9 +695,12 @@ mod tests {
     use super::*;
     use crate::error::Result;
     use crate::runtime::payload::UuidBuilder;
+    use crate::state::{EfsStateBackend, HashMapStateBackend};
+    use crate::test_util::init_in_memory_object_store;
     use crate::transmute::to_payload;
     use datafusion::arrow::csv;
     use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use std::time::Duration;
 
     fn init_batches() -> Vec<RecordBatch> {
         let schema = Schema::new(vec![
@@ -317,4 +764,253 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_arena_gathered_windows() -> Result<()> {
+        let batches = init_batches();
+        let uuids = UuidBuilder::new_with_ts("SX72HzqFz1Qij4bP-1_0", 1024, 2);
+        let payload = |i: usize, uuid: Uuid, index: usize| {
+            let mut payload = to_payload(&[batches[i].clone()], &[], uuid, false);
+            payload.shuffle_id = Some(1);
+            payload.input = Some((index, 2));
+            payload
+        };
+
+        // The second input stage sends a single data fragment to the window.
+        let mut arena = Arena::new();
+        let mut single = uuids.get(1);
+        single.seq_len = 1;
+        let status = arena.collect(payload(0, single, 1));
+        assert!(status == HashAggregateStatus::Ready);
+
+        let (first, second) = ((uuids.qid.clone(), 2), (uuids.qid.clone(), 3));
+        assert_eq!(
+            arena.gathered_windows(&second),
+            vec![first.clone(), second.clone()]
+        );
+        assert!(arena.is_complete(&second));
+        assert!(!arena.is_gathered_complete(&second));
+
+        // The first input stage sends two data fragments.
+        assert!(arena.collect(payload(1, uuids.get(1), 0)) == HashAggregateStatus::NotReady);
+        assert!(!arena.is_gathered_complete(&first));
+        assert!(arena.collect(payload(2, uuids.get(2), 0)) == HashAggregateStatus::Ready);
+        assert!(arena.is_gathered_complete(&first));
+        assert!(arena.is_gathered_complete(&second));
+
+        // Both windows keep the shuffle id of their data fragments.
+        assert_eq!(arena.get(&first).unwrap().shuffle_id(&first), Some(1));
+        assert_eq!(arena.get(&second).unwrap().shuffle_id(&second), Some(1));
+        assert_eq!(2, arena.take(&first).await?[0].len());
+        assert_eq!(1, arena.take(&second).await?[0].len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_arena_recovery() -> Result<()> {
+        let mount_path = std::env::temp_dir().join(format!("flock-efs-{}", uuid::Uuid::new_v4()));
+        let state_backend: Arc<dyn StateBackend> =
+            Arc::new(EfsStateBackend::with_mount_path(&mount_path));
+
+        let batches = init_batches();
+        let uuids = UuidBuilder::new_with_ts("SX72HzqFz1Qij4bP-1_0", 1024, batches.len());
+        let payloads = batches
+            .into_iter()
+            .enumerate()
+            .map(|(i, batch)| to_payload(&[batch], &[], uuids.get(i + 1), false))
+            .collect::<Vec<_>>();
+        let window_id = payloads[0].get_window_id();
+
+        // The function collects three data fragments before it is recycled.
+        let mut arena = Arena::new();
+        for payload in payloads.iter().take(3) {
+            state_backend
+                .persist_fragment(1, payload, serde_json::to_vec(payload)?)
+                .await?;
+            assert!(arena.collect(payload.clone()) == HashAggregateStatus::NotReady);
+        }
+        let fired = ("SX72HzqFz1Qij4bP-1_0-fired".to_owned(), 0);
+        arena.mark_processed(fired.clone());
+        let checkpoint = arena.checkpoint();
+        assert_eq!(checkpoint.windows.len(), 1);
+        assert_eq!(checkpoint.windows[0].seq_nums, vec![1, 2, 3]);
+        assert_eq!(checkpoint.processed, vec![fired.clone()]);
+        // Each window is checkpointed separately.
+        for id in [&window_id, &fired] {
+            state_backend
+                .save_checkpoint(
+                    "SX72HzqFz1Qij4bP-1_0",
+                    &arena.checkpoint_window(id).unwrap(),
+                )
+                .await?;
+        }
+        drop(arena);
+
+        // The cold-started function rehydrates the window from the checkpoint.
+        assert_eq!(
+            state_backend
+                .load_checkpoint("SX72HzqFz1Qij4bP-1_0")
+                .await?
+                .unwrap(),
+            checkpoint
+        );
+        let mut arena = Arena::new();
+        arena.recover(&state_backend, 1, &checkpoint).await?;
+        assert!(arena.is_processed(&fired));
+        assert_eq!(arena.get_bitmap(&window_id).unwrap().set_indices(), vec![1, 2, 3]);
+
+        // The redelivered data fragment is not collected twice.
+        assert!(arena.collect(payloads[2].clone()) == HashAggregateStatus::Processed);
+        for (i, payload) in payloads.iter().enumerate().skip(3) {
+            let status = arena.collect(payload.clone());
+            if i < 7 {
+                assert!(status == HashAggregateStatus::NotReady);
+            } else {
+                assert!(status == HashAggregateStatus::Ready);
+            }
+        }
+        assert_eq!(8, arena.take(&window_id).await?[0].len());
+
+        std::fs::remove_dir_all(mount_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_arena_spill() -> Result<()> {
+        let mount_path = std::env::temp_dir().join(format!("flock-efs-{}", uuid::Uuid::new_v4()));
+        let state_backend: Arc<dyn StateBackend> =
+            Arc::new(EfsStateBackend::with_mount_path(&mount_path));
+
+        let batches = init_batches();
+        let num_rows = batches.iter().map(|b| b.num_rows()).sum::<usize>();
+        let uuids = UuidBuilder::new_with_ts("SX72HzqFz1Qij4bP-1_0", 1024, batches.len());
+        let window_id = (uuids.qid.clone(), 0);
+
+        let mut arena = Arena::with_policy(ArenaPolicy {
+            spill_threshold: 0,
+            ..ArenaPolicy::default()
+        });
+        // Spilling is disabled by default.
+        arena.collect(to_payload(&[batches[0].clone()], &[], uuids.get(1), false));
+        assert_eq!(arena.spill().await?, 0);
+
+        arena.enable_spilling(state_backend, 1);
+        for (i, batch) in batches.iter().enumerate().skip(1).take(6) {
+            arena.collect(to_payload(&[batch.clone()], &[], uuids.get(i + 1), false));
+        }
+        assert_eq!(arena.spill().await?, 7);
+        assert_eq!(arena.bytes(), 0);
+        assert!(!arena.is_complete(&window_id));
+
+        // The last data fragment stays in memory.
+        let status = arena.collect(to_payload(&[batches[7].clone()], &[], uuids.get(8), false));
+        assert!(status == HashAggregateStatus::Ready);
+        assert!(arena.bytes() > 0);
+
+        // The spilled data fragments are read back transparently.
+        let output = arena.take(&window_id).await?;
+        assert_eq!(8, output[0].len());
+        assert_eq!(
+            num_rows,
+            output[0]
+                .iter()
+                .flatten()
+                .map(|b| b.num_rows())
+                .sum::<usize>()
+        );
+
+        std::fs::remove_dir_all(mount_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_arena_expiry() -> Result<()> {
+        init_in_memory_object_store();
+        assert_eq!("dead_letter".parse::<ExpiryPolicy>()?, ExpiryPolicy::DeadLetter);
+        assert!("unknown".parse::<ExpiryPolicy>().is_err());
+
+        // Nothing can be recovered from the in-memory state backend.
+        let hashmap = HashMapStateBackend::new();
+        let mount_path = std::env::temp_dir().join(format!("flock-efs-{}", uuid::Uuid::new_v4()));
+        let efs = EfsStateBackend::with_mount_path(&mount_path);
+        assert_eq!(
+            ExpiryPolicy::Recover.with_state_backend(&hashmap),
+            ExpiryPolicy::Fire
+        );
+        assert_eq!(
+            ExpiryPolicy::Recover.with_state_backend(&efs),
+            ExpiryPolicy::Recover
+        );
+        assert_eq!(
+            ExpiryPolicy::DeadLetter.with_state_backend(&hashmap),
+            ExpiryPolicy::DeadLetter
+        );
+
+        let policy = ArenaPolicy {
+            window_timeout:      Duration::from_secs(3600),
+            memory_budget:       usize::MAX,
+            spill_threshold:     usize::MAX,
+            expiry:              ExpiryPolicy::DeadLetter,
+            dead_letter_key:     "dead_letters".to_owned(),
+            processed_retention: Duration::from_secs(3600),
+        };
+
+        // Two windows receive one of eight data fragments each.
+        let batches = init_batches();
+        let first = UuidBuilder::new_with_ts("SX72HzqFz1Qij4bP-1_0", 1024, batches.len());
+        let second = UuidBuilder::new_with_ts("SX72HzqFz1Qij4bP-1_0", 2048, batches.len());
+        let collect = |arena: &mut Arena| {
+            arena.collect(to_payload(&[batches[0].clone()], &[], first.get(1), false));
+            arena.collect(to_payload(&[batches[1].clone()], &[], second.get(1), false));
+        };
+        let (first, second) = ((first.get(1).qid, 0), (second.get(1).qid, 0));
+
+        let mut arena = Arena::with_policy(policy.clone());
+        collect(&mut arena);
+        assert!(arena.bytes() > 0);
+        assert!(arena.expired_windows().is_empty());
+
+        // The deadlines have passed.
+        let mut arena = Arena::with_policy(ArenaPolicy {
+            window_timeout: Duration::from_secs(0),
+            ..policy.clone()
+        });
+        collect(&mut arena);
+        assert_eq!(arena.expired_windows().len(), 2);
+
+        // The oldest window is evicted to meet the memory budget.
+        let mut arena = Arena::with_policy(ArenaPolicy {
+            memory_budget: 1,
+            ..policy.clone()
+        });
+        collect(&mut arena);
+        arena.get_mut(&second).unwrap().deadline += Duration::from_secs(1);
+        let budget = arena.get(&second).unwrap().bytes;
+        arena.policy.memory_budget = budget;
+        assert_eq!(arena.expired_windows(), vec![first.clone()]);
+
+        // The expired window is reported to the dead-letter location.
+        arena.dead_letter("SX72HzqFz1Qij4bP-1_0", &first).await?;
+        assert!(arena.get(&first).is_none());
+        assert!(arena.is_processed(&first));
+        let report: WindowCheckpoint = serde_json::from_slice(
+            &object_store()
+                .get(
+                    &FLOCK_S3_BUCKET,
+                    &format!("dead_letters/SX72HzqFz1Qij4bP-1_0/{}/00", first.0),
+                )
+                .await?,
+        )?;
+        assert_eq!(report.size, 8);
+        assert_eq!(report.seq_nums, vec![1]);
+
+        // The processed window ids are pruned after the retention period.
+        assert!(arena.prune_processed().is_empty());
+        arena.policy.processed_retention = Duration::from_secs(0);
+        assert_eq!(arena.prune_processed(), vec![first.clone()]);
+        assert!(!arena.is_processed(&first));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,102 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The policy bounds the time and memory that the arena spends on the windows
+//! whose data fragments never arrive, for example, because an asynchronous
+//! invocation is lost, and the memory that the in-flight windows occupy before
+//! their data fragments are spilled to the state backend.
+
+use crate::configs::*;
+use crate::error::{FlockError, Result};
+use crate::state::StateBackend;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// What the aggregate function does with a window that expires before all of
+/// its data fragments arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryPolicy {
+    /// Fires the window with the partial data. The output payloads are flagged
+    /// with `partial_window` in the metadata.
+    Fire,
+    /// Recovers the missing data fragments from the state backend. If the
+    /// window is still incomplete, it is reported to the dead-letter location.
+    Recover,
+    /// Reports the window to the dead-letter location and drops its data.
+    DeadLetter,
+}
+
+impl FromStr for ExpiryPolicy {
+    type Err = FlockError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "fire" => Ok(ExpiryPolicy::Fire),
+            "recover" => Ok(ExpiryPolicy::Recover),
+            "dead_letter" => Ok(ExpiryPolicy::DeadLetter),
+            _ => Err(FlockError::Internal(format!(
+                "Unknown window expiry policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl ExpiryPolicy {
+    /// Returns the policy that applies to the aggregate functions with the
+    /// state backend. `Recover` falls back to `Fire` if the state backend is
+    /// not persistent, since no data fragment can be recovered from it.
+    ///
+    /// # Arguments
+    /// * `state_backend` - The state backend of the aggregate function.
+    pub fn with_state_backend(self, state_backend: &dyn StateBackend) -> Self {
+        match self {
+            ExpiryPolicy::Recover if !state_backend.is_persistent() => ExpiryPolicy::Fire,
+            policy => policy,
+        }
+    }
+}
+
+/// The deadlines and the memory budget of the arena.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaPolicy {
+    /// The maximum time to wait for all data fragments of a window.
+    pub window_timeout:      Duration,
+    /// The memory budget in bytes of all in-flight windows. If the budget is
+    /// exceeded, the oldest windows expire first.
+    pub memory_budget:       usize,
+    /// The in-memory byte size of the windows above which the cold data
+    /// fragments are spilled to the state backend.
+    pub spill_threshold:     usize,
+    /// The policy for the expired windows.
+    pub expiry:              ExpiryPolicy,
+    /// The key prefix in the Flock bucket to report the dead-letter windows.
+    pub dead_letter_key:     String,
+    /// The retention period of the processed window ids.
+    pub processed_retention: Duration,
+}
+
+impl Default for ArenaPolicy {
+    fn default() -> Self {
+        Self {
+            window_timeout:      Duration::from_secs(*FLOCK_ARENA_WINDOW_TIMEOUT),
+            memory_budget:       *FLOCK_ARENA_MEMORY_BUDGET,
+            spill_threshold:     *FLOCK_ARENA_SPILL_THRESHOLD,
+            expiry:              FLOCK_ARENA_EXPIRY_POLICY
+                .parse()
+                .expect("Invalid arena expiry policy."),
+            dead_letter_key:     FLOCK_ARENA_DEAD_LETTER_KEY.clone(),
+            processed_retention: Duration::from_secs(*FLOCK_ARENA_PROCESSED_RETENTION),
+        }
+    }
+}
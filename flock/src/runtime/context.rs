@@ -15,8 +15,14 @@
 //! corresponding execution context from the cloud environment variable.
 
 use crate::datasink::DataSinkType;
+use crate::datasource::side_input::SideInput;
+use crate::distributed_plan::range::{decode_boundaries, encode_boundaries, RANGE_BOUNDARIES};
+use crate::distributed_plan::{CombineAggregateExec, RangePartitionExec, RangeSampler};
 use crate::encoding::Encoding;
 use crate::error::{FlockError, Result};
+use crate::invoker::{default_invoker, FunctionInvoker};
+use crate::runtime::function::FunctionId;
+use crate::runtime::payload::{Payload, Uuid};
 use crate::runtime::plan::CloudExecutionPlan;
 use crate::state::*;
 use datafusion::arrow::datatypes::{Schema, SchemaRef};
@@ -27,6 +33,7 @@ use datafusion::physical_plan::repartition::RepartitionExec;
 use datafusion::physical_plan::ExecutionPlan;
 use datafusion::physical_plan::{collect, collect_partitioned};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::sync::Arc;
@@ -35,6 +42,14 @@ use tokio::task::JoinHandle;
 type CloudFunctionName = String;
 type GroupSize = usize;
 
+/// The metadata key of the number of data fragments in the window of the next
+/// level of the combiner tree.
+const COMBINED_FRAGMENTS: &str = "combined_fragments";
+
+/// The metadata key of the number of shuffled partitions of the window, which
+/// is the number of windows in the next function group.
+pub const SHUFFLED_PARTITIONS: &str = "shuffled_partitions";
+
 /// Cloud environment context is a wrapper to support compression and
 /// serialization.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
@@ -105,29 +120,38 @@ pub struct ExecutionContext {
     pub plan:          CloudExecutionPlan,
     /// Cloud Function name in the current execution context.
     ///
-    /// |      Cloud Function Naming Convention       |
-    /// |---------------------------------------------|
-    /// |  query code  -  plan index  -  group index  |
-    ///
-    /// - query code: the hash digest of a sql query.
-    ///
-    /// - plan index: the 2-digit number [00-99] indicates the index of the
-    ///   subplan of the current query in the dag.
-    ///
-    /// - group index: the 2-digit number [00-99] indicates the index of the
-    ///  function name in the group.
-    ///
-    /// # Example
-    ///
-    /// The following is the name of one cloud function generated by the query
-    /// at a certain moment.
-    ///
-    /// SX72HzqFz1Qij4bP-00-00
+    /// If the function runs a query stage, the name is derived from `id`, see
+    /// [`FunctionId`] for the naming convention.
     pub name:          CloudFunctionName,
+    /// The identity of the function, or `None` if the function doesn't run a
+    /// query stage, such as the data source function.
+    #[serde(default)]
+    pub id:            Option<FunctionId>,
     /// Lambda function name(s) for next invocation(s).
     pub next:          CloudFunction,
+    /// The fan-in of the next function group if it combines the partial
+    /// aggregates, see [`CombineAggregateExec`].
+    #[serde(default)]
+    pub fan_in:        Option<usize>,
+    /// The index of the current stage among the input stages of the next
+    /// function group, and the number of these input stages, if the group
+    /// gathers the outputs of several input stages, such as a join.
+    #[serde(default)]
+    pub next_input:    Option<(usize, usize)>,
     /// The current state of the execution context.
     pub state_backend: Arc<dyn StateBackend>,
+    /// The invoker to call the next function(s) in the dataflow pipeline.
+    #[serde(default = "default_invoker")]
+    pub invoker:       Arc<dyn FunctionInvoker>,
+    /// The side inputs that the function reads, in addition to its input
+    /// data.
+    #[serde(default)]
+    pub side_inputs:   Vec<SideInput>,
+    /// The sampler of the range boundaries of the distributed sort, if the
+    /// function reads from the data source of such a query, see
+    /// [`ExecutionContext::sample_range_boundaries`].
+    #[serde(default)]
+    pub range_sampler: Option<RangeSampler>,
 }
 
 impl Default for ExecutionContext {
@@ -135,8 +159,14 @@ impl Default for ExecutionContext {
         ExecutionContext {
             plan:          CloudExecutionPlan::default(),
             name:          CloudFunctionName::default(),
+            id:            None,
             next:          CloudFunction::default(),
+            fan_in:        None,
+            next_input:    None,
             state_backend: Arc::new(HashMapStateBackend::default()),
+            invoker:       default_invoker(),
+            side_inputs:   vec![],
+            range_sampler: None,
         }
     }
 }
@@ -144,7 +174,13 @@ impl Default for ExecutionContext {
 impl PartialEq for ExecutionContext {
     fn eq(&self, other: &ExecutionContext) -> bool {
         self.name == other.name
+            && self.id == other.id
             && self.next == other.next
+            && self.fan_in == other.fan_in
+            && self.next_input == other.next_input
+            && self.side_inputs == other.side_inputs
+            && serde_json::to_string(&self.range_sampler).unwrap()
+                == serde_json::to_string(&other.range_sampler).unwrap()
             && serde_json::to_string(&self.plan).unwrap()
                 == serde_json::to_string(&other.plan).unwrap()
     }
@@ -165,6 +201,32 @@ impl ExecutionContext {
         self.plan = plan;
     }
 
+    /// Places the execution plan in the object store if the encoded context
+    /// exceeds the size limit, so that it fits in the cloud environment.
+    ///
+    /// # Arguments
+    /// * `size_limit` - The maximum size of the encoded context in bytes, see
+    ///   `FLOCK_CONTEXT_SIZE_LIMIT`.
+    pub async fn place_plan(&mut self, size_limit: usize) -> Result<()> {
+        if marshal(self, Encoding::default())?.len() > size_limit {
+            self.plan.place_in_object_store().await?;
+        }
+        Ok(())
+    }
+
+    /// Loads the side inputs of the function, see [`SideInput::load`].
+    ///
+    /// # Returns
+    /// The content of each side input as a single partition, which is fed to
+    /// the execution plan along with the input data.
+    pub async fn load_side_inputs(&self) -> Result<Vec<Vec<Vec<RecordBatch>>>> {
+        let mut sources = vec![];
+        for side_input in &self.side_inputs {
+            sources.push(vec![side_input.load().await?]);
+        }
+        Ok(sources)
+    }
+
     /// Executes the physical plan.
     ///
     /// `execute` must be called after the execution of `feed_one_source` or
@@ -201,9 +263,13 @@ impl ExecutionContext {
             .into_iter()
             .map(|plan| {
                 tokio::spawn(async move {
-                    collect_partitioned(plan)
-                        .await
-                        .map_err(|e| FlockError::Execution(e.to_string()))
+                    match plan.as_any().downcast_ref::<RangePartitionExec>() {
+                        // The input is executed once for all the ranges, see
+                        // `feed_range_boundaries`.
+                        Some(range) => range.execute_ranges().await,
+                        None => collect_partitioned(plan.clone()).await,
+                    }
+                    .map_err(|e| FlockError::Execution(e.to_string()))
                 })
             })
             .collect::<Vec<JoinHandle<Result<Vec<Vec<RecordBatch>>>>>>();
@@ -324,16 +390,147 @@ impl ExecutionContext {
         Ok(())
     }
 
+    /// Samples the range boundaries of the distributed sort from the window of
+    /// the data source, and passes them down the dataflow pipeline in the
+    /// metadata. All the functions of the stage below the sort split their
+    /// outputs at the same boundaries, so the sorted ranges don't overlap.
+    ///
+    /// If the data source executes the leaf stage by itself, the boundaries
+    /// have to be fed to its plan as well, see [`Self::feed_range_boundaries`].
+    ///
+    /// # Arguments
+    /// * `sources` - The relations of the window.
+    /// * `metadata` - The metadata of the output payloads.
+    pub fn sample_range_boundaries(
+        &self,
+        sources: &[Vec<Vec<RecordBatch>>],
+        metadata: &mut Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        if let Some(sampler) = &self.range_sampler {
+            if let Some(boundaries) = sampler.sample(sources)? {
+                metadata
+                    .get_or_insert_with(HashMap::new)
+                    .insert(RANGE_BOUNDARIES.to_owned(), encode_boundaries(&boundaries)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Feeds the range boundaries of the window in the metadata to the range
+    /// partitioner of the execution plan, if any.
+    ///
+    /// # Arguments
+    /// * `metadata` - The metadata of the current payload.
+    pub async fn feed_range_boundaries(
+        &mut self,
+        metadata: &Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        let boundaries = metadata
+            .as_ref()
+            .and_then(|m| m.get(RANGE_BOUNDARIES))
+            .map(|b| decode_boundaries(b))
+            .transpose()?;
+        for mut plan in self.plan().await? {
+            if plan.as_any().is::<RangePartitionExec>() {
+                unsafe {
+                    Arc::get_mut_unchecked(&mut plan)
+                        .as_mut_any()
+                        .downcast_mut::<RangePartitionExec>()
+                        .unwrap()
+                        .set_boundaries(boundaries.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Checks whether the execution plan needs to be shuffled.
     pub async fn is_shuffling(&self) -> Result<bool> {
         assert!(!self.plan.execution_plans.is_empty());
-        Ok(self.plan.execution_plans.iter().all(|p| {
-            p.as_any().downcast_ref::<CoalesceBatchesExec>().is_some()
-                && !p.children().is_empty()
-                && p.children()
-                    .iter()
-                    .all(|c| c.as_any().downcast_ref::<RepartitionExec>().is_some())
-        }))
+        Ok(self.is_range_partitioning().await?
+            || self.plan.execution_plans.iter().all(|p| {
+                p.as_any().downcast_ref::<CoalesceBatchesExec>().is_some()
+                    && !p.children().is_empty()
+                    && p.children()
+                        .iter()
+                        .all(|c| c.as_any().downcast_ref::<RepartitionExec>().is_some())
+            }))
+    }
+
+    /// Checks whether the execution plan splits its output into the ranges of
+    /// a distributed sort. Each range is sent to its own function of the next
+    /// function group.
+    pub async fn is_range_partitioning(&self) -> Result<bool> {
+        assert!(!self.plan.execution_plans.is_empty());
+        Ok(self
+            .plan
+            .execution_plans
+            .iter()
+            .all(|p| p.as_any().is::<RangePartitionExec>()))
+    }
+
+    /// Checks whether the execution plan combines the partial aggregates of
+    /// the data fragments in a window.
+    pub fn is_combining(&self) -> bool {
+        !self.plan.execution_plans.is_empty()
+            && self
+                .plan
+                .execution_plans
+                .iter()
+                .all(|p| p.as_any().is::<CombineAggregateExec>())
+    }
+
+    /// Returns the uuid and the shuffle id of the output payload for the next
+    /// function group, if the output is not shuffled.
+    ///
+    /// The partial aggregates of a window can be merged by a tree of
+    /// combiners. The `c`-th combiner of a level merges the data fragments
+    /// `c * fan_in + 1` to `(c + 1) * fan_in` of the window, and its output is
+    /// the `c + 1`-th data fragment of the window in the next level. The window
+    /// of the `c`-th combiner is identified by the shuffle id `c + 1`, and its
+    /// function in the group is picked by both the query id and the shuffle
+    /// id.
+    ///
+    /// # Arguments
+    /// * `uuid` - The uuid of the current payload.
+    /// * `shuffle_id` - The shuffle id of the current payload.
+    /// * `metadata` - The metadata of the output payload, which keeps the
+    ///   number of data fragments in the window of the next level.
+    pub fn next_fragment(
+        &self,
+        mut uuid: Uuid,
+        shuffle_id: Option<usize>,
+        metadata: &mut Option<HashMap<String, String>>,
+    ) -> Result<(Uuid, Option<usize>)> {
+        if self.is_combining() {
+            uuid.seq_num = shuffle_id.ok_or_else(|| {
+                FlockError::Execution("The combiner's window has no shuffle id.".to_owned())
+            })?;
+            uuid.seq_len = metadata
+                .as_mut()
+                .and_then(|m| m.remove(COMBINED_FRAGMENTS))
+                .ok_or_else(|| {
+                    FlockError::Execution(
+                        "The combiner's window has no combined fragments.".to_owned(),
+                    )
+                })?
+                .parse::<usize>()
+                .map_err(|e| FlockError::Execution(e.to_string()))?;
+        }
+
+        match self.fan_in {
+            Some(fan_in) => {
+                let combiner = (uuid.seq_num - 1) / fan_in;
+                metadata.get_or_insert_with(HashMap::new).insert(
+                    COMBINED_FRAGMENTS.to_owned(),
+                    ((uuid.seq_len + fan_in - 1) / fan_in).to_string(),
+                );
+                uuid.seq_num -= combiner * fan_in;
+                uuid.seq_len = fan_in.min(uuid.seq_len - combiner * fan_in);
+                Ok((uuid, Some(combiner + 1)))
+            }
+            None => Ok((uuid, None)),
+        }
     }
 
     /// Checks whether the execution plan is the last one.
@@ -346,20 +543,46 @@ impl ExecutionContext {
 
     /// Check the current function type.
     ///
-    /// If the function name is "<query code>-<plan index>-<group index>",
-    /// then it is a group-type function.
-    /// If the function name is "<query code>-<plan index>",
-    /// then it is a lambda-type function.
+    /// If the function is a member of a function group, then it is a
+    /// group-type function. Otherwise, it is a lambda-type function.
     pub fn is_aggregate(&self) -> bool {
-        let dash_count = self.name.matches('-').count();
-        if dash_count == 2 {
-            true
-        } else if dash_count == 1 {
-            false
-        } else {
-            panic!("Invalid function name: {}", self.name);
+        self.id.as_ref().map_or(false, FunctionId::is_member)
+    }
+
+    /// Returns the identity of the function that runs a query stage.
+    pub fn function_id(&self) -> Result<&FunctionId> {
+        self.id.as_ref().ok_or_else(|| {
+            FlockError::Internal(format!("Function {} doesn't run a query stage.", self.name))
+        })
+    }
+
+    /// Returns the index of the query stage that the function runs.
+    pub fn stage(&self) -> Result<usize> {
+        Ok(self.function_id()?.stage)
+    }
+
+    /// Checks whether the payload is sent to the current function, so that a
+    /// misrouted payload is never collected into the window of another stage.
+    pub fn check_destination(&self, payload: &Payload) -> Result<()> {
+        match (&payload.function, &self.id) {
+            (Some(target), Some(id)) if target != id => Err(FlockError::Execution(format!(
+                "The payload for function {} is delivered to function {}.",
+                target, id
+            ))),
+            _ => Ok(()),
         }
     }
+
+    /// Returns the execution context of the `index`-th member of the function
+    /// group that runs the current query stage.
+    pub fn group_member(&self, index: usize) -> Result<ExecutionContext> {
+        let id = self.function_id()?.member(index);
+        Ok(ExecutionContext {
+            name: id.to_cloud_name(),
+            id: Some(id),
+            ..self.clone()
+        })
+    }
 }
 
 /// Serializes `ExecutionContext` from client-side.
@@ -420,6 +643,9 @@ mod tests {
     use super::*;
     use crate::assert_batches_eq;
     use crate::error::Result;
+    use crate::objectstore::object_store;
+    use crate::runtime::plan::physical_plan;
+    use crate::test_util::init_in_memory_object_store;
     use datafusion::arrow::array::*;
     use datafusion::arrow::datatypes::{DataType, Field, Schema};
     use datafusion::arrow::record_batch::RecordBatch;
@@ -590,4 +816,57 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn load_placed_plan() -> Result<()> {
+        init_in_memory_object_store();
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from(vec![1, 2, 3]))],
+        )?;
+
+        let mut ctx = datafusion::execution::context::ExecutionContext::new();
+        let table = MemTable::try_new(schema.clone(), vec![vec![RecordBatch::new_empty(schema)]])?;
+        ctx.register_table("placed", Arc::new(table))?;
+        let plan = physical_plan(&ctx, "SELECT SUM(a) FROM placed").await?;
+
+        let mut ctx = ExecutionContext {
+            plan: CloudExecutionPlan::new(vec![plan], None),
+            name: "placed".to_string(),
+            ..Default::default()
+        };
+        ctx.place_plan(usize::MAX).await?;
+        assert!(ctx.plan.object_storage.is_none());
+        ctx.place_plan(0).await?;
+        let (bucket, key) = ctx.plan.object_storage.clone().unwrap();
+
+        let expected = vec![
+            "+---------------+",
+            "| SUM(placed.a) |",
+            "+---------------+",
+            "| 6             |",
+            "+---------------+",
+        ];
+
+        // A cold function loads the plan from the object store.
+        let encoded = marshal(&ctx, Encoding::default())?;
+        let mut cold = unmarshal(&encoded)?;
+        cold.feed_data_sources(vec![vec![vec![batch.clone()]]])
+            .await?;
+        assert_batches_eq!(&expected, &cold.execute().await?[0]);
+
+        // The warm functions keep the loaded plan, so the object store is no
+        // longer accessed.
+        object_store().delete(&bucket, &key).await?;
+        cold.feed_data_sources(vec![vec![vec![batch.clone()]]])
+            .await?;
+        assert_batches_eq!(&expected, &cold.execute().await?[0]);
+
+        let mut warm = unmarshal(&encoded)?;
+        warm.feed_data_sources(vec![vec![vec![batch]]]).await?;
+        assert_batches_eq!(&expected, &warm.execute().await?[0]);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,205 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Each query stage runs in a cloud function, or in a function group if its
+//! input is aggregated or shuffled. A function is identified by the query
+//! code, the stage id and, for the members of a function group, the member
+//! index. [`FunctionId`] maps the identity to the cloud function name and
+//! back:
+//!
+//! |      Cloud Function Naming Convention       |
+//! |---------------------------------------------|
+//! |  query code  -  stage id  [ _  member ]     |
+//!
+//! The stage id and the member index are both in the last `-` separated token
+//! of the name, so the query code can contain `-` and `_` as well. For example,
+//! the second member of the function group of stage 3 of the query
+//! `SX72HzqFz1Qij4bP` is named `SX72HzqFz1Qij4bP-3_1`.
+
+use crate::error::{FlockError, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// The maximum length of a cloud function name.
+pub const MAX_FUNCTION_NAME_LEN: usize = 64;
+
+/// The maximum length of a query code. The stage id and the member index have
+/// at most 20 digits each, so the name of every function of the query fits in
+/// [`MAX_FUNCTION_NAME_LEN`].
+pub const MAX_QUERY_CODE_LEN: usize = MAX_FUNCTION_NAME_LEN - 2 * (1 + 20);
+
+/// The identity of the cloud function that runs a query stage.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FunctionId {
+    /// The code of the query.
+    pub query_code: String,
+    /// The index of the query stage. The data flows from the first stage, of
+    /// which the index is 0, to the last one.
+    pub stage:      usize,
+    /// The index of the function in its function group, if any.
+    pub member:     Option<usize>,
+}
+
+impl FunctionId {
+    /// Creates the identity of the function that runs the query stage.
+    ///
+    /// # Arguments
+    /// * `query_code` - The code of the query, which consists of at most
+    ///   [`MAX_QUERY_CODE_LEN`] alphanumeric characters, `-` or `_`.
+    /// * `stage` - The index of the query stage.
+    pub fn try_new(query_code: &str, stage: usize) -> Result<Self> {
+        if query_code.is_empty()
+            || query_code.len() > MAX_QUERY_CODE_LEN
+            || !query_code
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(FlockError::Plan(format!(
+                "Invalid query code {:?}: it must consist of 1 to {} alphanumeric characters, \
+                 '-' or '_'.",
+                query_code, MAX_QUERY_CODE_LEN
+            )));
+        }
+        Ok(Self {
+            query_code: query_code.to_owned(),
+            stage,
+            member: None,
+        })
+    }
+
+    /// Returns the identity of the `index`-th member of the function group.
+    pub fn member(&self, index: usize) -> Self {
+        Self {
+            member: Some(index),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the identities of all the members of the function group.
+    pub fn members(&self, group_size: usize) -> impl Iterator<Item = FunctionId> + '_ {
+        (0..group_size).map(move |i| self.member(i))
+    }
+
+    /// Checks whether the function is a member of a function group.
+    pub fn is_member(&self) -> bool {
+        self.member.is_some()
+    }
+
+    /// Returns the cloud function name.
+    pub fn to_cloud_name(&self) -> String {
+        self.to_string()
+    }
+
+    /// Returns the identity of the function from its cloud function name.
+    pub fn from_cloud_name(name: &str) -> Result<Self> {
+        name.parse()
+    }
+
+    /// Returns the query code of the cloud function. If the function doesn't
+    /// run a query stage, such as the data source function, the name itself is
+    /// returned.
+    pub fn query_code_of(name: &str) -> String {
+        match Self::from_cloud_name(name) {
+            Ok(id) => id.query_code,
+            Err(_) => name.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for FunctionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.query_code, self.stage)?;
+        if let Some(member) = self.member {
+            write!(f, "_{}", member)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FunctionId {
+    type Err = FlockError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        let invalid = || FlockError::Internal(format!("Invalid function name: {}", name));
+        let (query_code, suffix) = name.rsplit_once('-').ok_or_else(invalid)?;
+        let (stage, member) = match suffix.split_once('_') {
+            Some((stage, member)) => (stage, Some(member)),
+            None => (suffix, None),
+        };
+        let index = |s: &str| s.parse::<usize>().map_err(|_| invalid());
+
+        let id = FunctionId {
+            member: member.map(index).transpose()?,
+            ..FunctionId::try_new(query_code, index(stage)?).map_err(|_| invalid())?
+        };
+        // The mapping is one-to-one, so the indexes can't have leading zeros.
+        if id.to_string() != name {
+            return Err(invalid());
+        }
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cloud_function_names() -> Result<()> {
+        let id = FunctionId::try_new("SX72HzqFz1Qij4bP", 3)?;
+        assert_eq!(id.to_cloud_name(), "SX72HzqFz1Qij4bP-3");
+        assert_eq!(id.member(1).to_cloud_name(), "SX72HzqFz1Qij4bP-3_1");
+        assert_eq!(
+            id.members(3).map(|m| m.to_string()).collect::<Vec<_>>(),
+            vec![
+                "SX72HzqFz1Qij4bP-3_0",
+                "SX72HzqFz1Qij4bP-3_1",
+                "SX72HzqFz1Qij4bP-3_2"
+            ]
+        );
+
+        // The stage ids and the group members are not limited to 2 digits, and
+        // the query code can contain `-` and `_`.
+        for id in [
+            id.clone(),
+            id.member(0),
+            FunctionId::try_new("q-1_a", 120)?.member(345),
+            FunctionId::try_new("q-1-", 0)?,
+            FunctionId::try_new(&"x".repeat(MAX_QUERY_CODE_LEN), usize::MAX)?.member(usize::MAX),
+        ] {
+            let name = id.to_cloud_name();
+            assert!(name.len() <= MAX_FUNCTION_NAME_LEN);
+            assert_eq!(FunctionId::from_cloud_name(&name)?, id);
+            assert_eq!(FunctionId::query_code_of(&name), id.query_code);
+        }
+
+        // Invalid query codes.
+        assert!(FunctionId::try_new("", 0).is_err());
+        assert!(FunctionId::try_new("q1.fifo", 0).is_err());
+        assert!(FunctionId::try_new(&"x".repeat(MAX_QUERY_CODE_LEN + 1), 0).is_err());
+
+        // Invalid function names.
+        for name in [
+            "ysb", "q3-", "-3", "q3-01", "q3-1_", "q3-1_02", "q3-x", "q3-+1",
+        ] {
+            assert!(FunctionId::from_cloud_name(name).is_err());
+        }
+        assert_eq!(
+            FunctionId::query_code_of("flock_datasource"),
+            "flock_datasource"
+        );
+
+        Ok(())
+    }
+}
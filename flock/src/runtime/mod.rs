@@ -15,7 +15,9 @@
 //! such as execution plan and the next lambda functions, which instructs the
 //! lambda instance to perform the correct operation.
 
+pub mod actor;
 pub mod arena;
 pub mod context;
+pub mod function;
 pub mod payload;
 pub mod plan;
@@ -18,7 +18,10 @@
 
 use crate::datasource::DataSource;
 use crate::encoding::Encoding;
+use crate::error::{FlockError, Result};
+use crate::runtime::function::FunctionId;
 use crate::transmute::*;
+use chrono::Utc;
 use datafusion::arrow::datatypes::Schema;
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::arrow_flight::utils::flight_data_to_arrow_batch;
@@ -29,6 +32,11 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use uuid::Uuid as RandomId;
 
+/// The metadata key of the window id of a query execution. The entry payload
+/// of each data source function carries the same window id, so that the
+/// windows of all the leaf stages share the same query ids.
+pub const WINDOW_ID: &str = "window_id";
+
 /// A helper struct for building uuids of payloads.
 #[derive(Default, Debug, Clone)]
 pub struct UuidBuilder {
@@ -44,7 +52,7 @@ pub struct UuidBuilder {
 impl UuidBuilder {
     /// Returns a new UuidBuilder.
     pub fn new_with_ts(function_name: &str, timestamp: i64, len: usize) -> Self {
-        let query_code = function_name.split('-').next().unwrap();
+        let query_code = FunctionId::query_code_of(function_name);
         Self {
             qid: format!(
                 "{}-{}-{}",
@@ -59,7 +67,7 @@ impl UuidBuilder {
 
     /// Returns a new UuidBuilder.
     pub fn new_with_ts_uuid(function_name: &str, timestamp: i64, uuid: u128, len: usize) -> Self {
-        let query_code = function_name.split('-').next().unwrap();
+        let query_code = FunctionId::query_code_of(function_name);
         Self {
             qid: format!("{}-{}-{}", query_code, timestamp, uuid),
             pos: 1,
@@ -67,6 +75,55 @@ impl UuidBuilder {
         }
     }
 
+    /// Returns a new UuidBuilder for the `window`-th window of a query
+    /// execution.
+    ///
+    /// If the metadata carries a [`WINDOW_ID`], the data source functions of
+    /// the leaf stages build the same query id for the same window, so that a
+    /// function group that gathers several input stages, such as a join,
+    /// collects them in the same window. Otherwise, the query id is random.
+    ///
+    /// # Arguments
+    /// * `function_name` - The name of the function that sends the window.
+    /// * `metadata` - The metadata of the entry payload.
+    /// * `window` - The index of the window in the query execution.
+    /// * `len` - The number of data fragments in the window.
+    pub fn new_with_window(
+        function_name: &str,
+        metadata: &Option<HashMap<String, String>>,
+        window: usize,
+        len: usize,
+    ) -> Result<Self> {
+        match metadata.as_ref().and_then(|m| m.get(WINDOW_ID)) {
+            Some(window_id) => {
+                let invalid = || FlockError::Internal(format!("Invalid window id: {}", window_id));
+                let (timestamp, uuid) = window_id.split_once('-').ok_or_else(invalid)?;
+                let timestamp = timestamp.parse::<i64>().map_err(|_| invalid())?;
+                let uuid = uuid.parse::<u128>().map_err(|_| invalid())?;
+                Ok(Self::new_with_ts_uuid(
+                    function_name,
+                    timestamp + window as i64,
+                    uuid,
+                    len,
+                ))
+            }
+            None => Ok(Self::new_with_ts(
+                function_name,
+                Utc::now().timestamp(),
+                len,
+            )),
+        }
+    }
+
+    /// Returns a new window id for a query execution, see [`WINDOW_ID`].
+    pub fn window_id() -> String {
+        format!(
+            "{}-{}",
+            Utc::now().timestamp(),
+            RandomId::new_v4().as_u128()
+        )
+    }
+
     /// Returns the next Uuid for the next payload.
     pub fn next_uuid(&mut self) -> Uuid {
         assert!(self.pos <= self.len);
@@ -154,6 +211,19 @@ pub struct Payload {
     pub shuffle_id:   Option<usize>,
     /// The extra metadata for the payload.
     pub metadata:     Option<HashMap<String, String>>,
+    /// The identity of the function that the payload is sent to, if it runs a
+    /// query stage.
+    #[serde(default)]
+    pub function:     Option<FunctionId>,
+    /// Whether the sender has persisted the payload in the state backend, so
+    /// that the aggregator doesn't persist it again.
+    #[serde(default)]
+    pub persisted:    bool,
+    /// The index of the input stage that sends the payload, and the number of
+    /// the input stages, if the next function group gathers the outputs of
+    /// several input stages, such as the inputs of a join.
+    #[serde(default)]
+    pub input:        Option<(usize, usize)>,
 }
 
 impl Payload {
@@ -197,8 +267,16 @@ impl Payload {
     }
 
     /// Returns the window id of the payload.
+    ///
+    /// If the function gathers the outputs of several input stages, each input
+    /// stage is collected in a window of its own: the window id of the `i`-th
+    /// of `n` input stages is `shuffle id * n + i`.
     pub fn get_window_id(&self) -> (String, usize) {
-        (self.get_query_id(), self.get_shuffle_id())
+        let shuffle_id = match self.input {
+            Some((index, inputs)) => self.get_shuffle_id() * inputs + index,
+            None => self.get_shuffle_id(),
+        };
+        (self.get_query_id(), shuffle_id)
     }
 
     /// Returns the squence number of the payload.
@@ -241,7 +319,6 @@ impl Payload {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::error::Result;
     use datafusion::arrow::array::{Array, StructArray};
     use datafusion::arrow::csv;
     use datafusion::arrow::datatypes::{DataType, Field, Schema};
@@ -253,7 +330,7 @@ mod tests {
 
     #[test]
     fn uuid_builder() {
-        let function_name = "SX72HzqFz1Qij4bP-00-01";
+        let function_name = "SX72HzqFz1Qij4bP-0_1";
         let timestamp: i64 = 1;
         let uuid: u128 = 2;
         let payload_num = 10;
@@ -277,6 +354,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn uuid_builder_with_window() -> Result<()> {
+        let metadata = Some(HashMap::from([(WINDOW_ID.to_owned(), "1-2".to_owned())]));
+
+        // The leaf stages of the same query build the same query id for the
+        // same window.
+        let a = UuidBuilder::new_with_window("SX72HzqFz1Qij4bP-1", &metadata, 3, 10)?;
+        let b = UuidBuilder::new_with_window("SX72HzqFz1Qij4bP-2", &metadata, 3, 5)?;
+        assert_eq!("SX72HzqFz1Qij4bP-4-2", a.qid);
+        assert_eq!(a.qid, b.qid);
+
+        let c = UuidBuilder::new_with_window("SX72HzqFz1Qij4bP-1", &metadata, 4, 10)?;
+        assert_ne!(a.qid, c.qid);
+
+        // Without a window id, the query ids are random.
+        let d = UuidBuilder::new_with_window("SX72HzqFz1Qij4bP-1", &None, 3, 10)?;
+        let e = UuidBuilder::new_with_window("SX72HzqFz1Qij4bP-2", &None, 3, 10)?;
+        assert_ne!(d.qid, e.qid);
+
+        let invalid = Some(HashMap::from([(WINDOW_ID.to_owned(), "1".to_owned())]));
+        assert!(UuidBuilder::new_with_window("SX72HzqFz1Qij4bP-1", &invalid, 3, 10).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn flight_data_compression_ratio_1() {
         let schema = Schema::new(vec![
@@ -471,7 +573,7 @@ mod tests {
     #[tokio::test]
     async fn serde_payload() -> Result<()> {
         let batches = init_batches();
-        let mut uuid_builder = UuidBuilder::new_with_ts("SX72HzqFz1Qij4bP-00-01", 1, 10);
+        let mut uuid_builder = UuidBuilder::new_with_ts("SX72HzqFz1Qij4bP-0_1", 1, 10);
         let uuid = uuid_builder.next_uuid();
 
         let now = Instant::now();
@@ -14,9 +14,15 @@
 //! This crate defines the execution plan on cloud platforms. The execution plan
 //! can be stored in the `ExecutionContext` or can be stored in the object
 //! store.
+//!
+//! The plans in the object store are content-addressed: the key is the hash of
+//! the serialized plans, so the same plans are only stored once, and a stored
+//! object never changes. Hence, the warm functions can keep the loaded plans.
 
-use crate::aws::s3;
-use crate::error::Result;
+use crate::configs::*;
+use crate::encoding::content_hash;
+use crate::error::{FlockError, Result};
+use crate::objectstore::object_store;
 use datafusion::execution::context::ExecutionContext;
 use datafusion::physical_plan::displayable;
 use datafusion::physical_plan::empty::EmptyExec;
@@ -24,13 +30,23 @@ use datafusion::physical_plan::hash_aggregate::HashAggregateExec;
 use datafusion::physical_plan::hash_join::HashJoinExec;
 use datafusion::physical_plan::sort::SortExec;
 use datafusion::physical_plan::ExecutionPlan;
+use lazy_static::lazy_static;
 use log::info;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
 type S3BUCKET = String;
 type S3KEY = String;
 
+lazy_static! {
+    /// The serialized plans loaded from the object store by the current
+    /// process. The plans are deserialized for each execution context, because
+    /// the data sources are fed into the plans in place.
+    static ref LOADED_PLANS: RwLock<HashMap<(S3BUCKET, S3KEY), Arc<Vec<u8>>>> =
+        RwLock::new(HashMap::new());
+}
+
 /// The execution plan on cloud.
 #[derive(Default, Clone, Deserialize, Serialize)]
 pub struct CloudExecutionPlan {
@@ -70,22 +86,55 @@ impl CloudExecutionPlan {
         }
     }
 
-    /// Create a new CloudExecutionPlan from
+    /// Returns true if the plans are only stored in the object store.
+    fn is_placed(&self) -> bool {
+        self.execution_plans.is_empty()
+            || (self.execution_plans.len() == 1
+                && self.execution_plans[0].as_any().is::<EmptyExec>())
+    }
+
+    /// Places the execution plans in the object store, so that they don't
+    /// count towards the size of the execution context. The key of the plans
+    /// is derived from their content.
+    pub async fn place_in_object_store(&mut self) -> Result<()> {
+        if self.is_placed() {
+            return Ok(());
+        }
+
+        let body = serde_json::to_vec(&self.execution_plans)?;
+        let bucket = FLOCK_S3_BUCKET.clone();
+        let key = format!("{}/{}", *FLOCK_S3_PLAN_KEY, content_hash(&body));
+        info!("Placing plan in object storage {:?}", (&bucket, &key));
+        object_store().put_if_missing(&bucket, &key, body).await?;
+
+        self.execution_plans = vec![FLOCK_EMPTY_PLAN.clone()];
+        self.object_storage = Some((bucket, key));
+        Ok(())
+    }
 
     /// Returns the execution plan.
+    ///
+    /// If the plans are stored in the object store, they are loaded on the
+    /// first call and kept in the execution plan afterwards.
     pub async fn get_execution_plans(&mut self) -> Result<Vec<Arc<dyn ExecutionPlan>>> {
-        if self.execution_plans.is_empty()
-            || (self.execution_plans.len() == 1
-                && self.execution_plans[0].as_any().is::<EmptyExec>())
-        {
-            if self.object_storage.is_some() {
-                info!("Loading plan from S3 {:?}", self.object_storage);
-                let (bucket, key) = self.object_storage.as_ref().unwrap();
-                self.execution_plans =
-                    vec![serde_json::from_slice(&s3::get_object(bucket, key).await?)?];
-            } else {
-                panic!("The query plan is not stored in the environment variable and S3.");
-            }
+        if self.is_placed() {
+            let location = self.object_storage.clone().ok_or_else(|| {
+                FlockError::Internal(
+                    "The query plan is not stored in the environment variable and S3.".to_owned(),
+                )
+            })?;
+
+            let cached = LOADED_PLANS.read().unwrap().get(&location).cloned();
+            let body = match cached {
+                Some(body) => body,
+                None => {
+                    info!("Loading plan from object storage {:?}", location);
+                    let body = Arc::new(object_store().get(&location.0, &location.1).await?);
+                    LOADED_PLANS.write().unwrap().insert(location, body.clone());
+                    body
+                }
+            };
+            self.execution_plans = serde_json::from_slice(&body)?;
         }
         Ok(self.execution_plans.clone())
     }
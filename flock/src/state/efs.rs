@@ -13,17 +13,56 @@
 
 //! Use EFS state backend to manage the state of the execution engine.
 
-use super::StateBackend;
-use crate::error::Result;
+use super::{checkpoint_key, fragment_key, fragment_prefix, StateBackend, CHECKPOINT_DIR};
+use crate::configs::*;
+use crate::error::{FlockError, Result};
+use crate::runtime::arena::{ArenaCheckpoint, Bitmap, WindowCheckpoint, WindowId};
 use crate::runtime::payload::Payload;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::any::Any;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::task::JoinHandle;
 
 /// EfsStateBackend is a state backend that stores query states in Amazon
 /// Elastic File System (EFS).
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct EfsStateBackend {}
+///
+/// EFS is mounted to the local directory `mount_path` of the cloud function,
+/// and the query states are laid out in the same way as the S3 state backend:
+///
+/// | mount path | qid | plan index | shuffle id | sequence id |
+///
+/// The directory of the qid plays the role of the S3 bucket, and the relative
+/// path `<plan index>/<shuffle id>/<sequence id>` is the S3 key. If the
+/// corresponding data partition is empty, we add a negative sign to the
+/// sequence id.
+///
+/// Since EFS is a POSIX file system, any local directory can be used as the
+/// mount path for testing.
+///
+/// A file operation on EFS is a network round trip, so all of them go through
+/// `tokio::fs`, which runs them on the blocking thread pool instead of the
+/// threads of the async runtime.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EfsStateBackend {
+    /// The local mount path of the file system.
+    #[serde(default = "default_mount_path")]
+    pub mount_path: String,
+}
+
+fn default_mount_path() -> String {
+    FLOCK_EFS_MOUNT_PATH.clone()
+}
+
+impl Default for EfsStateBackend {
+    fn default() -> Self {
+        Self {
+            mount_path: default_mount_path(),
+        }
+    }
+}
 
 #[async_trait]
 #[typetag::serde(name = "efs_state_backend")]
@@ -40,18 +79,282 @@ impl StateBackend for EfsStateBackend {
         self
     }
 
-    async fn write(&self, _: String, _: String, _: Vec<u8>) -> Result<()> {
-        unimplemented!();
+    async fn write(&self, bucket: String, key: String, payload_bytes: Vec<u8>) -> Result<()> {
+        let path = self.path(&bucket, &key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        // Write to a temporary file first, so the readers in other functions never
+        // see a partially written payload.
+        let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        fs::write(&tmp, payload_bytes).await?;
+        fs::rename(tmp, path).await?;
+        Ok(())
+    }
+
+    async fn read(&self, bucket: String, keys: Vec<String>) -> Result<Vec<Payload>> {
+        let tasks = keys
+            .into_iter()
+            .map(|key| {
+                let path = self.path(&bucket, &key);
+                tokio::spawn(async move { Ok(serde_json::from_slice(&fs::read(path).await?)?) })
+            })
+            .collect::<Vec<JoinHandle<Result<Payload>>>>();
+
+        futures::future::join_all(tasks)
+            .await
+            .into_iter()
+            .map(|r| r.map_err(|e| FlockError::Internal(e.to_string()))?)
+            .collect()
+    }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
+    async fn prepare_query(&self, qid: &str) -> Result<()> {
+        fs::create_dir_all(Path::new(&self.mount_path).join(qid)).await?;
+        Ok(())
     }
 
-    async fn read(&self, _: String, _: Vec<String>) -> Result<Vec<Payload>> {
-        unreachable!()
+    async fn persist_fragment(
+        &self,
+        stage: usize,
+        payload: &Payload,
+        payload_bytes: Vec<u8>,
+    ) -> Result<()> {
+        self.write(
+            payload.get_query_id(),
+            fragment_key(stage, payload),
+            payload_bytes,
+        )
+        .await
+    }
+
+    async fn list_fragments(
+        &self,
+        stage: usize,
+        window_id: &WindowId,
+        bitmap: &Bitmap,
+    ) -> Result<Vec<String>> {
+        self.new_keys(&window_id.0, &fragment_prefix(stage, window_id), bitmap)
+            .await
+    }
+
+    async fn load_fragments(&self, qid: &str, keys: Vec<String>) -> Result<Vec<Payload>> {
+        self.read(qid.to_owned(), keys).await
+    }
+
+    async fn cleanup(&self, stage: usize, window_id: &WindowId) -> Result<()> {
+        let dir = self.path(&window_id.0, &fragment_prefix(stage, window_id));
+        not_found_as_none(fs::remove_dir_all(dir).await)?;
+        Ok(())
+    }
+
+    async fn save_checkpoint(
+        &self,
+        function_name: &str,
+        checkpoint: &WindowCheckpoint,
+    ) -> Result<()> {
+        self.write(
+            CHECKPOINT_DIR.to_owned(),
+            checkpoint_key(function_name, &checkpoint.window_id),
+            serde_json::to_vec(checkpoint)?,
+        )
+        .await
+    }
+
+    async fn remove_checkpoint(&self, function_name: &str, window_id: &WindowId) -> Result<()> {
+        let path = self.path(CHECKPOINT_DIR, &checkpoint_key(function_name, window_id));
+        not_found_as_none(fs::remove_file(path).await)?;
+        Ok(())
+    }
+
+    async fn load_checkpoint(&self, function_name: &str) -> Result<Option<ArenaCheckpoint>> {
+        let dir = self.path(CHECKPOINT_DIR, function_name);
+        let mut entries = match not_found_as_none(fs::read_dir(dir).await)? {
+            Some(entries) => entries,
+            None => return Ok(None),
+        };
+        let mut windows = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            // Skip the temporary files of the ongoing writes.
+            if entry.file_name().to_string_lossy().contains(".tmp-") {
+                continue;
+            }
+            let checkpoint: WindowCheckpoint =
+                serde_json::from_slice(&fs::read(entry.path()).await?)?;
+            windows.push(checkpoint);
+        }
+        if windows.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(windows.into_iter().collect()))
     }
 }
 
 impl EfsStateBackend {
-    /// Creates a new EFSStateBackend.
+    /// Creates a new EfsStateBackend at the default mount path.
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Creates a new EfsStateBackend at the given mount path.
+    pub fn with_mount_path<P: AsRef<Path>>(mount_path: P) -> Self {
+        Self {
+            mount_path: mount_path.as_ref().to_string_lossy().into_owned(),
+        }
+    }
+
+    /// Returns the file path of the key in the query's directory.
+    fn path(&self, bucket: &str, key: &str) -> PathBuf {
+        Path::new(&self.mount_path).join(bucket).join(key)
+    }
+
+    /// Reads the sequence ids written to the query's directory with a prefix.
+    ///
+    /// # Arguments
+    /// * `bucket` - The query id of the payloads.
+    /// * `prefix` - The key prefix `<plan index>/<shuffle id>` of the data
+    ///   partitions.
+    ///
+    /// # Returns
+    /// A vector of sequence ids. The sequence ids of empty data partitions are
+    /// negative.
+    pub async fn read_seq_nums(&self, bucket: &str, prefix: &str) -> Result<Vec<i32>> {
+        let mut entries = match not_found_as_none(fs::read_dir(self.path(bucket, prefix)).await)? {
+            Some(entries) => entries,
+            None => return Ok(vec![]),
+        };
+        let mut seq_nums = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            // Skip the temporary files of the ongoing writes.
+            if let Ok(seq_num) = name.to_string_lossy().parse::<i32>() {
+                seq_nums.push(seq_num);
+            }
+        }
+        seq_nums.sort_unstable();
+        Ok(seq_nums)
+    }
+
+    /// Counts the number of data partitions written with a prefix.
+    pub async fn get_key_num(&self, bucket: &str, prefix: &str) -> Result<usize> {
+        Ok(self.read_seq_nums(bucket, prefix).await?.len())
+    }
+
+    /// Returns the keys of the data partitions that are written to the file
+    /// system but not collected yet.
+    ///
+    /// # Arguments
+    /// * `bucket` - The query id of the payloads.
+    /// * `prefix` - The key prefix `<plan index>/<shuffle id>`.
+    /// * `old_keys` - The sequence ids that have been collected before.
+    ///
+    /// # Returns
+    /// * The difference between the written keys and the old keys.
+    pub async fn new_keys(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        old_keys: &Bitmap,
+    ) -> Result<Vec<String>> {
+        Ok(self
+            .read_seq_nums(bucket, prefix)
+            .await?
+            .into_iter()
+            .filter(|seq_num| !old_keys.is_set((*seq_num).abs() as usize))
+            .map(|seq_num| format!("{}/{:02}", prefix, seq_num))
+            .collect())
+    }
+
+    /// Removes all the states of the query.
+    pub async fn delete(&self, bucket: &str) -> Result<()> {
+        let dir = Path::new(&self.mount_path).join(bucket);
+        not_found_as_none(fs::remove_dir_all(dir).await)?;
+        Ok(())
+    }
+}
+
+/// Returns `None` if the file or the directory doesn't exist.
+fn not_found_as_none<T>(result: std::io::Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::payload::Uuid;
+
+    #[tokio::test]
+    async fn efs_state_backend() -> Result<()> {
+        let mount_path = std::env::temp_dir().join(format!("flock-efs-{}", uuid::Uuid::new_v4()));
+        let state_backend = EfsStateBackend::with_mount_path(&mount_path);
+        let qid = "q4-1642991536-218735128523183619391499820347984139655";
+
+        for seq_num in [1, -2, 3] {
+            let payload = Payload {
+                uuid: Uuid {
+                    qid:     qid.to_owned(),
+                    seq_num: seq_num.abs() as usize,
+                    seq_len: 3,
+                },
+                ..Default::default()
+            };
+            state_backend
+                .write(
+                    qid.to_owned(),
+                    format!("{:02}/{:02}/{:02}", 2, 1, seq_num),
+                    serde_json::to_vec(&payload)?,
+                )
+                .await?;
+        }
+        // The other shuffle partition of the same stage.
+        state_backend
+            .write(
+                qid.to_owned(),
+                "02/02/01".to_owned(),
+                serde_json::to_vec(&Payload::default())?,
+            )
+            .await?;
+
+        assert_eq!(
+            state_backend.read_seq_nums(qid, "02/01").await?,
+            vec![-2, 1, 3]
+        );
+        assert_eq!(state_backend.get_key_num(qid, "02/02").await?, 1);
+        assert!(state_backend.read_seq_nums(qid, "03/01").await?.is_empty());
+
+        let mut old_keys = Bitmap::new(4);
+        old_keys.set(1);
+        old_keys.set(3);
+        let keys = state_backend.new_keys(qid, "02/01", &old_keys).await?;
+        assert_eq!(keys, vec!["02/01/-2".to_string()]);
+
+        let payloads = state_backend.read(qid.to_owned(), keys).await?;
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].uuid.seq_num, 2);
+        assert_eq!(payloads[0].uuid.qid, qid);
+
+        // Serde keeps the mount path, so the functions share the same layout.
+        let boxed: Box<dyn StateBackend> = Box::new(state_backend.clone());
+        let de: Box<dyn StateBackend> = serde_json::from_str(&serde_json::to_string(&boxed)?)?;
+        assert_eq!(
+            de.as_any()
+                .downcast_ref::<EfsStateBackend>()
+                .unwrap()
+                .mount_path,
+            state_backend.mount_path
+        );
+
+        state_backend.delete(qid).await?;
+        assert!(state_backend.read_seq_nums(qid, "02/01").await?.is_empty());
+        fs::remove_dir_all(mount_path).await?;
+
+        Ok(())
     }
 }
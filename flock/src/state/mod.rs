@@ -66,6 +66,7 @@ mod efs;
 pub use efs::EfsStateBackend;
 
 use crate::error::Result;
+use crate::runtime::arena::{ArenaCheckpoint, Bitmap, WindowCheckpoint, WindowId};
 use crate::runtime::payload::Payload;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -73,6 +74,28 @@ use std::any::Any;
 use std::fmt::Debug;
 
 /// The state backend trait defines the interface for state backends.
+///
+/// The lifecycle of the query states in a persistent state backend is:
+///
+/// 1. `prepare_query`: the data source creates the namespace of a new query,
+///    such as the S3 bucket of the query id.
+/// 2. `persist_fragment`: each function persists the data fragments it sends to
+///    the aggregators in the next stage, and flags them as `persisted` in the
+///    payloads. The aggregator only persists the fragments that are not
+///    flagged, such as the ones sent by the data source.
+/// 3. `list_fragments`: an aggregator lists the fragments of a window that
+///    haven't reached it through the function invocations yet.
+/// 4. `load_fragments`: the aggregator loads these fragments to complete the
+///    window.
+/// 5. `cleanup`: the aggregator removes the fragments once the output of the
+///    window is delivered to the next stage.
+///
+/// Besides, the aggregator records the progress of each window in its arena by
+/// `save_checkpoint`, and rehydrates the in-flight windows by `load_checkpoint`
+/// after a cold start.
+///
+/// All lifecycle hooks are no-ops by default, which is the behavior of the
+/// in-memory state backend.
 #[async_trait]
 #[typetag::serde(tag = "state_backend")]
 pub trait StateBackend: Debug + Send + Sync {
@@ -88,6 +111,135 @@ pub trait StateBackend: Debug + Send + Sync {
     async fn write(&self, bucket: String, key: String, payload_bytes: Vec<u8>) -> Result<()>;
     /// Reads payloads from the state backend.
     async fn read(&self, bucket: String, keys: Vec<String>) -> Result<Vec<Payload>>;
+
+    /// Returns true if the state backend persists the data fragments outside
+    /// of the function's memory.
+    fn is_persistent(&self) -> bool {
+        false
+    }
+    /// Prepares the namespace of the query before any fragment is persisted.
+    ///
+    /// # Arguments
+    /// * `qid` - The query id.
+    async fn prepare_query(&self, _qid: &str) -> Result<()> {
+        Ok(())
+    }
+    /// Persists a data fragment sent to a window in the given query stage.
+    ///
+    /// # Arguments
+    /// * `stage` - The index of the query stage that receives the fragment.
+    /// * `payload` - The payload of the fragment.
+    /// * `payload_bytes` - The serialized payload.
+    async fn persist_fragment(
+        &self,
+        _stage: usize,
+        _payload: &Payload,
+        _payload_bytes: Vec<u8>,
+    ) -> Result<()> {
+        Ok(())
+    }
+    /// Lists the keys of the fragments in the window which are persisted but
+    /// not yet collected.
+    ///
+    /// # Arguments
+    /// * `stage` - The index of the query stage that owns the window.
+    /// * `window_id` - The window id.
+    /// * `bitmap` - The sequence numbers of the collected fragments.
+    async fn list_fragments(
+        &self,
+        _stage: usize,
+        _window_id: &WindowId,
+        _bitmap: &Bitmap,
+    ) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
+    /// Loads the fragments of the query by their keys.
+    ///
+    /// # Arguments
+    /// * `qid` - The query id.
+    /// * `keys` - The keys returned by `list_fragments`.
+    async fn load_fragments(&self, _qid: &str, _keys: Vec<String>) -> Result<Vec<Payload>> {
+        Ok(vec![])
+    }
+    /// Removes the persisted fragments of the window.
+    ///
+    /// # Arguments
+    /// * `stage` - The index of the query stage that owns the window.
+    /// * `window_id` - The window id.
+    async fn cleanup(&self, _stage: usize, _window_id: &WindowId) -> Result<()> {
+        Ok(())
+    }
+    /// Saves the checkpoint of a window in the function's arena. The checkpoint
+    /// overwrites the previous one of the same window, so an invocation only
+    /// writes the window it changes.
+    ///
+    /// # Arguments
+    /// * `function_name` - The name of the aggregate function.
+    /// * `checkpoint` - The checkpoint of the window.
+    async fn save_checkpoint(
+        &self,
+        _function_name: &str,
+        _checkpoint: &WindowCheckpoint,
+    ) -> Result<()> {
+        Ok(())
+    }
+    /// Removes the checkpoint of a window in the function's arena, such as a
+    /// processed window that is pruned from the arena.
+    ///
+    /// # Arguments
+    /// * `function_name` - The name of the aggregate function.
+    /// * `window_id` - The window id.
+    async fn remove_checkpoint(&self, _function_name: &str, _window_id: &WindowId) -> Result<()> {
+        Ok(())
+    }
+    /// Loads the latest checkpoint of the function's arena, which is assembled
+    /// from the checkpoints of its windows.
+    ///
+    /// # Arguments
+    /// * `function_name` - The name of the aggregate function.
+    ///
+    /// # Returns
+    /// The checkpoint of the arena, or `None` if the function has never saved
+    /// one.
+    async fn load_checkpoint(&self, _function_name: &str) -> Result<Option<ArenaCheckpoint>> {
+        Ok(None)
+    }
+}
+
+/// The directory of the arena checkpoints in the state backend.
+pub const CHECKPOINT_DIR: &str = "checkpoints";
+
+/// Returns the key of the window's checkpoint in the directory of the
+/// function.
+///
+/// | function name | query id - shuffle id |
+pub fn checkpoint_key(function_name: &str, window_id: &WindowId) -> String {
+    format!("{}/{}-{:02}", function_name, window_id.0, window_id.1)
+}
+
+/// Returns the key prefix of the fragments in a window.
+///
+/// | plan index | shuffle id |
+pub fn fragment_prefix(stage: usize, window_id: &WindowId) -> String {
+    format!("{:02}/{:02}", stage, window_id.1)
+}
+
+/// Returns the key of the data fragment in the window.
+///
+/// | plan index | shuffle id | sequence id |
+///
+/// If the data fragment is empty, the sequence id is negative.
+pub fn fragment_key(stage: usize, payload: &Payload) -> String {
+    let seq_num = if payload.is_empty_data() {
+        -(payload.get_seq_num() as i32)
+    } else {
+        payload.get_seq_num() as i32
+    };
+    format!(
+        "{}/{:02}",
+        fragment_prefix(stage, &payload.get_window_id()),
+        seq_num
+    )
 }
 
 /// The default state backend.
@@ -128,3 +280,157 @@ impl HashMapStateBackend {
         Self {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::payload::{DataFrame, Uuid};
+    use crate::test_util::init_in_memory_object_store;
+    use std::sync::Arc;
+
+    /// Persists three fragments of a window to the state backend and recovers
+    /// the ones the function hasn't collected.
+    async fn check_lifecycle(state_backend: Arc<dyn StateBackend>, qid: &str) -> Result<()> {
+        let stage = 2;
+        let window_id = (qid.to_owned(), 1);
+        state_backend.prepare_query(qid).await?;
+
+        for seq_num in 1..=3 {
+            let payload = Payload {
+                uuid: Uuid {
+                    qid: qid.to_owned(),
+                    seq_num,
+                    seq_len: 3,
+                },
+                // The second fragment is empty.
+                data: if seq_num == 2 {
+                    vec![]
+                } else {
+                    vec![DataFrame::default()]
+                },
+                shuffle_id: Some(1),
+                ..Default::default()
+            };
+            let bytes = serde_json::to_vec(&payload)?;
+            state_backend
+                .persist_fragment(stage, &payload, bytes)
+                .await?;
+        }
+
+        // The fragments of the other shuffle partitions are invisible.
+        let payload = Payload {
+            uuid: Uuid {
+                qid:     qid.to_owned(),
+                seq_num: 1,
+                seq_len: 3,
+            },
+            shuffle_id: Some(10),
+            ..Default::default()
+        };
+        state_backend
+            .persist_fragment(stage, &payload, serde_json::to_vec(&payload)?)
+            .await?;
+
+        let mut bitmap = Bitmap::new(4);
+        bitmap.set(1);
+        let keys = state_backend
+            .list_fragments(stage, &window_id, &bitmap)
+            .await?;
+        assert_eq!(keys, vec!["02/01/-2".to_owned(), "02/01/03".to_owned()]);
+
+        let mut payloads = state_backend.load_fragments(qid, keys).await?;
+        payloads.sort_by_key(|p| p.uuid.seq_num);
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads[0].is_empty_data());
+        assert_eq!(payloads[1].uuid.seq_num, 3);
+
+        state_backend.cleanup(stage, &window_id).await?;
+        assert!(state_backend
+            .list_fragments(stage, &window_id, &Bitmap::new(4))
+            .await?
+            .is_empty());
+        assert_eq!(
+            state_backend
+                .list_fragments(stage, &(qid.to_owned(), 10), &Bitmap::new(4))
+                .await?,
+            vec!["02/10/-1".to_owned()]
+        );
+
+        let function_name = "SX72HzqFz1Qij4bP-2_0";
+        assert!(state_backend.load_checkpoint(function_name).await?.is_none());
+        let in_flight = |seq_nums: Vec<usize>| WindowCheckpoint {
+            window_id: (qid.to_owned(), 10),
+            size: 3,
+            seq_nums,
+            processed: false,
+        };
+        let processed = WindowCheckpoint {
+            window_id: window_id.clone(),
+            size:      0,
+            seq_nums:  vec![],
+            processed: true,
+        };
+        for checkpoint in [in_flight(vec![1]), processed, in_flight(vec![1, 3])] {
+            state_backend
+                .save_checkpoint(function_name, &checkpoint)
+                .await?;
+        }
+        // The checkpoint of a window overwrites the previous one.
+        assert_eq!(
+            state_backend.load_checkpoint(function_name).await?,
+            Some(ArenaCheckpoint {
+                windows:   vec![in_flight(vec![1, 3])],
+                processed: vec![window_id.clone()],
+            })
+        );
+
+        state_backend
+            .remove_checkpoint(function_name, &window_id)
+            .await?;
+        state_backend
+            .remove_checkpoint(function_name, &(qid.to_owned(), 10))
+            .await?;
+        assert!(state_backend
+            .load_checkpoint(function_name)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_state_backend_lifecycle() -> Result<()> {
+        init_in_memory_object_store();
+        let state_backend: Arc<dyn StateBackend> = Arc::new(S3StateBackend::new());
+        assert!(state_backend.is_persistent());
+        check_lifecycle(state_backend, "q1-1642991536-lifecycle").await
+    }
+
+    #[tokio::test]
+    async fn efs_state_backend_lifecycle() -> Result<()> {
+        let mount_path = std::env::temp_dir().join(format!("flock-efs-{}", uuid::Uuid::new_v4()));
+        let state_backend: Arc<dyn StateBackend> =
+            Arc::new(EfsStateBackend::with_mount_path(&mount_path));
+        assert!(state_backend.is_persistent());
+        check_lifecycle(state_backend, "q1-1642991536-lifecycle").await?;
+        std::fs::remove_dir_all(mount_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hashmap_state_backend_lifecycle() -> Result<()> {
+        let state_backend: Arc<dyn StateBackend> = Arc::new(HashMapStateBackend::new());
+        let window_id = ("q1-1642991536-lifecycle".to_owned(), 1);
+        assert!(!state_backend.is_persistent());
+        state_backend.prepare_query(&window_id.0).await?;
+        state_backend
+            .persist_fragment(2, &Payload::default(), vec![])
+            .await?;
+        assert!(state_backend
+            .list_fragments(2, &window_id, &Bitmap::new(4))
+            .await?
+            .is_empty());
+        state_backend.cleanup(2, &window_id).await?;
+        Ok(())
+    }
+}
@@ -13,10 +13,11 @@
 
 //! Use S3 state backend to manage the state of the execution engine.
 
-use super::StateBackend;
-use crate::aws::s3;
+use super::{checkpoint_key, fragment_key, fragment_prefix, StateBackend, CHECKPOINT_DIR};
+use crate::configs::*;
 use crate::error::Result;
-use crate::runtime::arena::Bitmap;
+use crate::objectstore::object_store;
+use crate::runtime::arena::{ArenaCheckpoint, Bitmap, WindowCheckpoint, WindowId};
 use crate::runtime::payload::Payload;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -63,7 +64,7 @@ impl StateBackend for S3StateBackend {
     }
 
     async fn write(&self, bucket: String, key: String, payload_bytes: Vec<u8>) -> Result<()> {
-        s3::put_object(&bucket, &key, payload_bytes).await
+        object_store().put(&bucket, &key, payload_bytes).await
     }
 
     async fn read(&self, bucket: String, keys: Vec<String>) -> Result<Vec<Payload>> {
@@ -72,7 +73,7 @@ impl StateBackend for S3StateBackend {
             .map(|key| {
                 let b = bucket.clone();
                 tokio::spawn(async move {
-                    Ok(serde_json::from_slice(&s3::get_object(&b, &key).await?)?)
+                    Ok(serde_json::from_slice(&object_store().get(&b, &key).await?)?)
                 })
             })
             .collect::<Vec<JoinHandle<Result<Payload>>>>();
@@ -83,6 +84,102 @@ impl StateBackend for S3StateBackend {
             .map(|r| r.unwrap().unwrap())
             .collect())
     }
+
+    fn is_persistent(&self) -> bool {
+        true
+    }
+
+    async fn prepare_query(&self, qid: &str) -> Result<()> {
+        object_store().create_bucket(qid).await
+    }
+
+    async fn persist_fragment(
+        &self,
+        stage: usize,
+        payload: &Payload,
+        payload_bytes: Vec<u8>,
+    ) -> Result<()> {
+        self.write(
+            payload.get_query_id(),
+            fragment_key(stage, payload),
+            payload_bytes,
+        )
+        .await
+    }
+
+    async fn list_fragments(
+        &self,
+        stage: usize,
+        window_id: &WindowId,
+        bitmap: &Bitmap,
+    ) -> Result<Vec<String>> {
+        self.new_s3_keys(&window_id.0, &fragment_prefix(stage, window_id), bitmap)
+            .await
+    }
+
+    async fn load_fragments(&self, qid: &str, keys: Vec<String>) -> Result<Vec<Payload>> {
+        self.read(qid.to_owned(), keys).await
+    }
+
+    async fn cleanup(&self, stage: usize, window_id: &WindowId) -> Result<()> {
+        let store = object_store();
+        let prefix = format!("{}/", fragment_prefix(stage, window_id));
+        for key in store.list(&window_id.0, &prefix).await? {
+            store.delete(&window_id.0, &key).await?;
+        }
+        Ok(())
+    }
+
+    async fn save_checkpoint(
+        &self,
+        function_name: &str,
+        checkpoint: &WindowCheckpoint,
+    ) -> Result<()> {
+        object_store()
+            .put(
+                &FLOCK_S3_BUCKET,
+                &format!(
+                    "{}/{}",
+                    CHECKPOINT_DIR,
+                    checkpoint_key(function_name, &checkpoint.window_id)
+                ),
+                serde_json::to_vec(checkpoint)?,
+            )
+            .await
+    }
+
+    async fn remove_checkpoint(&self, function_name: &str, window_id: &WindowId) -> Result<()> {
+        object_store()
+            .delete(
+                &FLOCK_S3_BUCKET,
+                &format!(
+                    "{}/{}",
+                    CHECKPOINT_DIR,
+                    checkpoint_key(function_name, window_id)
+                ),
+            )
+            .await
+    }
+
+    async fn load_checkpoint(&self, function_name: &str) -> Result<Option<ArenaCheckpoint>> {
+        let store = object_store();
+        let keys = store
+            .list(
+                &FLOCK_S3_BUCKET,
+                &format!("{}/{}/", CHECKPOINT_DIR, function_name),
+            )
+            .await?;
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        let mut windows = vec![];
+        for key in keys {
+            let checkpoint: WindowCheckpoint =
+                serde_json::from_slice(&store.get(&FLOCK_S3_BUCKET, &key).await?)?;
+            windows.push(checkpoint);
+        }
+        Ok(Some(windows.into_iter().collect()))
+    }
 }
 
 impl S3StateBackend {
@@ -106,7 +203,8 @@ impl S3StateBackend {
     /// # Returns
     /// A vector of S3 keys in usize format.
     pub async fn read_s3_keys(&self, bucket: &str, prefix: &str) -> Result<Vec<i32>> {
-        Ok(s3::get_matched_keys(bucket, prefix)
+        Ok(object_store()
+            .list(bucket, &format!("{}/", prefix))
             .await?
             .into_iter()
             .map(|key| {
@@ -127,7 +225,10 @@ impl S3StateBackend {
     /// # Returns
     /// The number of S3 keys.
     pub async fn get_s3_key_num(&self, bucket: &str, prefix: &str) -> Result<usize> {
-        Ok(s3::get_matched_keys(bucket, prefix).await?.len())
+        Ok(object_store()
+            .list(bucket, &format!("{}/", prefix))
+            .await?
+            .len())
     }
 
     /// Returns the latest checkpointed keys.
@@ -158,6 +259,51 @@ impl S3StateBackend {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::runtime::payload::Uuid;
+    use crate::test_util::init_in_memory_object_store;
+
+    #[tokio::test]
+    async fn s3_state_backend_in_memory() -> Result<()> {
+        init_in_memory_object_store();
+
+        let s3_state_backend = S3StateBackend::new();
+        let bucket = "q4-1642991536-in-memory";
+        for seq_num in [1, -2, 3] {
+            let payload = Payload {
+                uuid: Uuid {
+                    qid:     bucket.to_owned(),
+                    seq_num: seq_num.abs() as usize,
+                    seq_len: 3,
+                },
+                ..Default::default()
+            };
+            s3_state_backend
+                .write(
+                    bucket.to_owned(),
+                    format!("02/01/{:02}", seq_num),
+                    serde_json::to_vec(&payload)?,
+                )
+                .await?;
+        }
+
+        let mut seq_nums = s3_state_backend.read_s3_keys(bucket, "02/01").await?;
+        seq_nums.sort_unstable();
+        assert_eq!(seq_nums, vec![-2, 1, 3]);
+        assert_eq!(s3_state_backend.get_s3_key_num(bucket, "02/01").await?, 3);
+
+        let mut old_keys = Bitmap::new(4);
+        old_keys.set(1);
+        let new_keys = s3_state_backend
+            .new_s3_keys(bucket, "02/01", &old_keys)
+            .await?;
+        assert_eq!(new_keys.len(), 2);
+
+        let payloads = s3_state_backend.read(bucket.to_owned(), new_keys).await?;
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads.iter().all(|p| p.uuid.qid == bucket));
+
+        Ok(())
+    }
 
     #[tokio::test]
     #[ignore]
@@ -0,0 +1,310 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! By default, a window emits its results only once, when the watermark passes
+//! the end of the window plus the allowed lateness. A long window, such as a
+//! 1-hour tumbling window, gives no intermediate visibility of its results.
+//!
+//! The firing policy of a window refines its results:
+//!
+//! - Early firings emit the results before the watermark passes the end of the
+//!   window, either periodically in processing time or every N records.
+//! - The on-time firing emits the results once the watermark passes the end of
+//!   the window.
+//! - Late firings emit the refined results for the late records that arrive
+//!   within the allowed lateness.
+//!
+//! Each firing of a window is a pane. In the accumulating mode, a pane
+//! contains all the records of the window so far. In the discarding mode, a
+//! pane only contains the records since the previous firing. The panes of a
+//! window are numbered by a firing sequence number, so that the sinks can
+//! upsert the results of the window.
+//!
+//! The session and the global windows close by their own timeouts rather than
+//! by the watermark. A record that arrives after such a window closes opens a
+//! new window, so these windows only fire early and on time, see
+//! [`FiringStates`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+/// When the window fires before the watermark passes its end.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub enum EarlyFiring {
+    /// Fires periodically with the processing-time interval in seconds.
+    ProcessingTime(usize),
+    /// Fires every N new records of the window.
+    Elements(usize),
+}
+
+/// How the panes of a window relate to each other.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+pub enum AccumulationMode {
+    /// Each pane contains all the records of the window so far.
+    Accumulating,
+    /// Each pane only contains the records since the previous firing.
+    Discarding,
+}
+
+impl Default for AccumulationMode {
+    fn default() -> Self {
+        AccumulationMode::Accumulating
+    }
+}
+
+impl AccumulationMode {
+    /// Returns the records of a pane from the records of the window. In the
+    /// discarding mode, the records are taken out of the window.
+    pub fn pane_records<T: Clone>(&self, records: &mut Vec<T>) -> Vec<T> {
+        match self {
+            AccumulationMode::Accumulating => records.clone(),
+            AccumulationMode::Discarding => std::mem::take(records),
+        }
+    }
+}
+
+/// The firing policy of a window.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Firing {
+    /// The early firings. The window fires as soon as any of them is met.
+    pub early: Vec<EarlyFiring>,
+    /// Whether the window fires for the late records within the allowed
+    /// lateness. If not, the on-time firing waits for the allowed lateness.
+    pub late:  bool,
+    /// The accumulation mode of the panes.
+    pub mode:  AccumulationMode,
+}
+
+impl Firing {
+    /// Returns true if the window should fire early.
+    ///
+    /// # Arguments
+    /// * `records` - The number of new records since the previous firing.
+    /// * `elapsed` - The processing time in milliseconds since the previous
+    ///   firing or since the window opened.
+    pub fn fires_early(&self, records: usize, elapsed: i64) -> bool {
+        records > 0
+            && self.early.iter().any(|early| match early {
+                EarlyFiring::ProcessingTime(sec) => elapsed >= *sec as i64 * 1000,
+                EarlyFiring::Elements(n) => records >= *n,
+            })
+    }
+}
+
+/// The timing of a pane relative to the watermark.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+pub enum FiringTiming {
+    /// Fired before the watermark passes the end of the window.
+    Early,
+    /// Fired when the watermark passes the end of the window.
+    OnTime,
+    /// Fired for the late records after the on-time firing.
+    Late,
+}
+
+impl fmt::Display for FiringTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FiringTiming::Early => write!(f, "EARLY"),
+            FiringTiming::OnTime => write!(f, "ON_TIME"),
+            FiringTiming::Late => write!(f, "LATE"),
+        }
+    }
+}
+
+/// A firing of a window.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+pub struct Pane {
+    /// The timing of the firing.
+    pub timing: FiringTiming,
+    /// The firing sequence number of the window, starting from 0.
+    pub seq:    usize,
+}
+
+impl Default for Pane {
+    fn default() -> Self {
+        Self {
+            timing: FiringTiming::OnTime,
+            seq:    0,
+        }
+    }
+}
+
+impl Pane {
+    /// Adds the pane to the payload's metadata.
+    pub fn to_metadata(&self, metadata: &mut HashMap<String, String>) {
+        metadata.insert("firing_seq".to_string(), self.seq.to_string());
+        metadata.insert("firing_timing".to_string(), self.timing.to_string());
+    }
+}
+
+/// The firing state of an open window.
+#[derive(Debug, Default)]
+struct FiringState {
+    /// The number of new records since the previous firing.
+    records:   usize,
+    /// The sequence number of the next firing.
+    seq:       usize,
+    /// The processing time in milliseconds of the previous firing, or when
+    /// the window is first seen by the firing policy.
+    last_fire: Option<i64>,
+}
+
+impl FiringState {
+    /// Fires a pane of the window.
+    fn fire(&mut self, timing: FiringTiming, now: i64) -> Pane {
+        let pane = Pane {
+            timing,
+            seq: self.seq,
+        };
+        self.seq += 1;
+        self.records = 0;
+        self.last_fire = Some(now);
+        pane
+    }
+}
+
+/// `FiringStates` tracks the firings of the keyed windows that close by their
+/// own timeouts, such as the session and the global windows. The windows fire
+/// early by the firing policy, and on time when they close. Without a firing
+/// policy, each window fires once when it closes.
+#[derive(Debug)]
+pub struct FiringStates<K> {
+    /// The firing policy of the windows.
+    firing: Option<Firing>,
+    /// The firing states of the open windows.
+    states: HashMap<K, FiringState>,
+}
+
+impl<K: Clone + Eq + Hash> FiringStates<K> {
+    /// Creates the firing states of the windows.
+    pub fn new(firing: Option<Firing>) -> Self {
+        Self {
+            firing,
+            states: HashMap::new(),
+        }
+    }
+
+    /// Returns the accumulation mode of the panes.
+    pub fn mode(&self) -> AccumulationMode {
+        self.firing
+            .as_ref()
+            .map_or(AccumulationMode::Accumulating, |f| f.mode)
+    }
+
+    /// Counts the new records of the window.
+    pub fn observe(&mut self, key: &K, records: usize) {
+        self.states.entry(key.clone()).or_default().records += records;
+    }
+
+    /// Fires the open windows early by the firing policy.
+    ///
+    /// # Arguments
+    /// * `now` - The processing time in milliseconds.
+    ///
+    /// # Returns
+    /// The keys of the fired windows with their panes.
+    pub fn fire_early(&mut self, now: i64) -> Vec<(K, Pane)> {
+        let firing = match &self.firing {
+            Some(firing) => firing,
+            None => return vec![],
+        };
+        let mut fired = vec![];
+        for (key, state) in self.states.iter_mut() {
+            let last_fire = *state.last_fire.get_or_insert(now);
+            if firing.fires_early(state.records, now - last_fire) {
+                fired.push((key.clone(), state.fire(FiringTiming::Early, now)));
+            }
+        }
+        fired
+    }
+
+    /// Closes the window.
+    ///
+    /// # Returns
+    /// The on-time pane of the window, or none if the window has nothing new
+    /// to emit in the discarding mode.
+    pub fn close(&mut self, key: &K) -> Option<Pane> {
+        let mut state = self.states.remove(key).unwrap_or_default();
+        if state.records > 0 || self.mode() == AccumulationMode::Accumulating {
+            Some(state.fire(FiringTiming::OnTime, 0))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyed_firings() {
+        use FiringTiming::*;
+
+        // Without a firing policy, each window fires once when it closes.
+        let mut states = FiringStates::new(None);
+        states.observe(&"alice", 3);
+        assert!(states.fire_early(0).is_empty());
+        assert_eq!(states.close(&"alice"), Some(Pane::default()));
+
+        let firing = Firing {
+            early: vec![EarlyFiring::Elements(2)],
+            late:  false,
+            mode:  AccumulationMode::Discarding,
+        };
+        let mut states = FiringStates::new(Some(firing));
+        states.observe(&"alice", 1);
+        states.observe(&"bob", 2);
+        assert_eq!(
+            states.fire_early(0),
+            vec![(
+                "bob",
+                Pane {
+                    timing: Early,
+                    seq:    0,
+                }
+            )]
+        );
+
+        // Bob has nothing new since the early firing.
+        assert_eq!(states.close(&"bob"), None);
+        states.observe(&"alice", 1);
+        let fired = states.fire_early(0);
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].1.seq, 0);
+        states.observe(&"alice", 1);
+        assert_eq!(
+            states.close(&"alice"),
+            Some(Pane {
+                timing: OnTime,
+                seq:    1,
+            })
+        );
+
+        // Only the discarding mode takes the records out of the window.
+        let mut records = vec![1, 2];
+        assert_eq!(
+            AccumulationMode::Accumulating.pane_records(&mut records),
+            vec![1, 2]
+        );
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            AccumulationMode::Discarding.pane_records(&mut records),
+            vec![1, 2]
+        );
+        assert!(records.is_empty());
+    }
+}
@@ -14,5 +14,26 @@
 //! The stream module is used to define the interface for streaming data
 //! sources.
 
+pub mod firing;
+pub use firing::{AccumulationMode, EarlyFiring, Firing, FiringStates, FiringTiming, Pane};
+
+pub mod watermark;
+pub use watermark::{EventTime, EventTimeWindow, EventTimeWindows, Watermark};
+
+pub mod pane;
+pub use pane::Panes;
+
+pub mod sliding;
+pub use sliding::SlidingWindows;
+
+pub mod stagger;
+pub use stagger::StaggerWindows;
+
+pub mod trigger;
+pub use trigger::{CronExpr, Trigger, TriggeredWindows};
+
+pub mod tvf;
+pub use tvf::{WindowFunction, WINDOW_END, WINDOW_START};
+
 pub mod window;
 pub use window::{Schedule, Window};
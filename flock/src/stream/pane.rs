@@ -0,0 +1,232 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Hopping windows overlap, so each record belongs to `size / hop` windows.
+//! Instead of aggregating every window from scratch, the stream is cut into
+//! panes of `gcd(size, hop)` seconds. Each window is a whole number of panes,
+//! and each pane is shared by all the windows it belongs to.
+//!
+//! The partial aggregates of a pane are computed only once, when the pane
+//! closes. When a window fires, the partial aggregates of its panes are merged
+//! by the final aggregation of the query.
+
+use crate::datasource::RelationPartitions;
+use crate::error::{FlockError, Result};
+use std::collections::VecDeque;
+
+/// Returns the greatest common divisor of two numbers.
+pub fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// `Panes` keeps the panes of the open hopping windows.
+#[derive(Debug)]
+pub struct Panes<T> {
+    /// The pane size in seconds.
+    pane:   usize,
+    /// The number of panes in a window.
+    window: usize,
+    /// The number of panes in a hop.
+    hop:    usize,
+    /// The panes of the open windows, oldest first.
+    panes:  VecDeque<T>,
+    /// The number of panes since the start of the stream.
+    count:  usize,
+}
+
+impl<T> Panes<T> {
+    /// Creates the panes of a hopping window.
+    ///
+    /// # Arguments
+    /// * `window_size` - The window size in seconds.
+    /// * `hop_size` - The hop size in seconds.
+    pub fn new(window_size: usize, hop_size: usize) -> Result<Self> {
+        if window_size == 0 || hop_size == 0 {
+            return Err(FlockError::Execution(
+                "The hopping window size and hop size must be positive.".to_string(),
+            ));
+        }
+        let pane = gcd(window_size, hop_size);
+        Ok(Self {
+            pane,
+            window: window_size / pane,
+            hop: hop_size / pane,
+            panes: VecDeque::new(),
+            count: 0,
+        })
+    }
+
+    /// Returns the pane size in seconds.
+    pub fn pane_size(&self) -> usize {
+        self.pane
+    }
+
+    /// Appends the next pane of the stream.
+    ///
+    /// # Returns
+    /// The panes of the window that ends with this pane, if any.
+    pub fn push(&mut self, pane: T) -> Option<Vec<&T>> {
+        self.panes.push_back(pane);
+        if self.panes.len() > self.window {
+            self.panes.pop_front();
+        }
+        self.count += 1;
+
+        if self.count >= self.window && (self.count - self.window) % self.hop == 0 {
+            Some(self.panes.iter().collect())
+        } else {
+            None
+        }
+    }
+}
+
+/// Merges the partial aggregates of the panes in a window.
+///
+/// Each pane holds the output partitions of the partial aggregation, one
+/// `RelationPartitions` per relation. The `i`-th partition of the window
+/// contains the `i`-th partitions of all its panes, so that the same keys still
+/// go to the same partition of the final aggregation.
+pub fn merge_panes(panes: &[&Vec<RelationPartitions>]) -> Vec<RelationPartitions> {
+    let num_relations = panes.iter().map(|p| p.len()).max().unwrap_or(0);
+    (0..num_relations)
+        .map(|r| {
+            let num_partitions = panes
+                .iter()
+                .filter_map(|p| p.get(r).map(|partitions| partitions.len()))
+                .max()
+                .unwrap_or(0);
+            (0..num_partitions)
+                .map(|i| {
+                    panes
+                        .iter()
+                        .filter_map(|p| p.get(r).and_then(|partitions| partitions.get(i)))
+                        .flatten()
+                        .cloned()
+                        .collect()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_batches_sorted_eq;
+    use crate::datasink::DataSinkType;
+    use crate::datasource::DataSource;
+    use crate::launcher::{AwsLambdaLauncher, LocalLauncher};
+    use crate::query::{Query, QueryType, StreamType, Table};
+    use crate::state::HashMapStateBackend;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn hopping_panes() -> Result<()> {
+        assert_eq!(gcd(10, 4), 2);
+        assert_eq!(gcd(10, 5), 5);
+        assert!(Panes::<usize>::new(10, 0).is_err());
+
+        // A 10-second window that hops every 4 seconds has 2-second panes.
+        let mut panes = Panes::new(10, 4)?;
+        assert_eq!(panes.pane_size(), 2);
+        let mut windows = vec![];
+        for time in (0..22).step_by(2) {
+            if let Some(window) = panes.push(time) {
+                windows.push(window.into_iter().copied().collect::<Vec<_>>());
+            }
+        }
+        assert_eq!(
+            windows,
+            vec![
+                vec![0, 2, 4, 6, 8],
+                vec![4, 6, 8, 10, 12],
+                vec![8, 10, 12, 14, 16],
+                vec![12, 14, 16, 18, 20],
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn merge_pane_partials() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("k", DataType::Utf8, false),
+            Field::new("v", DataType::Int64, false),
+        ]));
+        let batch = |keys: Vec<&str>, values: Vec<i64>| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(StringArray::from(keys)),
+                    Arc::new(Int64Array::from(values)),
+                ],
+            )
+        };
+        let epochs = vec![
+            batch(vec!["a", "b", "a"], vec![1, 2, 3])?,
+            batch(vec!["b", "c"], vec![4, 5])?,
+            batch(vec!["a", "c", "c"], vec![6, 7, 8])?,
+            batch(vec!["d"], vec![9])?,
+        ];
+
+        let query = Query::new(
+            "SELECT k, COUNT(*), SUM(v), MAX(v) FROM t GROUP BY k",
+            vec![Table("t".to_string(), schema.clone())],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::Streaming(StreamType::NEXMarkBench),
+            Arc::new(HashMapStateBackend::new()),
+        );
+        let mut launcher = AwsLambdaLauncher::new(&query).await?;
+        launcher.create_cloud_contexts(1)?;
+        let stages = launcher.dag.get_all_stages();
+        assert_eq!(stages.len(), 2);
+
+        // A window of 4 epochs that hops every 2 epochs has 2-epoch panes.
+        let mut panes = Panes::new(4, 2)?;
+        let mut ctx = stages[0].context.clone().unwrap();
+        let mut window = None;
+        for pane in epochs.chunks(panes.pane_size()) {
+            ctx.feed_data_sources(vec![vec![pane.to_vec()]]).await?;
+            let output = ctx.execute_partitioned().await?;
+            ctx.clean_data_sources().await?;
+            window = panes.push(output).map(|w| merge_panes(&w));
+        }
+
+        // The final aggregation merges the partial aggregates of the panes.
+        let window = window.unwrap();
+        let mut ctx = stages[1].context.clone().unwrap();
+        let mut batches = vec![];
+        for partition in &window[0] {
+            ctx.feed_data_sources(vec![vec![partition.clone()]]).await?;
+            batches.extend(ctx.execute().await?.into_iter().flatten());
+            ctx.clean_data_sources().await?;
+        }
+
+        let mut launcher = LocalLauncher::new(&query).await?;
+        launcher.feed_data_sources(vec![vec![epochs]]);
+        let expected = pretty_format_batches(&launcher.collect().await?)?.to_string();
+        let expected = expected.trim().lines().collect::<Vec<_>>();
+        assert_batches_sorted_eq!(expected, &batches);
+        Ok(())
+    }
+}
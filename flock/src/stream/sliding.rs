@@ -0,0 +1,429 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Sliding windows continuously aggregate the records of the last window size.
+//!
+//! - If the slide is zero, a window is emitted per record. The window of a
+//!   record at time `t` contains all the records in `(t - size, t]`. The
+//!   records with the same event time share the same window.
+//! - Otherwise, a window is emitted per slide interval. The window that ends at
+//!   `k * slide` contains all the records in `[k * slide - size, k * slide)`.
+//!   The windows without any record are not emitted.
+//!
+//! The relations without the event-time column, such as the dimension tables,
+//! are attached to every window.
+
+use super::firing::Pane;
+use super::watermark::{event_times, EventTime, EventTimeWindow, Watermark};
+use crate::datasource::RelationPartitions;
+use crate::error::{FlockError, Result};
+use datafusion::arrow::array::BooleanArray;
+use datafusion::arrow::compute::filter_record_batch;
+use datafusion::arrow::record_batch::RecordBatch;
+use std::collections::BTreeSet;
+
+/// The buffered records of a relation. Each record batch is tagged with the
+/// per-row states of the window assigner.
+#[derive(Debug)]
+pub(crate) struct RelationBuffer<T> {
+    /// The record batches and their tags.
+    pub(crate) batches: Vec<(RecordBatch, T)>,
+    /// The latest partitions of a relation without the event-time column.
+    pub(crate) fixed:   Option<RelationPartitions>,
+}
+
+impl<T> Default for RelationBuffer<T> {
+    fn default() -> Self {
+        Self {
+            batches: vec![],
+            fixed:   None,
+        }
+    }
+}
+
+impl<T> RelationBuffer<T> {
+    /// Returns true if the relation has no event-time column.
+    pub(crate) fn is_fixed(&self) -> bool {
+        self.fixed.is_some()
+    }
+
+    /// Returns the buffered rows that satisfy the predicate as a single data
+    /// partition. If the relation has no event-time column, its latest data
+    /// partitions are returned.
+    pub(crate) fn select<F>(&self, predicate: F) -> Result<RelationPartitions>
+    where
+        F: Fn(&T, usize) -> bool,
+    {
+        if let Some(fixed) = &self.fixed {
+            return Ok(fixed.clone());
+        }
+        let mut batches = vec![];
+        for (batch, tag) in &self.batches {
+            let mask = (0..batch.num_rows())
+                .map(|i| predicate(tag, i))
+                .collect::<Vec<_>>();
+            if mask.iter().any(|m| *m) {
+                batches.push(filter_record_batch(batch, &BooleanArray::from(mask))?);
+            }
+        }
+        Ok(if batches.is_empty() {
+            vec![]
+        } else {
+            vec![batches]
+        })
+    }
+}
+
+/// Returns true if the data partitions have the event-time column. Empty data
+/// partitions are considered as timed.
+pub(crate) fn is_timed(event_time: &EventTime, partitions: &RelationPartitions) -> bool {
+    partitions
+        .iter()
+        .flatten()
+        .find(|b| b.num_rows() > 0)
+        .map_or(true, |b| event_time.find_column(&b.schema()).is_some())
+}
+
+/// `SlidingWindows` assigns the records to the sliding windows by event time.
+#[derive(Debug)]
+pub struct SlidingWindows {
+    /// The event-time settings.
+    event_time:    EventTime,
+    /// The window size in milliseconds.
+    size:          i64,
+    /// The slide in milliseconds. Zero emits a window per record.
+    slide:         i64,
+    /// The buffered records of the relations tagged with their event times.
+    relations:     Vec<RelationBuffer<Vec<i64>>>,
+    /// The watermark of the windows.
+    watermark:     Watermark,
+    /// The windows that end at or before this time have been emitted.
+    emitted_until: Option<i64>,
+}
+
+impl SlidingWindows {
+    /// Creates a new sliding window assigner.
+    ///
+    /// # Arguments
+    /// * `event_time` - The event-time settings.
+    /// * `window_size` - The window size in seconds.
+    /// * `slide` - The slide in seconds. Zero emits a window per record.
+    /// * `num_relations` - The number of relations in the query.
+    pub fn new(
+        event_time: EventTime,
+        window_size: usize,
+        slide: usize,
+        num_relations: usize,
+    ) -> Result<Self> {
+        if window_size == 0 || slide > window_size {
+            return Err(FlockError::Execution(format!(
+                "Invalid sliding window: size {}s, slide {}s.",
+                window_size, slide
+            )));
+        }
+        Ok(Self {
+            watermark: Watermark::new(event_time.max_out_of_orderness),
+            event_time,
+            size: window_size as i64 * 1000,
+            slide: slide as i64 * 1000,
+            relations: (0..num_relations)
+                .map(|_| RelationBuffer::default())
+                .collect(),
+            emitted_until: None,
+        })
+    }
+
+    /// Buffers the data partitions of the relation.
+    pub fn insert(&mut self, relation: usize, partitions: &RelationPartitions) -> Result<()> {
+        if !is_timed(&self.event_time, partitions) {
+            self.relations[relation].fixed = Some(partitions.clone());
+            return Ok(());
+        }
+        for batch in partitions.iter().flatten() {
+            if batch.num_rows() == 0 {
+                continue;
+            }
+            let times = event_times(batch, &self.event_time.column(&batch.schema())?)?;
+            self.watermark.observe(&times);
+            self.relations[relation]
+                .batches
+                .push((batch.clone(), times));
+        }
+        Ok(())
+    }
+
+    /// Emits the windows that end at or before the watermark.
+    pub fn advance(&mut self) -> Result<Vec<EventTimeWindow>> {
+        match self.watermark.current() {
+            Some(watermark) => self.emit(watermark),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Emits all the remaining windows at the end of the stream.
+    pub fn flush(&mut self) -> Result<Vec<EventTimeWindow>> {
+        let max = match self.event_times().max() {
+            Some(max) => max,
+            None => return Ok(vec![]),
+        };
+        let windows = self.emit(if self.slide == 0 {
+            max
+        } else {
+            max + self.size
+        })?;
+        self.relations.iter_mut().for_each(|r| r.batches.clear());
+        Ok(windows)
+    }
+
+    /// Returns the event times of all the buffered records.
+    fn event_times(&self) -> impl Iterator<Item = i64> + '_ {
+        self.relations
+            .iter()
+            .flat_map(|r| r.batches.iter())
+            .flat_map(|(_, times)| times.iter().copied())
+    }
+
+    /// Returns the bounds `[start, end)` of the windows to emit.
+    fn bounds(&self, until: i64) -> Vec<(i64, i64)> {
+        if self.slide == 0 {
+            self.event_times()
+                .filter(|t| self.emitted_until.map_or(true, |e| *t > e) && *t <= until)
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(|t| (t - self.size + 1, t + 1))
+                .collect()
+        } else {
+            let first = match self.emitted_until {
+                Some(e) => e + self.slide,
+                None => match self.event_times().min() {
+                    Some(min) => (min.div_euclid(self.slide) + 1) * self.slide,
+                    None => return vec![],
+                },
+            };
+            (0..)
+                .map(|k| first + k * self.slide)
+                .take_while(|end| *end <= until)
+                .map(|end| (end - self.size, end))
+                .collect()
+        }
+    }
+
+    /// Emits the windows up to the time, and evicts the records that no
+    /// future window contains.
+    fn emit(&mut self, until: i64) -> Result<Vec<EventTimeWindow>> {
+        let mut windows = vec![];
+        for (start, end) in self.bounds(until) {
+            let relations = self
+                .relations
+                .iter()
+                .map(|r| r.select(|times, i| times[i] >= start && times[i] < end))
+                .collect::<Result<Vec<_>>>()?;
+            if self
+                .relations
+                .iter()
+                .zip(relations.iter())
+                .any(|(r, p)| !r.is_fixed() && !p.is_empty())
+            {
+                windows.push(EventTimeWindow {
+                    start,
+                    end,
+                    relations,
+                    pane: Pane::default(),
+                });
+            }
+        }
+
+        let until = if self.slide == 0 {
+            until
+        } else {
+            until.div_euclid(self.slide) * self.slide
+        };
+        let until = self.emitted_until.map_or(until, |e| e.max(until));
+        self.emitted_until = Some(until);
+
+        let bound = if self.slide == 0 {
+            until - self.size + 1
+        } else {
+            until + self.slide - self.size
+        };
+        for relation in &mut self.relations {
+            relation
+                .batches
+                .retain(|(_, times)| times.iter().any(|t| *t >= bound));
+        }
+
+        Ok(windows)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int64Array, TimestampMillisecondArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    /// Returns the record batch of the rows `(id, key, ts)`.
+    pub(crate) fn batch(rows: &[(i64, i64, i64)]) -> Result<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("key", DataType::Int64, false),
+            Field::new(
+                "ts",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+        ]));
+        Ok(RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(
+                    rows.iter().map(|r| r.0).collect::<Vec<_>>(),
+                )),
+                Arc::new(Int64Array::from(
+                    rows.iter().map(|r| r.1).collect::<Vec<_>>(),
+                )),
+                Arc::new(TimestampMillisecondArray::from(
+                    rows.iter().map(|r| r.2).collect::<Vec<_>>(),
+                )),
+            ],
+        )?)
+    }
+
+    /// Returns the sorted ids of the rows in the data partitions.
+    pub(crate) fn ids(partitions: &RelationPartitions) -> Vec<i64> {
+        let mut ids = partitions
+            .iter()
+            .flatten()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Returns the rows `(id, key, ts)` in the arrival order. The event times
+    /// are out of order by less than `disorder` milliseconds.
+    pub(crate) fn stream(n: i64, keys: i64, disorder: i64, seed: u64) -> Vec<(i64, i64, i64)> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut ts = 0;
+        let mut rows = (0..n)
+            .map(|id| {
+                ts += rng.gen_range(0..400);
+                let jitter = if disorder > 0 {
+                    rng.gen_range(0..disorder)
+                } else {
+                    0
+                };
+                (id, rng.gen_range(0..keys), ts, ts + jitter)
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|r| r.3);
+        rows.into_iter()
+            .map(|(id, key, ts, _)| (id, key, ts))
+            .collect()
+    }
+
+    /// The brute-force reference of the sliding windows.
+    fn reference(rows: &[(i64, i64, i64)], size: i64, slide: i64) -> BTreeMap<i64, Vec<i64>> {
+        let mut windows: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+        if slide == 0 {
+            for (_, _, t) in rows {
+                let ids = rows
+                    .iter()
+                    .filter(|(_, _, u)| *u > t - size && u <= t)
+                    .map(|(id, _, _)| *id)
+                    .collect();
+                windows.insert(t + 1, ids);
+            }
+        } else {
+            let max = rows.iter().map(|r| r.2).max().unwrap();
+            let mut end = slide;
+            while end - size <= max {
+                let ids = rows
+                    .iter()
+                    .filter(|(_, _, t)| *t >= end - size && *t < end)
+                    .map(|(id, _, _)| *id)
+                    .collect::<Vec<_>>();
+                if !ids.is_empty() {
+                    windows.insert(end, ids);
+                }
+                end += slide;
+            }
+        }
+        windows.values_mut().for_each(|ids| ids.sort_unstable());
+        windows
+    }
+
+    fn check_sliding_windows(size: usize, slide: usize, disorder: i64) -> Result<()> {
+        let rows = stream(500, 1, disorder, size as u64 * 10 + slide as u64);
+        let mut event_time = EventTime::new(vec!["ts"]);
+        event_time.max_out_of_orderness = 1;
+        let mut windows = SlidingWindows::new(event_time, size, slide, 2)?;
+
+        let mut emitted = BTreeMap::new();
+        let mut collect = |fired: Vec<EventTimeWindow>| {
+            for window in fired {
+                assert_eq!(window.end - window.start, size as i64 * 1000);
+                // The dimension table is attached to every window.
+                assert_eq!(ids(&window.relations[1]), vec![-1]);
+                assert!(emitted
+                    .insert(window.end, ids(&window.relations[0]))
+                    .is_none());
+            }
+        };
+        let dimension = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)])),
+            vec![Arc::new(Int64Array::from(vec![-1]))],
+        )?;
+        windows.insert(1, &vec![vec![dimension]])?;
+        for chunk in rows.chunks(7) {
+            windows.insert(0, &vec![vec![batch(chunk)?]])?;
+            collect(windows.advance()?);
+        }
+        collect(windows.flush()?);
+
+        assert_eq!(
+            emitted,
+            reference(&rows, size as i64 * 1000, slide as i64 * 1000)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sliding_windows_per_slide() -> Result<()> {
+        check_sliding_windows(3, 1, 0)?;
+        check_sliding_windows(5, 2, 0)?;
+        check_sliding_windows(4, 4, 0)?;
+        // The events are out of order within the bound of the watermark.
+        check_sliding_windows(3, 1, 1000)?;
+        assert!(SlidingWindows::new(EventTime::default(), 2, 3, 1).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sliding_windows_per_record() -> Result<()> {
+        check_sliding_windows(2, 0, 0)?;
+        check_sliding_windows(3, 0, 1000)?;
+        Ok(())
+    }
+}
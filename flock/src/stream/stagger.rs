@@ -0,0 +1,285 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Stagger windows open when the first record of a key arrives, so the windows
+//! of different keys start at different times. The window of a key contains
+//! all the records of the key in `[start, start + size)`. The next record of
+//! the key after the window opens a new window.
+//!
+//! The records are assigned in their arrival order, that is, a record before
+//! the start of the latest window of its key is late.
+
+use super::firing::Pane;
+use super::sliding::{is_timed, RelationBuffer};
+use super::watermark::{event_times, EventTime, EventTimeWindow, Watermark};
+use crate::datasource::RelationPartitions;
+use crate::error::{FlockError, Result};
+use datafusion::arrow::array::BooleanArray;
+use datafusion::arrow::compute::filter_record_batch;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::array_value_to_string;
+use std::collections::{BTreeSet, HashMap};
+
+/// The keys and the window starts of the buffered rows.
+#[derive(Debug)]
+struct StaggerRows {
+    /// The keys of the rows.
+    keys:   Vec<String>,
+    /// The window starts of the rows. Late rows have no window.
+    starts: Vec<Option<i64>>,
+}
+
+/// `StaggerWindows` assigns the records to the stagger windows of their keys.
+#[derive(Debug)]
+pub struct StaggerWindows {
+    /// The event-time settings.
+    event_time: EventTime,
+    /// The name of the key column.
+    key:        String,
+    /// The window size in milliseconds.
+    size:       i64,
+    /// The buffered records of the relations.
+    relations:  Vec<RelationBuffer<StaggerRows>>,
+    /// The watermark of the windows.
+    watermark:  Watermark,
+    /// The start of the open window of each key.
+    open:       HashMap<String, i64>,
+    /// The open windows ordered by the start time.
+    pending:    BTreeSet<(i64, String)>,
+}
+
+impl StaggerWindows {
+    /// Creates a new stagger window assigner.
+    ///
+    /// # Arguments
+    /// * `event_time` - The event-time settings.
+    /// * `key` - The name of the key column.
+    /// * `window_size` - The window size in seconds.
+    /// * `num_relations` - The number of relations in the query.
+    pub fn new(
+        event_time: EventTime,
+        key: &str,
+        window_size: usize,
+        num_relations: usize,
+    ) -> Result<Self> {
+        if window_size == 0 {
+            return Err(FlockError::Execution(
+                "The stagger window size must be positive.".to_string(),
+            ));
+        }
+        Ok(Self {
+            watermark: Watermark::new(event_time.max_out_of_orderness),
+            event_time,
+            key: key.to_owned(),
+            size: window_size as i64 * 1000,
+            relations: (0..num_relations)
+                .map(|_| RelationBuffer::default())
+                .collect(),
+            open: HashMap::new(),
+            pending: BTreeSet::new(),
+        })
+    }
+
+    /// Assigns the data partitions of the relation to the windows.
+    ///
+    /// # Returns
+    /// The late records.
+    pub fn insert(
+        &mut self,
+        relation: usize,
+        partitions: &RelationPartitions,
+    ) -> Result<Vec<RecordBatch>> {
+        if !is_timed(&self.event_time, partitions) {
+            self.relations[relation].fixed = Some(partitions.clone());
+            return Ok(vec![]);
+        }
+        let mut late = vec![];
+        for batch in partitions.iter().flatten() {
+            if batch.num_rows() == 0 {
+                continue;
+            }
+            let times = event_times(batch, &self.event_time.column(&batch.schema())?)?;
+            self.watermark.observe(&times);
+            let column = batch.column(batch.schema().index_of(&self.key)?);
+            let keys = (0..batch.num_rows())
+                .map(|i| array_value_to_string(column, i))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+
+            let mut order = (0..batch.num_rows()).collect::<Vec<_>>();
+            order.sort_by_key(|i| times[*i]);
+            let mut starts = vec![None; batch.num_rows()];
+            for i in order {
+                starts[i] = self.assign(&keys[i], times[i]);
+            }
+
+            if starts.iter().any(|s| s.is_none()) {
+                let mask = starts.iter().map(|s| s.is_none()).collect::<Vec<_>>();
+                late.push(filter_record_batch(batch, &BooleanArray::from(mask))?);
+            }
+            self.relations[relation]
+                .batches
+                .push((batch.clone(), StaggerRows { keys, starts }));
+        }
+        Ok(late)
+    }
+
+    /// Returns the window start of a record, or none if the record is late.
+    fn assign(&mut self, key: &str, time: i64) -> Option<i64> {
+        match self.open.get(key).copied() {
+            Some(start) if time < start => None,
+            Some(start) if time < start + self.size => Some(start),
+            _ => {
+                self.open.insert(key.to_owned(), time);
+                self.pending.insert((time, key.to_owned()));
+                Some(time)
+            }
+        }
+    }
+
+    /// Fires the windows that end at or before the watermark.
+    pub fn advance(&mut self) -> Result<Vec<EventTimeWindow>> {
+        match self.watermark.current() {
+            Some(watermark) => self.fire(watermark),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Fires all the open windows at the end of the stream.
+    pub fn flush(&mut self) -> Result<Vec<EventTimeWindow>> {
+        let windows = self.fire(i64::MAX - self.size)?;
+        self.relations.iter_mut().for_each(|r| r.batches.clear());
+        Ok(windows)
+    }
+
+    /// Fires the windows that end at or before the time, and evicts the
+    /// records of the fired windows.
+    fn fire(&mut self, until: i64) -> Result<Vec<EventTimeWindow>> {
+        let fired = self
+            .pending
+            .iter()
+            .filter(|(start, _)| start + self.size <= until)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut windows = vec![];
+        for (start, key) in fired {
+            let relations = self
+                .relations
+                .iter()
+                .map(|r| r.select(|rows, i| rows.starts[i] == Some(start) && rows.keys[i] == key))
+                .collect::<Result<Vec<_>>>()?;
+            windows.push(EventTimeWindow {
+                start,
+                end: start + self.size,
+                relations,
+                pane: Pane::default(),
+            });
+            self.pending.remove(&(start, key.clone()));
+            if self.open.get(&key) == Some(&start) {
+                self.open.remove(&key);
+            }
+        }
+
+        let size = self.size;
+        for relation in &mut self.relations {
+            relation.batches.retain(|(_, rows)| {
+                rows.starts
+                    .iter()
+                    .any(|s| s.map_or(false, |s| s + size > until))
+            });
+        }
+
+        Ok(windows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::sliding::tests::{batch, ids, stream};
+    use std::collections::BTreeMap;
+
+    /// The brute-force reference of the stagger windows.
+    fn reference(rows: &[(i64, i64, i64)], size: i64) -> BTreeMap<(i64, String), Vec<i64>> {
+        let mut rows = rows.to_vec();
+        rows.sort_by_key(|r| r.2);
+        let mut windows: BTreeMap<(i64, String), Vec<i64>> = BTreeMap::new();
+        let mut open: HashMap<i64, i64> = HashMap::new();
+        for (id, key, t) in rows {
+            let start = match open.get(&key) {
+                Some(start) if t < start + size => *start,
+                _ => t,
+            };
+            open.insert(key, start);
+            windows
+                .entry((start, key.to_string()))
+                .or_default()
+                .push(id);
+        }
+        windows.values_mut().for_each(|ids| ids.sort_unstable());
+        windows
+    }
+
+    #[tokio::test]
+    async fn stagger_windows() -> Result<()> {
+        let rows = stream(500, 5, 0, 42);
+        let mut windows = StaggerWindows::new(EventTime::new(vec!["ts"]), "key", 2, 1)?;
+
+        let mut emitted = BTreeMap::new();
+        let mut collect = |fired: Vec<EventTimeWindow>| {
+            for window in fired {
+                let key = window.relations[0][0][0]
+                    .column(1)
+                    .as_any()
+                    .downcast_ref::<datafusion::arrow::array::Int64Array>()
+                    .unwrap()
+                    .value(0);
+                assert!(emitted
+                    .insert((window.start, key.to_string()), ids(&window.relations[0]))
+                    .is_none());
+            }
+        };
+        for chunk in rows.chunks(9) {
+            assert!(windows.insert(0, &vec![vec![batch(chunk)?]])?.is_empty());
+            collect(windows.advance()?);
+        }
+        collect(windows.flush()?);
+
+        assert_eq!(emitted, reference(&rows, 2000));
+        assert!(StaggerWindows::new(EventTime::default(), "key", 0, 1).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stagger_late_records() -> Result<()> {
+        let mut windows = StaggerWindows::new(EventTime::new(vec!["ts"]), "key", 2, 1)?;
+        windows.insert(0, &vec![vec![batch(&[(0, 1, 1_000), (1, 2, 1_500)])?]])?;
+        // The record arrives before the window of its key starts.
+        let late = windows.insert(
+            0,
+            &vec![vec![batch(&[(2, 1, 500), (3, 1, 2_999), (4, 2, 3_000)])?]],
+        )?;
+        assert_eq!(ids(&vec![late]), vec![2]);
+
+        let fired = windows.advance()?;
+        assert_eq!(fired.len(), 1);
+        assert_eq!((fired[0].start, fired[0].end), (1_000, 3_000));
+        assert_eq!(ids(&fired[0].relations[0]), vec![0, 3]);
+
+        let fired = windows.flush()?;
+        assert_eq!(fired.len(), 1);
+        assert_eq!((fired[0].start, fired[0].end), (1_500, 3_500));
+        assert_eq!(ids(&fired[0].relations[0]), vec![1, 4]);
+        Ok(())
+    }
+}
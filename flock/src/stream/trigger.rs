@@ -0,0 +1,687 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! A trigger decides when a window fires. The schedules of the windows are
+//! parsed into triggers:
+//!
+//! - `Seconds(n)` and `Rate(expr)` fire the window at a fixed wall-clock
+//!   interval.
+//! - `Cron(expr)` fires the window at the wall-clock times of the cron
+//!   expression, for example, `cron(0 0 * * ? *)` fires every day at 00:00 UTC.
+//! - `Rows(n)` fires the window every `n` records, either per key or globally.
+
+use super::sliding::RelationBuffer;
+use super::window::Schedule;
+use crate::datasource::RelationPartitions;
+use crate::error::{FlockError, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use datafusion::arrow::array::UInt32Array;
+use datafusion::arrow::compute::take;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::array_value_to_string;
+use std::collections::{BTreeSet, HashMap};
+
+/// The month names in cron expressions.
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+/// The day-of-week names in cron expressions. Sunday is 1.
+const DAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// The day-of-month field of a cron expression.
+#[derive(Debug, Clone, PartialEq)]
+enum DayOfMonth {
+    /// Any day of the month.
+    Any,
+    /// The days of the month.
+    Days(BTreeSet<u32>),
+    /// The last day of the month (`L`).
+    Last,
+}
+
+/// The day-of-week field of a cron expression.
+#[derive(Debug, Clone, PartialEq)]
+enum DayOfWeek {
+    /// Any day of the week.
+    Any,
+    /// The days of the week. Sunday is 1.
+    Days(BTreeSet<u32>),
+    /// The n-th day of the week in the month (`day#n`).
+    Nth(u32, u32),
+}
+
+/// A cron expression in the format of Amazon EventBridge:
+/// `Minutes Hours Day-of-month Month Day-of-week Year`.
+///
+/// The fields support the wildcards `*`, `?`, `,`, `-` and `/`. The
+/// day-of-month field also supports `L`, and the day-of-week field supports
+/// `#`. One of the day-of-month and the day-of-week fields must be `?`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronExpr {
+    minutes:       BTreeSet<u32>,
+    hours:         BTreeSet<u32>,
+    days_of_month: DayOfMonth,
+    months:        BTreeSet<u32>,
+    days_of_week:  DayOfWeek,
+    years:         Option<BTreeSet<u32>>,
+}
+
+impl CronExpr {
+    /// Parses the cron expression, with or without the `cron(...)` wrapper.
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = unwrap_expr(expr, "cron");
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 6 {
+            return Err(invalid("cron", expr));
+        }
+        if (fields[2] == "?") == (fields[4] == "?") {
+            return Err(FlockError::Execution(format!(
+                "One of the day-of-month and day-of-week fields must be '?': {}",
+                expr
+            )));
+        }
+
+        let days_of_month = match fields[2] {
+            "?" | "*" => DayOfMonth::Any,
+            "L" => DayOfMonth::Last,
+            field => DayOfMonth::Days(parse_field(field, 1, 31, &[])?),
+        };
+        let days_of_week = match fields[4] {
+            "?" | "*" => DayOfWeek::Any,
+            field if field.contains('#') => {
+                let (day, nth) = field.split_once('#').unwrap();
+                let day = parse_value(day, 1, 7, &DAYS)?;
+                let nth = nth.parse::<u32>().map_err(|_| invalid("cron", expr))?;
+                if !(1..=5).contains(&nth) {
+                    return Err(invalid("cron", expr));
+                }
+                DayOfWeek::Nth(day, nth)
+            }
+            field => DayOfWeek::Days(parse_field(field, 1, 7, &DAYS)?),
+        };
+
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days_of_month,
+            months: parse_field(fields[3], 1, 12, &MONTHS)?,
+            days_of_week,
+            years: match fields[5] {
+                "*" | "?" => None,
+                field => Some(parse_field(field, 1970, 2199, &[])?),
+            },
+        })
+    }
+
+    /// Returns true if the cron expression matches the date.
+    fn matches_date(&self, date: &NaiveDate) -> bool {
+        let year = date.year() as u32;
+        if !self.months.contains(&date.month())
+            || !self.years.as_ref().map_or(true, |y| y.contains(&year))
+        {
+            return false;
+        }
+        let day_of_month = match &self.days_of_month {
+            DayOfMonth::Any => true,
+            DayOfMonth::Days(days) => days.contains(&date.day()),
+            DayOfMonth::Last => date.succ().month() != date.month(),
+        };
+        let weekday = date.weekday().number_from_sunday();
+        let day_of_week = match &self.days_of_week {
+            DayOfWeek::Any => true,
+            DayOfWeek::Days(days) => days.contains(&weekday),
+            DayOfWeek::Nth(day, nth) => *day == weekday && (date.day() - 1) / 7 + 1 == *nth,
+        };
+        day_of_month && day_of_week
+    }
+
+    /// Returns the first time that matches the cron expression after the time.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut date = time.date().naive_utc();
+        let last_year = self
+            .years
+            .as_ref()
+            .map_or(2199, |y| *y.iter().next_back().unwrap());
+        while date.year() as u32 <= last_year {
+            if self.matches_date(&date) {
+                for hour in &self.hours {
+                    for minute in &self.minutes {
+                        let next = Utc.from_utc_datetime(&date.and_hms(*hour, *minute, 0));
+                        if next > time {
+                            return Some(next);
+                        }
+                    }
+                }
+            }
+            date = date.succ();
+        }
+        None
+    }
+}
+
+/// Removes the `name(...)` wrapper of the schedule expression.
+fn unwrap_expr<'a>(expr: &'a str, name: &str) -> &'a str {
+    let expr = expr.trim();
+    expr.strip_prefix(name)
+        .and_then(|e| e.trim_start().strip_prefix('('))
+        .and_then(|e| e.strip_suffix(')'))
+        .unwrap_or(expr)
+        .trim()
+}
+
+fn invalid(kind: &str, expr: &str) -> FlockError {
+    FlockError::Execution(format!("Invalid {} expression: {}", kind, expr))
+}
+
+/// Parses a value of a cron field, which is a number or a name.
+fn parse_value(value: &str, min: u32, max: u32, names: &[&str]) -> Result<u32> {
+    let value = match names.iter().position(|n| n.eq_ignore_ascii_case(value)) {
+        Some(i) => i as u32 + min,
+        None => value.parse::<u32>().map_err(|_| invalid("cron", value))?,
+    };
+    if value < min || value > max {
+        return Err(invalid("cron", &value.to_string()));
+    }
+    Ok(value)
+}
+
+/// Parses a cron field into the set of its values.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<BTreeSet<u32>> {
+    let mut values = BTreeSet::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| invalid("cron", field))?,
+            ),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((a, b)) => (
+                    parse_value(a, min, max, names)?,
+                    parse_value(b, min, max, names)?,
+                ),
+                // `a/b` starts at `a` and repeats until the maximum.
+                None if step > 1 || part.contains('/') => {
+                    (parse_value(range, min, max, names)?, max)
+                }
+                None => {
+                    let value = parse_value(range, min, max, names)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            return Err(invalid("cron", field));
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    Ok(values)
+}
+
+/// Parses a rate expression such as `rate(5 minutes)` into the interval. For a
+/// singular value, the unit must be singular, otherwise plural.
+pub fn parse_rate(expr: &str) -> Result<Duration> {
+    let body = unwrap_expr(expr, "rate");
+    let (value, unit) = body
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| invalid("rate", expr))?;
+    let value = value
+        .parse::<i64>()
+        .ok()
+        .filter(|v| *v > 0)
+        .ok_or_else(|| invalid("rate", expr))?;
+    let unit = unit.trim().to_lowercase();
+    let singular = unit.strip_suffix('s').unwrap_or(&unit);
+    if (value == 1) != (singular == unit) {
+        return Err(invalid("rate", expr));
+    }
+    match singular {
+        "second" => Ok(Duration::seconds(value)),
+        "minute" => Ok(Duration::minutes(value)),
+        "hour" => Ok(Duration::hours(value)),
+        "day" => Ok(Duration::days(value)),
+        _ => Err(invalid("rate", expr)),
+    }
+}
+
+/// When a window fires.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// Fires at a fixed wall-clock interval.
+    Interval(Duration),
+    /// Fires at the wall-clock times of the cron expression.
+    Cron(CronExpr),
+    /// Fires every fixed number of records.
+    Count(usize),
+}
+
+impl Trigger {
+    /// Returns true if the trigger fires by the wall clock.
+    pub fn is_wall_clock(&self) -> bool {
+        !matches!(self, Trigger::Count(_))
+    }
+
+    /// Returns the next wall-clock time to fire after the time, or none if
+    /// the trigger doesn't fire by the wall clock, or will never fire again.
+    pub fn next_fire(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Trigger::Interval(interval) => Some(time + *interval),
+            Trigger::Cron(cron) => cron.next_after(time),
+            Trigger::Count(_) => None,
+        }
+    }
+}
+
+impl Schedule {
+    /// Parses the schedule into the trigger of the window.
+    pub fn trigger(&self) -> Result<Trigger> {
+        match self {
+            Schedule::Seconds(0) | Schedule::Rows(0) => Err(FlockError::Execution(format!(
+                "The schedule must be positive: {:?}",
+                self
+            ))),
+            Schedule::Seconds(sec) => Ok(Trigger::Interval(Duration::seconds(*sec as i64))),
+            Schedule::Rate(expr) => Ok(Trigger::Interval(parse_rate(expr)?)),
+            Schedule::Cron(expr) => Ok(Trigger::Cron(CronExpr::parse(expr)?)),
+            Schedule::Rows(rows) => Ok(Trigger::Count(*rows)),
+        }
+    }
+}
+
+/// Returns the rows of the record batch at the indices.
+fn take_rows(batch: &RecordBatch, indices: &[u32]) -> Result<RecordBatch> {
+    let indices = UInt32Array::from(indices.to_vec());
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|c| take(c.as_ref(), &indices, None))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+/// `TriggeredWindows` assigns the records to the windows of a trigger.
+///
+/// A wall-clock trigger fires all the buffered records of the relations at
+/// once. A count trigger fires the records of the first relation every `n`
+/// records, per key if the key column is given, otherwise globally. The other
+/// relations of a count trigger, such as the dimension tables, are attached to
+/// every window with their latest data partitions.
+#[derive(Debug)]
+pub struct TriggeredWindows {
+    /// The trigger of the windows.
+    trigger:   Trigger,
+    /// The key column of the count windows.
+    key:       Option<String>,
+    /// The next wall-clock time to fire.
+    next_fire: Option<DateTime<Utc>>,
+    /// The buffered data partitions of the relations.
+    relations: Vec<RelationBuffer<()>>,
+    /// The number of records and the records of the open count windows.
+    counts:    HashMap<String, (usize, Vec<RecordBatch>)>,
+}
+
+impl TriggeredWindows {
+    /// Creates a new triggered window assigner.
+    ///
+    /// # Arguments
+    /// * `trigger` - The trigger of the windows.
+    /// * `key` - The key column of the count windows.
+    /// * `num_relations` - The number of relations in the query.
+    /// * `now` - The wall-clock time when the first window opens.
+    pub fn new(
+        trigger: Trigger,
+        key: Option<String>,
+        num_relations: usize,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            next_fire: trigger.next_fire(now),
+            trigger,
+            key,
+            relations: (0..num_relations)
+                .map(|_| RelationBuffer::default())
+                .collect(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Returns the trigger of the windows.
+    pub fn trigger(&self) -> &Trigger {
+        &self.trigger
+    }
+
+    /// Assigns the data partitions of the relation to the windows.
+    ///
+    /// # Returns
+    /// The count windows that are full.
+    pub fn insert(
+        &mut self,
+        relation: usize,
+        partitions: &RelationPartitions,
+    ) -> Result<Vec<Vec<RelationPartitions>>> {
+        let size = match self.trigger {
+            Trigger::Count(size) if relation == 0 => size,
+            Trigger::Count(_) => {
+                if partitions.iter().flatten().any(|b| b.num_rows() > 0) {
+                    self.relations[relation].fixed = Some(partitions.clone());
+                }
+                return Ok(vec![]);
+            }
+            _ => {
+                let buffer = &mut self.relations[relation].batches;
+                for batch in partitions.iter().flatten().filter(|b| b.num_rows() > 0) {
+                    buffer.push((batch.clone(), ()));
+                }
+                return Ok(vec![]);
+            }
+        };
+
+        let mut windows = vec![];
+        for batch in partitions.iter().flatten() {
+            let keys = match &self.key {
+                Some(key) => {
+                    let column = batch.column(batch.schema().index_of(key)?);
+                    (0..batch.num_rows())
+                        .map(|i| array_value_to_string(column, i))
+                        .collect::<std::result::Result<Vec<_>, _>>()?
+                }
+                None => vec![String::new(); batch.num_rows()],
+            };
+
+            // The rows of the current batch in the open window of each key.
+            let mut rows: HashMap<&str, Vec<u32>> = HashMap::new();
+            for (i, key) in keys.iter().enumerate() {
+                let indices = rows.entry(key).or_default();
+                indices.push(i as u32);
+                let (count, batches) = self.counts.entry(key.to_owned()).or_default();
+                *count += 1;
+                if *count == size {
+                    batches.push(take_rows(batch, indices)?);
+                    indices.clear();
+                    let (_, batches) = self.counts.remove(key).unwrap();
+                    windows.push(self.window(batches));
+                }
+            }
+            for (key, indices) in rows.into_iter().filter(|(_, i)| !i.is_empty()) {
+                let batch = take_rows(batch, &indices)?;
+                self.counts.get_mut(key).unwrap().1.push(batch);
+            }
+        }
+        Ok(windows)
+    }
+
+    /// Fires the wall-clock window if its time has come.
+    ///
+    /// # Returns
+    /// The data partitions of the relations in the window, or none if the
+    /// trigger doesn't fire or the window is empty.
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Option<Vec<RelationPartitions>> {
+        match self.next_fire {
+            Some(next_fire) if now >= next_fire => {
+                // Skips the missed fire times, for example, after a long pause.
+                self.next_fire = self.trigger.next_fire(next_fire);
+                while self.next_fire.map_or(false, |t| t <= now) {
+                    self.next_fire = self.trigger.next_fire(self.next_fire.unwrap());
+                }
+                self.take()
+            }
+            _ => None,
+        }
+    }
+
+    /// Fires all the open windows at the end of the stream.
+    pub fn flush(&mut self) -> Vec<Vec<RelationPartitions>> {
+        if self.trigger.is_wall_clock() {
+            return self.take().into_iter().collect();
+        }
+        let mut keys = self.counts.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        keys.into_iter()
+            .map(|key| {
+                let (_, batches) = self.counts.remove(&key).unwrap();
+                self.window(batches)
+            })
+            .collect()
+    }
+
+    /// Returns the count window of the records with the other relations.
+    fn window(&self, batches: Vec<RecordBatch>) -> Vec<RelationPartitions> {
+        let mut relations = vec![vec![batches]];
+        relations.extend(
+            self.relations
+                .iter()
+                .skip(1)
+                .map(|r| r.fixed.clone().unwrap_or_default()),
+        );
+        relations
+    }
+
+    /// Takes all the buffered records of the wall-clock window.
+    fn take(&mut self) -> Option<Vec<RelationPartitions>> {
+        if self.relations.iter().all(|r| r.batches.is_empty()) {
+            return None;
+        }
+        Some(
+            self.relations
+                .iter_mut()
+                .map(|r| {
+                    let batches = std::mem::take(&mut r.batches)
+                        .into_iter()
+                        .map(|(b, _)| b)
+                        .collect::<Vec<_>>();
+                    if batches.is_empty() {
+                        vec![]
+                    } else {
+                        vec![batches]
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn next(cron: &str, after: &str) -> Result<String> {
+        Ok(CronExpr::parse(cron)?
+            .next_after(time(after))
+            .unwrap()
+            .to_rfc3339())
+    }
+
+    #[tokio::test]
+    async fn parse_schedules() -> Result<()> {
+        assert_eq!(parse_rate("rate(5 minutes)")?, Duration::minutes(5));
+        assert_eq!(parse_rate("1 hour")?, Duration::hours(1));
+        assert_eq!(parse_rate("rate(7 days)")?, Duration::days(7));
+        assert_eq!(parse_rate("rate(1 second)")?, Duration::seconds(1));
+        assert!(parse_rate("rate(1 hours)").is_err());
+        assert!(parse_rate("rate(5 minute)").is_err());
+        assert!(parse_rate("rate(0 minutes)").is_err());
+        assert!(parse_rate("rate(5 weeks)").is_err());
+
+        assert_eq!(Schedule::Rows(100).trigger()?, Trigger::Count(100),);
+        assert_eq!(
+            Schedule::Seconds(10).trigger()?,
+            Trigger::Interval(Duration::seconds(10))
+        );
+        assert!(Schedule::Rows(0).trigger().is_err());
+        assert!(Schedule::Cron("cron(0 0 * * * *)".to_string())
+            .trigger()
+            .is_err());
+        assert!(Schedule::Cron("cron(0 0 * *)".to_string())
+            .trigger()
+            .is_err());
+        assert!(Schedule::Cron("cron(60 0 * * ? *)".to_string())
+            .trigger()
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cron_expressions() -> Result<()> {
+        // Every day at 00:00 UTC.
+        assert_eq!(
+            next("cron(0 0 * * ? *)", "2021-01-01T12:00:00Z")?,
+            "2021-01-02T00:00:00+00:00"
+        );
+        // 10:15 AM every day.
+        assert_eq!(
+            next("cron(15 10 * * ? *)", "2021-01-01T10:15:00Z")?,
+            "2021-01-02T10:15:00+00:00"
+        );
+        // 6:00 PM Monday through Friday. 2021-01-01 is a Friday.
+        assert_eq!(
+            next("cron(0 18 ? * MON-FRI *)", "2021-01-01T18:30:00Z")?,
+            "2021-01-04T18:00:00+00:00"
+        );
+        // 8:00 AM on the first day of the month.
+        assert_eq!(
+            next("cron(0 8 1 * ? *)", "2021-01-01T09:00:00Z")?,
+            "2021-02-01T08:00:00+00:00"
+        );
+        // Every 10 min on weekdays.
+        assert_eq!(
+            next("cron(0/10 * ? * MON-FRI *)", "2021-01-01T23:55:00Z")?,
+            "2021-01-04T00:00:00+00:00"
+        );
+        // Every 5 minutes between 8:00 AM and 5:55 PM weekdays.
+        assert_eq!(
+            next("cron(0/5 8-17 ? * MON-FRI *)", "2021-01-04T17:55:00Z")?,
+            "2021-01-05T08:00:00+00:00"
+        );
+        // 9:00 AM on the first Monday of each month.
+        assert_eq!(
+            next("cron(0 9 ? * 2#1 *)", "2021-01-05T00:00:00Z")?,
+            "2021-02-01T09:00:00+00:00"
+        );
+        // The last day of the month.
+        assert_eq!(
+            next("cron(0 0 L * ? *)", "2021-02-01T00:00:00Z")?,
+            "2021-02-28T00:00:00+00:00"
+        );
+        // The year is bounded.
+        assert!(CronExpr::parse("cron(0 0 1 JAN ? 2020)")?
+            .next_after(time("2021-01-01T00:00:00Z"))
+            .is_none());
+        Ok(())
+    }
+
+    fn batch(keys: &[&str], values: &[i64]) -> Result<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        Ok(RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(keys.to_vec())),
+                Arc::new(Int64Array::from(values.to_vec())),
+            ],
+        )?)
+    }
+
+    fn values(partitions: &RelationPartitions) -> Vec<i64> {
+        partitions
+            .iter()
+            .flatten()
+            .flat_map(|b| {
+                b.column(1)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn count_windows() -> Result<()> {
+        let now = time("2021-01-01T00:00:00Z");
+        let mut windows = TriggeredWindows::new(Trigger::Count(3), None, 1, now);
+        let fired = windows.insert(0, &vec![vec![batch(&["a"; 5], &[1, 2, 3, 4, 5])?]])?;
+        assert_eq!(fired.len(), 1);
+        assert_eq!(values(&fired[0][0]), vec![1, 2, 3]);
+        assert!(windows.tick(now + Duration::days(1)).is_none());
+        let fired = windows.insert(0, &vec![vec![batch(&["a"; 2], &[6, 7])?]])?;
+        assert_eq!(values(&fired[0][0]), vec![4, 5, 6]);
+        let fired = windows.flush();
+        assert_eq!(values(&fired[0][0]), vec![7]);
+
+        // The count windows per key.
+        let mut windows = TriggeredWindows::new(Trigger::Count(2), Some("key".to_owned()), 2, now);
+        windows.insert(1, &vec![vec![batch(&["dim"], &[0])?]])?;
+        let fired = windows.insert(
+            0,
+            &vec![vec![batch(
+                &["a", "b", "a", "c", "b", "a"],
+                &[1, 2, 3, 4, 5, 6],
+            )?]],
+        )?;
+        assert_eq!(
+            fired
+                .iter()
+                .map(|w| (values(&w[0]), values(&w[1])))
+                .collect::<Vec<_>>(),
+            vec![(vec![1, 3], vec![0]), (vec![2, 5], vec![0])]
+        );
+        let fired = windows.flush();
+        assert_eq!(
+            fired.iter().map(|w| values(&w[0])).collect::<Vec<_>>(),
+            vec![vec![6], vec![4]]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wall_clock_windows() -> Result<()> {
+        let now = time("2021-01-01T23:59:00Z");
+        let trigger = Schedule::Cron("cron(0 0 * * ? *)".to_owned()).trigger()?;
+        let mut windows = TriggeredWindows::new(trigger, None, 2, now);
+        assert!(windows
+            .insert(0, &vec![vec![batch(&["a"], &[1])?]])?
+            .is_empty());
+        assert!(windows.tick(now + Duration::seconds(59)).is_none());
+        windows.insert(1, &vec![vec![batch(&["b"], &[2])?]])?;
+
+        let fired = windows.tick(now + Duration::seconds(60)).unwrap();
+        assert_eq!((values(&fired[0]), values(&fired[1])), (vec![1], vec![2]));
+        // The next window fires at the next midnight.
+        windows.insert(0, &vec![vec![batch(&["a"], &[3])?]])?;
+        assert!(windows.tick(now + Duration::hours(23)).is_none());
+        let fired = windows
+            .tick(now + Duration::days(1) + Duration::seconds(60))
+            .unwrap();
+        assert_eq!(values(&fired[0]), vec![3]);
+        assert!(fired[1].is_empty());
+        assert!(windows.flush().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! The windowing table-valued functions describe the window of a query in its
+//! SQL text:
+//!
+//! ```sql
+//! SELECT window_start, window_end, COUNT(*)
+//! FROM   TUMBLE(TABLE bid, DESCRIPTOR(b_date_time), INTERVAL '10' SECOND)
+//! GROUP  BY window_start, window_end;
+//! ```
+//!
+//! - `TUMBLE(TABLE t, DESCRIPTOR(ts), size)` is an event-time tumbling window.
+//! - `HOP(TABLE t, DESCRIPTOR(ts), slide, size)` is an event-time hopping
+//!   window.
+//! - `SESSION(TABLE t PARTITION BY key, DESCRIPTOR(ts), gap)` is a session
+//!   window of each key.
+//!
+//! The function is replaced by its table in the SQL, and the table is extended
+//! with the `window_start` and `window_end` columns, which are filled in by the
+//! runtime when the window fires.
+
+use super::watermark::EventTime;
+use super::window::{event_time_window, hopping_window, session_window, tumbling_window, Window};
+use crate::datasource::RelationPartitions;
+use crate::error::{FlockError, Result};
+use datafusion::arrow::array::TimestampMillisecondArray;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use sqlparser::ast::{Expr, Value};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::parser::{Parser, ParserError};
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The column of the window start in milliseconds (inclusive).
+pub const WINDOW_START: &str = "window_start";
+/// The column of the window end in milliseconds (exclusive).
+pub const WINDOW_END: &str = "window_end";
+
+/// A windowing table-valued function in the SQL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowFunction {
+    /// The table to apply the window to.
+    pub table:       String,
+    /// The event-time column of the table.
+    pub time_column: String,
+    /// The key column of the session window.
+    pub key:         Option<String>,
+    /// The window of the table.
+    pub window:      Window,
+}
+
+impl WindowFunction {
+    /// Returns the schema of the table with the window columns.
+    pub fn extend_schema(&self, schema: &Schema) -> Schema {
+        let mut fields = schema.fields().clone();
+        for name in [WINDOW_START, WINDOW_END] {
+            if schema.field_with_name(name).is_err() {
+                fields.push(Field::new(
+                    name,
+                    DataType::Timestamp(TimeUnit::Millisecond, None),
+                    false,
+                ));
+            }
+        }
+        Schema::new(fields)
+    }
+
+    /// Adds the settings of the window to the payload's metadata, so that the
+    /// data source function fills in the window columns.
+    pub fn to_metadata(&self, metadata: &mut HashMap<String, String>) {
+        metadata.insert("window_columns".to_string(), "true".to_string());
+        metadata.insert("session_time".to_string(), self.time_column.clone());
+        if let Some(key) = &self.key {
+            metadata.insert("session_key".to_string(), key.clone());
+            metadata.insert("session_name".to_string(), self.table.clone());
+        }
+    }
+}
+
+fn sql_error<T: Into<String>>(message: T) -> FlockError {
+    FlockError::SQL(ParserError::ParserError(message.into()))
+}
+
+/// Returns the interval in seconds, such as `INTERVAL '10' SECOND` or
+/// `INTERVAL '1 minute'`.
+fn interval_seconds(expr: &Expr) -> Result<usize> {
+    let (value, leading_field) = match expr {
+        Expr::Value(Value::Interval {
+            value,
+            leading_field,
+            ..
+        }) => (value.trim().to_lowercase(), leading_field.clone()),
+        _ => return Err(sql_error(format!("Expected an interval, found: {}", expr))),
+    };
+    let (value, unit) = match leading_field {
+        Some(field) => (value, format!("{}", field).to_lowercase()),
+        None => match value.split_once(char::is_whitespace) {
+            Some((value, unit)) => (value.to_owned(), unit.trim().to_owned()),
+            None => (value, "second".to_owned()),
+        },
+    };
+    let scale = match unit.strip_suffix('s').unwrap_or(&unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        _ => return Err(sql_error(format!("Unsupported interval: {}", expr))),
+    };
+    match value.parse::<usize>() {
+        Ok(value) if value > 0 => Ok(value * scale),
+        _ => Err(sql_error(format!(
+            "The interval must be a positive number of seconds: {}",
+            expr
+        ))),
+    }
+}
+
+/// Parses the arguments of the windowing table-valued function.
+fn parse_window_function(name: &str, tokens: Vec<Token>) -> Result<WindowFunction> {
+    let dialect = GenericDialect {};
+    let mut parser = Parser::new(tokens, &dialect);
+    if !parser.parse_keyword(Keyword::TABLE) {
+        return Err(sql_error(format!("{} expects a TABLE argument.", name)));
+    }
+    let table = parser.parse_object_name()?.to_string();
+    let key = if parser.parse_keywords(&[Keyword::PARTITION, Keyword::BY]) {
+        Some(parser.parse_identifier()?.value)
+    } else {
+        None
+    };
+    parser.expect_token(&Token::Comma)?;
+    match parser.next_token() {
+        Token::Word(w) if w.value.eq_ignore_ascii_case("DESCRIPTOR") => {}
+        token => return Err(sql_error(format!("Expected DESCRIPTOR, found: {}", token))),
+    }
+    parser.expect_token(&Token::LParen)?;
+    let time_column = parser.parse_identifier()?.value;
+    parser.expect_token(&Token::RParen)?;
+
+    let mut intervals = vec![];
+    while parser.consume_token(&Token::Comma) {
+        intervals.push(interval_seconds(&parser.parse_expr()?)?);
+    }
+    if parser.peek_token() != Token::EOF {
+        return Err(sql_error(format!(
+            "Unexpected argument of {}: {}",
+            name,
+            parser.peek_token()
+        )));
+    }
+
+    let event_time = EventTime::new(vec![time_column.clone()]);
+    let window = match (name, &intervals[..], &key) {
+        ("TUMBLE", [size], None) => event_time_window(tumbling_window(*size), event_time),
+        ("HOP", [slide, size], None) if slide <= size => {
+            event_time_window(hopping_window(*size, *slide), event_time)
+        }
+        ("SESSION", [gap], Some(_)) => session_window(*gap),
+        ("SESSION", [_], None) => {
+            return Err(sql_error("SESSION expects a PARTITION BY key."));
+        }
+        _ => {
+            return Err(sql_error(format!(
+                "Invalid arguments of {}: {:?}",
+                name, intervals
+            )))
+        }
+    };
+
+    Ok(WindowFunction {
+        table,
+        time_column,
+        key,
+        window,
+    })
+}
+
+/// Returns the index of the next token that is not a whitespace.
+fn next_token(tokens: &[Token], from: usize) -> Option<usize> {
+    (from..tokens.len()).find(|i| !matches!(tokens[*i], Token::Whitespace(_)))
+}
+
+/// Replaces the windowing table-valued functions in the SQL with their tables.
+///
+/// # Returns
+/// The rewritten SQL, and the window functions in the order of appearance.
+pub fn rewrite_window_functions(sql: &str) -> Result<(String, Vec<WindowFunction>)> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .map_err(|e| FlockError::SQL(ParserError::TokenizerError(format!("{:?}", e))))?;
+
+    let mut rewritten = String::new();
+    let mut functions = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let name = match &tokens[i] {
+            Token::Word(w) if w.quote_style.is_none() => w.value.to_uppercase(),
+            token => {
+                rewritten.push_str(&token.to_string());
+                i += 1;
+                continue;
+            }
+        };
+        let lparen = next_token(&tokens, i + 1).filter(|j| tokens[*j] == Token::LParen);
+        let is_table = lparen.and_then(|j| next_token(&tokens, j + 1)).map_or(
+            false,
+            |j| matches!(&tokens[j], Token::Word(w) if w.keyword == Keyword::TABLE),
+        );
+        if !["TUMBLE", "HOP", "SESSION"].contains(&name.as_str()) || !is_table {
+            rewritten.push_str(&tokens[i].to_string());
+            i += 1;
+            continue;
+        }
+
+        // Finds the matching right parenthesis of the function.
+        let lparen = lparen.unwrap();
+        let mut depth = 0;
+        let mut rparen = None;
+        for (j, token) in tokens.iter().enumerate().skip(lparen) {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        rparen = Some(j);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let rparen = rparen.ok_or_else(|| sql_error(format!("Unclosed {}(...)", name)))?;
+
+        let function = parse_window_function(&name, tokens[lparen + 1..rparen].to_vec())?;
+        rewritten.push_str(&function.table);
+        functions.push(function);
+        i = rparen + 1;
+    }
+
+    Ok((rewritten, functions))
+}
+
+/// Appends the window columns to the data partitions of a fired window.
+///
+/// # Arguments
+/// * `partitions` - The data partitions of a relation in the window.
+/// * `start` - The start of the window in milliseconds.
+/// * `end` - The end of the window in milliseconds.
+pub fn append_window_columns(
+    partitions: &RelationPartitions,
+    start: i64,
+    end: i64,
+) -> Result<RelationPartitions> {
+    partitions
+        .iter()
+        .map(|partition| {
+            partition
+                .iter()
+                .map(|batch| {
+                    let schema = batch.schema();
+                    if schema.field_with_name(WINDOW_START).is_ok() {
+                        return Ok(batch.clone());
+                    }
+                    let mut fields = schema.fields().clone();
+                    let mut columns = batch.columns().to_vec();
+                    for (name, time) in [(WINDOW_START, start), (WINDOW_END, end)] {
+                        fields.push(Field::new(
+                            name,
+                            DataType::Timestamp(TimeUnit::Millisecond, None),
+                            false,
+                        ));
+                        columns.push(Arc::new(TimestampMillisecondArray::from(vec![
+                            time;
+                            batch
+                                .num_rows(
+                                )
+                        ])));
+                    }
+                    Ok(RecordBatch::try_new(
+                        Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone())),
+                        columns,
+                    )?)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assert_batches_eq;
+    use crate::datasink::DataSinkType;
+    use crate::datasource::DataSource;
+    use crate::launcher::{Launcher, LocalLauncher};
+    use crate::query::{Query, QueryType, Table};
+    use crate::state::HashMapStateBackend;
+    use crate::stream::EventTimeWindows;
+    use datafusion::arrow::array::Int64Array;
+
+    #[tokio::test]
+    async fn parse_window_functions() -> Result<()> {
+        let (sql, functions) = rewrite_window_functions(
+            "SELECT window_start, COUNT(*) FROM TUMBLE(TABLE bid, DESCRIPTOR(b_date_time), \
+             INTERVAL '10' SECOND) AS b GROUP BY window_start",
+        )?;
+        assert_eq!(
+            sql,
+            "SELECT window_start, COUNT(*) FROM bid AS b GROUP BY window_start"
+        );
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].table, "bid");
+        assert_eq!(
+            functions[0].window,
+            event_time_window(tumbling_window(10), EventTime::new(vec!["b_date_time"]))
+        );
+
+        let (_, functions) = rewrite_window_functions(
+            "SELECT * FROM hop(TABLE bid, DESCRIPTOR(b_date_time), INTERVAL '5' SECOND, \
+             INTERVAL '1 minute')",
+        )?;
+        assert_eq!(
+            functions[0].window,
+            event_time_window(hopping_window(60, 5), EventTime::new(vec!["b_date_time"]))
+        );
+
+        let (sql, functions) = rewrite_window_functions(
+            "SELECT bidder, window_end FROM SESSION(TABLE bid PARTITION BY bidder, \
+             DESCRIPTOR(b_date_time), INTERVAL '10' SECOND) GROUP BY bidder, window_end",
+        )?;
+        assert_eq!(
+            sql,
+            "SELECT bidder, window_end FROM bid GROUP BY bidder, window_end"
+        );
+        assert_eq!(functions[0].window, session_window(10));
+        assert_eq!(functions[0].key, Some("bidder".to_owned()));
+
+        // A plain query has no window function.
+        let sql = "SELECT hop, session FROM tumble WHERE hop > 1";
+        assert_eq!(rewrite_window_functions(sql)?, (sql.to_owned(), vec![]));
+
+        for sql in [
+            "SELECT * FROM TUMBLE(TABLE bid, DESCRIPTOR(b_date_time))",
+            "SELECT * FROM TUMBLE(TABLE bid, b_date_time, INTERVAL '10' SECOND)",
+            "SELECT * FROM HOP(TABLE bid, DESCRIPTOR(ts), INTERVAL '1' MINUTE, INTERVAL '5' SECOND)",
+            "SELECT * FROM SESSION(TABLE bid, DESCRIPTOR(ts), INTERVAL '10' SECOND)",
+            "SELECT * FROM TUMBLE(TABLE bid, DESCRIPTOR(ts), INTERVAL '10' SECOND",
+        ] {
+            assert!(rewrite_window_functions(sql).is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tumble_window_function() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("price", DataType::Int64, false),
+            Field::new(
+                "b_date_time",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+        ]));
+        let query = Query::new(
+            "SELECT window_start, window_end, SUM(price) AS total \
+             FROM TUMBLE(TABLE bid, DESCRIPTOR(b_date_time), INTERVAL '10' SECOND) \
+             GROUP BY window_start, window_end",
+            vec![Table::new("bid", schema.clone())],
+            DataSource::Memory,
+            DataSinkType::Blackhole,
+            None,
+            QueryType::OLAP,
+            Arc::new(HashMapStateBackend::new()),
+        );
+        let (window, event_time, _) = query.window()?.unwrap().into_parts();
+        assert_eq!(window, tumbling_window(10));
+        let mut windows = EventTimeWindows::new(event_time.unwrap(), 10, 10, 1)?;
+
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2, 3, 4])),
+                Arc::new(TimestampMillisecondArray::from(vec![
+                    1_000, 12_000, 9_000, 15_000,
+                ])),
+            ],
+        )?;
+        windows.insert(0, &vec![vec![batch]])?;
+
+        let mut launcher = LocalLauncher::new(&query).await?;
+        let mut output = vec![];
+        for window in windows.flush() {
+            let input = append_window_columns(&window.relations[0], window.start, window.end)?;
+            launcher.feed_data_sources(vec![input]);
+            output.extend(launcher.collect().await?);
+        }
+
+        let expected = vec![
+            "+---------------------+---------------------+-------+",
+            "| window_start        | window_end          | total |",
+            "+---------------------+---------------------+-------+",
+            "| 1970-01-01 00:00:00 | 1970-01-01 00:00:10 | 4     |",
+            "| 1970-01-01 00:00:10 | 1970-01-01 00:00:20 | 6     |",
+            "+---------------------+---------------------+-------+",
+        ];
+        assert_batches_eq!(&expected, &output);
+        Ok(())
+    }
+}
@@ -0,0 +1,635 @@
+// Copyright (c) 2020-present, UMD Database Group.
+//
+// This program is free software: you can use, redistribute, and/or modify
+// it under the terms of the GNU Affero General Public License, version 3
+// or later ("AGPL"), as published by the Free Software Foundation.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or
+// FITNESS FOR A PARTICULAR PURPOSE.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Event-time processing assigns the records to the windows by the timestamps
+//! in a designated column, rather than by the epoch in which the records are
+//! generated or received.
+//!
+//! The progress of event time is measured by the watermark, which lags behind
+//! the maximum event time seen so far by a bounded out-of-orderness. A window
+//! fires once the watermark passes the end of the window plus the allowed
+//! lateness. The records that arrive after all of their windows have fired
+//! are late, and are emitted to the late side output instead of being dropped
+//! silently.
+//!
+//! The firing policy of the window adds early and late firings, see
+//! [`super::firing`].
+
+use super::firing::{AccumulationMode, Firing, FiringTiming, Pane};
+use crate::datasink::DataSinkType;
+use crate::datasource::RelationPartitions;
+use crate::error::{FlockError, Result};
+use chrono::Utc;
+use datafusion::arrow::array::{BooleanArray, Int64Array};
+use datafusion::arrow::compute::{cast, filter_record_batch};
+use datafusion::arrow::datatypes::{DataType, Schema, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// The event-time settings of a window.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct EventTime {
+    /// The candidate timestamp columns. The records of each relation are
+    /// assigned by the first column that exists in the relation's schema. If
+    /// empty, the first timestamp column of the relation is used.
+    pub columns:              Vec<String>,
+    /// The bound of the out-of-orderness in seconds. The watermark lags behind
+    /// the maximum event time by this bound.
+    pub max_out_of_orderness: usize,
+    /// How long in seconds a window waits for the late records after the
+    /// watermark passes the end of the window.
+    pub allowed_lateness:     usize,
+    /// The data sink of the late records.
+    pub late_sink:            DataSinkType,
+}
+
+impl EventTime {
+    /// Creates the event-time settings with the timestamp columns. There is no
+    /// out-of-orderness or allowed lateness, and the late records are dropped.
+    pub fn new<T: Into<String>>(columns: Vec<T>) -> Self {
+        Self {
+            columns:              columns.into_iter().map(|c| c.into()).collect(),
+            max_out_of_orderness: 0,
+            allowed_lateness:     0,
+            late_sink:            DataSinkType::Blackhole,
+        }
+    }
+
+    /// Returns the timestamp column in the schema. If no candidate column is
+    /// given, the first timestamp column in the schema is used.
+    pub fn find_column(&self, schema: &Schema) -> Option<String> {
+        if self.columns.is_empty() {
+            schema
+                .fields()
+                .iter()
+                .find(|f| matches!(f.data_type(), DataType::Timestamp(..)))
+                .map(|f| f.name().to_owned())
+        } else {
+            self.columns
+                .iter()
+                .find(|c| schema.field_with_name(c).is_ok())
+                .cloned()
+        }
+    }
+
+    /// Returns the timestamp column in the schema, or an error if the schema
+    /// has no event-time column.
+    pub fn column(&self, schema: &Schema) -> Result<String> {
+        self.find_column(schema).ok_or_else(|| {
+            FlockError::Execution(format!(
+                "No event-time column {:?} in the schema: {:?}",
+                self.columns, schema
+            ))
+        })
+    }
+}
+
+impl Default for EventTime {
+    fn default() -> Self {
+        Self::new(Vec::<String>::new())
+    }
+}
+
+/// Returns the event times in milliseconds of the timestamp column. The column
+/// is either an Arrow timestamp of any time unit, or an integer of
+/// milliseconds.
+pub fn event_times(batch: &RecordBatch, column: &str) -> Result<Vec<i64>> {
+    let index = batch.schema().index_of(column)?;
+    let array = batch.column(index);
+    let scale = |t: i64| match array.data_type() {
+        DataType::Timestamp(TimeUnit::Second, _) => t * 1000,
+        DataType::Timestamp(TimeUnit::Microsecond, _) => t / 1000,
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => t / 1_000_000,
+        _ => t,
+    };
+    let array = cast(array, &DataType::Int64)?;
+    let array = array
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .ok_or_else(|| FlockError::Execution(format!("{} is not a timestamp.", column)))?;
+    Ok(array.iter().map(|t| scale(t.unwrap_or_default())).collect())
+}
+
+/// `Watermark` tracks the progress of event time.
+#[derive(Debug, Clone, Default)]
+pub struct Watermark {
+    /// The maximum event time in milliseconds seen so far.
+    max_event_time:       Option<i64>,
+    /// The bound of the out-of-orderness in milliseconds.
+    max_out_of_orderness: i64,
+}
+
+impl Watermark {
+    /// Creates a new watermark with the bound of the out-of-orderness in
+    /// seconds.
+    pub fn new(max_out_of_orderness: usize) -> Self {
+        Self {
+            max_event_time:       None,
+            max_out_of_orderness: max_out_of_orderness as i64 * 1000,
+        }
+    }
+
+    /// Observes the event times.
+    pub fn observe(&mut self, event_times: &[i64]) {
+        if let Some(max) = event_times.iter().max() {
+            self.max_event_time = Some(self.max_event_time.map_or(*max, |t| t.max(*max)));
+        }
+    }
+
+    /// Returns the current watermark in milliseconds. No record with an event
+    /// time before the watermark is expected anymore.
+    pub fn current(&self) -> Option<i64> {
+        self.max_event_time.map(|t| t - self.max_out_of_orderness)
+    }
+}
+
+/// A window fired by the watermark.
+#[derive(Debug, Clone)]
+pub struct EventTimeWindow {
+    /// The start of the window in milliseconds (inclusive).
+    pub start:     i64,
+    /// The end of the window in milliseconds (exclusive).
+    pub end:       i64,
+    /// The data partitions of the relations in the window.
+    pub relations: Vec<RelationPartitions>,
+    /// The firing of the window.
+    pub pane:      Pane,
+}
+
+/// The state of an open event-time window.
+#[derive(Debug)]
+struct WindowState {
+    /// The data partitions of the relations in the window.
+    relations: Vec<RelationPartitions>,
+    /// The number of new records since the previous firing.
+    records:   usize,
+    /// The sequence number of the next firing.
+    seq:       usize,
+    /// Whether the on-time firing is done.
+    on_time:   bool,
+    /// The processing time in milliseconds of the previous firing, or when
+    /// the window is first seen by the firing policy.
+    last_fire: Option<i64>,
+}
+
+impl WindowState {
+    fn new(num_relations: usize) -> Self {
+        Self {
+            relations: vec![vec![]; num_relations],
+            records:   0,
+            seq:       0,
+            on_time:   false,
+            last_fire: None,
+        }
+    }
+
+    /// Returns the timing of the final firing when the window closes, or none
+    /// if the window has nothing new to emit.
+    fn final_timing(&self, mode: AccumulationMode) -> Option<FiringTiming> {
+        if !self.on_time && (self.records > 0 || mode == AccumulationMode::Accumulating) {
+            Some(FiringTiming::OnTime)
+        } else if self.records > 0 {
+            Some(FiringTiming::Late)
+        } else {
+            None
+        }
+    }
+
+    /// Fires a pane of the window.
+    fn fire(
+        &mut self,
+        start: i64,
+        size: i64,
+        timing: FiringTiming,
+        mode: AccumulationMode,
+        now: i64,
+    ) -> EventTimeWindow {
+        let relations = match mode {
+            AccumulationMode::Accumulating => self.relations.clone(),
+            AccumulationMode::Discarding => self.relations.iter_mut().map(std::mem::take).collect(),
+        };
+        let pane = Pane {
+            timing,
+            seq: self.seq,
+        };
+        self.seq += 1;
+        self.records = 0;
+        self.on_time |= timing == FiringTiming::OnTime;
+        self.last_fire = Some(now);
+        EventTimeWindow {
+            start,
+            end: start + size,
+            relations,
+            pane,
+        }
+    }
+}
+
+/// `EventTimeWindows` assigns the records to the hopping windows by event
+/// time. A tumbling window is a hopping window whose hop equals its size.
+#[derive(Debug)]
+pub struct EventTimeWindows {
+    /// The event-time settings.
+    event_time:    EventTime,
+    /// The window size in milliseconds.
+    size:          i64,
+    /// The hop size in milliseconds.
+    hop:           i64,
+    /// The number of relations.
+    num_relations: usize,
+    /// The watermark of the windows.
+    watermark:     Watermark,
+    /// The open windows indexed by the start time.
+    windows:       BTreeMap<i64, WindowState>,
+    /// All windows starting at or before this time have been fired.
+    closed_until:  Option<i64>,
+    /// The firing policy of the windows.
+    firing:        Option<Firing>,
+}
+
+impl EventTimeWindows {
+    /// Creates a new event-time window assigner.
+    ///
+    /// # Arguments
+    /// * `event_time` - The event-time settings.
+    /// * `window_size` - The window size in seconds.
+    /// * `hop_size` - The hop size in seconds.
+    /// * `num_relations` - The number of relations in the query.
+    pub fn new(
+        event_time: EventTime,
+        window_size: usize,
+        hop_size: usize,
+        num_relations: usize,
+    ) -> Result<Self> {
+        if window_size == 0 || hop_size == 0 || hop_size > window_size {
+            return Err(FlockError::Execution(format!(
+                "Invalid event-time window: size {}s, hop {}s.",
+                window_size, hop_size
+            )));
+        }
+        Ok(Self {
+            watermark: Watermark::new(event_time.max_out_of_orderness),
+            event_time,
+            size: window_size as i64 * 1000,
+            hop: hop_size as i64 * 1000,
+            num_relations,
+            windows: BTreeMap::new(),
+            closed_until: None,
+            firing: None,
+        })
+    }
+
+    /// Sets the firing policy of the windows.
+    pub fn with_firing(mut self, firing: Firing) -> Self {
+        self.firing = Some(firing);
+        self
+    }
+
+    /// Returns the accumulation mode of the panes.
+    fn mode(&self) -> AccumulationMode {
+        self.firing
+            .as_ref()
+            .map_or(AccumulationMode::Accumulating, |f| f.mode)
+    }
+
+    /// Returns the current watermark in milliseconds.
+    pub fn watermark(&self) -> Option<i64> {
+        self.watermark.current()
+    }
+
+    /// Returns the start times of the windows that contain the event time.
+    fn window_starts(&self, t: i64) -> impl Iterator<Item = i64> {
+        let (size, hop) = (self.size, self.hop);
+        let first = (t - size).div_euclid(hop) + 1;
+        let last = t.div_euclid(hop);
+        (first..=last).map(move |k| k * hop)
+    }
+
+    /// Assigns a data partition of the relation to the windows.
+    ///
+    /// # Arguments
+    /// * `relation` - The index of the relation.
+    /// * `partitions` - The data partitions generated in the same epoch.
+    ///
+    /// # Returns
+    /// The late records whose windows have all been fired.
+    pub fn insert(
+        &mut self,
+        relation: usize,
+        partitions: &RelationPartitions,
+    ) -> Result<Vec<RecordBatch>> {
+        assert!(relation < self.num_relations);
+        let mut late = vec![];
+        for partition in partitions {
+            // The filtered data partition of each window.
+            let mut assigned: HashMap<i64, Vec<RecordBatch>> = HashMap::new();
+            for batch in partition {
+                if batch.num_rows() == 0 {
+                    continue;
+                }
+                let column = self.event_time.column(&batch.schema())?;
+                let times = event_times(batch, &column)?;
+                self.watermark.observe(&times);
+
+                let mut masks: HashMap<i64, Vec<bool>> = HashMap::new();
+                let mut late_mask = vec![false; times.len()];
+                for (row, t) in times.iter().enumerate() {
+                    let mut on_time = false;
+                    for start in self.window_starts(*t) {
+                        if self.closed_until.map_or(false, |c| start <= c) {
+                            continue;
+                        }
+                        on_time = true;
+                        masks
+                            .entry(start)
+                            .or_insert_with(|| vec![false; times.len()])[row] = true;
+                    }
+                    late_mask[row] = !on_time;
+                }
+
+                for (start, mask) in masks {
+                    let filtered = filter_record_batch(batch, &BooleanArray::from(mask))?;
+                    assigned.entry(start).or_default().push(filtered);
+                }
+                if late_mask.iter().any(|l| *l) {
+                    late.push(filter_record_batch(batch, &BooleanArray::from(late_mask))?);
+                }
+            }
+
+            let num_relations = self.num_relations;
+            for (start, batches) in assigned {
+                let state = self
+                    .windows
+                    .entry(start)
+                    .or_insert_with(|| WindowState::new(num_relations));
+                state.records += batches.iter().map(|b| b.num_rows()).sum::<usize>();
+                state.relations[relation].push(batches);
+            }
+        }
+        Ok(late)
+    }
+
+    /// Fires the windows by the watermark and the firing policy at the current
+    /// processing time.
+    pub fn advance(&mut self) -> Vec<EventTimeWindow> {
+        self.advance_at(Utc::now().timestamp_millis())
+    }
+
+    /// Fires the windows by the watermark and the firing policy.
+    ///
+    /// The windows whose end plus the allowed lateness is at or before the
+    /// watermark are closed. With a firing policy, the open windows also fire
+    /// early before the watermark passes their end, on time when it does, and
+    /// late for the records that arrive afterwards.
+    ///
+    /// # Arguments
+    /// * `now` - The processing time in milliseconds.
+    pub fn advance_at(&mut self, now: i64) -> Vec<EventTimeWindow> {
+        let watermark = match self.watermark.current() {
+            Some(watermark) => watermark,
+            None => return vec![],
+        };
+        let (size, mode) = (self.size, self.mode());
+        let mut fired = vec![];
+
+        let lateness = self.event_time.allowed_lateness as i64 * 1000;
+        // The latest window start that is closed by the watermark.
+        let bound = (watermark - lateness - size).div_euclid(self.hop) * self.hop;
+        if !self.closed_until.map_or(false, |c| bound <= c) {
+            self.closed_until = Some(bound);
+            let open = self.windows.split_off(&(bound + 1));
+            let closed = std::mem::replace(&mut self.windows, open);
+            for (start, mut state) in closed {
+                if let Some(timing) = state.final_timing(mode) {
+                    fired.push(state.fire(start, size, timing, mode, now));
+                }
+            }
+        }
+
+        if let Some(firing) = &self.firing {
+            for (start, state) in self.windows.iter_mut() {
+                let last_fire = *state.last_fire.get_or_insert(now);
+                let timing = if state.on_time {
+                    Some(FiringTiming::Late).filter(|_| state.records > 0)
+                } else if firing.late && start + size <= watermark {
+                    Some(FiringTiming::OnTime)
+                } else {
+                    Some(FiringTiming::Early)
+                        .filter(|_| firing.fires_early(state.records, now - last_fire))
+                };
+                if let Some(timing) = timing {
+                    fired.push(state.fire(*start, size, timing, mode, now));
+                }
+            }
+        }
+
+        fired
+    }
+
+    /// Fires all the open windows at the end of the stream.
+    pub fn flush(&mut self) -> Vec<EventTimeWindow> {
+        if let Some((start, _)) = self.windows.iter().next_back() {
+            self.closed_until = Some(*start);
+        }
+        let (size, mode) = (self.size, self.mode());
+        let now = Utc::now().timestamp_millis();
+        std::mem::take(&mut self.windows)
+            .into_iter()
+            .filter_map(|(start, mut state)| {
+                state
+                    .final_timing(mode)
+                    .map(|timing| state.fire(start, size, timing, mode, now))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int64Array, TimestampMillisecondArray};
+    use datafusion::arrow::datatypes::Field;
+    use std::sync::Arc;
+
+    fn batch(times: &[i64]) -> Result<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new(
+                "b_date_time",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("price", DataType::Int64, false),
+        ]));
+        Ok(RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(TimestampMillisecondArray::from(times.to_vec())),
+                Arc::new(Int64Array::from(vec![1; times.len()])),
+            ],
+        )?)
+    }
+
+    fn num_rows(partitions: &RelationPartitions) -> usize {
+        partitions.iter().flatten().map(|b| b.num_rows()).sum()
+    }
+
+    #[tokio::test]
+    async fn tumbling_event_time_windows() -> Result<()> {
+        let mut event_time = EventTime::new(vec!["a_date_time", "b_date_time"]);
+        event_time.max_out_of_orderness = 2;
+        event_time.allowed_lateness = 3;
+        let mut windows = EventTimeWindows::new(event_time, 10, 10, 1)?;
+
+        // The records of the second epoch are generated out of order.
+        let late = windows.insert(0, &vec![vec![batch(&[1_000, 4_000, 9_500])?]])?;
+        assert!(late.is_empty());
+        assert!(windows.advance().is_empty());
+        let late = windows.insert(0, &vec![vec![batch(&[12_000, 8_000, 14_000])?]])?;
+        assert!(late.is_empty());
+        assert_eq!(windows.watermark(), Some(12_000));
+        // The window [0s, 10s) waits for the allowed lateness.
+        assert!(windows.advance().is_empty());
+
+        let late = windows.insert(0, &vec![vec![batch(&[16_000, 7_000])?]])?;
+        assert!(late.is_empty());
+        let fired = windows.advance();
+        assert_eq!(fired.len(), 1);
+        assert_eq!((fired[0].start, fired[0].end), (0, 10_000));
+        assert_eq!(num_rows(&fired[0].relations[0]), 5);
+
+        // The record is late after its window fires.
+        let late = windows.insert(0, &vec![vec![batch(&[9_999, 19_000])?]])?;
+        assert_eq!(late.len(), 1);
+        assert_eq!(event_times(&late[0], "b_date_time")?, vec![9_999]);
+
+        let fired = windows.flush();
+        assert_eq!(fired.len(), 1);
+        assert_eq!((fired[0].start, fired[0].end), (10_000, 20_000));
+        assert_eq!(num_rows(&fired[0].relations[0]), 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hopping_event_time_windows() -> Result<()> {
+        let mut windows = EventTimeWindows::new(EventTime::new(vec!["b_date_time"]), 10, 5, 2)?;
+        assert!(EventTimeWindows::new(EventTime::new(vec!["b_date_time"]), 5, 10, 1).is_err());
+
+        windows.insert(1, &vec![vec![batch(&[3_000, 7_000, 12_000])?]])?;
+        let fired = windows.advance();
+        // The windows [-5s, 5s) and [0s, 10s) have been closed by the watermark.
+        assert_eq!(
+            fired
+                .iter()
+                .map(|w| (w.start, num_rows(&w.relations[1])))
+                .collect::<Vec<_>>(),
+            vec![(-5_000, 1), (0, 2)]
+        );
+        assert!(fired[0].relations[0].is_empty());
+
+        let fired = windows.flush();
+        assert_eq!(
+            fired
+                .iter()
+                .map(|w| (w.start, num_rows(&w.relations[1])))
+                .collect::<Vec<_>>(),
+            vec![(5_000, 2), (10_000, 1)]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn event_time_units() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "ts",
+            DataType::Timestamp(TimeUnit::Second, None),
+            false,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(
+                datafusion::arrow::array::TimestampSecondArray::from(vec![1, 2]),
+            )],
+        )?;
+        assert_eq!(event_times(&batch, "ts")?, vec![1_000, 2_000]);
+        assert!(EventTime::new(vec!["missing"])
+            .column(&batch.schema())
+            .is_err());
+        Ok(())
+    }
+
+    fn panes(fired: &[EventTimeWindow]) -> Vec<(i64, FiringTiming, usize, usize)> {
+        fired
+            .iter()
+            .map(|w| {
+                (
+                    w.start,
+                    w.pane.timing,
+                    w.pane.seq,
+                    num_rows(&w.relations[0]),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn early_and_late_firings() -> Result<()> {
+        use crate::stream::firing::EarlyFiring;
+        use FiringTiming::*;
+
+        // Fires every 2 records early, and for each late record, in the
+        // discarding mode.
+        let mut event_time = EventTime::new(vec!["b_date_time"]);
+        event_time.allowed_lateness = 5;
+        let mut windows = EventTimeWindows::new(event_time, 10, 10, 1)?.with_firing(Firing {
+            early: vec![EarlyFiring::Elements(2)],
+            late:  true,
+            mode:  AccumulationMode::Discarding,
+        });
+
+        windows.insert(0, &vec![vec![batch(&[1_000, 2_000])?]])?;
+        assert_eq!(panes(&windows.advance_at(0)), vec![(0, Early, 0, 2)]);
+        windows.insert(0, &vec![vec![batch(&[3_000])?]])?;
+        assert!(windows.advance_at(1).is_empty());
+        windows.insert(0, &vec![vec![batch(&[12_000])?]])?;
+        assert_eq!(panes(&windows.advance_at(2)), vec![(0, OnTime, 1, 1)]);
+        windows.insert(0, &vec![vec![batch(&[9_000])?]])?;
+        assert_eq!(panes(&windows.advance_at(3)), vec![(0, Late, 2, 1)]);
+
+        // The window [0s, 10s) closes after the allowed lateness.
+        windows.insert(0, &vec![vec![batch(&[16_000])?]])?;
+        assert_eq!(panes(&windows.advance_at(4)), vec![(10_000, Early, 0, 2)]);
+        assert_eq!(windows.insert(0, &vec![vec![batch(&[9_500])?]])?.len(), 1);
+        assert!(windows.flush().is_empty());
+
+        // Fires every 10 seconds in processing time in the accumulating mode.
+        let mut windows = EventTimeWindows::new(EventTime::new(vec!["b_date_time"]), 10, 10, 1)?
+            .with_firing(Firing {
+                early: vec![EarlyFiring::ProcessingTime(10)],
+                ..Default::default()
+            });
+
+        windows.insert(0, &vec![vec![batch(&[1_000])?]])?;
+        assert!(windows.advance_at(0).is_empty());
+        windows.insert(0, &vec![vec![batch(&[2_000])?]])?;
+        assert_eq!(panes(&windows.advance_at(10_000)), vec![(0, Early, 0, 2)]);
+        assert!(windows.advance_at(20_000).is_empty());
+        windows.insert(0, &vec![vec![batch(&[11_000])?]])?;
+        assert_eq!(panes(&windows.advance_at(20_500)), vec![(0, OnTime, 1, 2)]);
+        assert_eq!(panes(&windows.flush()), vec![(10_000, OnTime, 0, 1)]);
+
+        Ok(())
+    }
+}
@@ -27,6 +27,8 @@
 //! Reference:
 //! <https://docs.microsoft.com/en-us/stream-analytics-query/windowing-azure-stream-analytics>
 
+use super::firing::Firing;
+use super::watermark::EventTime;
 use serde::{Deserialize, Serialize};
 
 type Slide = usize; // seconds
@@ -71,7 +73,8 @@ pub enum Schedule {
     ///
     /// Cron expressionsfor frequencies of up to once per minute.
     Cron(String),
-    /// The window size in terms of the number of rows.
+    /// The window size in terms of the number of rows. The window fires every
+    /// `n` rows, per key if the window has a key column, otherwise globally.
     Rows(usize),
 }
 
@@ -90,7 +93,8 @@ pub enum Window {
     /// simply a hopping window whose 'hop' is equal to its window.
     Hopping((WindowSize, Hop)),
     /// A query that aggregates data continuously, using a fixed time or
-    /// rowcount interval.
+    /// rowcount interval. If the slide is zero, a window is emitted per record.
+    /// Otherwise, a window is emitted per slide interval.
     Sliding((WindowSize, Slide)),
     /// Session windows group events that arrive at similar times, filtering out
     /// periods of time where there is no data.
@@ -106,10 +110,20 @@ pub enum Window {
     /// any time-series analytics use case, such as a set of related sales or
     /// log records. Stagger windows address the issue of related records not
     /// falling into the same time-restricted window, such as when tumbling
-    /// windows were used.
-    Stagger,
+    /// windows were used. The window of each key opens when the first record
+    /// of the key arrives.
+    Stagger(WindowSize),
     /// Element-wise stream processing at epoch level.
     ElementWise,
+    /// A time-based window whose records are assigned by the event time in a
+    /// timestamp column instead of the epoch in which they are generated.
+    /// The window fires once the watermark passes its end plus the allowed
+    /// lateness.
+    EventTime((Box<Window>, EventTime)),
+    /// A window with early and late firings. The window emits refined results
+    /// before the watermark passes its end, and for the late records within
+    /// the allowed lateness.
+    Firing((Box<Window>, Firing)),
 }
 
 impl Default for Window {
@@ -118,6 +132,48 @@ impl Default for Window {
     }
 }
 
+impl Window {
+    /// Returns the event-time settings if the window is driven by event time.
+    pub fn event_time(&self) -> Option<&EventTime> {
+        match self {
+            Window::EventTime((_, event_time)) => Some(event_time),
+            Window::Firing((window, _)) => window.event_time(),
+            _ => None,
+        }
+    }
+
+    /// Returns the firing policy if the window has early or late firings.
+    pub fn firing(&self) -> Option<&Firing> {
+        match self {
+            Window::Firing((_, firing)) => Some(firing),
+            Window::EventTime((window, _)) => window.firing(),
+            _ => None,
+        }
+    }
+
+    /// Splits the window into the underlying window, the event-time settings
+    /// and the firing policy.
+    pub fn into_parts(self) -> (Window, Option<EventTime>, Option<Firing>) {
+        match self {
+            Window::EventTime((window, event_time)) => {
+                let (window, _, firing) = window.into_parts();
+                (window, Some(event_time), firing)
+            }
+            Window::Firing((window, firing)) => {
+                let (window, event_time, _) = window.into_parts();
+                (window, event_time, Some(firing))
+            }
+            window => (window, None, None),
+        }
+    }
+
+    /// Returns the allowed lateness in seconds of the window. Processing-time
+    /// windows never wait for the late records.
+    pub fn allowed_lateness(&self) -> usize {
+        self.event_time().map_or(0, |e| e.allowed_lateness)
+    }
+}
+
 /// Returns a new tumbling window.
 pub fn tumbling_window(sec: usize) -> Window {
     Window::Tumbling(Schedule::Seconds(sec))
@@ -133,6 +189,11 @@ pub fn hopping_window(sec: usize, hop: usize) -> Window {
     Window::Hopping((sec, hop))
 }
 
+/// Returns a new stagger window.
+pub fn stagger_window(sec: usize) -> Window {
+    Window::Stagger(sec)
+}
+
 /// Returns a new session window.
 pub fn session_window(sec: usize) -> Window {
     Window::Session(Schedule::Seconds(sec))
@@ -147,3 +208,21 @@ pub fn global_window(sec: usize) -> Window {
 pub fn element_wise_window() -> Window {
     Window::ElementWise
 }
+
+/// Returns a new event-time window.
+///
+/// # Arguments
+/// * `window` - The tumbling or hopping window to assign the records to.
+/// * `event_time` - The event-time settings.
+pub fn event_time_window(window: Window, event_time: EventTime) -> Window {
+    Window::EventTime((Box::new(window), event_time))
+}
+
+/// Returns a new window with early and late firings.
+///
+/// # Arguments
+/// * `window` - The event-time window to fire.
+/// * `firing` - The firing policy.
+pub fn firing_window(window: Window, firing: Firing) -> Window {
+    Window::Firing((Box::new(window), firing))
+}
@@ -14,8 +14,11 @@
 //! Utility functions to make testing DataFusion based crates easier
 
 use std::collections::BTreeMap;
+use std::sync::Once;
 use std::{env, error::Error, path::PathBuf, sync::Arc};
 
+use crate::objectstore::{set_object_store, InMemoryObjectStore};
+
 use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
 
 /// Compares formatted output of a record batch with an expected
@@ -251,6 +254,14 @@ pub fn aggr_test_schema() -> SchemaRef {
     Arc::new(schema)
 }
 
+/// Replaces the object store of the current process with an in-memory object
+/// store, so that the features depending on the object store can be tested
+/// without a network. All tests in the process share the same object store.
+pub fn init_in_memory_object_store() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| set_object_store(Arc::new(InMemoryObjectStore::new())));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;